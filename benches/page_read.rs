@@ -0,0 +1,130 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use memmap2::{Mmap, MmapMut};
+use rand::Rng;
+use std::hint::black_box;
+
+use mmdb::constants::*;
+use mmdb::data_page::{DataPage, DirtyPage};
+use mmdb::page::{Page, PageRef};
+
+const NUM_PAGES: usize = 1024;
+const KEYS_PER_PAGE: usize = 100;
+const PAGE_SIZE: usize = DEFAULT_PAGE_SIZE;
+
+fn key(pgno: usize, i: usize) -> Vec<u8> {
+    format!("{pgno:06}-{i:04}").into_bytes()
+}
+
+fn build_file() -> Mmap {
+    let mut mmap = MmapMut::map_anon(NUM_PAGES * PAGE_SIZE).unwrap();
+    for pgno in 0..NUM_PAGES {
+        let empty = DataPage::new_page(pgno as Pgno, PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&empty).unwrap(), pgno as Pgno);
+        for i in 0..KEYS_PER_PAGE {
+            dirty.put(&key(pgno, i), b"value").unwrap();
+        }
+        let page = dirty.into_page();
+        mmap[pgno * PAGE_SIZE..(pgno + 1) * PAGE_SIZE].copy_from_slice(page.as_bytes());
+    }
+    mmap.make_read_only().unwrap()
+}
+
+fn random_gets(c: &mut Criterion) {
+    let mmap = build_file();
+    let mut group = c.benchmark_group("random_get");
+    let random_key = || {
+        let mut rng = rand::rng();
+        let pgno = rng.random_range(0..NUM_PAGES);
+        (pgno, key(pgno, rng.random_range(0..KEYS_PER_PAGE)))
+    };
+
+    group.bench_function("copied_page", |b| {
+        b.iter_batched(
+            random_key,
+            |(pgno, key)| {
+                let page = Page::read_from_mmap(&mmap, PAGE_SIZE, pgno).unwrap();
+                let data_page = DataPage::from(&page).unwrap();
+                black_box(data_page.get(&key).unwrap().len())
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("page_ref", |b| {
+        b.iter_batched(
+            random_key,
+            |(pgno, key)| {
+                let page = PageRef::from_mmap(&mmap, PAGE_SIZE, pgno).unwrap();
+                let data_page = DataPage::from(page).unwrap();
+                black_box(data_page.get(&key).unwrap().len())
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+// pages holding only a few nodes, which bisection handles as well as a
+// linear scan, within the noise
+fn small_page_searches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small_page_search");
+    for num_keys in [2, 4, 8, 16, 32, 64] {
+        let empty = DataPage::new_page(0, PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&empty).unwrap(), 0);
+        for i in 0..num_keys {
+            dirty.put(&key(0, i), b"value").unwrap();
+        }
+        let page = dirty.into_page();
+        let data_page = DataPage::from(&page).unwrap();
+        group.bench_function(num_keys.to_string(), |b| {
+            b.iter_batched(
+                || key(0, rand::rng().random_range(0..num_keys)),
+                |key| black_box(data_page.search(&key).unwrap()),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// bisecting a full page, comparing only the stored keys against parsing
+// every node the search visits
+fn full_page_searches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("full_page_search");
+    let empty = DataPage::new_page(0, PAGE_SIZE);
+    let mut dirty = DirtyPage::from(&DataPage::from(&empty).unwrap(), 0);
+    let mut num_keys = 0;
+    while dirty.put(&key(0, num_keys), b"value").is_ok() {
+        num_keys += 1;
+    }
+    let page = dirty.into_page();
+    let data_page = DataPage::from(&page).unwrap();
+    let random_key = || key(0, rand::rng().random_range(0..num_keys));
+
+    group.bench_function("key_only", |b| {
+        b.iter_batched(
+            random_key,
+            |key| black_box(data_page.search(&key).unwrap()),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("parsed_node", |b| {
+        let offsets: Vec<usize> = data_page.get_offsets().iter().map(usize::from).collect();
+        b.iter_batched(
+            random_key,
+            |key| {
+                black_box(offsets.binary_search_by(|&offset| {
+                    data_page.read_node_from_offset(offset).unwrap().cmp_key(&key)
+                }))
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, random_gets, small_page_searches, full_page_searches);
+criterion_main!(benches);
@@ -0,0 +1,33 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::hint::black_box;
+use tempfile::tempdir;
+
+use mmdb::env::Env;
+
+const NUM_ENTRIES: u64 = 100_000;
+
+fn key(i: u64) -> Vec<u8> {
+    format!("key-{i:010}").into_bytes()
+}
+
+fn full_scan(c: &mut Criterion) {
+    let dir = tempdir().unwrap();
+    let env = Env::open(dir.path().join("db")).unwrap();
+    let mut txn = env.begin_write();
+    txn.write_batch((0..NUM_ENTRIES).map(|i| (key(i), b"value"))).unwrap();
+    txn.commit().unwrap();
+
+    let mut group = c.benchmark_group("full_scan");
+    group.throughput(Throughput::Elements(NUM_ENTRIES));
+    group.bench_function("cursor", |b| {
+        let txn = env.begin_read().unwrap();
+        b.iter(|| {
+            let cursor = txn.tree().cursor().unwrap();
+            black_box(cursor.map(|entry| entry.unwrap().1.len()).sum::<usize>())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, full_scan);
+criterion_main!(benches);
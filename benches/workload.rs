@@ -0,0 +1,131 @@
+//! Whole-environment workloads at a small and a large tree size. Keys are
+//! drawn from fixed seeds, so every run does the same work and results can be
+//! compared across commits with criterion's baselines (`--save-baseline` and
+//! `--baseline`).
+//!
+//! Each put commits on its own: the commit rewrites every page on the path to
+//! the leaf, so the bytes it writes per byte of entry (printed before the put
+//! benchmarks) are the write amplification of the put path.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+use std::path::Path;
+use tempfile::{tempdir, TempDir};
+
+use mmdb::env::Env;
+
+const SIZES: [u64; 2] = [10_000, 1_000_000];
+const VALUE: &[u8] = &[0xab; 100];
+const SCAN_LENGTH: usize = 100;
+
+fn key(i: u64) -> Vec<u8> {
+    format!("key-{i:010}").into_bytes()
+}
+
+fn load(dir: &Path, n: u64) -> Env {
+    Env::bulk_load(dir.join("db"), (0..n).map(|i| (key(i), VALUE))).unwrap()
+}
+
+// average bytes one put commits (its dirty pages plus the meta page) per byte
+// of key and value, over `puts` consecutive keys from `next_key`
+fn write_amplification(env: &Env, puts: u64, mut next_key: impl FnMut() -> Vec<u8>) -> f64 {
+    let mut written = 0;
+    let mut entry_bytes = 0;
+    for _ in 0..puts {
+        let key = next_key();
+        let mut txn = env.begin_write();
+        txn.put(&key, VALUE).unwrap();
+        written += txn.stats().bytes_pending + env.get_page_size() as u64;
+        entry_bytes += (key.len() + VALUE.len()) as u64;
+        txn.commit().unwrap();
+    }
+    written as f64 / entry_bytes as f64
+}
+
+fn puts(c: &mut Criterion) {
+    let mut group = c.benchmark_group("put");
+    for n in SIZES {
+        let dir = tempdir().unwrap();
+        let env = load(dir.path(), n);
+        let mut rng = StdRng::seed_from_u64(n);
+        let amplification = write_amplification(&env, 100, || key(rng.random_range(0..n)));
+        println!("put/random/{n}: {amplification:.1}x write amplification");
+        group.bench_function(BenchmarkId::new("random", n), |b| {
+            b.iter(|| {
+                let mut txn = env.begin_write();
+                txn.put(&key(rng.random_range(0..n)), VALUE).unwrap();
+                txn.commit().unwrap();
+            })
+        });
+
+        // appends past the loaded keys
+        let dir = tempdir().unwrap();
+        let env = load(dir.path(), n);
+        let mut next = n;
+        let mut append = || {
+            next += 1;
+            key(next)
+        };
+        let amplification = write_amplification(&env, 100, &mut append);
+        println!("put/sequential/{n}: {amplification:.1}x write amplification");
+        group.bench_function(BenchmarkId::new("sequential", n), |b| {
+            b.iter(|| {
+                let mut txn = env.begin_write();
+                txn.put(&append(), VALUE).unwrap();
+                txn.commit().unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn reads(c: &mut Criterion) {
+    let mut gets = c.benchmark_group("get");
+    let loaded: Vec<(u64, TempDir, Env)> = SIZES
+        .into_iter()
+        .map(|n| {
+            let dir = tempdir().unwrap();
+            let env = load(dir.path(), n);
+            (n, dir, env)
+        })
+        .collect();
+    for (n, _, env) in &loaded {
+        let n = *n;
+        let mut rng = StdRng::seed_from_u64(n);
+        let txn = env.begin_read().unwrap();
+        gets.bench_function(BenchmarkId::new("random", n), |b| {
+            b.iter(|| black_box(txn.get(&key(rng.random_range(0..n))).unwrap().len()))
+        });
+    }
+    gets.finish();
+
+    let mut scans = c.benchmark_group("range_scan");
+    for (n, _, env) in &loaded {
+        let n = *n;
+        let mut rng = StdRng::seed_from_u64(n);
+        let txn = env.begin_read().unwrap();
+        scans.bench_function(BenchmarkId::new(SCAN_LENGTH.to_string(), n), |b| {
+            b.iter(|| {
+                let mut cursor = txn.tree().cursor().unwrap();
+                cursor.seek(&key(rng.random_range(0..n))).unwrap();
+                let entries = cursor.take(SCAN_LENGTH);
+                black_box(entries.map(|entry| entry.unwrap().1.len()).sum::<usize>())
+            })
+        });
+    }
+    scans.finish();
+
+    let mut reopens = c.benchmark_group("reopen");
+    for (n, dir, env) in loaded {
+        drop(env);
+        let path = dir.path().join("db");
+        reopens.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter(|| black_box(Env::open(&path).unwrap().get_meta().get_txnid()))
+        });
+    }
+    reopens.finish();
+}
+
+criterion_group!(benches, puts, reads);
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+#![no_main]
+
+//! Decodes arbitrary bytes as varints and the lengths and posting lists built
+//! from them. Decoding must fail with an error rather than panic, and what it
+//! accepts must be exactly what the encoder writes. Run with
+//! `cargo fuzz run decode_varint` from the repository root.
+
+use libfuzzer_sys::fuzz_target;
+use mmdb::buf::{read_varint_len, read_varint_u64, varint_len, write_varint_u64};
+use mmdb::inverted_index::{decode_postings, encode_postings};
+
+fuzz_target!(|input: &[u8]| {
+    if let Ok((value, len)) = read_varint_u64(input) {
+        let mut encoded = Vec::new();
+        write_varint_u64(&mut encoded, value);
+        assert_eq!(encoded, input[..len]);
+        assert_eq!(varint_len(value), len);
+    }
+    if let Ok((len, read)) = read_varint_len(input, input.len()) {
+        assert!(len <= input.len() && read <= input.len());
+    }
+    if let Ok(ids) = decode_postings(input) {
+        assert!(ids.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(decode_postings(&encode_postings(&ids)).unwrap(), ids);
+    }
+});
@@ -0,0 +1,47 @@
+#![no_main]
+
+//! Parses arbitrary bytes as every kind of page, the way reads from a damaged
+//! file would. Every failure must surface as an error, never a panic. Run with
+//! `cargo fuzz run parse_page` from the repository root.
+
+use libfuzzer_sys::fuzz_target;
+use mmdb::btree_page::BranchPage;
+use mmdb::constants::*;
+use mmdb::data_page::DataPage;
+use mmdb::log_page::LogPage;
+use mmdb::meta::Meta;
+use mmdb::page::Page;
+
+fuzz_target!(|input: &[u8]| {
+    // inputs are padded or cut to a whole page, so the interesting bytes are
+    // the header and whatever sits at the start of the data area
+    let mut bytes = input.to_vec();
+    bytes.resize(MIN_PAGE_SIZE, 0);
+    let page = Page::from_bytes(&bytes).unwrap();
+    let _ = page.as_page_ref().verify_checksum();
+    let _ = Meta::from(page.as_page_ref());
+
+    if let Ok(segment) = LogPage::from(&page) {
+        for record in segment.records() {
+            let _ = record;
+        }
+    }
+
+    let Ok(data_page) = DataPage::from(&page) else {
+        return;
+    };
+    let _ = data_page.validate();
+    for node in data_page.nodes().include_deleted().flatten() {
+        let _ = (node.get_key(), node.get_data());
+    }
+    let key = input.get(PAGE_HEADER_SIZE..).unwrap_or_default();
+    let _ = data_page.get(key);
+    let _ = data_page.put(0, b"key", b"value");
+    if let Ok(branch) = BranchPage::from(data_page) {
+        let _ = branch.get(key);
+        for idx in 0..branch.num_children() {
+            let _ = branch.child_at(idx);
+            let _ = branch.entries_at(idx);
+        }
+    }
+});
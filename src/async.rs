@@ -0,0 +1,336 @@
+use futures_core::Stream;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::ops::{Bound, RangeBounds};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::constants::*;
+use crate::env::{Env, EnvOptions};
+use crate::pin::{key_range, KeyRange};
+use crate::txn::WriteTxn;
+
+/// How many entries a scan reads ahead of the stream yielding them.
+pub const SCAN_READ_AHEAD: usize = 256;
+
+/// An `Env` for async code. Opening, bulk loads and commits, which wait on
+/// the disk and on the writer lock, run on a thread of their own, and scans
+/// are read on one and streamed back, so none of them blocks the executor,
+/// whichever one it is. Single lookups don't wait on anything but the page
+/// reads themselves and can go straight to `get_env`.
+#[derive(Clone)]
+pub struct AsyncEnv {
+    env: Arc<Env>,
+}
+
+impl AsyncEnv {
+    pub fn new(env: impl Into<Arc<Env>>) -> Self {
+        AsyncEnv { env: env.into() }
+    }
+
+    /// See `EnvOptions::open`.
+    pub async fn open(options: EnvOptions, path: impl AsRef<Path>) -> Result<Self, DBError> {
+        let path = path.as_ref().to_path_buf();
+        spawn_blocking(move || options.open(path))?.await.map(Self::new)
+    }
+
+    /// See `EnvOptions::bulk_load`; `entries` are iterated on the loading
+    /// thread.
+    pub async fn bulk_load<I, K, V>(
+        options: EnvOptions,
+        path: impl AsRef<Path>,
+        entries: I,
+    ) -> Result<Self, DBError>
+    where
+        I: IntoIterator<Item = (K, V)> + Send + 'static,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let path = path.as_ref().to_path_buf();
+        spawn_blocking(move || options.bulk_load(path, entries))?.await.map(Self::new)
+    }
+
+    pub fn get_env(&self) -> &Arc<Env> {
+        &self.env
+    }
+
+    /// Runs `write` in a write transaction and commits it, unless `write`
+    /// fails, in which case nothing it did is committed.
+    pub async fn write<R>(
+        &self,
+        write: impl FnOnce(&mut WriteTxn<'_>) -> Result<R, DBError> + Send + 'static,
+    ) -> Result<R, DBError>
+    where
+        R: Send + 'static,
+    {
+        let env = Arc::clone(&self.env);
+        spawn_blocking(move || {
+            let mut txn = env.begin_write();
+            let result = write(&mut txn)?;
+            txn.commit()?;
+            Ok(result)
+        })?
+        .await
+    }
+
+    /// The entries of `range` in order, all from the commit that is current
+    /// now. The read transaction stays open until the stream ends or is
+    /// dropped.
+    pub fn scan<'k>(&self, range: impl RangeBounds<&'k [u8]>) -> Result<ScanStream, DBError> {
+        let range = key_range(&range);
+        let env = Arc::clone(&self.env);
+        let channel = Arc::new(Channel::new(SCAN_READ_AHEAD));
+        let sender = Arc::clone(&channel);
+        thread::Builder::new().name("mmdb-scan".into()).spawn(move || {
+            if let Err(err) = scan_range(&env, &range, &sender) {
+                sender.send(Err(err));
+            }
+            sender.finish();
+        })?;
+        Ok(ScanStream { channel })
+    }
+}
+
+// sends the entries of `range` until they run out or the stream is dropped
+fn scan_range(
+    env: &Env,
+    range: &KeyRange,
+    channel: &Channel<Result<KeyValue, DBError>>,
+) -> Result<(), DBError> {
+    let txn = env.begin_read()?;
+    let tree = txn.tree();
+    let bounds = (range.0.as_ref().map(Vec::as_slice), range.1.as_ref().map(Vec::as_slice));
+    let mut cursor = tree.cursor()?;
+    if let Bound::Included(start) | Bound::Excluded(start) = bounds.0 {
+        cursor.seek(start)?;
+    }
+    for entry in cursor {
+        let (key, data) = entry?;
+        match tree.get_order().compare_to_range(&key, &bounds) {
+            Ordering::Less => continue,
+            Ordering::Greater => break,
+            Ordering::Equal => {
+                if !channel.send(Ok((key.into_owned(), data.to_vec()))) {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Entries from `AsyncEnv::scan`.
+pub struct ScanStream {
+    channel: Arc<Channel<Result<KeyValue, DBError>>>,
+}
+
+impl Stream for ScanStream {
+    type Item = Result<KeyValue, DBError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.channel.poll_recv(cx)
+    }
+}
+
+impl Drop for ScanStream {
+    fn drop(&mut self) {
+        self.channel.close();
+    }
+}
+
+/// The result of a closure run on a thread of its own, like tokio's
+/// `spawn_blocking`. A panic in the closure is raised again where this is
+/// awaited.
+pub struct Blocking<R> {
+    channel: Arc<Channel<thread::Result<R>>>,
+}
+
+pub fn spawn_blocking<R: Send + 'static>(
+    run: impl FnOnce() -> R + Send + 'static,
+) -> Result<Blocking<R>, DBError> {
+    let channel = Arc::new(Channel::new(1));
+    let sender = Arc::clone(&channel);
+    thread::Builder::new().name("mmdb-blocking".into()).spawn(move || {
+        sender.send(panic::catch_unwind(AssertUnwindSafe(run)));
+        sender.finish();
+    })?;
+    Ok(Blocking { channel })
+}
+
+impl<R> Future for Blocking<R> {
+    type Output = R;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        match self.channel.poll_recv(cx) {
+            Poll::Ready(Some(Ok(result))) => Poll::Ready(result),
+            Poll::Ready(Some(Err(payload))) => panic::resume_unwind(payload),
+            Poll::Ready(None) => panic!("blocking task polled after it completed"),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// A bounded queue from a thread to a task: the thread waits while it's full,
+// and the task is woken when there is something to take.
+struct Channel<T> {
+    capacity: usize,
+    state: Mutex<ChannelState<T>>,
+    space: Condvar,
+}
+
+struct ChannelState<T> {
+    items: VecDeque<T>,
+    // the sender has sent everything
+    finished: bool,
+    // the receiver is gone
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl<T> Channel<T> {
+    fn new(capacity: usize) -> Self {
+        Channel {
+            capacity,
+            state: Mutex::new(ChannelState {
+                items: VecDeque::new(),
+                finished: false,
+                closed: false,
+                waker: None,
+            }),
+            space: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ChannelState<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // false once the receiver is gone, and the item with it
+    fn send(&self, item: T) -> bool {
+        let mut state = self.lock();
+        while state.items.len() >= self.capacity && !state.closed {
+            state = self.space.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+        if state.closed {
+            return false;
+        }
+        state.items.push_back(item);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+
+    fn finish(&self) {
+        let mut state = self.lock();
+        state.finished = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.space.notify_one();
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.lock();
+        if let Some(item) = state.items.pop_front() {
+            self.space.notify_one();
+            return Poll::Ready(Some(item));
+        }
+        if state.finished {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future;
+    use std::task::Wake;
+    use tempfile::tempdir;
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // the simplest executor there is: polls on the current thread, parking
+    // it in between
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    fn collect(mut stream: ScanStream) -> Result<Vec<KeyValue>, DBError> {
+        let mut entries = Vec::new();
+        while let Some(entry) =
+            block_on(future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)))
+        {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    #[test]
+    fn test_async_env() {
+        let dir = tempdir().unwrap();
+        let entries = (0..1000u32).map(|i| (i.to_be_bytes(), [7u8; 10]));
+        let load = AsyncEnv::bulk_load(EnvOptions::new(), dir.path().join("db"), entries);
+        let env = block_on(load).unwrap();
+
+        let written = env.write(|txn| {
+            txn.put(&1000u32.to_be_bytes(), b"new")?;
+            Ok(txn.stats().keys_written)
+        });
+        assert_eq!(block_on(written).unwrap(), 1);
+        let failed = env.write(|txn| {
+            txn.delete(&0u32.to_be_bytes())?;
+            txn.delete(b"missing")
+        });
+        assert!(matches!(block_on(failed), Err(DBError::KeyNotFound)));
+        let txn = env.get_env().begin_read().unwrap();
+        assert_eq!(txn.get(&1000u32.to_be_bytes()).unwrap(), b"new");
+        assert!(txn.get(&0u32.to_be_bytes()).is_ok());
+        drop(txn);
+
+        // more entries than the scan reads ahead
+        let (start, end) = (10u32.to_be_bytes(), 600u32.to_be_bytes());
+        let scanned = collect(env.scan(&start[..]..&end[..]).unwrap()).unwrap();
+        assert_eq!(scanned.len(), 590);
+        assert_eq!(scanned[0], (start.to_vec(), vec![7; 10]));
+        assert_eq!(collect(env.scan(..).unwrap()).unwrap().len(), 1001);
+
+        // a stream dropped early lets its scan stop, and the reader go
+        drop(env.scan(..).unwrap());
+        while env.get_env().oldest_reader().is_some() {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "boom")]
+    fn test_blocking_panic() {
+        block_on(spawn_blocking(|| panic!("boom")).unwrap());
+    }
+}
@@ -0,0 +1,690 @@
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use crate::btree_page::{BranchPage, LeafPage};
+use crate::constants::*;
+use crate::cursor::{Cursor, Entry};
+use crate::data_page::{DataNode, DataPage, NO_COMPRESSION};
+use crate::key_order::{IterationOrder, KeyOrder};
+use crate::page_cache::ParsedBranch;
+use crate::pin::KeyRange;
+
+// deeper than any real tree gets; a corrupt file could otherwise send a
+// descent around a cycle of branch pages forever
+pub const MAX_TREE_DEPTH: usize = 32;
+
+/// The smallest and largest keys of a tree.
+pub type KeyBounds = (Vec<u8>, Vec<u8>);
+
+/// Page and entry counts for one tree, from `BTree::stat`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TreeStat {
+    /// Number of levels, counting the leaves.
+    pub depth: usize,
+    pub branch_pages: u64,
+    pub leaf_pages: u64,
+    /// Values too large for a leaf; always 0 until they are supported.
+    pub overflow_pages: u64,
+    /// Live entries, not counting soft-deleted nodes still on their pages.
+    pub entries: u64,
+    /// Bytes of page data in use (node offsets, nodes and the shared prefix)
+    /// and available, over every branch and leaf page.
+    pub used_bytes: u64,
+    pub capacity_bytes: u64,
+    /// Values stored compressed, with their bytes as stored and as written.
+    pub compressed_values: u64,
+    pub compressed_bytes: u64,
+    pub uncompressed_bytes: u64,
+}
+
+impl TreeStat {
+    /// Share of the page data space in use, from 0 to 1.
+    pub fn fill_factor(&self) -> f64 {
+        match self.capacity_bytes {
+            0 => 0.0,
+            capacity => self.used_bytes as f64 / capacity as f64,
+        }
+    }
+
+    /// How many times smaller compression made the values it was used on; 1
+    /// if there are none.
+    pub fn compression_ratio(&self) -> f64 {
+        match self.compressed_bytes {
+            0 => 1.0,
+            stored => self.uncompressed_bytes as f64 / stored as f64,
+        }
+    }
+}
+
+/// Totals for the entries whose keys share one prefix, from
+/// `BTree::prefix_stats`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PrefixStats {
+    pub prefix: Vec<u8>,
+    pub entries: u64,
+    pub key_bytes: u64,
+    pub value_bytes: u64,
+}
+
+/// Where a tree's pages are read from: a committed snapshot, or a write
+/// transaction's dirty pages layered over one.
+pub trait PageSource {
+    fn get_page(&self, pgno: Pgno) -> Result<DataPage<'_>, DBError>;
+
+    /// Page `pgno` already parsed, for sources that keep a cache of branch
+    /// pages; `None` if it isn't a branch or there is no cache.
+    fn get_parsed_branch(&self, _pgno: Pgno) -> Result<Option<Arc<ParsedBranch>>, DBError> {
+        Ok(None)
+    }
+
+    /// `data`, a value stored compressed, decompressed into memory the source
+    /// holds on to for as long as it lives. Sources that hold none, and every
+    /// source without the `compression` feature, fail with `Unsupported`.
+    fn decompress(&self, _data: &[u8]) -> Result<&[u8], DBError> {
+        Err(NO_COMPRESSION)
+    }
+
+    /// Hints that pages `pgnos` will be read soon; sources that read from
+    /// memory ignore it.
+    fn prefetch(&self, _pgnos: &[Pgno]) {}
+
+    /// Whether page `pgno` was written since the last commit, in which case
+    /// the entry count its parent keeps for it may be out of date.
+    fn is_dirty(&self, _pgno: Pgno) -> bool {
+        false
+    }
+}
+
+/// The branch pages visited on the way down to a leaf, each with the index of
+/// the child that was followed, and the leaf itself.
+pub struct Descent<'a> {
+    pub path: Vec<(Pgno, usize)>,
+    pub leaf: LeafPage<'a>,
+}
+
+/// Read access to the tree rooted at `root`.
+#[derive(Clone, Copy)]
+pub struct BTree<'a> {
+    pages: &'a dyn PageSource,
+    root: Option<Pgno>,
+    order: KeyOrder,
+}
+
+impl<'a> BTree<'a> {
+    pub fn new(pages: &'a dyn PageSource, root: Option<Pgno>, order: KeyOrder) -> Self {
+        BTree { pages, root, order }
+    }
+
+    pub const fn get_root(&self) -> Option<Pgno> {
+        self.root
+    }
+
+    pub const fn get_order(&self) -> KeyOrder {
+        self.order
+    }
+
+    /// The ordering guarantees this tree's cursors give.
+    pub const fn ordering(&self) -> IterationOrder {
+        IterationOrder::of(self.order)
+    }
+
+    pub fn get_page(&self, pgno: Pgno) -> Result<DataPage<'a>, DBError> {
+        Ok(self.pages.get_page(pgno)?.with_order(self.order))
+    }
+
+    /// Follows `key` from the root down to the leaf it belongs in. Fails with
+    /// `KeyNotFound` if the tree is empty.
+    pub fn descend(&self, key: &[u8]) -> Result<Descent<'a>, DBError> {
+        let mut pgno = self.root.ok_or(DBError::KeyNotFound)?;
+        let mut path = Vec::new();
+        loop {
+            let (idx, child) = match self.pages.get_parsed_branch(pgno)? {
+                Some(branch) => {
+                    let idx = branch.child_index(key, self.order);
+                    (idx, branch.child_at(idx))
+                }
+                None => {
+                    let page = self.get_page(pgno)?;
+                    if !page.get_flags().contains(PageFlag::BRANCH) {
+                        return Ok(Descent { path, leaf: LeafPage::from(page)? });
+                    }
+                    let branch = BranchPage::from(page)?;
+                    let idx = branch.child_index(key)?;
+                    (idx, branch.child_at(idx)?)
+                }
+            };
+            if path.len() == MAX_TREE_DEPTH {
+                return Err(DBError::CorruptPage { pgno, reason: "tree is too deep" });
+            }
+            path.push((pgno, idx));
+            pgno = child;
+        }
+    }
+
+    /// Follows the last child of every branch page down to the last leaf;
+    /// `None` if the tree is empty.
+    pub fn descend_last(&self) -> Result<Option<Descent<'a>>, DBError> {
+        let Some(mut pgno) = self.root else {
+            return Ok(None);
+        };
+        let mut path = Vec::new();
+        loop {
+            let page = self.get_page(pgno)?;
+            if !page.get_flags().contains(PageFlag::BRANCH) {
+                return Ok(Some(Descent { path, leaf: LeafPage::from(page)? }));
+            }
+            if path.len() == MAX_TREE_DEPTH {
+                return Err(DBError::CorruptPage { pgno, reason: "tree is too deep" });
+            }
+            let branch = BranchPage::from(page)?;
+            let idx = branch.num_children() - 1;
+            path.push((pgno, idx));
+            pgno = branch.child_at(idx)?;
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<&'a [u8], DBError> {
+        match self.descend(key)?.leaf.as_data_page().get_node(key)? {
+            node if node.is_alive() => self.get_value(&node),
+            _ => Err(DBError::KeyNotFound),
+        }
+    }
+
+    /// The values of `keys`, in the order given, with `None` for keys that
+    /// aren't there. The keys are looked up in sorted order, so the tree is
+    /// only descended once per leaf they fall in, rather than once per key;
+    /// a key past the last one of the leaf the previous key was found in is
+    /// looked up from the root, as it may belong in the next leaf.
+    pub fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<&'a [u8]>>, DBError> {
+        let mut values = vec![None; keys.len()];
+        if self.root.is_none() {
+            return Ok(values);
+        }
+        let mut sorted: Vec<usize> = (0..keys.len()).collect();
+        sorted.sort_by(|&a, &b| self.order.compare(keys[a], keys[b]));
+        let mut leaf: Option<DataPage<'a>> = None;
+        for i in sorted {
+            let key = keys[i];
+            let in_leaf = match leaf {
+                Some(page) => match page.search(key)? {
+                    Err(end) if end == page.num_nodes() => None,
+                    found => Some((page, found)),
+                },
+                None => None,
+            };
+            let (page, found) = match in_leaf {
+                Some(in_leaf) => in_leaf,
+                None => {
+                    let page = *self.descend(key)?.leaf.as_data_page();
+                    (page, page.search(key)?)
+                }
+            };
+            leaf = Some(page);
+            if let Ok(idx) = found {
+                let node = page.read_node(idx)?;
+                if node.is_alive() {
+                    values[i] = Some(self.get_value(&node)?);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Whether `key` has a live entry, found without reading its value.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool, DBError> {
+        if self.root.is_none() {
+            return Ok(false);
+        }
+        let leaf = *self.descend(key)?.leaf.as_data_page();
+        match leaf.search(key)? {
+            Ok(idx) => leaf.is_alive_at(idx),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// The value `node` holds, decompressed if it is stored compressed.
+    pub fn get_value(&self, node: &DataNode<'a>) -> Result<&'a [u8], DBError> {
+        if node.is_compressed() {
+            self.pages.decompress(node.get_data())
+        } else {
+            Ok(node.get_data())
+        }
+    }
+
+    /// Number of entries under the child at `idx` of `branch`: the count the
+    /// branch keeps, unless the child is dirty and has to be counted itself.
+    pub fn child_entries(&self, branch: &BranchPage, idx: usize) -> Result<u64, DBError> {
+        let child = branch.child_at(idx)?;
+        if !self.pages.is_dirty(child) {
+            return branch.entries_at(idx);
+        }
+        let page = self.get_page(child)?;
+        if !page.get_flags().contains(PageFlag::BRANCH) {
+            return page.nodes().try_fold(0, |count, node| node.map(|_| count + 1));
+        }
+        let branch = BranchPage::from(page)?;
+        (0..branch.num_children()).map(|idx| self.child_entries(&branch, idx)).sum()
+    }
+
+    /// See `PageSource::prefetch`.
+    pub fn prefetch(&self, pgnos: &[Pgno]) {
+        self.pages.prefetch(pgnos);
+    }
+
+    /// A cursor positioned at the first entry.
+    pub fn cursor(&self) -> Result<Cursor<'a>, DBError> {
+        Cursor::new(*self)
+    }
+
+    /// The entry with the largest key, found without scanning from the first;
+    /// see `Cursor::seek_last`.
+    pub fn last_key_value(&self) -> Result<Option<Entry<'a>>, DBError> {
+        let mut cursor = self.cursor()?;
+        cursor.seek_last()?;
+        cursor.next().transpose()
+    }
+
+    /// The entries whose keys start with `prefix`, in order, starting from the
+    /// first key >= `prefix`. Keys sharing a prefix are only guaranteed to be
+    /// next to each other under byte order; under other orders the scan ends
+    /// at the first key that doesn't match.
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+    ) -> Result<impl Iterator<Item = Result<Entry<'a>, DBError>> + 'a, DBError> {
+        let mut cursor = self.cursor()?;
+        cursor.seek(prefix)?;
+        let prefix = prefix.to_vec();
+        // errors are passed through, and end the cursor on their own
+        Ok(cursor.take_while(move |entry| {
+            entry.as_ref().map_or(true, |(key, _)| key.starts_with(&prefix))
+        }))
+    }
+
+    /// Up to `limit` entries of `range`, after skipping the first `offset` of
+    /// them. An offset reaching past the first leaf costs a single descent
+    /// however large it is, going by the entry counts of the branch pages
+    /// (see `Cursor::skip_entries`).
+    pub fn page<'k>(
+        &self,
+        range: impl RangeBounds<&'k [u8]>,
+        offset: u64,
+        limit: usize,
+    ) -> Result<Vec<Entry<'a>>, DBError> {
+        let mut entries = Vec::new();
+        if limit == 0 {
+            return Ok(entries);
+        }
+        let mut cursor = self.cursor()?;
+        let mut offset = offset;
+        match range.start_bound() {
+            Bound::Included(start) => cursor.seek(start)?,
+            Bound::Excluded(start) => {
+                cursor.seek(start)?;
+                // the start itself is skipped along with the offset
+                offset += self.get(start).is_ok() as u64;
+            }
+            Bound::Unbounded => {}
+        }
+        cursor.skip_entries(offset)?;
+        for entry in cursor {
+            let (key, data) = entry?;
+            if self.order.compare_to_range(&key, &range).is_gt() {
+                break;
+            }
+            entries.push((key, data));
+            if entries.len() == limit {
+                break;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Entry counts and sizes grouped by the first `depth` bytes of each key
+    /// (keys shorter than that are their own prefix), in byte order of the
+    /// prefixes. Reads every leaf once.
+    pub fn prefix_stats(&self, depth: usize) -> Result<Vec<PrefixStats>, DBError> {
+        let mut groups: BTreeMap<Vec<u8>, PrefixStats> = BTreeMap::new();
+        for entry in self.cursor()? {
+            let (key, data) = entry?;
+            let prefix = &key[..depth.min(key.len())];
+            // entries usually arrive grouped, but other key orders needn't keep
+            // a prefix together
+            let stats = match groups.get_mut(prefix) {
+                Some(stats) => stats,
+                None => groups.entry(prefix.to_vec()).or_insert_with(|| PrefixStats {
+                    prefix: prefix.to_vec(),
+                    ..PrefixStats::default()
+                }),
+            };
+            stats.entries += 1;
+            stats.key_bytes += key.len() as u64;
+            stats.value_bytes += data.len() as u64;
+        }
+        Ok(groups.into_values().collect())
+    }
+
+    /// Walks every page of the tree to count its pages and entries.
+    pub fn stat(&self) -> Result<TreeStat, DBError> {
+        let mut stat = TreeStat::default();
+        let mut pending: Vec<(Pgno, usize)> = self.root.map(|root| (root, 1)).into_iter().collect();
+        while let Some((pgno, depth)) = pending.pop() {
+            if depth > MAX_TREE_DEPTH {
+                return Err(DBError::CorruptPage { pgno, reason: "tree is too deep" });
+            }
+            let page = self.get_page(pgno)?;
+            let capacity = page.get_page_size() - PAGE_HEADER_SIZE;
+            stat.capacity_bytes += capacity as u64;
+            stat.used_bytes += (capacity - (page.get_upper() - page.get_lower()) as usize) as u64;
+            stat.depth = stat.depth.max(depth);
+            if page.get_flags().contains(PageFlag::BRANCH) {
+                stat.branch_pages += 1;
+                let branch = BranchPage::from(page)?;
+                for idx in 0..branch.num_children() {
+                    pending.push((branch.child_at(idx)?, depth + 1));
+                }
+            } else {
+                stat.leaf_pages += 1;
+                for node in page.nodes() {
+                    let node = node?;
+                    stat.entries += 1;
+                    if node.is_compressed() {
+                        stat.compressed_values += 1;
+                        stat.compressed_bytes += node.get_data().len() as u64;
+                        stat.uncompressed_bytes += node.get_value_size()? as u64;
+                    }
+                }
+            }
+        }
+        Ok(stat)
+    }
+
+    /// Every page a lookup or scan of `range` reads: the leaves that can hold
+    /// its keys and the branch pages above them, parents before children.
+    pub fn pages_in_range<'k>(
+        &self,
+        range: impl RangeBounds<&'k [u8]>,
+    ) -> Result<Vec<Pgno>, DBError> {
+        let mut pages = Vec::new();
+        let mut pending: Vec<(Pgno, usize)> = self.root.map(|root| (root, 1)).into_iter().collect();
+        while let Some((pgno, depth)) = pending.pop() {
+            if depth > MAX_TREE_DEPTH {
+                return Err(DBError::CorruptPage { pgno, reason: "tree is too deep" });
+            }
+            let page = self.get_page(pgno)?;
+            pages.push(pgno);
+            if !page.get_flags().contains(PageFlag::BRANCH) {
+                continue;
+            }
+            let branch = BranchPage::from(page)?;
+            let separator = |idx| page.read_node(idx).map(|node| node.get_key());
+            // child `idx` holds the keys from its separator up to the next one
+            for idx in (0..branch.num_children()).rev() {
+                let past_end = idx > 0
+                    && self.order.compare_to_range(&separator(idx)?, &range).is_gt();
+                let before_start = idx + 1 < branch.num_children()
+                    && self.order.compare_to_range(&separator(idx + 1)?, &range).is_lt();
+                if !past_end && !before_start {
+                    pending.push((branch.child_at(idx)?, depth + 1));
+                }
+            }
+        }
+        Ok(pages)
+    }
+
+    /// The key ranges of the leaves numbered `first` or later, which are the
+    /// ones written since the commit whose pages end at `first`, with each
+    /// run of adjacent leaves merged into one range; the whole key space for
+    /// an empty tree. Branch pages numbered before `first` aren't read, as
+    /// nothing under them was written since.
+    pub fn written_ranges(&self, first: Pgno) -> Result<Vec<KeyRange>, DBError> {
+        let Some(root) = self.root else {
+            return Ok(vec![(Bound::Unbounded, Bound::Unbounded)]);
+        };
+        let mut ranges: Vec<KeyRange> = Vec::new();
+        let mut pending = vec![(root, 1, (Bound::Unbounded, Bound::Unbounded))];
+        while let Some((pgno, depth, (start, end))) = pending.pop() {
+            if pgno < first {
+                continue;
+            }
+            if depth > MAX_TREE_DEPTH {
+                return Err(DBError::CorruptPage { pgno, reason: "tree is too deep" });
+            }
+            let page = self.get_page(pgno)?;
+            if !page.get_flags().contains(PageFlag::BRANCH) {
+                let adjacent = match (ranges.last(), &start) {
+                    (Some((_, Bound::Excluded(last_end))), Bound::Included(start)) => {
+                        last_end == start
+                    }
+                    _ => false,
+                };
+                match ranges.last_mut() {
+                    Some(last) if adjacent => last.1 = end,
+                    _ => ranges.push((start, end)),
+                }
+                continue;
+            }
+            let branch = BranchPage::from(page)?;
+            let separator = |idx| page.read_node(idx).map(|node| node.get_key().into_owned());
+            // children are pushed last first, so they are taken in key order
+            for idx in (0..branch.num_children()).rev() {
+                let child_start = match idx {
+                    0 => start.clone(),
+                    _ => Bound::Included(separator(idx)?),
+                };
+                let child_end = match idx + 1 < branch.num_children() {
+                    true => Bound::Excluded(separator(idx + 1)?),
+                    false => end.clone(),
+                };
+                pending.push((branch.child_at(idx)?, depth + 1, (child_start, child_end)));
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// Number of levels, counting the leaves; 0 for an empty tree.
+    pub fn height(&self) -> Result<usize, DBError> {
+        Ok(self.edge_leaf(false)?.map_or(0, |(height, _)| height))
+    }
+
+    /// The smallest and largest keys stored, including deleted ones, or `None`
+    /// if the tree holds no nodes.
+    pub fn key_bounds(&self) -> Result<Option<KeyBounds>, DBError> {
+        let (Some((_, first)), Some((_, last))) = (self.edge_leaf(false)?, self.edge_leaf(true)?)
+        else {
+            return Ok(None);
+        };
+        if first.num_nodes() == 0 || last.num_nodes() == 0 {
+            return Ok(None);
+        }
+        let first_key = first.read_node(0)?.get_key().into_owned();
+        let last_key = last.read_node(last.num_nodes() - 1)?.get_key().into_owned();
+        Ok(Some((first_key, last_key)))
+    }
+
+    // follows the first (or last) child of every branch page down to a leaf,
+    // returning the leaf and the number of levels down to it
+    fn edge_leaf(&self, last: bool) -> Result<Option<(usize, DataPage<'a>)>, DBError> {
+        let Some(mut pgno) = self.root else {
+            return Ok(None);
+        };
+        for height in 1..=MAX_TREE_DEPTH {
+            let page = self.get_page(pgno)?;
+            if !page.get_flags().contains(PageFlag::BRANCH) {
+                return Ok(Some((height, page)));
+            }
+            let branch = BranchPage::from(page)?;
+            pgno = branch.child_at(if last { branch.num_children() - 1 } else { 0 })?;
+        }
+        Err(DBError::CorruptPage { pgno, reason: "tree is too deep" })
+    }
+
+    /// Up to `n - 1` keys, in order, that split the tree into `n` ranges of
+    /// roughly equal size. They are taken from the shallowest level with at
+    /// least `n` children, so only the top of the tree is read; a tree with
+    /// fewer than `n` entries has fewer split points.
+    pub fn split_points(&self, n: usize) -> Result<Vec<Vec<u8>>, DBError> {
+        let Some(root) = self.root else {
+            return Ok(Vec::new());
+        };
+        // each page with the smallest key that can be under it
+        let mut level = vec![(root, Vec::new())];
+        for _ in 0..=MAX_TREE_DEPTH {
+            // keys[i] is the smallest key under the level's i-th child (or,
+            // on the leaf level, the i-th entry)
+            let (mut keys, mut children) = (Vec::new(), Vec::new());
+            let mut is_leaf = false;
+            for (pgno, lower) in level {
+                let page = self.get_page(pgno)?;
+                is_leaf = !page.get_flags().contains(PageFlag::BRANCH);
+                if is_leaf {
+                    for node in page.nodes() {
+                        keys.push(node?.get_key().into_owned());
+                    }
+                    continue;
+                }
+                let branch = BranchPage::from(page)?;
+                // the first child's key isn't stored; it's the page's own bound
+                let mut first = Some(lower);
+                for idx in 0..branch.num_children() {
+                    let key = match first.take() {
+                        Some(lower) => lower,
+                        None => page.read_node(idx)?.get_key().into_owned(),
+                    };
+                    keys.push(key.clone());
+                    children.push((branch.child_at(idx)?, key));
+                }
+            }
+            if is_leaf || keys.len() >= n {
+                let mut splits: Vec<usize> = (1..n).map(|i| i * keys.len() / n).collect();
+                splits.dedup();
+                return Ok(splits
+                    .into_iter()
+                    .filter(|&idx| idx > 0)
+                    .map(|idx| std::mem::take(&mut keys[idx]))
+                    .collect());
+            }
+            level = children;
+        }
+        Err(DBError::CorruptPage { pgno: root, reason: "tree is too deep" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Env;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_page() {
+        let dir = tempdir().unwrap();
+        let key = |i: u32| format!("key-{i:06}").into_bytes();
+        let env = Env::bulk_load(dir.path().join("db"), (0..20_000).map(|i| (key(i), key(i))))
+            .unwrap();
+        let txn = env.begin_read().unwrap();
+        let tree = txn.tree();
+        let index = |entry: &Entry| {
+            std::str::from_utf8(&entry.0[4..]).unwrap().parse::<u32>().unwrap()
+        };
+
+        // offsets within the first leaf are exact
+        let page = tree.page(.., 3, 5).unwrap();
+        assert_eq!(page.iter().map(index).collect::<Vec<_>>(), [3, 4, 5, 6, 7]);
+        let (start, end) = (key(100), key(200));
+        let page = tree.page(&start[..]..&end[..], 10, 5).unwrap();
+        assert_eq!(index(&page[0]), 110);
+        let page = tree.page((Bound::Excluded(&start[..]), Bound::Unbounded), 0, 1).unwrap();
+        assert_eq!(index(&page[0]), 101);
+
+        // far ones land close, and the page stays inside the range
+        let page = tree.page(.., 12_000, 50).unwrap();
+        assert_eq!(page.len(), 50);
+        assert!(index(&page[0]).abs_diff(12_000) < 1000, "{}", index(&page[0]));
+        let page = tree.page(&start[..]..&end[..], 90, 50).unwrap();
+        assert!(page.iter().all(|entry| (100..200).contains(&index(entry))));
+        assert!(tree.page(&start[..]..&end[..], 500, 50).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_many() {
+        let dir = tempdir().unwrap();
+        let key = |i: u32| format!("key-{i:06}").into_bytes();
+        let env = Env::bulk_load(dir.path().join("db"), (0..20_000).map(|i| (key(i), key(i))))
+            .unwrap();
+        let txn = env.begin_read().unwrap();
+        let tree = txn.tree();
+        // scattered, with repeats, and with keys before, between and after
+        // the stored ones
+        let wanted: Vec<Vec<u8>> = (0..2_000u32)
+            .map(|i| key(i * 7919 % 20_500))
+            .chain([b"a".to_vec(), b"key-".to_vec(), key(5), b"z".to_vec()])
+            .collect();
+        let wanted: Vec<&[u8]> = wanted.iter().map(Vec::as_slice).collect();
+        let reads = env.metrics().page_reads;
+        let values = tree.get_many(&wanted).unwrap();
+        let batch_reads = env.metrics().page_reads - reads;
+        for (key, value) in wanted.iter().zip(&values) {
+            assert_eq!(*value, tree.get(key).ok(), "{key:?}");
+        }
+        assert_eq!(values.iter().flatten().count(), 1_952);
+        // one-by-one lookups read a leaf each
+        assert!(batch_reads < env.metrics().page_reads - reads - batch_reads);
+
+        let empty = Env::open(dir.path().join("empty")).unwrap();
+        assert_eq!(empty.begin_read().unwrap().get_many(&wanted[..2]).unwrap(), [None, None]);
+    }
+
+    #[test]
+    fn test_iteration_order_is_stable() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        // written in a scrambled order, over several commits and page splits
+        for round in 0..4u32 {
+            let mut txn = env.begin_write();
+            for i in round * 1000..(round + 1) * 1000 {
+                let n = i * 7919 % 4000;
+                txn.put(&n.to_be_bytes()[1..], &round.to_le_bytes()).unwrap();
+            }
+            txn.commit().unwrap();
+        }
+        let mut txn = env.begin_write();
+        txn.delete(&17u32.to_be_bytes()[1..]).unwrap();
+        txn.commit().unwrap();
+
+        let scan = |env: &Env| -> Vec<Vec<u8>> {
+            let txn = env.begin_read().unwrap();
+            let keys = txn.tree().cursor().unwrap().map(|entry| entry.unwrap().0.into_owned());
+            keys.collect()
+        };
+        let keys = scan(&env);
+        let order = env.begin_read().unwrap().tree().ordering();
+        assert_eq!(order, IterationOrder::of(KeyOrder::Bytes));
+        assert_eq!(keys.len(), 3999);
+        assert!(keys.windows(2).all(|pair| KeyOrder::Bytes.compare(&pair[0], &pair[1]).is_lt()));
+        drop(env);
+        assert_eq!(scan(&Env::open(&path).unwrap()), keys);
+    }
+
+    #[test]
+    fn test_prefix_stats() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        for (tenant, count) in [("acme", 700u32), ("globex", 20), ("initech", 300)] {
+            for i in 0..count {
+                txn.put(format!("{tenant}:{i:05}").as_bytes(), &[0u8; 10]).unwrap();
+            }
+        }
+        txn.put(b"a", b"short").unwrap();
+
+        let stats = txn.tree().prefix_stats(4).unwrap();
+        let summary: Vec<_> = stats.iter().map(|s| (&s.prefix[..], s.entries)).collect();
+        assert_eq!(summary, [(&b"a"[..], 1), (b"acme", 700), (b"glob", 20), (b"init", 300)]);
+        let acme = &stats[1];
+        assert_eq!((acme.key_bytes, acme.value_bytes), (700 * 10, 700 * 10));
+        assert_eq!(txn.tree().prefix_stats(0).unwrap()[0].entries, 1021);
+    }
+}
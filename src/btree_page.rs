@@ -1,39 +1,272 @@
 use crate::constants::*;
-use crate::data_page::DataPage;
+use crate::data_page::{DataNode, DataPage, DirtyPage};
 use crate::page::Page;
 
+// Branch pages are data pages flagged BRANCH whose nodes map the smallest key
+// of each child subtree to the child's page number (u64) and the number of
+// live entries under it (u64). A key routes to the last child whose key is <=
+// it. The first node's key is left empty and never compared: everything below
+// the second key goes to the first child, so no separator needs updating when
+// smaller keys are inserted.
+//
+// A write transaction leaves the counts of the children it dirties stale until
+// it commits, which brings them up to date from the leaves up; the counts of
+// committed children are always right.
+pub const CHILD_SIZE: usize = 16;
+const PGNO_SIZE: usize = 8;
+
+/// A branch node's data: the child's page number and its entry count.
+pub fn child_data(pgno: Pgno, entries: u64) -> [u8; CHILD_SIZE] {
+    let mut data = [0u8; CHILD_SIZE];
+    data[..PGNO_SIZE].copy_from_slice(&pgno.to_le_bytes());
+    data[PGNO_SIZE..].copy_from_slice(&entries.to_le_bytes());
+    data
+}
+
+/// Fill below which a page that lost nodes is merged with or borrows from a
+/// sibling; see `EnvOptions::min_fill`.
+pub const DEFAULT_MIN_FILL: f64 = 0.25;
+
+/// What `rebalance` made of two sibling pages.
+pub enum Rebalanced {
+    /// Everything fit in one page, numbered like the left one; the right
+    /// page's entry in the parent has to go.
+    Merged(DirtyPage),
+    /// Nodes were moved across to even out the pages' sizes, and the right
+    /// page now starts at the returned separator.
+    Borrowed(DirtyPage, DirtyPage, Vec<u8>),
+}
+
+/// Combines the neighbouring pages `left` and `right`, where `separator` is
+/// the key their parent routes to `right` under, into one page if they fit
+/// and otherwise into two of about the same size. Fails with `PageFull` in the
+/// rare case that they don't fit either way, because splitting them
+/// differently shortens the prefix their keys share.
+pub fn rebalance(
+    left: &DataPage,
+    right: &DataPage,
+    separator: &[u8],
+) -> Result<Rebalanced, DBError> {
+    let (pgno, page_size) = (left.get_pgno(), left.get_page_size());
+    let (mut flags, order) = (left.get_flags(), left.get_order());
+    // a fixed-key leaf can't take the keys of one written without fixed
+    // keys, while the other layout takes any key
+    if !right.has_fixed_keys() {
+        flags.remove(PageFlag::FIXED_KEY);
+    }
+    let is_branch = flags.contains(PageFlag::BRANCH);
+    let mut nodes = left.read_nodes()?;
+    let first_right = nodes.len();
+    nodes.extend(right.read_nodes()?);
+    // the right page's first key is implied by the parent, and has to be
+    // spelled out next to the left page's nodes
+    if is_branch {
+        nodes[first_right] = DataNode::from(separator, nodes[first_right].get_data());
+    }
+    match DirtyPage::from_nodes(pgno, page_size, flags, order, &nodes) {
+        Err(DBError::PageFull) => {}
+        merged => return merged.map(Rebalanced::Merged),
+    }
+
+    let mid = DataPage::split_point(&nodes);
+    let separator = nodes[mid].get_key().into_owned();
+    if is_branch {
+        nodes[mid] = DataNode::from(&[], nodes[mid].get_data());
+    }
+    let (left_nodes, right_nodes) = nodes.split_at(mid);
+    Ok(Rebalanced::Borrowed(
+        DirtyPage::from_nodes(pgno, page_size, flags, order, left_nodes)?,
+        DirtyPage::from_nodes(right.get_pgno(), page_size, flags, order, right_nodes)?,
+        separator,
+    ))
+}
+
+/// A data page read as a branch: the same slotted page as a leaf, with child
+/// page numbers for values. Both kinds share `DataPage` for searching,
+/// splitting and writing, so only the payload differs between them.
 pub struct BranchPage<'a> {
     inner: DataPage<'a>
 }
 
+/// A data page read as a leaf, whose values are the entries' own.
 pub struct LeafPage<'a> {
     inner: DataPage<'a>
 }
 
 impl<'a> BranchPage<'a> {
+    pub fn from(page: DataPage<'a>) -> Result<Self, DBError> {
+        if !page.get_flags().contains(PageFlag::BRANCH) {
+            return Err(DBError::CorruptPage {
+                pgno: page.get_pgno(),
+                reason: "not a branch page",
+            });
+        }
+        if page.num_nodes() == 0 {
+            return Err(DBError::CorruptPage {
+                pgno: page.get_pgno(),
+                reason: "branch page has no children",
+            });
+        }
+        Ok(BranchPage { inner: page })
+    }
+
+    /// An empty branch page of `page_size` bytes.
+    pub fn new_page(pgno: Pgno, page_size: usize) -> Page {
+        DataPage::new_page_with_flags(pgno, page_size, PageFlag::ALIVE | PageFlag::BRANCH)
+    }
+
+    pub fn as_data_page(&self) -> &DataPage<'a> {
+        &self.inner
+    }
+
+    pub fn num_children(&self) -> usize {
+        self.inner.num_nodes()
+    }
+
+    /// Index of the child whose subtree `key` belongs to.
+    pub fn child_index(&self, key: &[u8]) -> Result<usize, DBError> {
+        Ok(match self.inner.search(key)? {
+            Ok(idx) => idx,
+            Err(idx) => idx.saturating_sub(1),
+        })
+    }
+
+    pub fn child_at(&self, idx: usize) -> Result<Pgno, DBError> {
+        Ok(self.read_child(idx)?.0)
+    }
+
+    /// Number of entries under the child at `idx`, as of the commit it was
+    /// last written in.
+    pub fn entries_at(&self, idx: usize) -> Result<u64, DBError> {
+        Ok(self.read_child(idx)?.1)
+    }
+
+    fn read_child(&self, idx: usize) -> Result<(Pgno, u64), DBError> {
+        let node = self.inner.read_node(idx)?;
+        let child = <[u8; CHILD_SIZE]>::try_from(node.get_data()).map_err(|_| {
+            DBError::CorruptPage {
+                pgno: self.inner.get_pgno(),
+                reason: "branch node is not a child pointer",
+            }
+        })?;
+        let (pgno, entries) = child.split_at(PGNO_SIZE);
+        Ok((
+            Pgno::from_le_bytes(pgno.try_into().unwrap()),
+            u64::from_le_bytes(entries.try_into().unwrap()),
+        ))
+    }
+
     pub fn split(&self, pgno_left: Pgno, pgno_right: Pgno) -> Result<(Page, Page), DBError> {
-        todo!();
+        self.inner.split(pgno_left, pgno_right)
     }
 
     pub fn get(&self, key: &[u8]) -> Result<Pgno, DBError> {
-        todo!();
+        self.child_at(self.child_index(key)?)
     }
 
-    pub fn put(&self, key: &[u8], pgno: Pgno) -> Result<Page, DBError> {
-        todo!();
+    pub fn put(
+        &self,
+        new_pgno: Pgno,
+        key: &[u8],
+        pgno: Pgno,
+        entries: u64,
+    ) -> Result<Page, DBError> {
+        self.inner.put(new_pgno, key, &child_data(pgno, entries))
     }
 }
 
 impl<'a> LeafPage<'a> {
+    pub fn from(page: DataPage<'a>) -> Result<Self, DBError> {
+        if page.get_flags().intersects(PageFlag::BRANCH | PageFlag::META) {
+            return Err(DBError::CorruptPage {
+                pgno: page.get_pgno(),
+                reason: "not a leaf page",
+            });
+        }
+        Ok(LeafPage { inner: page })
+    }
+
+    pub fn as_data_page(&self) -> &DataPage<'a> {
+        &self.inner
+    }
+
     pub fn split(&self, pgno_left: Pgno, pgno_right: Pgno) -> Result<(Page, Page), DBError> {
-        todo!();
+        self.inner.split(pgno_left, pgno_right)
     }
 
-    pub fn get(&self, key: &[u8]) -> Result<Pgno, DBError> {
-        todo!();
+    pub fn get(&self, key: &[u8]) -> Result<&'a [u8], DBError> {
+        self.inner.get(key)
     }
 
     pub fn put(&self, new_pgno: Pgno, key: &[u8], data: &[u8]) -> Result<Page, DBError> {
         self.inner.put(new_pgno, key, data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branch_routing() {
+        let page = BranchPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 0);
+        for (key, child) in [(&b""[..], 10u64), (b"f", 11), (b"m", 12)] {
+            dirty.put(key, &child_data(child, child * 2)).unwrap();
+        }
+        let branch = BranchPage::from(dirty.as_data_page().unwrap()).unwrap();
+
+        assert_eq!(branch.num_children(), 3);
+        assert_eq!((branch.child_at(1).unwrap(), branch.entries_at(1).unwrap()), (11, 22));
+        // keys before the first separator route to the first child
+        for (key, child) in [(&b"a"[..], 10), (b"b", 10), (b"e", 10), (b"f", 11), (b"z", 12)] {
+            assert_eq!(branch.get(key).unwrap(), child);
+        }
+        let leaf = DataPage::new_page(1, DEFAULT_PAGE_SIZE);
+        assert!(BranchPage::from(DataPage::from(&leaf).unwrap()).is_err());
+        assert!(LeafPage::from(DataPage::from(&page).unwrap()).is_err());
+    }
+
+    fn branch(pgno: Pgno, separators: &[&str]) -> DirtyPage {
+        let page = BranchPage::new_page(pgno, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), pgno);
+        dirty.put(b"", &child_data(pgno * 100, 1)).unwrap();
+        for (i, separator) in separators.iter().enumerate() {
+            dirty.put(separator.as_bytes(), &child_data(pgno * 100 + i as u64 + 1, 1)).unwrap();
+        }
+        dirty
+    }
+
+    #[test]
+    fn test_rebalance_branches() {
+        // the right page's implied first key comes back from the separator
+        let (left, right) = (branch(1, &["b"]), branch(2, &["n"]));
+        let (left, right) = (left.as_data_page().unwrap(), right.as_data_page().unwrap());
+        let Ok(Rebalanced::Merged(merged)) = rebalance(&left, &right, b"k") else {
+            panic!("small pages merge");
+        };
+        let merged = BranchPage::from(merged.as_data_page().unwrap()).unwrap();
+        assert_eq!(merged.num_children(), 4);
+        for (key, child) in [(&b"a"[..], 100), (b"c", 101), (b"k", 200), (b"z", 201)] {
+            assert_eq!(merged.get(key).unwrap(), child);
+        }
+
+        // too much for one page: the halves even out around a new separator
+        let keys: Vec<String> = (0..200).map(|i| format!("key-{i:04}")).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let (left, right) = (branch(1, &keys[..130]), branch(2, &keys[131..]));
+        let (left, right) = (left.as_data_page().unwrap(), right.as_data_page().unwrap());
+        let Ok(Rebalanced::Borrowed(new_left, new_right, separator)) =
+            rebalance(&left, &right, keys[130].as_bytes())
+        else {
+            panic!("full pages can't merge");
+        };
+        let (new_left, new_right) =
+            (new_left.as_data_page().unwrap(), new_right.as_data_page().unwrap());
+        assert_eq!(new_left.num_nodes() + new_right.num_nodes(), 201);
+        assert!(new_left.num_nodes().abs_diff(new_right.num_nodes()) <= 2);
+        assert_eq!(new_right.read_node(0).unwrap().get_key(), &b""[..]);
+        let new_right = BranchPage::from(new_right).unwrap();
+        assert_eq!(new_right.get(&separator).unwrap(), 100 + new_left.num_nodes() as u64);
+    }
+}
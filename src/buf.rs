@@ -1,5 +1,3 @@
-use crate::constants::USIZE_N;
-
 use std::convert::TryInto;
 
 pub trait ByteBuf {
@@ -19,33 +17,121 @@ pub trait ByteBuf {
         let data = self.read_n_bytes(offset, 8)?;
         Some(u64::from_le_bytes(data.try_into().ok()?))
     }
-
-    fn read_usize_le(&self, offset: usize) -> Option<usize> {
-        let bytes = self.read_n_bytes(offset, USIZE_N)?;
-        Some(match USIZE_N {
-            4 => {
-                let arr: [u8; 4] = bytes.try_into().ok()?;
-                u32::from_le_bytes(arr) as usize
-            }
-            8 => {
-                let arr: [u8; 8] = bytes.try_into().ok()?;
-                u64::from_le_bytes(arr) as usize
-            }
-            _ => unreachable!("unsupported usize size"),
-        })
-    }
 }
 
 impl ByteBuf for [u8] {
     #[inline]
     fn read_n_bytes(&self, offset: usize, n: usize) -> Option<&[u8]> {
-        self.get(offset..offset + n)
+        self.get(offset..offset.checked_add(n)?)
+    }
+}
+
+pub fn write_varint_u64(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
     }
+    buf.push(value as u8);
+}
+
+/// Number of bytes `write_varint_u64` uses for `value`.
+pub fn varint_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
 }
 
-pub fn as_u16_slice(buf: &[u8]) -> &[u16] {
-    assert!(buf.len() % 2 == 0, "slice length must be multiple of 2");
+/// Longest encoding `write_varint_u64` produces, that of `u64::MAX`.
+pub const MAX_VARINT_LEN: usize = 10;
 
-    // buf must be aligned, and length should be even so no partial u16
-    unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u16, buf.len() / 2) }
+/// Why `read_varint_u64` or `read_varint_len` rejected its input.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VarintError {
+    /// The input ends before the varint does.
+    Truncated,
+    /// The value doesn't fit in a u64: more than `MAX_VARINT_LEN` bytes, or
+    /// a last byte with bits past the 64th.
+    Overflow,
+    /// A zero last byte, which `write_varint_u64` never writes; every value
+    /// has exactly one accepted encoding.
+    NonCanonical,
+    /// A length over the limit `read_varint_len` was given.
+    TooLong,
+}
+
+impl VarintError {
+    pub const fn reason(self) -> &'static str {
+        match self {
+            VarintError::Truncated => "truncated varint",
+            VarintError::Overflow => "varint overflows u64",
+            VarintError::NonCanonical => "varint is not in its shortest form",
+            VarintError::TooLong => "length runs past the end of its buffer",
+        }
+    }
+}
+
+/// Decodes a LEB128 varint from the start of `buf`, returning the value and
+/// the number of bytes read. Only the encodings `write_varint_u64` writes
+/// are accepted, so nothing decodes to a value it couldn't have come from.
+pub fn read_varint_u64(buf: &[u8]) -> Result<(u64, usize), VarintError> {
+    let mut value = 0u64;
+    for (i, &byte) in buf.iter().enumerate().take(MAX_VARINT_LEN) {
+        // the last byte can only hold bit 63
+        if i == MAX_VARINT_LEN - 1 && byte > 1 {
+            return Err(VarintError::Overflow);
+        }
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            if byte == 0 && i > 0 {
+                return Err(VarintError::NonCanonical);
+            }
+            return Ok((value, i + 1));
+        }
+    }
+    Err(VarintError::Truncated)
+}
+
+/// Decodes a varint holding a length of at most `max` bytes, typically what
+/// is left of the buffer it describes, so that offsets computed from it
+/// can't overflow.
+pub fn read_varint_len(buf: &[u8], max: usize) -> Result<(usize, usize), VarintError> {
+    let (len, read) = read_varint_u64(buf)?;
+    match usize::try_from(len) {
+        Ok(len) if len <= max => Ok((len, read)),
+        _ => Err(VarintError::TooLong),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX - 1, u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint_u64(&mut buf, value);
+            assert_eq!(buf.len(), varint_len(value));
+            assert_eq!(read_varint_u64(&buf), Ok((value, buf.len())));
+            // trailing bytes are left alone, and any cut is caught
+            buf.push(0xff);
+            assert_eq!(read_varint_u64(&buf), Ok((value, buf.len() - 1)));
+            assert_eq!(read_varint_u64(&buf[..buf.len() - 2]), Err(VarintError::Truncated));
+        }
+    }
+
+    #[test]
+    fn test_rejects_malformed_varints() {
+        let mut max = Vec::new();
+        write_varint_u64(&mut max, u64::MAX);
+        assert_eq!(max.len(), MAX_VARINT_LEN);
+        *max.last_mut().unwrap() = 2;
+        assert_eq!(read_varint_u64(&max), Err(VarintError::Overflow));
+        assert_eq!(read_varint_u64(&[0x80; 11]), Err(VarintError::Overflow));
+        assert_eq!(read_varint_u64(&[0x80, 0x00]), Err(VarintError::NonCanonical));
+        assert_eq!(read_varint_u64(&[0xff, 0x80, 0x00]), Err(VarintError::NonCanonical));
+        assert_eq!(read_varint_u64(&[]), Err(VarintError::Truncated));
+
+        assert_eq!(read_varint_len(&[0x80, 0x01], 128), Ok((128, 2)));
+        assert_eq!(read_varint_len(&[0x81, 0x01], 128), Err(VarintError::TooLong));
+        assert_eq!(read_varint_len(&max[..9], usize::MAX), Err(VarintError::Truncated));
+    }
 }
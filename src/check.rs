@@ -0,0 +1,558 @@
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+use crate::btree::MAX_TREE_DEPTH;
+use crate::btree_page::{BranchPage, CHILD_SIZE};
+use crate::constants::*;
+use crate::data_page::DataPage;
+use crate::key_order::KeyOrder;
+use crate::meta::{Meta, NUM_META_PAGES};
+use crate::page::PageRef;
+use crate::progress::{self, no_progress, ProgressFn, Stage};
+
+/// A single inconsistency found while checking.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Problem {
+    pub pgno: Pgno,
+    pub description: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "page {}: {}", self.pgno, self.description)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub pages_checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn push(&mut self, pgno: Pgno, description: impl Into<String>) {
+        self.problems.push(Problem {
+            pgno,
+            description: description.into(),
+        });
+    }
+
+    fn push_error(&mut self, pgno: Pgno, err: DBError) {
+        self.push(pgno, err.to_string());
+    }
+
+    pub fn merge(&mut self, other: CheckReport) {
+        self.pages_checked += other.pages_checked;
+        self.problems.extend(other.problems);
+    }
+}
+
+/// Checks every page in a file of `page_size` pages, collecting all problems
+/// rather than stopping at the first one. Keys are checked against the order
+/// the file records, if it records one.
+pub fn check_file(mmap: &Mmap, page_size: usize) -> CheckReport {
+    let mut report = CheckReport::default();
+    // a file whose meta pages are both damaged is checked in byte order
+    let order = Meta::read(mmap).map_or(Some(KeyOrder::Bytes), |meta| meta.get_key_order());
+    for pgno in 0..mmap.len() / page_size {
+        report.merge(check_page_at(mmap, page_size, pgno as Pgno, order));
+    }
+    if !mmap.len().is_multiple_of(page_size) {
+        report.push(
+            (mmap.len() / page_size) as Pgno,
+            "file ends with a partial page",
+        );
+    }
+    report
+}
+
+/// Checks the page at `pgno`; keys of data pages must be in `order`, unless
+/// it is `None`, for a file whose order isn't recorded.
+pub fn check_page_at(
+    mmap: &Mmap,
+    page_size: usize,
+    pgno: Pgno,
+    order: Option<KeyOrder>,
+) -> CheckReport {
+    let mut report = CheckReport {
+        pages_checked: 1,
+        ..CheckReport::default()
+    };
+    let page = match PageRef::from_mmap(mmap, page_size, pgno as usize) {
+        Ok(page) => page,
+        Err(err) => {
+            report.push_error(pgno, err);
+            return report;
+        }
+    };
+    if let Err(err) = page.verify_checksum() {
+        report.push_error(pgno, err);
+    }
+
+    if page.get_flag().contains(PageFlag::META) {
+        if let Err(err) = Meta::from(page) {
+            report.push_error(pgno, err);
+        }
+    } else {
+        check_data_page(page, order, &mut report);
+    }
+    report
+}
+
+/// Validates header bounds, that every node parses and lies inside the used
+/// region without overlapping another node, and that keys are strictly sorted
+/// in `order`, if it is given. Nodes of branch pages must also hold a child
+/// page number.
+pub fn check_data_page(page: PageRef, order: Option<KeyOrder>, report: &mut CheckReport) {
+    let pgno = page.get_pgno();
+    let data_page = match DataPage::from(page) {
+        Ok(data_page) => data_page,
+        Err(err) => {
+            report.push_error(pgno, err);
+            return;
+        }
+    };
+
+    let is_branch = data_page.get_flags().contains(PageFlag::BRANCH);
+    let mut extents = Vec::with_capacity(data_page.num_nodes());
+    let mut prev_key: Option<Cow<[u8]>> = None;
+    for (idx, offset) in data_page.get_offsets().iter().enumerate() {
+        let node = match data_page.read_node(idx) {
+            Ok(node) => node,
+            Err(err) => {
+                report.push(pgno, format!("node {}: {}", idx, err));
+                continue;
+            }
+        };
+        if is_branch && node.get_data().len() != CHILD_SIZE {
+            report.push(pgno, format!("node {} is not a child pointer", idx));
+        }
+        let key = node.get_key();
+        if let (Some(prev), Some(order)) = (&prev_key, order) {
+            if idx != data_page.first_searchable() && order.compare(prev, &key).is_ge() {
+                report.push(pgno, format!("node {} is not in key order", idx));
+            }
+        }
+        prev_key = Some(key);
+        extents.push((offset as usize, offset as usize + node.get_size(), idx));
+    }
+
+    extents.sort_unstable();
+    for pair in extents.windows(2) {
+        let ((_, end, a), (start, _, b)) = (pair[0], pair[1]);
+        if end > start {
+            report.push(pgno, format!("nodes {} and {} overlap", a, b));
+        }
+    }
+}
+
+/// Checks `count` pages picked at random from `pgnos` (all of them if the
+/// range is smaller), as a cheap stand-in for `check_file` on large files;
+/// keys are checked as `check_page_at` does.
+pub fn check_sample(
+    mmap: &Mmap,
+    page_size: usize,
+    pgnos: Range<Pgno>,
+    count: usize,
+    order: Option<KeyOrder>,
+) -> CheckReport {
+    let mut report = CheckReport::default();
+    let len = pgnos.end.saturating_sub(pgnos.start) as usize;
+    let mut sample = rand::seq::index::sample(&mut rand::rng(), len, count.min(len)).into_vec();
+    // visit in file order so the reads stay mostly sequential
+    sample.sort_unstable();
+    for idx in sample {
+        report.merge(check_page_at(mmap, page_size, pgnos.start + idx as Pgno, order));
+    }
+    report
+}
+
+/// Checks that keys are sorted in `order` across a sequence of data pages,
+/// such as the leaves of a tree in order.
+pub fn check_key_order(
+    mmap: &Mmap,
+    page_size: usize,
+    pgnos: &[Pgno],
+    order: KeyOrder,
+) -> CheckReport {
+    let mut report = CheckReport::default();
+    let mut prev_last: Option<(Pgno, Vec<u8>)> = None;
+    for &pgno in pgnos {
+        report.pages_checked += 1;
+        let page = PageRef::from_mmap(mmap, page_size, pgno as usize);
+        let data_page = match page.and_then(DataPage::from) {
+            Ok(data_page) => data_page,
+            Err(err) => {
+                report.push_error(pgno, err);
+                continue;
+            }
+        };
+        if data_page.num_nodes() == 0 {
+            continue;
+        }
+        let first = data_page.read_node(0);
+        let last = data_page.read_node(data_page.num_nodes() - 1);
+        let (first, last) = match (first, last) {
+            (Ok(first), Ok(last)) => (first, last),
+            (Err(err), _) | (_, Err(err)) => {
+                report.push_error(pgno, err);
+                continue;
+            }
+        };
+        if let Some((prev_pgno, prev_key)) = &prev_last {
+            if order.compare(prev_key, &first.get_key()).is_ge() {
+                report.push(
+                    pgno,
+                    format!("first key is not greater than the last key of page {}", prev_pgno),
+                );
+            }
+        }
+        prev_last = Some((pgno, last.get_key().to_vec()));
+    }
+    report
+}
+
+/// Checks that both meta pages are valid and alternate as commits do: each
+/// holds a commit written to its own slot, one apart. Both holding the same
+/// commit, as a new file or a copy does, is also fine.
+pub fn check_metas(mmap: &Mmap, page_size: usize) -> CheckReport {
+    let mut report = CheckReport::default();
+    let mut metas = Vec::new();
+    for pgno in 0..NUM_META_PAGES {
+        report.pages_checked += 1;
+        let page = PageRef::from_mmap_verified(mmap, page_size, pgno as usize);
+        match page.and_then(Meta::from) {
+            Ok(meta) => metas.push((pgno, meta)),
+            Err(err) => report.push_error(pgno, err),
+        }
+    }
+    if let [(_, a), (_, b)] = metas[..] {
+        if a.get_txnid() != b.get_txnid() {
+            if a.get_txnid().abs_diff(b.get_txnid()) != 1 {
+                report.push(0, "meta pages are not consecutive commits");
+            }
+            for (pgno, meta) in &metas {
+                if meta.get_pgno() != *pgno {
+                    let txnid = meta.get_txnid();
+                    report.push(*pgno, format!("commit {} is in the wrong slot", txnid));
+                }
+            }
+        }
+    }
+    for (pgno, meta) in &metas {
+        if meta.get_root().is_some_and(|root| root >= meta.get_next_pgno()) {
+            report.push(*pgno, "root is past the end of the commit");
+        }
+    }
+    report
+}
+
+/// Checks every page reachable from the root of the commit `meta` describes
+/// that isn't in `verified` yet, adding those that pass. Committed pages
+/// never change (until `Env::compact` moves them), so a watcher can check
+/// each new commit without checking the pages it shares with earlier ones
+/// again. Keys are checked against the order `meta` records, if any.
+pub fn check_commit(mmap: &Mmap, meta: &Meta, verified: &mut HashSet<Pgno>) -> CheckReport {
+    check_commit_with(mmap, meta, meta.get_key_order(), verified, &mut no_progress)
+        .expect("never cancelled")
+}
+
+/// Like `check_commit`, checking keys against `order` (see `check_page_at`)
+/// and reporting each page checked to `progress`; fails only with
+/// `Cancelled`, keeping the pages that passed by then in `verified`.
+pub fn check_commit_with(
+    mmap: &Mmap,
+    meta: &Meta,
+    order: Option<KeyOrder>,
+    verified: &mut HashSet<Pgno>,
+    progress: &mut ProgressFn,
+) -> Result<CheckReport, DBError> {
+    let total = meta.get_next_pgno() - NUM_META_PAGES;
+    let mut report = CheckReport::default();
+    let mut pending: Vec<(Pgno, usize)> = Vec::from_iter(meta.get_root().map(|root| (root, 0)));
+    while let Some((pgno, depth)) = pending.pop() {
+        if verified.contains(&pgno) {
+            continue;
+        }
+        if pgno >= meta.get_next_pgno() || depth == MAX_TREE_DEPTH {
+            report.push(pgno, "page is past the end of the commit or too deep");
+            continue;
+        }
+        let checked = check_page_at(mmap, meta.get_page_size(), pgno, order);
+        let ok = checked.is_ok();
+        report.merge(checked);
+        progress::report(progress, Stage::Checking, report.pages_checked as u64, total)?;
+        if !ok {
+            continue;
+        }
+        verified.insert(pgno);
+        let page = PageRef::from_mmap(mmap, meta.get_page_size(), pgno as usize);
+        let branch = page.and_then(DataPage::from).and_then(|page| {
+            match page.get_flags().contains(PageFlag::BRANCH) {
+                true => BranchPage::from(page).map(Some),
+                false => Ok(None),
+            }
+        });
+        match branch {
+            Ok(Some(branch)) => {
+                for idx in 0..branch.num_children() {
+                    match branch.child_at(idx) {
+                        Ok(child) => pending.push((child, depth + 1)),
+                        Err(err) => report.push_error(pgno, err),
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(err) => report.push_error(pgno, err),
+        }
+    }
+    Ok(report)
+}
+
+/// Reads the live entries of the commit `meta` describes, in key order, to
+/// rebuild a damaged tree from. The subtree under any page that fails
+/// `check_page_at` is skipped, and so are keys out of order with the entries
+/// before them; each shows up in the report, so a clean report means nothing
+/// was left out.
+pub fn salvage_commit(
+    mmap: &Mmap,
+    meta: &Meta,
+    order: KeyOrder,
+    progress: &mut ProgressFn,
+) -> Result<(Vec<KeyValue>, CheckReport), DBError> {
+    let total = meta.get_next_pgno() - NUM_META_PAGES;
+    let mut report = CheckReport::default();
+    let mut entries: Vec<KeyValue> = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending: Vec<(Pgno, usize)> = Vec::from_iter(meta.get_root().map(|root| (root, 0)));
+    while let Some((pgno, depth)) = pending.pop() {
+        if pgno >= meta.get_next_pgno() || depth == MAX_TREE_DEPTH || !visited.insert(pgno) {
+            report.push(pgno, "page is past the end of the commit, too deep or seen twice");
+            continue;
+        }
+        let checked = check_page_at(mmap, meta.get_page_size(), pgno, Some(order));
+        let ok = checked.is_ok();
+        report.merge(checked);
+        progress::report(progress, Stage::Checking, report.pages_checked as u64, total)?;
+        if !ok {
+            continue;
+        }
+        let page = PageRef::from_mmap(mmap, meta.get_page_size(), pgno as usize);
+        let page = match page.and_then(DataPage::from) {
+            Ok(page) if page.get_flags().contains(PageFlag::META) => {
+                report.push(pgno, "tree refers to a page that isn't a data page");
+                continue;
+            }
+            Ok(page) => page.with_order(order),
+            Err(err) => {
+                report.push_error(pgno, err);
+                continue;
+            }
+        };
+
+        if page.get_flags().contains(PageFlag::BRANCH) {
+            let children: Result<Vec<Pgno>, _> = BranchPage::from(page).and_then(|branch| {
+                (0..branch.num_children()).map(|idx| branch.child_at(idx)).collect()
+            });
+            match children {
+                // pushed last child first, so they come off the stack in order
+                Ok(children) => {
+                    pending.extend(children.into_iter().rev().map(|child| (child, depth + 1)))
+                }
+                Err(err) => report.push_error(pgno, err),
+            }
+            continue;
+        }
+        let mut dropped = 0;
+        for node in page.nodes() {
+            let node = match node {
+                Ok(node) => node,
+                Err(err) => {
+                    report.push_error(pgno, err);
+                    break;
+                }
+            };
+            let key = node.get_key();
+            if entries.last().is_some_and(|(last, _)| order.compare(last, &key).is_ge()) {
+                dropped += 1;
+                continue;
+            }
+            match node.read_value() {
+                Ok(value) => entries.push((key.into_owned(), value)),
+                Err(err) => report.push_error(pgno, err),
+            }
+        }
+        if dropped > 0 {
+            report.push(pgno, format!("{} keys out of order with the tree dropped", dropped));
+        }
+    }
+    Ok((entries, report))
+}
+
+/// Reports pages that are both reachable and on the freelist, and freelist
+/// entries that appear more than once.
+pub fn check_freelist(live: &[Pgno], free: &[Pgno]) -> CheckReport {
+    let mut report = CheckReport::default();
+    let live: HashSet<Pgno> = live.iter().copied().collect();
+    let mut seen = HashSet::with_capacity(free.len());
+    for &pgno in free {
+        if live.contains(&pgno) {
+            report.push(pgno, "free page is still reachable");
+        }
+        if !seen.insert(pgno) {
+            report.push(pgno, "page appears on the freelist more than once");
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_page::DirtyPage;
+    use crate::page::Page;
+    use memmap2::MmapMut;
+
+    const PAGE_SIZE: usize = DEFAULT_PAGE_SIZE;
+
+    fn data_page(pgno: Pgno, keys: &[&[u8]]) -> Page {
+        let empty = DataPage::new_page(pgno, PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&empty).unwrap(), pgno);
+        for key in keys {
+            dirty.put(key, b"value").unwrap();
+        }
+        dirty.into_page()
+    }
+
+    fn map_pages(pages: &[Page]) -> MmapMut {
+        let mut mmap = MmapMut::map_anon(pages.len() * PAGE_SIZE).unwrap();
+        for (i, page) in pages.iter().enumerate() {
+            mmap[i * PAGE_SIZE..(i + 1) * PAGE_SIZE].copy_from_slice(page.as_bytes());
+        }
+        mmap
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let pages = [
+            Meta::new(PAGE_SIZE).unwrap().write_page(),
+            data_page(1, &[b"a", b"b"]),
+            data_page(2, &[b"c", b"d"]),
+        ];
+        let mmap = map_pages(&pages).make_read_only().unwrap();
+
+        let report = check_file(&mmap, PAGE_SIZE);
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.pages_checked, 3);
+        assert!(check_key_order(&mmap, PAGE_SIZE, &[1, 2], KeyOrder::Bytes).is_ok());
+        assert!(!check_key_order(&mmap, PAGE_SIZE, &[2, 1], KeyOrder::Bytes).is_ok());
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        let pages = [data_page(0, &[b"a", b"b"]), data_page(1, &[b"c"]), data_page(2, &[b"d"])];
+        let mut mmap = map_pages(&pages);
+        // swap the two offsets of page 0 so its keys are out of order
+        let offsets = PAGE_HEADER_SIZE;
+        mmap.copy_within(offsets..offsets + 2, offsets + 4);
+        mmap.copy_within(offsets + 2..offsets + 4, offsets);
+        mmap.copy_within(offsets + 4..offsets + 6, offsets + 2);
+        // scribble over page 2's header pgno
+        mmap[2 * PAGE_SIZE] = 9;
+        let mmap = mmap.make_read_only().unwrap();
+
+        let report = check_file(&mmap, PAGE_SIZE);
+        let pgnos: Vec<Pgno> = report.problems.iter().map(|p| p.pgno).collect();
+        assert!(pgnos.contains(&0));
+        assert!(pgnos.contains(&2));
+        assert!(!pgnos.contains(&1));
+    }
+
+    #[test]
+    fn test_sample() {
+        let pages: Vec<Page> = (0..50).map(|pgno| data_page(pgno, &[b"a", b"b"])).collect();
+        let mut mmap = map_pages(&pages);
+        mmap[7 * PAGE_SIZE + PAGE_HEADER_SIZE] ^= 0xff;
+        let mmap = mmap.make_read_only().unwrap();
+
+        let report = check_sample(&mmap, PAGE_SIZE, 10..50, 5, Some(KeyOrder::Bytes));
+        assert_eq!(report.pages_checked, 5);
+        assert!(report.is_ok());
+        // a sample larger than the range checks all of it
+        let report = check_sample(&mmap, PAGE_SIZE, 0..50, 100, Some(KeyOrder::Bytes));
+        assert_eq!(report.pages_checked, 50);
+        assert!(!report.is_ok());
+        assert!(report.problems.iter().all(|problem| problem.pgno == 7));
+    }
+
+    #[test]
+    fn test_commits_of_a_live_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = crate::env::Env::open(&path).unwrap();
+        let map = || unsafe { Mmap::map(&std::fs::File::open(&path).unwrap()).unwrap() };
+        let mut verified = HashSet::new();
+        for round in 0..3u32 {
+            let mut txn = env.begin_write();
+            for i in 0..500u32 {
+                txn.put(&(i * 3 + round).to_be_bytes(), &[0u8; 50]).unwrap();
+            }
+            txn.commit().unwrap();
+            let mmap = map();
+            let report = check_metas(&mmap, PAGE_SIZE);
+            assert!(report.is_ok(), "{:?}", report.problems);
+            let report = check_commit(&mmap, &Meta::read(&mmap).unwrap(), &mut verified);
+            assert!(report.is_ok(), "{:?}", report.problems);
+            // only the pages the commit wrote are new
+            let stat = env.stat().unwrap();
+            assert!(report.pages_checked as u64 <= stat.tree.branch_pages + stat.tree.leaf_pages);
+        }
+
+        // the root of the next commit is damaged, and a meta from the future
+        // sits in the slot of the older one
+        let mut txn = env.begin_write();
+        txn.put(b"new", b"value").unwrap();
+        txn.commit().unwrap();
+        let meta = env.get_meta();
+        let root = meta.get_root().unwrap() as usize;
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[root * PAGE_SIZE + PAGE_SIZE - 1] ^= 0xff;
+        let future = meta.next_commit(None, 2).next_commit(None, 2);
+        let mut wrong = future.write_page();
+        let slot = 1 - meta.get_pgno();
+        wrong.set_pgno(slot);
+        wrong.update_checksum();
+        let offset = slot as usize * PAGE_SIZE;
+        bytes[offset..offset + PAGE_SIZE].copy_from_slice(wrong.as_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        let mmap = map();
+        let report = check_commit(&mmap, &meta, &mut verified);
+        assert_eq!(report.problems.iter().map(|p| p.pgno).collect::<Vec<_>>(), [root as Pgno]);
+        let report = check_metas(&mmap, PAGE_SIZE);
+        let problems: Vec<String> = report.problems.iter().map(|p| p.to_string()).collect();
+        assert_eq!(problems, [
+            "page 0: meta pages are not consecutive commits".to_string(),
+            format!("page {}: commit {} is in the wrong slot", slot, future.get_txnid()),
+        ]);
+    }
+
+    #[test]
+    fn test_freelist_overlap() {
+        let report = check_freelist(&[1, 2, 3], &[4, 3, 4]);
+        assert_eq!(
+            report.problems,
+            vec![
+                Problem { pgno: 3, description: "free page is still reachable".into() },
+                Problem { pgno: 4, description: "page appears on the freelist more than once".into() },
+            ]
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::buf::{read_varint_len, write_varint_u64, ByteBuf};
+use crate::constants::*;
+
+// Values are compressed in the LZ4 block format, after a varint holding the
+// size they decompress to. A block is a run of sequences, each a token (the
+// number of literals in the high nibble, the match length less MIN_MATCH in
+// the low one, 15 meaning more length bytes follow), the literals, and a
+// 2-byte offset back to where the match is copied from; the last sequence
+// is literals only.
+const MIN_MATCH: usize = 4;
+// the last match starts at least this far from the end, and the last
+// LAST_LITERALS bytes are always literals
+const MATCH_LIMIT: usize = 12;
+const LAST_LITERALS: usize = 5;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn corrupt(reason: &'static str) -> DBError {
+    DBError::CorruptValue { reason }
+}
+
+fn read_u32(input: &[u8], pos: usize) -> u32 {
+    input.read_u32_le(pos).expect("matches start before the last literals")
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// `value` compressed, to be stored with `NodeFlag::COMPRESSED`. Values
+/// without repetition come out a little larger than they went in.
+pub fn compress_value(value: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(value.len() / 2 + 16);
+    write_varint_u64(&mut out, value.len() as u64);
+    // where each hashed 4 bytes were last seen, plus one so 0 means nowhere
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let (mut pos, mut anchor) = (0, 0);
+    while pos + MATCH_LIMIT < value.len() {
+        let sequence = read_u32(value, pos);
+        let slot = &mut table[hash(sequence)];
+        let candidate = slot.checked_sub(1);
+        *slot = pos + 1;
+        let Some(start) = candidate
+            .filter(|&start| pos - start <= MAX_OFFSET && read_u32(value, start) == sequence)
+        else {
+            pos += 1;
+            continue;
+        };
+        let mut len = MIN_MATCH;
+        while pos + len < value.len() - LAST_LITERALS && value[start + len] == value[pos + len] {
+            len += 1;
+        }
+        write_sequence(&mut out, &value[anchor..pos], Some((pos - start, len)));
+        pos += len;
+        anchor = pos;
+    }
+    write_sequence(&mut out, &value[anchor..], None);
+    out
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(15) as u8) << 4) | match_len.min(15) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_length(out, match_len - 15);
+        }
+    }
+}
+
+fn write_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// The value `stored` was compressed from by `compress_value`.
+pub fn decompress_value(stored: &[u8]) -> Result<Vec<u8>, DBError> {
+    let (size, read) =
+        read_varint_len(stored, usize::MAX).map_err(|err| corrupt(err.reason()))?;
+    let input = &stored[read..];
+    // no block grows by more than 255 times, so a corrupt size can't ask for
+    // much more memory than the value takes up
+    let mut out = Vec::with_capacity(size.min(input.len().saturating_mul(255)));
+    let mut pos = 0;
+    loop {
+        let token = *input.get(pos).ok_or_else(|| corrupt("compressed value is truncated"))?;
+        pos += 1;
+        let literals = read_length(input, &mut pos, usize::from(token >> 4))?;
+        let literals = input
+            .read_n_bytes(pos, literals)
+            .ok_or_else(|| corrupt("compressed value is truncated"))?;
+        pos += literals.len();
+        if out.len() + literals.len() > size {
+            return Err(corrupt("compressed value is longer than its size"));
+        }
+        out.extend_from_slice(literals);
+        if pos == input.len() {
+            break;
+        }
+        let offset = input
+            .read_u16_le(pos)
+            .ok_or_else(|| corrupt("compressed value is truncated"))? as usize;
+        pos += 2;
+        if offset == 0 || offset > out.len() {
+            return Err(corrupt("compressed value refers back past its start"));
+        }
+        let len = read_length(input, &mut pos, usize::from(token & 15))? + MIN_MATCH;
+        if out.len() + len > size {
+            return Err(corrupt("compressed value is longer than its size"));
+        }
+        // byte by byte, since a match can overlap what it copies
+        let start = out.len() - offset;
+        for i in start..start + len {
+            out.push(out[i]);
+        }
+    }
+    if out.len() != size {
+        return Err(corrupt("compressed value is shorter than its size"));
+    }
+    Ok(out)
+}
+
+fn read_length(input: &[u8], pos: &mut usize, nibble: usize) -> Result<usize, DBError> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = *input.get(*pos).ok_or_else(|| corrupt("compressed value is truncated"))?;
+            *pos += 1;
+            len += usize::from(byte);
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// Values a transaction has decompressed, held for as long as it is, so
+/// reads can borrow them just like values stored as they are. A scan over
+/// many compressed values keeps all of them until the transaction ends.
+#[derive(Default)]
+pub struct ValueArena {
+    values: Mutex<Vec<Arc<[u8]>>>,
+}
+
+impl ValueArena {
+    pub fn hold(&self, value: Vec<u8>) -> &[u8] {
+        let value: Arc<[u8]> = value.into();
+        let bytes = Arc::as_ptr(&value);
+        self.values.lock().unwrap_or_else(PoisonError::into_inner).push(value);
+        // values are never removed, so the bytes live as long as the arena
+        unsafe { &*bytes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let random: Vec<u8> = (0..3000).map(|_| rng.random()).collect();
+        let text = b"the quick brown fox jumps over the lazy dog. ".repeat(40);
+        for value in [&b""[..], b"short", &[0u8; 1000], &random, &text] {
+            let stored = compress_value(value);
+            assert_eq!(decompress_value(&stored).unwrap(), value);
+        }
+        assert!(compress_value(&text).len() < text.len() / 10);
+        // long runs need more than one length byte
+        assert!(compress_value(&[0u8; 1000]).len() < 20);
+    }
+
+    #[test]
+    fn test_corrupt_values() {
+        let stored = compress_value(&b"abcd".repeat(100));
+        assert!(decompress_value(&stored[..stored.len() - 1]).is_err());
+        let mut longer = stored.clone();
+        longer[0] += 1;
+        assert!(matches!(decompress_value(&longer), Err(DBError::CorruptValue { .. })));
+        // an offset back past the start
+        assert!(decompress_value(&[8, 0x10, b'a', 2, 0]).is_err());
+        assert!(decompress_value(&[]).is_err());
+    }
+
+    #[test]
+    fn test_arena_outlives_its_growth() {
+        let arena = ValueArena::default();
+        let first = arena.hold(b"first".to_vec());
+        let rest: Vec<&[u8]> = (0..100u32).map(|i| arena.hold(i.to_le_bytes().to_vec())).collect();
+        assert_eq!(first, b"first");
+        assert_eq!(rest[99], 99u32.to_le_bytes());
+    }
+}
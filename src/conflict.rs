@@ -0,0 +1,99 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::constants::*;
+
+/// The committed pages each commit replaced, kept for the optimistic
+/// transactions (`Env::begin_optimistic`) open alongside them. One of those
+/// can only commit over the commits made after its base if none of them
+/// replaced a leaf it read, since it can't know what it would have read
+/// instead. Commits are kept from the oldest base on, and only while an
+/// optimistic transaction is open.
+#[derive(Debug, Default)]
+pub struct ConflictTable {
+    // the base commit of every open optimistic transaction, with how many
+    // share it
+    open: BTreeMap<TxnId, usize>,
+    // None for a commit that may have replaced any page, as `WriteTxn::clear`
+    commits: BTreeMap<TxnId, Option<HashSet<Pgno>>>,
+}
+
+impl ConflictTable {
+    pub fn new() -> Self {
+        ConflictTable::default()
+    }
+
+    /// Counts an optimistic transaction reading `base`, so commits made from
+    /// here on are recorded for it.
+    pub fn begin(&mut self, base: TxnId) {
+        *self.open.entry(base).or_default() += 1;
+    }
+
+    /// Ends an optimistic transaction `begin` counted, dropping the commits
+    /// no other one needs.
+    pub fn end(&mut self, base: TxnId) {
+        if let Some(count) = self.open.get_mut(&base) {
+            *count -= 1;
+            if *count == 0 {
+                self.open.remove(&base);
+            }
+        }
+        match self.open.first_key_value() {
+            Some((&oldest, _)) => self.commits = self.commits.split_off(&(oldest + 1)),
+            None => self.commits.clear(),
+        }
+    }
+
+    /// Records the pages commit `txnid` replaced, or every page for None, if
+    /// an optimistic transaction is open.
+    pub fn record(&mut self, txnid: TxnId, replaced: Option<HashSet<Pgno>>) {
+        if !self.open.is_empty() {
+            self.commits.insert(txnid, replaced);
+        }
+    }
+
+    /// Fails with `Conflict` unless every commit after `base`, up to and
+    /// including `current`, was recorded without replacing any of `reads`.
+    /// Commits made other than by a write transaction, such as compaction's,
+    /// aren't recorded, so they conflict with everything.
+    pub fn check(&self, base: TxnId, current: TxnId, reads: &HashSet<Pgno>) -> Result<(), DBError> {
+        for txnid in base + 1..=current {
+            match self.commits.get(&txnid) {
+                Some(Some(replaced)) if replaced.is_disjoint(reads) => {}
+                _ => return Err(DBError::Conflict { txnid }),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commits_kept_for_open_bases() {
+        let conflict = |result: Result<(), DBError>| match result {
+            Err(DBError::Conflict { txnid }) => Some(txnid),
+            _ => None,
+        };
+        let mut table = ConflictTable::new();
+        table.record(3, Some(HashSet::from([7])));
+        table.begin(3);
+        table.begin(4);
+        table.record(4, Some(HashSet::from([7])));
+        table.record(5, Some(HashSet::from([8])));
+        assert!(table.check(4, 5, &HashSet::from([7])).is_ok());
+        assert_eq!(conflict(table.check(3, 5, &HashSet::from([7]))), Some(4));
+        // commit 6 was never recorded
+        assert_eq!(conflict(table.check(3, 6, &HashSet::new())), Some(6));
+
+        table.record(6, None);
+        assert_eq!(conflict(table.check(5, 6, &HashSet::new())), Some(6));
+        table.end(3);
+        assert_eq!(table.commits.keys().copied().collect::<Vec<_>>(), [5, 6]);
+        table.end(4);
+        assert!(table.commits.is_empty());
+        table.record(7, None);
+        assert!(table.commits.is_empty());
+    }
+}
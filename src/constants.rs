@@ -1,22 +1,30 @@
 use bitflags::bitflags;
 use std::error::Error;
 use std::fmt;
+use std::io;
 
 // type aliases
 pub type Pgno = u64;
 pub type TxnId = u64;
+pub type KeyValue = (Vec<u8>, Vec<u8>);
 
 // sizes
-pub const PAGE_HEADER_SIZE: usize = 16;
-pub const PAGE_SIZE: usize = 4096;
-pub const PAGE_BUF_SIZE: usize = PAGE_SIZE - PAGE_HEADER_SIZE;
+pub const PAGE_HEADER_SIZE: usize = 20;
+// the page size is chosen when a file is created and recorded in its meta page;
+// it must be a power of two in MIN_PAGE_SIZE..=MAX_PAGE_SIZE
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+pub const MIN_PAGE_SIZE: usize = 4096;
+pub const MAX_PAGE_SIZE: usize = 65536;
+// the longest key that can be stored, whatever the page size; values are
+// limited by the page size instead, see `data_page::max_value_size`
+pub const MAX_KEY_SIZE: usize = 511;
+// the size of every key of a database opened with `EnvOptions::fixed_keys`
+pub const FIXED_KEY_SIZE: usize = 8;
 
-pub const USIZE_N: usize = std::mem::size_of::<usize>();
 pub const U16_N: usize = 2;
-pub const KEY_SIZE: usize = USIZE_N;
-pub const DATA_SIZE: usize = USIZE_N;
 
 pub const MAX_PGNO: usize = usize::MAX;
+pub const INVALID_PGNO: Pgno = Pgno::MAX;
 pub const MAGIC_NUMBER: u16 = 0xBEEF;
 
 // flags
@@ -26,6 +34,11 @@ bitflags! {
     pub struct PageFlag: u16 {
         const ALIVE = 1;
         const DIRTY = 2;
+        const META = 8;
+        const BRANCH = 16;
+        // a leaf whose keys are all FIXED_KEY_SIZE bytes, which its nodes
+        // don't store the size of
+        const FIXED_KEY = 32;
     }
 
     #[repr(transparent)]
@@ -33,22 +46,138 @@ bitflags! {
     pub struct NodeFlag: u16 {
         const ALIVE = 1;
         const DIRTY = 2;
+        // the data is a value compressed by `compress::compress_value`
+        const COMPRESSED = 4;
+    }
+
+    // how a single put treats the key it writes, like LMDB's write flags
+    #[repr(transparent)]
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct PutFlag: u16 {
+        // the default: replace the value of a key that is already there
+        const OVERWRITE = 0;
+        // fail with `KeyExists` if the key is already there
+        const NO_OVERWRITE = 1;
+        // the key must sort after every key already stored, or the put fails
+        // with `KeyExists`; for loading keys in order
+        const APPEND = 2;
     }
 }
 
 // Errors
 pub enum DBError {
+    Io(io::Error),
     WriteLeafPageFailed,
     KeyNotFound,
+    PageFull,
+    PageOutOfBounds { pgno: Pgno },
+    CorruptPage { pgno: Pgno, reason: &'static str },
+    CorruptValue { reason: &'static str },
+    KeyTooLarge { size: usize, max: usize },
+    KeySizeMismatch { size: usize, expected: usize },
+    ValueTooLarge { size: usize, max: usize },
+    ChecksumMismatch { pgno: Pgno },
+    VersionMismatch { expected: u32, found: u32 },
+    TxnReadOnly,
+    KeyExists,
+    InvalidPageSize { size: usize },
+    BatchNotSorted,
+    ReadersFull { max: usize },
+    WriterLocked,
+    TxnFull { max: usize },
+    IncompatibleFile { reason: &'static str },
+    Unsupported { reason: &'static str },
+    RangeOverlap,
+    NoMergeOperator,
+    CompareFailed { actual: Option<Vec<u8>> },
+    ReadersActive { oldest: TxnId },
+    MigrationOutOfOrder { version: u64 },
+    Encoding { message: String },
+    EmptyKey,
+    PreparedTxnPending { txnid: TxnId },
+    PreparedTxnNotFound { txnid: TxnId },
+    Cancelled,
+    Conflict { txnid: TxnId },
 }
 
-impl Error for DBError {}
+impl Error for DBError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DBError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DBError {
+    fn from(err: io::Error) -> Self {
+        DBError::Io(err)
+    }
+}
 
 impl fmt::Debug for DBError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            DBError::Io(err) => write!(f, "Io({:?})", err),
             DBError::WriteLeafPageFailed => write!(f, "WriteLeafPageFailed"),
             DBError::KeyNotFound => write!(f, "KeyNotFound"),
+            DBError::PageFull => write!(f, "PageFull"),
+            DBError::PageOutOfBounds { pgno } => {
+                write!(f, "PageOutOfBounds {{ pgno: {} }}", pgno)
+            }
+            DBError::CorruptPage { pgno, reason } => {
+                write!(f, "CorruptPage {{ pgno: {}, reason: {:?} }}", pgno, reason)
+            }
+            DBError::CorruptValue { reason } => {
+                write!(f, "CorruptValue {{ reason: {:?} }}", reason)
+            }
+            DBError::KeyTooLarge { size, max } => {
+                write!(f, "KeyTooLarge {{ size: {}, max: {} }}", size, max)
+            }
+            DBError::KeySizeMismatch { size, expected } => {
+                write!(f, "KeySizeMismatch {{ size: {}, expected: {} }}", size, expected)
+            }
+            DBError::ValueTooLarge { size, max } => {
+                write!(f, "ValueTooLarge {{ size: {}, max: {} }}", size, max)
+            }
+            DBError::ChecksumMismatch { pgno } => {
+                write!(f, "ChecksumMismatch {{ pgno: {} }}", pgno)
+            }
+            DBError::VersionMismatch { expected, found } => {
+                write!(f, "VersionMismatch {{ expected: {}, found: {} }}", expected, found)
+            }
+            DBError::TxnReadOnly => write!(f, "TxnReadOnly"),
+            DBError::KeyExists => write!(f, "KeyExists"),
+            DBError::InvalidPageSize { size } => write!(f, "InvalidPageSize {{ size: {} }}", size),
+            DBError::BatchNotSorted => write!(f, "BatchNotSorted"),
+            DBError::ReadersFull { max } => write!(f, "ReadersFull {{ max: {} }}", max),
+            DBError::WriterLocked => write!(f, "WriterLocked"),
+            DBError::TxnFull { max } => write!(f, "TxnFull {{ max: {} }}", max),
+            DBError::IncompatibleFile { reason } => {
+                write!(f, "IncompatibleFile {{ reason: {:?} }}", reason)
+            }
+            DBError::Unsupported { reason } => write!(f, "Unsupported {{ reason: {:?} }}", reason),
+            DBError::RangeOverlap => write!(f, "RangeOverlap"),
+            DBError::NoMergeOperator => write!(f, "NoMergeOperator"),
+            DBError::CompareFailed { actual } => {
+                write!(f, "CompareFailed {{ actual: {:?} }}", actual)
+            }
+            DBError::ReadersActive { oldest } => {
+                write!(f, "ReadersActive {{ oldest: {} }}", oldest)
+            }
+            DBError::MigrationOutOfOrder { version } => {
+                write!(f, "MigrationOutOfOrder {{ version: {} }}", version)
+            }
+            DBError::Encoding { message } => write!(f, "Encoding {{ message: {:?} }}", message),
+            DBError::EmptyKey => write!(f, "EmptyKey"),
+            DBError::PreparedTxnPending { txnid } => {
+                write!(f, "PreparedTxnPending {{ txnid: {} }}", txnid)
+            }
+            DBError::PreparedTxnNotFound { txnid } => {
+                write!(f, "PreparedTxnNotFound {{ txnid: {} }}", txnid)
+            }
+            DBError::Cancelled => write!(f, "Cancelled"),
+            DBError::Conflict { txnid } => write!(f, "Conflict {{ txnid: {} }}", txnid),
         }
     }
 }
@@ -56,8 +185,77 @@ impl fmt::Debug for DBError {
 impl fmt::Display for DBError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            DBError::Io(err) => write!(f, "io error: {}", err),
             DBError::WriteLeafPageFailed => write!(f, "WriteLeafPageFailed"),
             DBError::KeyNotFound => write!(f, "KeyNotFound"),
+            DBError::PageFull => write!(f, "not enough free space in page"),
+            DBError::PageOutOfBounds { pgno } => write!(f, "page {} is out of bounds", pgno),
+            DBError::CorruptPage { pgno, reason } => {
+                write!(f, "page {} is corrupt: {}", pgno, reason)
+            }
+            DBError::CorruptValue { reason } => write!(f, "value is corrupt: {}", reason),
+            DBError::KeyTooLarge { size, max } => {
+                write!(f, "key of {} bytes exceeds the maximum of {} bytes", size, max)
+            }
+            DBError::KeySizeMismatch { size, expected } => {
+                write!(f, "key of {} bytes where every key is {} bytes", size, expected)
+            }
+            DBError::ValueTooLarge { size, max } => {
+                write!(f, "value of {} bytes exceeds the maximum of {} bytes", size, max)
+            }
+            DBError::ChecksumMismatch { pgno } => {
+                write!(f, "page {} failed checksum verification", pgno)
+            }
+            DBError::VersionMismatch { expected, found } => {
+                write!(f, "file format version {} is not supported (expected {})", found, expected)
+            }
+            DBError::TxnReadOnly => write!(f, "transaction is read-only"),
+            DBError::KeyExists => write!(f, "key already exists"),
+            DBError::InvalidPageSize { size } => write!(
+                f,
+                "page size {} is not a power of two between {} and {}",
+                size, MIN_PAGE_SIZE, MAX_PAGE_SIZE
+            ),
+            DBError::BatchNotSorted => {
+                write!(f, "batch keys are not in strictly increasing order")
+            }
+            DBError::ReadersFull { max } => {
+                write!(f, "all {} reader slots are in use", max)
+            }
+            DBError::WriterLocked => {
+                write!(f, "the file is already open for writing, maybe by another process")
+            }
+            DBError::TxnFull { max } => {
+                write!(f, "transaction already holds {} dirty pages", max)
+            }
+            DBError::IncompatibleFile { reason } => write!(f, "incompatible file: {}", reason),
+            DBError::Unsupported { reason } => write!(f, "unsupported: {}", reason),
+            DBError::RangeOverlap => write!(f, "key range overlaps keys already in the tree"),
+            DBError::NoMergeOperator => write!(f, "no merge operator is registered"),
+            DBError::CompareFailed { actual: Some(actual) } => {
+                write!(f, "value did not match: found {} bytes", actual.len())
+            }
+            DBError::CompareFailed { actual: None } => {
+                write!(f, "value did not match: key is absent")
+            }
+            DBError::ReadersActive { oldest } => {
+                write!(f, "a reader of commit {} is still open", oldest)
+            }
+            DBError::MigrationOutOfOrder { version } => {
+                write!(f, "migration {} is older than one already applied", version)
+            }
+            DBError::Encoding { message } => write!(f, "encoding failed: {}", message),
+            DBError::EmptyKey => write!(f, "keys must not be empty"),
+            DBError::PreparedTxnPending { txnid } => {
+                write!(f, "prepared transaction {} has to be resolved first", txnid)
+            }
+            DBError::PreparedTxnNotFound { txnid } => {
+                write!(f, "no transaction {} is prepared", txnid)
+            }
+            DBError::Cancelled => write!(f, "cancelled by its progress callback"),
+            DBError::Conflict { txnid } => {
+                write!(f, "commit {} changed what the transaction read", txnid)
+            }
         }
     }
 }
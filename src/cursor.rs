@@ -0,0 +1,644 @@
+use std::borrow::Cow;
+
+use crate::btree::{BTree, MAX_TREE_DEPTH};
+use crate::btree_page::BranchPage;
+use crate::constants::*;
+use crate::data_page::DataPage;
+
+pub type Entry<'a> = (Cow<'a, [u8]>, &'a [u8]);
+
+/// How many leaves ahead of a forward scan the OS is asked to read in.
+pub const PREFETCH_LEAVES: usize = 16;
+
+/// Walks a tree's live entries in key order. Iterating yields the entry the
+/// cursor is positioned at and then moves past it.
+pub struct Cursor<'a> {
+    tree: BTree<'a>,
+    // soft-deleted entries are yielded too
+    include_deleted: bool,
+    // the pages from the root down to the current leaf, each with the index of
+    // the child (or, for the leaf, the node) the cursor is at
+    stack: Vec<(DataPage<'a>, usize)>,
+}
+
+impl<'a> Cursor<'a> {
+    /// A cursor positioned at the first entry.
+    pub fn new(tree: BTree<'a>) -> Result<Self, DBError> {
+        let mut cursor = Cursor {
+            tree,
+            include_deleted: false,
+            stack: Vec::new(),
+        };
+        if let Some(root) = tree.get_root() {
+            cursor.descend(root, None)?;
+        }
+        Ok(cursor)
+    }
+
+    /// Also yields the entries `WriteTxn::soft_delete` hid, among the live
+    /// ones, as `prev` and `keys` then do too. Positions and counts, as of
+    /// `seek_to_nth`, still only count live entries.
+    pub fn include_deleted(mut self) -> Self {
+        self.include_deleted = true;
+        self
+    }
+
+    /// Moves to the first entry whose key is >= `key`.
+    pub fn seek(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.stack.clear();
+        if let Some(root) = self.tree.get_root() {
+            self.descend(root, Some(key))?;
+        }
+        Ok(())
+    }
+
+    /// Moves just past the last key starting with `prefix`, to the first key
+    /// after all of them, by seeking to the smallest key that sorts after
+    /// every key with the prefix; `prev` then returns the last entry under
+    /// the prefix, if there is one. Reads one page per level. Like
+    /// `BTree::scan_prefix`, only meaningful under byte order.
+    pub fn seek_prefix_end(&mut self, prefix: &[u8]) -> Result<(), DBError> {
+        match prefix_successor(prefix) {
+            Some(successor) => self.seek(&successor),
+            // nothing sorts after an empty or all-0xff prefix but the end
+            None => {
+                self.stack.clear();
+                Ok(())
+            }
+        }
+    }
+
+    /// Moves to the last entry, so that `next` returns it and `prev` the one
+    /// before, by following the last child of every branch page down: one
+    /// page per level, unless the last leaf holds only deleted nodes. Not
+    /// `last`, which `Iterator::last` would win over on a temporary cursor,
+    /// walking every entry to get there.
+    pub fn seek_last(&mut self) -> Result<(), DBError> {
+        self.stack.clear();
+        self.prev()?;
+        Ok(())
+    }
+
+    /// Moves back over the entry before the cursor and returns it, so that
+    /// `next` returns it again; `None`, leaving the cursor where it is, if
+    /// the cursor is at the first entry.
+    pub fn prev(&mut self) -> Result<Option<Entry<'a>>, DBError> {
+        if self.stack.is_empty() {
+            // past the end, which an empty stack always is
+            let Some(root) = self.tree.get_root() else {
+                return Ok(None);
+            };
+            self.descend_last(root)?;
+        }
+        while let Some((leaf, idx)) = self.stack.last_mut() {
+            if *idx == 0 {
+                if !self.prev_leaf()? {
+                    return Ok(None);
+                }
+                continue;
+            }
+            *idx -= 1;
+            let node = leaf.read_node(*idx)?;
+            if node.is_alive() || self.include_deleted {
+                return Ok(Some((node.get_key(), self.tree.get_value(&node)?)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// How many entries come before the one the cursor is at, or how many
+    /// there are, past the end. Reads nothing but the pages on the cursor's
+    /// path, going by the entry counts branch pages keep for their children,
+    /// as `seek_to_nth` does; in a write transaction, children it has written
+    /// are counted as they are now.
+    pub fn position(&self) -> Result<u64, DBError> {
+        if self.stack.is_empty() {
+            return self.len();
+        }
+        let mut rank = 0;
+        for &(page, idx) in &self.stack {
+            if page.get_flags().contains(PageFlag::BRANCH) {
+                let branch = BranchPage::from(page)?;
+                for child in 0..idx {
+                    rank += self.tree.child_entries(&branch, child)?;
+                }
+            } else {
+                for node in 0..idx {
+                    rank += u64::from(page.is_alive_at(node)?);
+                }
+            }
+        }
+        Ok(rank)
+    }
+
+    /// How many entries are left, counted the same way as `position`.
+    pub fn remaining(&self) -> Result<u64, DBError> {
+        Ok(self.len()? - Cursor::position(self)?)
+    }
+
+    // every entry of the tree, from the counts the root keeps
+    fn len(&self) -> Result<u64, DBError> {
+        let Some(root) = self.tree.get_root() else {
+            return Ok(0);
+        };
+        let page = self.tree.get_page(root)?;
+        if !page.get_flags().contains(PageFlag::BRANCH) {
+            return page.nodes().try_fold(0, |count, node| node.map(|_| count + 1));
+        }
+        let branch = BranchPage::from(page)?;
+        (0..branch.num_children()).map(|idx| self.tree.child_entries(&branch, idx)).sum()
+    }
+
+    /// Moves to the `rank`-th entry, as counted by `position`; the same as
+    /// `seek_to_nth`.
+    pub fn seek_position(&mut self, rank: u64) -> Result<(), DBError> {
+        self.seek_to_nth(rank)
+    }
+
+    /// Moves to the `n`-th entry in key order, counting from 0, or past the
+    /// end if there are no more than `n`. Reads one page per level, going by
+    /// the entry counts branch pages keep for their children; in a write
+    /// transaction, children it has written are counted as they are now.
+    pub fn seek_to_nth(&mut self, mut n: u64) -> Result<(), DBError> {
+        self.stack.clear();
+        let Some(mut pgno) = self.tree.get_root() else {
+            return Ok(());
+        };
+        loop {
+            let page = self.tree.get_page(pgno)?;
+            if !page.get_flags().contains(PageFlag::BRANCH) {
+                let mut idx = 0;
+                while idx < page.num_nodes() {
+                    if page.read_node(idx)?.is_alive() {
+                        if n == 0 {
+                            break;
+                        }
+                        n -= 1;
+                    }
+                    idx += 1;
+                }
+                self.stack.push((page, idx));
+                return Ok(());
+            }
+            if self.stack.len() == MAX_TREE_DEPTH {
+                return Err(DBError::CorruptPage { pgno, reason: "tree is too deep" });
+            }
+            let branch = BranchPage::from(page)?;
+            let mut idx = 0;
+            loop {
+                let entries = self.tree.child_entries(&branch, idx)?;
+                if n < entries {
+                    break;
+                }
+                n -= entries;
+                idx += 1;
+                if idx == branch.num_children() {
+                    self.stack.clear();
+                    return Ok(());
+                }
+            }
+            pgno = branch.child_at(idx)?;
+            self.stack.push((page, idx));
+        }
+    }
+
+    /// Moves past `n` entries. A skip that stays in the current leaf steps
+    /// over its nodes; a longer one seeks to the entry `n` past this one with
+    /// `seek_to_nth` instead of reading the leaves in between.
+    pub fn skip_entries(&mut self, n: u64) -> Result<(), DBError> {
+        let in_leaf = self.stack.last().map_or(0, |(leaf, idx)| leaf.num_nodes() - idx);
+        if n > in_leaf as u64 {
+            // `self.position()` would find `Iterator::position` first
+            return self.seek_to_nth(Cursor::position(self)?.saturating_add(n));
+        }
+        for _ in 0..n {
+            if self.advance()?.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // pushes the pages from `pgno` down to a leaf, following `key` or, without
+    // one, the leftmost children
+    fn descend(&mut self, mut pgno: Pgno, key: Option<&[u8]>) -> Result<(), DBError> {
+        loop {
+            let page = self.tree.get_page(pgno)?;
+            if !page.get_flags().contains(PageFlag::BRANCH) {
+                let idx = match key {
+                    Some(key) => page.search(key)?.unwrap_or_else(|idx| idx),
+                    None => 0,
+                };
+                self.prefetch_siblings(key.is_some())?;
+                self.stack.push((page, idx));
+                return Ok(());
+            }
+            if self.stack.len() == MAX_TREE_DEPTH {
+                return Err(DBError::CorruptPage { pgno, reason: "tree is too deep" });
+            }
+            let branch = BranchPage::from(page)?;
+            let idx = match key {
+                Some(key) => branch.child_index(key)?,
+                None => 0,
+            };
+            pgno = branch.child_at(idx)?;
+            self.stack.push((page, idx));
+        }
+    }
+
+    // Hints that the `PREFETCH_LEAVES` leaves after the one being descended
+    // into, under the same parent, will be read soon. A scan steps into them
+    // one at a time, so it only asks again once it is through the batch; a
+    // seek starts a batch of its own.
+    fn prefetch_siblings(&self, seeking: bool) -> Result<(), DBError> {
+        let Some(&(parent, idx)) = self.stack.last() else {
+            return Ok(());
+        };
+        if !seeking && idx % PREFETCH_LEAVES != 0 {
+            return Ok(());
+        }
+        let branch = BranchPage::from(parent)?;
+        let end = branch.num_children().min(idx + 1 + PREFETCH_LEAVES);
+        let pgnos: Result<Vec<Pgno>, _> = (idx + 1..end).map(|i| branch.child_at(i)).collect();
+        self.tree.prefetch(&pgnos?);
+        Ok(())
+    }
+
+    // pushes the pages from `pgno` down to a leaf along the rightmost
+    // children, leaving the cursor past the leaf's last node
+    fn descend_last(&mut self, mut pgno: Pgno) -> Result<(), DBError> {
+        loop {
+            let page = self.tree.get_page(pgno)?;
+            if !page.get_flags().contains(PageFlag::BRANCH) {
+                self.stack.push((page, page.num_nodes()));
+                return Ok(());
+            }
+            if self.stack.len() == MAX_TREE_DEPTH {
+                return Err(DBError::CorruptPage { pgno, reason: "tree is too deep" });
+            }
+            let branch = BranchPage::from(page)?;
+            let idx = branch.num_children() - 1;
+            pgno = branch.child_at(idx)?;
+            self.stack.push((page, idx));
+        }
+    }
+
+    // moves past the last node of the previous leaf, returning false and
+    // staying put if the cursor is in the first leaf
+    fn prev_leaf(&mut self) -> Result<bool, DBError> {
+        let leaf = self.stack.len() - 1;
+        let Some(level) = self.stack[..leaf].iter().rposition(|&(_, idx)| idx > 0) else {
+            return Ok(false);
+        };
+        self.stack.truncate(level + 1);
+        let (page, idx) = self.stack.last_mut().expect("the level is on the stack");
+        *idx -= 1;
+        let child = BranchPage::from(*page)?.child_at(*idx)?;
+        self.descend_last(child)?;
+        Ok(true)
+    }
+
+    // moves to the first node of the next leaf, or past the end. Leaves don't
+    // link to their siblings: a copied leaf gets a new page number, so both
+    // neighbours would have to be copied to point at it, and theirs in turn,
+    // across the whole level. The parent is already on the stack, so stepping
+    // over from it reads the same leaves with nothing extra until the scan
+    // crosses into the next parent.
+    fn next_leaf(&mut self) -> Result<(), DBError> {
+        self.stack.pop();
+        while let Some((page, idx)) = self.stack.last_mut() {
+            let branch = BranchPage::from(*page)?;
+            if *idx + 1 < branch.num_children() {
+                *idx += 1;
+                let child = branch.child_at(*idx)?;
+                return self.descend(child, None);
+            }
+            self.stack.pop();
+        }
+        Ok(())
+    }
+
+    // moves past the nodes up to the first one `read` returns something for,
+    // and past that one too
+    fn advance_with<T>(
+        &mut self,
+        read: impl Fn(&BTree<'a>, &DataPage<'a>, usize) -> Result<Option<T>, DBError>,
+    ) -> Result<Option<T>, DBError> {
+        while let Some((leaf, idx)) = self.stack.last_mut() {
+            if *idx == leaf.num_nodes() {
+                self.next_leaf()?;
+                continue;
+            }
+            let item = read(&self.tree, leaf, *idx)?;
+            *idx += 1;
+            if item.is_some() {
+                return Ok(item);
+            }
+        }
+        Ok(None)
+    }
+
+    fn advance(&mut self) -> Result<Option<Entry<'a>>, DBError> {
+        let include_deleted = self.include_deleted;
+        self.advance_with(|tree, leaf, idx| {
+            let node = leaf.read_node(idx)?;
+            match node.is_alive() || include_deleted {
+                true => Ok(Some((node.get_key(), tree.get_value(&node)?))),
+                false => Ok(None),
+            }
+        })
+    }
+
+    // nothing sensible follows a corrupt page
+    fn end_on_error<T>(&mut self, item: Result<Option<T>, DBError>) -> Option<Result<T, DBError>> {
+        if item.is_err() {
+            self.stack.clear();
+        }
+        item.transpose()
+    }
+
+    /// The keys from where the cursor is on, without their values: nodes are
+    /// read up to their keys, so values are neither sliced nor, when they
+    /// are stored compressed, decompressed.
+    pub fn keys(self) -> Keys<'a> {
+        Keys { cursor: self }
+    }
+}
+
+/// A cursor yielding keys alone, from `Cursor::keys`.
+pub struct Keys<'a> {
+    cursor: Cursor<'a>,
+}
+
+impl<'a> Iterator for Keys<'a> {
+    type Item = Result<Cow<'a, [u8]>, DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let include_deleted = self.cursor.include_deleted;
+        let key = self.cursor.advance_with(|_, leaf, idx| match include_deleted {
+            true => Ok(Some(leaf.read_node(idx)?.get_key())),
+            false => leaf.read_live_key(idx),
+        });
+        self.cursor.end_on_error(key)
+    }
+}
+
+// the smallest key after every key starting with `prefix`, under byte order:
+// the prefix with trailing 0xff bytes dropped and the last byte left bumped up
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&byte| byte != 0xff)?;
+    let mut successor = prefix[..=last].to_vec();
+    successor[last] += 1;
+    Some(successor)
+}
+
+impl<'a> Iterator for Cursor<'a> {
+    type Item = Result<Entry<'a>, DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.advance();
+        self.end_on_error(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Env;
+    use crate::txn::WriteTxn;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scan_and_seek() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        assert_eq!(Cursor::new(txn.tree()).unwrap().count(), 0);
+        for i in (0..3000u32).rev() {
+            txn.put(&(2 * i).to_be_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        let keys: Vec<u32> = Cursor::new(txn.tree())
+            .unwrap()
+            .map(|entry| u32::from_be_bytes(entry.unwrap().0[..].try_into().unwrap()))
+            .collect();
+        assert_eq!(keys, (0..3000).map(|i| 2 * i).collect::<Vec<_>>());
+
+        let mut cursor = Cursor::new(txn.tree()).unwrap();
+        cursor.seek(&1001u32.to_be_bytes()).unwrap();
+        let (key, value) = cursor.next().unwrap().unwrap();
+        assert_eq!((&key[..], value), (&1002u32.to_be_bytes()[..], &501u32.to_le_bytes()[..]));
+        cursor.seek(&6000u32.to_be_bytes()).unwrap();
+        assert!(cursor.next().is_none());
+    }
+
+    #[test]
+    fn test_scan_prefix() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        for user in ["alice", "bob", "bobby", "carol"] {
+            for item in 0..300u32 {
+                txn.put(format!("{user}/{item:04}").as_bytes(), user.as_bytes()).unwrap();
+            }
+        }
+        let count = |txn: &WriteTxn, prefix: &[u8]| txn.scan_prefix(prefix).unwrap().count();
+        assert_eq!(count(&txn, b"bob/"), 300);
+        assert_eq!(count(&txn, b"bob"), 600);
+        assert_eq!(count(&txn, b"dave/"), 0);
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        let keys: Vec<_> = txn
+            .scan_prefix(b"carol/001")
+            .unwrap()
+            .map(|entry| entry.unwrap().0.into_owned())
+            .collect();
+        let expected: Vec<_> = (10..20).map(|i| format!("carol/{i:04}").into_bytes()).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_seek_prefix_end() {
+        assert_eq!(prefix_successor(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_successor(b"a\xff\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_successor(b"\xff"), None);
+        assert_eq!(prefix_successor(b""), None);
+
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        for user in ["alice", "bob", "bobby"] {
+            for item in 0..500u32 {
+                txn.put(format!("{user}/{item:04}").as_bytes(), user.as_bytes()).unwrap();
+            }
+        }
+        txn.put(b"\xff\xff", b"last").unwrap();
+        txn.delete(b"bob/0499").unwrap();
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        let mut cursor = Cursor::new(txn.tree()).unwrap();
+        cursor.seek_prefix_end(b"bob/").unwrap();
+        assert_eq!(&cursor.prev().unwrap().unwrap().0[..], b"bob/0498");
+        // the same entry comes back going forwards, then what follows it
+        assert_eq!(&cursor.next().unwrap().unwrap().0[..], b"bob/0498");
+        assert_eq!(&cursor.next().unwrap().unwrap().0[..], b"bobby/0000");
+
+        // stepping back across every leaf to the start
+        cursor.seek_prefix_end(b"alice/").unwrap();
+        let mut count = 0;
+        while let Some(entry) = cursor.prev().unwrap() {
+            assert!(entry.0.starts_with(b"alice/"));
+            count += 1;
+        }
+        assert_eq!(count, 500);
+        assert_eq!(&cursor.next().unwrap().unwrap().0[..], b"alice/0000");
+
+        cursor.seek_prefix_end(b"\xff").unwrap();
+        assert!(cursor.next().is_none());
+        assert_eq!(&cursor.prev().unwrap().unwrap().0[..], b"\xff\xff");
+        cursor.seek_prefix_end(b"carol").unwrap();
+        assert_eq!(&cursor.prev().unwrap().unwrap().0[..], b"bobby/0499");
+    }
+
+    #[test]
+    fn test_last() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        assert!(txn.last_key_value().unwrap().is_none());
+        for i in 0..5000u64 {
+            txn.put(&i.to_be_bytes(), &(i * 2).to_le_bytes()).unwrap();
+        }
+        let (key, value) = txn.last_key_value().unwrap().unwrap();
+        assert_eq!((&key[..], value), (&4999u64.to_be_bytes()[..], &9998u64.to_le_bytes()[..]));
+        // deleted keys at the end, leaves' worth of them, are stepped over
+        for i in 1000..5000u64 {
+            txn.delete(&i.to_be_bytes()).unwrap();
+        }
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        assert_eq!(&txn.last_key_value().unwrap().unwrap().0[..], 999u64.to_be_bytes());
+        let mut cursor = txn.tree().cursor().unwrap();
+        cursor.seek(&10u64.to_be_bytes()).unwrap();
+        cursor.seek_last().unwrap();
+        assert_eq!(&cursor.prev().unwrap().unwrap().0[..], 998u64.to_be_bytes());
+        assert_eq!(&cursor.next().unwrap().unwrap().0[..], 998u64.to_be_bytes());
+        assert_eq!(&cursor.next().unwrap().unwrap().0[..], 999u64.to_be_bytes());
+        assert!(cursor.next().is_none());
+    }
+
+    #[test]
+    fn test_keys() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        assert!(!txn.contains_key(b"user/0001").unwrap());
+        for i in 0..3000u32 {
+            txn.put(format!("user/{i:04}").as_bytes(), &[0xab; 200]).unwrap();
+        }
+        txn.commit().unwrap();
+        let mut txn = env.begin_write();
+        for i in (0..3000u32).step_by(3) {
+            txn.delete(format!("user/{i:04}").as_bytes()).unwrap();
+        }
+
+        // deleted nodes are still on the pages of the write transaction
+        let entries: Vec<_> =
+            txn.tree().cursor().unwrap().map(|entry| entry.unwrap().0.into_owned()).collect();
+        let keys: Vec<_> =
+            txn.tree().cursor().unwrap().keys().map(|key| key.unwrap().into_owned()).collect();
+        assert_eq!(keys.len(), 2000);
+        assert_eq!(keys, entries);
+        let mut cursor = txn.tree().cursor().unwrap();
+        cursor.seek(b"user/2990").unwrap();
+        let keys: Vec<_> = cursor.keys().take(3).map(Result::unwrap).collect();
+        assert_eq!(keys, [&b"user/2990"[..], b"user/2992", b"user/2993"]);
+
+        assert!(txn.contains_key(b"user/0001").unwrap());
+        assert!(!txn.contains_key(b"user/0003").unwrap());
+        assert!(!txn.contains_key(b"user/3000").unwrap());
+        txn.commit().unwrap();
+        let txn = env.begin_read().unwrap();
+        assert!(txn.contains_key(b"user/2999").unwrap() && !txn.contains_key(b"user/").unwrap());
+    }
+
+    #[test]
+    fn test_positions() {
+        let dir = tempdir().unwrap();
+        let key = |i: u32| format!("key-{i:06}").into_bytes();
+        let env = Env::bulk_load(dir.path().join("db"), (0..20_000).map(|i| (key(i), key(i))))
+            .unwrap();
+        let txn = env.begin_read().unwrap();
+        let mut cursor = Cursor::new(txn.tree()).unwrap();
+        assert_eq!(cursor.position().unwrap(), 0);
+
+        for i in [1000, 7500, 15_000, 19_000] {
+            cursor.seek(&key(i)).unwrap();
+            assert_eq!(cursor.position().unwrap(), i as u64);
+            assert_eq!(cursor.remaining().unwrap(), 20_000 - i as u64);
+            cursor.seek_position(i as u64 + 1).unwrap();
+            assert_eq!(cursor.next().unwrap().unwrap().0, key(i + 1));
+        }
+        cursor.seek(b"z").unwrap();
+        assert!(cursor.next().is_none());
+        assert_eq!(cursor.remaining().unwrap(), 0);
+        drop(txn);
+
+        // skips land exactly, over deleted entries and split leaves too
+        let mut txn = env.begin_write();
+        for i in (0..20_000).step_by(3) {
+            txn.delete(&key(i)).unwrap();
+        }
+        let live: Vec<_> = (0..20_000).filter(|i| i % 3 != 0).map(key).collect();
+        let mut cursor = txn.tree().cursor().unwrap();
+        cursor.skip_entries(5000).unwrap();
+        assert_eq!(cursor.position().unwrap(), 5000);
+        assert_eq!(cursor.next().unwrap().unwrap().0, live[5000]);
+        cursor.skip_entries(3).unwrap();
+        assert_eq!(cursor.next().unwrap().unwrap().0, live[5004]);
+        let page = txn.tree().page(&live[100][..].., 4000, 2).unwrap();
+        assert_eq!(page.iter().map(|(key, _)| key.to_vec()).collect::<Vec<_>>(), live[4100..4102]);
+    }
+
+    #[test]
+    fn test_seek_to_nth() {
+        let dir = tempdir().unwrap();
+        let entries = (0..5000u32).map(|i| (i.to_be_bytes(), [1]));
+        let env = Env::bulk_load(dir.path().join("db"), entries).unwrap();
+        // scattered deletes and inserts leave dead nodes and split leaves
+        let mut txn = env.begin_write();
+        for i in (0..5000u32).step_by(3) {
+            txn.delete(&i.to_be_bytes()).unwrap();
+        }
+        for i in 5000..6000u32 {
+            txn.put(&((i * 7919) % 6000 + 10_000).to_be_bytes(), &[2]).unwrap();
+        }
+        let nth = |tree: BTree, n: u64| {
+            let mut cursor = tree.cursor().unwrap();
+            cursor.seek_to_nth(n).unwrap();
+            cursor.next().map(|entry| entry.unwrap().0.into_owned())
+        };
+        let keys: Vec<Vec<u8>> =
+            txn.tree().cursor().unwrap().map(|entry| entry.unwrap().0.into_owned()).collect();
+        assert_eq!(keys.len() as u64, txn.len());
+        // the transaction's own pages are counted as they are
+        for n in [0, 1, 1234, 3332, 3333, 4000, keys.len() - 1] {
+            assert_eq!(nth(txn.tree(), n as u64).as_ref(), Some(&keys[n]), "entry {n}");
+        }
+        assert_eq!(nth(txn.tree(), keys.len() as u64), None);
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        for n in (0..keys.len()).step_by(97) {
+            assert_eq!(nth(txn.tree(), n as u64).as_ref(), Some(&keys[n]), "entry {n}");
+        }
+        let mut cursor = txn.tree().cursor().unwrap();
+        cursor.seek_to_nth(keys.len() as u64 + 5).unwrap();
+        assert_eq!(cursor.prev().unwrap().unwrap().0, keys[keys.len() - 1]);
+    }
+}
@@ -1,22 +1,42 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::fmt;
 
-use crate::buf::{as_u16_slice, ByteBuf};
+use crate::buf::{read_varint_len, varint_len, write_varint_u64, ByteBuf, VarintError};
 use crate::constants::*;
-use crate::page::Page;
+use crate::key_order::KeyOrder;
+use crate::page::{Page, PageRef};
 
+// what reading a compressed value fails with in builds that can't decompress
+pub(crate) const NO_COMPRESSION: DBError = DBError::Unsupported {
+    reason: "compressed values need the compression feature",
+};
+
+// node offsets, `lower` and `upper` are stored as u16s, which the data area of
+// the largest page has to fit
+const _: () = assert!(MAX_PAGE_SIZE - PAGE_HEADER_SIZE <= u16::MAX as usize);
+
+#[derive(Clone, Copy)]
 pub struct DataPage<'a> {
     pgno: Pgno,
     flags: PageFlag,
     lower: u16,
     upper: u16,
-    offsets: &'a [u16],
+    offsets: Offsets<'a>,
+    prefix: &'a [u8],
     data: &'a [u8],
+    order: KeyOrder,
 }
 
+// `key` holds only the part of the key after the page's common `prefix`, and
+// `key_size` is its length as stored on the page. `fixed` nodes come from a
+// `PageFlag::FIXED_KEY` page, which leaves their key size out.
 pub struct DataNode<'a> {
     flags: NodeFlag,
+    fixed: bool,
     key_size: usize,
     data_size: usize,
+    prefix: &'a [u8],
     key: &'a [u8],
     data: &'a [u8],
 }
@@ -29,7 +49,9 @@ impl fmt::Debug for DataPage<'_> {
             .field("lower", &self.lower)
             .field("upper", &self.upper)
             .field("offsets", &self.offsets)
+            .field("prefix", &self.prefix)
             .field("data", &self.data)
+            .field("order", &self.order)
             .finish()
     }
 }
@@ -39,154 +61,651 @@ impl fmt::Debug for DataNode<'_> {
         f.debug_struct("DataNode")
             .field("flags", &self.flags)
             .field("key_size", &self.key_size)
-            .field("key", &String::from_utf8(self.key.to_vec()).unwrap())
+            .field("key", &String::from_utf8_lossy(&self.get_key()))
             .field("data_size", &self.data_size)
-            .field("data", &String::from_utf8(self.data.to_vec()).unwrap())
+            .field("data", &String::from_utf8_lossy(self.data))
             .finish()
     }
 }
 
 impl<'a> PartialEq for DataNode<'a> {
     fn eq(&self, other: &Self) -> bool {
-        self.key == other.key && self.data == other.data
+        self.get_key() == other.get_key() && self.data == other.data
     }
 }
 
 impl<'a> Eq for DataNode<'a> {}
 
+/// The longest value that can be stored under a `key_len`-byte key in pages
+/// of `page_size` bytes. A node, with its slot, never takes more than half a
+/// page, so a full page can always be split in two around a new one; values
+/// don't spill onto overflow pages.
+pub fn max_value_size(page_size: usize, key_len: usize) -> usize {
+    let half = (page_size - PAGE_HEADER_SIZE) / 2;
+    // the slot, the flags and both sizes, counting the data size at its widest
+    let overhead = U16_N + U16_N + varint_len(key_len as u64) + varint_len(half as u64);
+    half.saturating_sub(overhead + key_len)
+}
+
+// flags + both varint sizes (just the data size, for fixed keys) + key + data
+fn node_size(fixed: bool, key_size: usize, data_size: usize) -> usize {
+    let key_size_len = if fixed { 0 } else { varint_len(key_size as u64) };
+    U16_N + key_size_len + varint_len(data_size as u64) + key_size + data_size
+}
+
 impl<'a> DataNode<'a> {
-    fn from(key: &'a [u8], data: &'a [u8]) -> Self {
+    pub fn from(key: &'a [u8], data: &'a [u8]) -> Self {
         DataNode {
             flags: NodeFlag::ALIVE,
+            fixed: false,
             key_size: key.len(),
             data_size: data.len(),
+            prefix: &[],
             key,
             data,
         }
     }
 
+    /// Gives the node `flags` instead of just `ALIVE`, e.g. to carry a node
+    /// over from another page as it was.
+    pub const fn with_flags(mut self, flags: NodeFlag) -> Self {
+        self.flags = flags;
+        self
+    }
+
     pub fn pack(&self) -> Vec<u8> {
-        // flags (u16) + key_size (usize) + data_size (usize) + key + data
-        let mut buf = Vec::with_capacity(U16_N + USIZE_N * 2 + self.key.len() + self.data.len());
+        self.pack_without_prefix(0, false)
+    }
+
+    // Packs the node with the first `prefix_len` bytes of its full key left
+    // out, for storage on a page whose common prefix is that long:
+    // flags (u16) + key_size (varint) + data_size (varint) + key suffix + data,
+    // or for `fixed` keys, whose size is known, and which come first so
+    // searches find them without decoding anything:
+    // flags (u16) + key suffix + data_size (varint) + data
+    fn pack_without_prefix(&self, prefix_len: usize, fixed: bool) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.packed_size(prefix_len, fixed));
         buf.extend_from_slice(&self.flags.bits().to_le_bytes());
-        buf.extend_from_slice(&self.key_size.to_le_bytes());
-        buf.extend_from_slice(&self.data_size.to_le_bytes());
-        buf.extend_from_slice(self.key);
+        if !fixed {
+            write_varint_u64(&mut buf, (self.key_len() - prefix_len) as u64);
+            write_varint_u64(&mut buf, self.data_size as u64);
+        }
+        if prefix_len <= self.prefix.len() {
+            buf.extend_from_slice(&self.prefix[prefix_len..]);
+            buf.extend_from_slice(self.key);
+        } else {
+            buf.extend_from_slice(&self.key[prefix_len - self.prefix.len()..]);
+        }
+        if fixed {
+            write_varint_u64(&mut buf, self.data_size as u64);
+        }
         buf.extend_from_slice(self.data);
         buf
     }
 
-    fn get_size(&self) -> usize {
-        self.key_size + self.data_size + 2 * USIZE_N + 2
+    fn packed_size(&self, prefix_len: usize, fixed: bool) -> usize {
+        node_size(fixed, self.key_len() - prefix_len, self.data_size)
+    }
+
+    /// Size of the node as stored on its page.
+    pub fn get_size(&self) -> usize {
+        node_size(self.fixed, self.key_size, self.data_size)
+    }
+
+    pub const fn get_flags(&self) -> NodeFlag {
+        self.flags
+    }
+
+    /// Soft-deleted nodes stay on the page with `ALIVE` cleared.
+    pub const fn is_alive(&self) -> bool {
+        self.flags.contains(NodeFlag::ALIVE)
+    }
+
+    pub fn key_len(&self) -> usize {
+        self.prefix.len() + self.key.len()
+    }
+
+    /// The full key; only allocates when the page stores a common prefix.
+    pub fn get_key(&self) -> Cow<'a, [u8]> {
+        if self.prefix.is_empty() {
+            Cow::Borrowed(self.key)
+        } else {
+            Cow::Owned([self.prefix, self.key].concat())
+        }
+    }
+
+    pub const fn get_key_suffix(&self) -> &'a [u8] {
+        self.key
+    }
+
+    /// Compares the node's full key against `key` without reassembling it.
+    pub fn cmp_key(&self, key: &[u8]) -> Ordering {
+        cmp_split((self.prefix, self.key), key)
+    }
+
+    /// Compares like `KeyOrder::BytesAfter(header)`, without reassembling the
+    /// node's key.
+    pub fn cmp_key_after(&self, key: &[u8], header: usize) -> Ordering {
+        cmp_split_after((self.prefix, self.key), key, header)
+    }
+
+    fn starts_with(&self, prefix: &[u8]) -> bool {
+        if prefix.len() <= self.prefix.len() {
+            self.prefix.starts_with(prefix)
+        } else {
+            let (head, tail) = prefix.split_at(self.prefix.len());
+            head == self.prefix && self.key.starts_with(tail)
+        }
+    }
+
+    /// The data as stored, which for a compressed node `BTree::get_value`
+    /// decompresses.
+    pub const fn get_data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub const fn is_compressed(&self) -> bool {
+        self.flags.contains(NodeFlag::COMPRESSED)
+    }
+
+    /// Size of the value as it was written: the data size, or for a
+    /// compressed node the size it decompresses to, which its data starts
+    /// with.
+    pub fn get_value_size(&self) -> Result<usize, DBError> {
+        if !self.is_compressed() {
+            return Ok(self.data_size);
+        }
+        let (size, _) = read_varint_len(self.data, usize::MAX)
+            .map_err(|err| DBError::CorruptValue { reason: err.reason() })?;
+        Ok(size)
+    }
+
+    /// The value as it was written, decompressed into a copy if need be; see
+    /// `BTree::get_value` for one that borrows.
+    pub fn read_value(&self) -> Result<Vec<u8>, DBError> {
+        if !self.is_compressed() {
+            return Ok(self.data.to_vec());
+        }
+        #[cfg(feature = "compression")]
+        return crate::compress::decompress_value(self.data);
+        #[cfg(not(feature = "compression"))]
+        Err(NO_COMPRESSION)
+    }
+}
+
+// a key stored in two parts, as a page's common prefix and a node's suffix
+type SplitKey<'a> = (&'a [u8], &'a [u8]);
+
+// compares the concatenation of `split` against `key`
+fn cmp_split((head, tail): SplitKey, key: &[u8]) -> Ordering {
+    let shared = head.len().min(key.len());
+    match head[..shared].cmp(&key[..shared]) {
+        Ordering::Equal if key.len() < head.len() => Ordering::Greater,
+        Ordering::Equal => tail.cmp(&key[head.len()..]),
+        ordering => ordering,
+    }
+}
+
+// compares the concatenation of `split` against `key` in
+// `KeyOrder::BytesAfter(header)` order
+fn cmp_split_after(split: SplitKey, key: &[u8], header: usize) -> Ordering {
+    let (split_header, split_rest) = split_at(split, header);
+    let (key_header, key_rest) = key.split_at(header.min(key.len()));
+    cmp_split(split_rest, key_rest).then_with(|| cmp_split(split_header, key_header))
+}
+
+// splits the concatenation of `split` after `at` bytes (or at its end)
+fn split_at<'a>((head, tail): SplitKey<'a>, at: usize) -> (SplitKey<'a>, SplitKey<'a>) {
+    if at <= head.len() {
+        ((&head[..at], &[]), (&head[at..], tail))
+    } else {
+        let (first, rest) = tail.split_at((at - head.len()).min(tail.len()));
+        ((head, first), (&[], rest))
     }
 }
 
 impl<'a> DataPage<'a> {
-    pub fn from(page: &'a Page) -> Result<Self, DBError> {
+    pub fn from(page: impl Into<PageRef<'a>>) -> Result<Self, DBError> {
+        let page = page.into();
+        let pgno = page.get_pgno();
+        let lower = page.get_lower();
+        let upper = page.get_upper();
+        let buf_size = page.get_data().len();
+        if upper as usize > buf_size {
+            return Err(DBError::CorruptPage { pgno, reason: "upper exceeds page size" });
+        }
+        if lower > upper {
+            return Err(DBError::CorruptPage { pgno, reason: "lower exceeds upper" });
+        }
+        if !(lower as usize).is_multiple_of(U16_N) {
+            return Err(DBError::CorruptPage { pgno, reason: "misaligned offset array" });
+        }
+        // the page's common key prefix sits at the very top of the data area,
+        // with its length in the header's pad field
+        let prefix_start = buf_size
+            .checked_sub(page.get_pad() as usize)
+            .filter(|&start| start >= upper as usize)
+            .ok_or(DBError::CorruptPage { pgno, reason: "key prefix overlaps nodes" })?;
+
         let leaf_page = DataPage {
-            pgno: page.get_pgno(),
+            pgno,
             flags: page.get_flag(),
-            lower: page.get_lower(),
-            upper: page.get_upper(),
-            offsets: Self::get_node_offset(page.get_data(), page.get_lower()),
+            lower,
+            upper,
+            offsets: Offsets { bytes: &page.get_data()[..lower as usize] },
+            prefix: &page.get_data()[prefix_start..],
             data: page.get_data(),
+            order: KeyOrder::Bytes,
         };
 
         Ok(leaf_page)
     }
 
-    fn get_node_offset(data: &[u8], lower: u16) -> &[u16] {
-        let offsets_end = lower as usize;
-        as_u16_slice(&data[..offsets_end])
+    /// Orders keys by `order` instead of byte order; it must match the order
+    /// the page was written in.
+    pub const fn with_order(mut self, order: KeyOrder) -> Self {
+        self.order = order;
+        self
     }
 
-    pub fn read_node_from_offset(&self, offset: usize) -> DataNode<'_> {
-        let flags = NodeFlag::from_bits(self.data.read_u16_le(offset).unwrap())
-            .expect("Unrecognized node flags");
-        let key_size = self.data.read_usize_le(offset + U16_N).unwrap();
-        let data_size = self.data.read_usize_le(offset + U16_N + USIZE_N).unwrap();
-        let key_start = offset + U16_N + USIZE_N * 2;
-        let key = self.data.read_n_bytes(key_start, key_size).unwrap();
-        let data = self
-            .data
-            .read_n_bytes(key_start + key_size, data_size)
-            .unwrap();
+    pub const fn get_order(&self) -> KeyOrder {
+        self.order
+    }
 
-        DataNode {
+    // compares a key stored as the page prefix and `suffix`; the byte-based
+    // orders compare against it in place, other orders need the full key
+    // reassembled
+    fn cmp_stored_key(&self, suffix: &[u8], key: &[u8]) -> Ordering {
+        let split = (self.prefix, suffix);
+        match self.order {
+            KeyOrder::Bytes => cmp_split(split, key),
+            KeyOrder::BytesReversed => cmp_split(split, key).reverse(),
+            KeyOrder::BytesAfter(header) => cmp_split_after(split, key, header),
+            order => order.compare(&[self.prefix, suffix].concat(), key),
+        }
+    }
+
+    /// Like `from`, but also walks every node, failing on the first one that
+    /// doesn't parse, is out of key order or overlaps another node. Meant for
+    /// paranoid reads, where catching corruption early is worth the CPU cost.
+    pub fn from_validated(page: impl Into<PageRef<'a>>) -> Result<Self, DBError> {
+        let data_page = Self::from(page)?;
+        data_page.validate()?;
+        Ok(data_page)
+    }
+
+    pub fn validate(&self) -> Result<(), DBError> {
+        let mut extents = Vec::with_capacity(self.num_nodes());
+        let mut prev: Option<DataNode> = None;
+        for (idx, offset) in self.offsets.iter().enumerate() {
+            let node = self.read_node_from_offset(offset as usize)?;
+            // every node shares the page prefix, so in byte order suffixes
+            // order the same way as the full keys
+            let in_order = match (&prev, self.order) {
+                (None, _) => true,
+                _ if idx == self.first_searchable() => true,
+                (Some(prev), KeyOrder::Bytes) => prev.get_key_suffix() < node.get_key_suffix(),
+                (Some(prev), order) => order.compare(&prev.get_key(), &node.get_key()).is_lt(),
+            };
+            if !in_order {
+                return Err(self.corrupt("keys are not in order"));
+            }
+            extents.push((offset as usize, offset as usize + node.get_size()));
+            prev = Some(node);
+        }
+
+        extents.sort_unstable();
+        if extents.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+            return Err(self.corrupt("nodes overlap"));
+        }
+        Ok(())
+    }
+
+    // offset of the node at `idx`, which must be in range
+    fn offset(&self, idx: usize) -> usize {
+        let offset = self.offsets.get(idx);
+        offset.unwrap_or_else(|| panic!("node {idx} is past the end of the page")) as usize
+    }
+
+    fn corrupt(&self, reason: &'static str) -> DBError {
+        DBError::CorruptPage {
+            pgno: self.pgno,
+            reason,
+        }
+    }
+
+    pub fn read_node_from_offset(&self, offset: usize) -> Result<DataNode<'a>, DBError> {
+        if offset < self.upper as usize {
+            return Err(self.corrupt("node offset points into free space"));
+        }
+        let nodes = &self.data[..self.data.len() - self.prefix.len()];
+        let flags = nodes
+            .read_u16_le(offset)
+            .ok_or_else(|| self.corrupt("truncated node flags"))?;
+        let flags =
+            NodeFlag::from_bits(flags).ok_or_else(|| self.corrupt("unrecognized node flags"))?;
+        // no size can be larger than the page, which keeps the offsets below
+        // from overflowing
+        let read_size = |pos: usize, truncated: &'static str| {
+            let (size, len) = read_varint_len(nodes.get(pos..).unwrap_or_default(), nodes.len())
+                .map_err(|err| match err {
+                    VarintError::Truncated => self.corrupt(truncated),
+                    err => self.corrupt(err.reason()),
+                })?;
+            Ok::<_, DBError>((size, pos + len))
+        };
+        let fixed = self.has_fixed_keys();
+        let (key_size, data_size, key_start, data_start) = if fixed {
+            let key_size = self.fixed_key_suffix_len()?;
+            let key_start = offset + U16_N;
+            let (data_size, data_start) =
+                read_size(key_start + key_size, "truncated data size")?;
+            (key_size, data_size, key_start, Some(data_start))
+        } else {
+            let (key_size, pos) = read_size(offset + U16_N, "truncated key size")?;
+            let (data_size, key_start) = read_size(pos, "truncated data size")?;
+            (key_size, data_size, key_start, key_start.checked_add(key_size))
+        };
+        let key = nodes
+            .read_n_bytes(key_start, key_size)
+            .ok_or_else(|| self.corrupt("key extends past end of page"))?;
+        let data = data_start
+            .and_then(|data_start| nodes.read_n_bytes(data_start, data_size))
+            .ok_or_else(|| self.corrupt("data extends past end of page"))?;
+
+        Ok(DataNode {
             flags,
+            fixed,
             key_size,
             data_size,
+            prefix: self.prefix,
             key,
             data,
+        })
+    }
+
+    // how much of each fixed-size key the nodes store, past the prefix
+    fn fixed_key_suffix_len(&self) -> Result<usize, DBError> {
+        FIXED_KEY_SIZE
+            .checked_sub(self.prefix.len())
+            .ok_or_else(|| self.corrupt("key prefix longer than fixed-size keys"))
+    }
+
+    /// The stored key suffix of the node at `offset`, as
+    /// `read_node_from_offset(offset)?.get_key_suffix()` but reading only the
+    /// sizes in front of it: the flags aren't checked and the data isn't
+    /// sliced, which is all a search comparing keys needs.
+    pub fn read_key_at_offset(&self, offset: usize) -> Result<&'a [u8], DBError> {
+        if offset < self.upper as usize {
+            return Err(self.corrupt("node offset points into free space"));
         }
+        let nodes = &self.data[..self.data.len() - self.prefix.len()];
+        let (key_size, key_start) = if self.has_fixed_keys() {
+            // fixed keys come before anything that would need decoding
+            (self.fixed_key_suffix_len()?, offset + U16_N)
+        } else {
+            let read_size = |pos: usize, truncated: &'static str| {
+                let slice = nodes.get(pos..).unwrap_or_default();
+                let (size, len) = read_varint_len(slice, nodes.len()).map_err(|err| match err {
+                    VarintError::Truncated => self.corrupt(truncated),
+                    err => self.corrupt(err.reason()),
+                })?;
+                Ok::<_, DBError>((size, pos + len))
+            };
+            let (key_size, pos) = read_size(offset + U16_N, "truncated key size")?;
+            let (_, key_start) = read_size(pos, "truncated data size")?;
+            (key_size, key_start)
+        };
+        nodes
+            .read_n_bytes(key_start, key_size)
+            .ok_or_else(|| self.corrupt("key extends past end of page"))
+    }
+
+    // compares the key of the node at `idx`, which must be in range
+    fn cmp_key_at(&self, idx: usize, key: &[u8]) -> Result<Ordering, DBError> {
+        let suffix = self.read_key_at_offset(self.offset(idx))?;
+        Ok(self.cmp_stored_key(suffix, key))
+    }
+
+    pub const fn get_pgno(&self) -> Pgno {
+        self.pgno
+    }
+
+    pub const fn get_flags(&self) -> PageFlag {
+        self.flags
+    }
+
+    /// Whether every key on the page is `FIXED_KEY_SIZE` bytes, and its
+    /// nodes are laid out for it; see `EnvOptions::fixed_keys`.
+    pub const fn has_fixed_keys(&self) -> bool {
+        self.flags.contains(PageFlag::FIXED_KEY)
+    }
+
+    pub const fn get_lower(&self) -> u16 {
+        self.lower
     }
 
-    pub fn get_node(&self, key: &[u8]) -> Result<DataNode<'_>, DBError> {
-        let offset_idx_or_error = match self.offsets.binary_search_by(|offset| {
-            let node = self.read_node_from_offset(*offset as usize);
-            node.key.cmp(key)
-        }) {
-            Ok(idx) => Ok(idx),
+    pub const fn get_upper(&self) -> u16 {
+        self.upper
+    }
+
+    pub const fn get_offsets(&self) -> Offsets<'a> {
+        self.offsets
+    }
+
+    /// Key prefix shared by every node on the page.
+    pub const fn get_prefix(&self) -> &'a [u8] {
+        self.prefix
+    }
+
+    pub fn get_page_size(&self) -> usize {
+        PAGE_HEADER_SIZE + self.data.len()
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn read_node(&self, idx: usize) -> Result<DataNode<'a>, DBError> {
+        let offset = self.offsets.get(idx).ok_or(DBError::KeyNotFound)?;
+        self.read_node_from_offset(offset as usize)
+    }
+
+    /// Whether the node at `idx` isn't soft-deleted, from its flags alone.
+    pub fn is_alive_at(&self, idx: usize) -> Result<bool, DBError> {
+        let offset = self.offsets.get(idx).ok_or(DBError::KeyNotFound)? as usize;
+        if offset < self.upper as usize {
+            return Err(self.corrupt("node offset points into free space"));
+        }
+        let flags = self.data.read_u16_le(offset).and_then(NodeFlag::from_bits);
+        let flags = flags.ok_or_else(|| self.corrupt("unrecognized node flags"))?;
+        Ok(flags.contains(NodeFlag::ALIVE))
+    }
+
+    /// The full key of the node at `idx`, or `None` if it is soft-deleted,
+    /// read without touching its data.
+    pub fn read_live_key(&self, idx: usize) -> Result<Option<Cow<'a, [u8]>>, DBError> {
+        if !self.is_alive_at(idx)? {
+            return Ok(None);
+        }
+        let suffix = self.read_key_at_offset(self.offset(idx))?;
+        Ok(Some(match self.prefix.is_empty() {
+            true => Cow::Borrowed(suffix),
+            false => Cow::Owned([self.prefix, suffix].concat()),
+        }))
+    }
+
+    /// Every node in key order, including soft-deleted ones.
+    pub fn read_nodes(&self) -> Result<Vec<DataNode<'a>>, DBError> {
+        self.offsets
+            .iter()
+            .map(|offset| self.read_node_from_offset(offset as usize))
+            .collect()
+    }
+
+    /// The first node whose key takes part in searches. A branch page's first
+    /// node stands for every key below the second node's, so its key is left
+    /// empty and skipped.
+    pub fn first_searchable(&self) -> usize {
+        if self.flags.contains(PageFlag::BRANCH) {
+            self.offsets.len().min(1)
+        } else {
+            0
+        }
+    }
+
+    /// Same contract as `slice::binary_search`: `Ok(idx)` of the matching
+    /// slot, or `Err(idx)` of the slot the key would be inserted at.
+    // Reading a node can fail on a corrupt page, so slice::binary_search_by
+    // can't be used here.
+    pub fn search(&self, key: &[u8]) -> Result<Result<usize, usize>, DBError> {
+        let mut lo = self.first_searchable();
+        let mut hi = self.offsets.len();
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.cmp_key_at(mid, key)? {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Ok(Ok(mid)),
+            }
+        }
+
+        Ok(Err(lo))
+    }
+
+    /// Returns the node stored under `key`, including a soft-deleted one.
+    pub fn get_node(&self, key: &[u8]) -> Result<DataNode<'a>, DBError> {
+        match self.search(key)? {
+            Ok(idx) => self.read_node_from_offset(self.offset(idx)),
             Err(_idx) => Err(DBError::KeyNotFound),
-        };
+        }
+    }
 
-        offset_idx_or_error.map(|idx| {
-            let offset = self.offsets[idx] as usize;
-            self.read_node_from_offset(offset)
-        })
+    pub fn get(&self, key: &[u8]) -> Result<&'a [u8], DBError> {
+        match self.get_node(key)? {
+            node if node.is_alive() => Ok(node.data),
+            _ => Err(DBError::KeyNotFound),
+        }
+    }
+
+    /// Iterates over the page's live nodes in key order.
+    pub fn nodes(&self) -> DataNodes<'a> {
+        DataNodes {
+            page: *self,
+            idx: 0,
+            include_deleted: false,
+        }
     }
 
-    pub fn get(&self, key: &[u8]) -> Result<&[u8], DBError> {
-        let maybe_node = self.get_node(key);
-        maybe_node.map(|res| res.data)
+    /// How much of the page its nodes would fill once compacted, from 0 to 1;
+    /// unlike the free gap, this doesn't count bytes left behind by updates.
+    pub fn fill(&self) -> Result<f64, DBError> {
+        let nodes = self.read_nodes()?;
+        Ok(Self::packed_page_size(&nodes, self.flags) as f64 / self.data.len() as f64)
     }
 
     pub fn has_space(&self, new_node: DataNode) -> bool {
         let remaining_space = (self.upper - self.lower) as usize;
-        remaining_space > new_node.get_size()
+        remaining_space > new_node.packed_size(0, self.has_fixed_keys())
     }
 
+    /// Copies the page with `key` set to `data`, replacing any value it
+    /// already has; see `put_with_flags`.
     pub fn put(&self, new_pgno: Pgno, key: &[u8], data: &[u8]) -> Result<Page, DBError> {
-        let mut nodes: Vec<DataNode> = self
-            .offsets
-            .iter()
-            .map(|&offset| self.read_node_from_offset(offset as usize))
-            .collect();
-        match nodes.binary_search_by(|n| n.key.cmp(key)) {
-            Ok(idx) => {
-                // upsert
-                nodes[idx] = DataNode::from(key, data);
-            }
-            Err(idx) => {
-                // insert
-                nodes.insert(idx, DataNode::from(key, data));
-            }
-        }
+        self.put_with_flags(new_pgno, key, data, PutFlag::OVERWRITE)
+    }
 
-        Ok(Self::write_new_page(new_pgno, &nodes))
+    /// Like `put`, but fails with `KeyExists` where `put_flags` rule the put
+    /// out. `APPEND` only looks at this page, so it takes the page to be the
+    /// last one.
+    pub fn put_with_flags(
+        &self,
+        new_pgno: Pgno,
+        key: &[u8],
+        data: &[u8],
+        put_flags: PutFlag,
+    ) -> Result<Page, DBError> {
+        // search before copying so corruption is reported against this page
+        let slot = self.search(key)?;
+        check_put(slot, self.num_nodes(), put_flags)?;
+        let mut dirty = DirtyPage::from(self, new_pgno);
+        dirty.put_at(slot, DataNode::from(key, data))?;
+        Ok(dirty.into_page())
     }
 
     pub fn split(&self, pgno_left: Pgno, pgno_right: Pgno) -> Result<(Page, Page), DBError> {
-        let nodes: Vec<DataNode> = self
-            .offsets
-            .iter()
-            .map(|&offset| self.read_node_from_offset(offset as usize))
-            .collect();
+        let nodes = self.read_nodes()?;
         let mid = nodes.len() / 2;
         let (left, right) = nodes.split_at(mid);
 
-        let left_page = Self::write_new_page(pgno_left, left);
-        let right_page = Self::write_new_page(pgno_right, right);
+        let left_page = Self::write_new_page(pgno_left, self.get_page_size(), self.flags, left);
+        let right_page = Self::write_new_page(pgno_right, self.get_page_size(), self.flags, right);
 
         Ok((left_page, right_page))
     }
 
-    fn write_new_page(pgno: Pgno, nodes: &[DataNode]) -> Page {
-        let mut page_data_buf = [0u8; PAGE_BUF_SIZE];
+    // under byte order this is just the prefix of the first and last keys, but
+    // other orders can put any key first, so every key is considered
+    fn common_prefix_len(nodes: &[DataNode]) -> usize {
+        let Some(first) = nodes.first().filter(|_| nodes.len() > 1) else {
+            return 0;
+        };
+        let first = first.get_key();
+        nodes[1..].iter().fold(first.len(), |len, node| {
+            let key = node.get_key();
+            first[..len].iter().zip(key.iter()).take_while(|(a, b)| a == b).count()
+        })
+    }
+
+    /// The index that splits `nodes` into two runs of about the same packed
+    /// size, each holding at least one node.
+    pub fn split_point(nodes: &[DataNode]) -> usize {
+        // fixed keys would save the same byte on every node, which doesn't
+        // move the balance
+        let sizes: Vec<usize> = nodes.iter().map(|n| n.packed_size(0, false) + U16_N).collect();
+        let total: usize = sizes.iter().sum();
+        let mut left_size = 0;
+        let mut best = (usize::MAX, 1);
+        for (mid, size) in sizes.iter().enumerate().take(nodes.len().saturating_sub(1)) {
+            left_size += size;
+            best = best.min((left_size.max(total - left_size), mid + 1));
+        }
+        best.1
+    }
+
+    fn packed_page_size(nodes: &[DataNode], flags: PageFlag) -> usize {
+        let prefix_len = Self::common_prefix_len(nodes);
+        let fixed = flags.contains(PageFlag::FIXED_KEY);
+        let nodes_size: usize =
+            nodes.iter().map(|n| n.packed_size(prefix_len, fixed) + U16_N).sum();
+        prefix_len + nodes_size
+    }
+
+    /// An empty data page of `page_size` bytes.
+    pub fn new_page(pgno: Pgno, page_size: usize) -> Page {
+        Self::new_page_with_flags(pgno, page_size, PageFlag::ALIVE)
+    }
+
+    pub fn new_page_with_flags(pgno: Pgno, page_size: usize, flags: PageFlag) -> Page {
+        Self::write_new_page(pgno, page_size, flags, &[])
+    }
+
+    fn write_new_page(pgno: Pgno, page_size: usize, flags: PageFlag, nodes: &[DataNode]) -> Page {
+        let mut page_data_buf = vec![0u8; page_size - PAGE_HEADER_SIZE];
+        // callers check the fit first; past it, the offsets below would wrap
+        assert!(
+            Self::packed_page_size(nodes, flags) <= page_data_buf.len(),
+            "nodes don't fit in a page"
+        );
+        let prefix_len = Self::common_prefix_len(nodes);
+        let fixed = flags.contains(PageFlag::FIXED_KEY);
         let mut lower = 0;
-        let mut upper = PAGE_BUF_SIZE;
+        let mut upper = page_data_buf.len() - prefix_len;
+        if let Some(first) = nodes.first() {
+            page_data_buf[upper..].copy_from_slice(&first.get_key()[..prefix_len]);
+        }
         for node in nodes.iter() {
-            let node_bytes = node.pack();
+            let node_bytes = node.pack_without_prefix(prefix_len, fixed);
             page_data_buf[upper - node_bytes.len()..upper].copy_from_slice(&node_bytes);
             upper -= node_bytes.len();
 
@@ -197,25 +716,425 @@ impl<'a> DataPage<'a> {
 
         Page::from(
             pgno,
-            0x0,
-            PageFlag::ALIVE,
+            prefix_len as u16,
+            flags,
             lower as u16,
             upper as u16,
-            page_data_buf,
+            &page_data_buf,
         )
     }
 }
 
+/// A page's node offset array. The offsets are little-endian u16s, read a
+/// byte at a time, so the page parses the same whatever the host's byte order
+/// and however its bytes happen to be aligned.
+#[derive(Clone, Copy)]
+pub struct Offsets<'a> {
+    bytes: &'a [u8],
+}
+
+impl fmt::Debug for Offsets<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'a> Offsets<'a> {
+    pub const fn len(&self) -> usize {
+        self.bytes.len() / U16_N
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, idx: usize) -> Option<u16> {
+        self.bytes.read_u16_le(idx.checked_mul(U16_N)?)
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = u16> + 'a {
+        let bytes = self.bytes;
+        bytes.chunks_exact(U16_N).map(|offset| u16::from_le_bytes([offset[0], offset[1]]))
+    }
+}
+
+// fixed-key pages have no room for a key of any other size
+fn check_key_size(fixed: bool, node: &DataNode) -> Result<(), DBError> {
+    if fixed && node.key_len() != FIXED_KEY_SIZE {
+        return Err(DBError::KeySizeMismatch {
+            size: node.key_len(),
+            expected: FIXED_KEY_SIZE,
+        });
+    }
+    Ok(())
+}
+
+// whether a put that lands in `slot` of a page holding `num_nodes` nodes may
+// go ahead
+fn check_put(
+    slot: Result<usize, usize>,
+    num_nodes: usize,
+    put_flags: PutFlag,
+) -> Result<(), DBError> {
+    if slot.is_ok() && put_flags.contains(PutFlag::NO_OVERWRITE) {
+        return Err(DBError::KeyExists);
+    }
+    if slot != Err(num_nodes) && put_flags.contains(PutFlag::APPEND) {
+        return Err(DBError::KeyExists);
+    }
+    Ok(())
+}
+
+pub struct DataNodes<'a> {
+    page: DataPage<'a>,
+    idx: usize,
+    include_deleted: bool,
+}
+
+impl DataNodes<'_> {
+    /// Also yield soft-deleted nodes.
+    pub fn include_deleted(mut self) -> Self {
+        self.include_deleted = true;
+        self
+    }
+}
+
+impl<'a> Iterator for DataNodes<'a> {
+    type Item = Result<DataNode<'a>, DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.idx < self.page.num_nodes() {
+            let node = self.page.read_node(self.idx);
+            self.idx += 1;
+            match node {
+                Ok(node) if !node.is_alive() && !self.include_deleted => continue,
+                node => return Some(node),
+            }
+        }
+        None
+    }
+}
+
+/// Where a full page is split when a node is put into it.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SplitBias {
+    /// Into two halves of about the same size, leaving room on both sides
+    /// for keys that arrive in any order.
+    #[default]
+    Middle,
+    /// Just before a node put after the page's last one, leaving the page
+    /// full and the new node alone on the next, so keys put in increasing
+    /// order fill every page. A node put anywhere else splits in the middle.
+    Right,
+}
+
+/// A privately owned copy of a page that is modified in place. Inserts write
+/// the new node into the free gap between `lower` and `upper` and shift the
+/// offset array; the page is only compacted once the gap is too small.
+#[derive(Clone)]
+pub struct DirtyPage {
+    page: Page,
+    order: KeyOrder,
+}
+
+impl DirtyPage {
+    /// An empty page with `flags`, ordering keys by `order`.
+    pub fn new(pgno: Pgno, page_size: usize, flags: PageFlag, order: KeyOrder) -> Self {
+        DirtyPage {
+            page: DataPage::write_new_page(pgno, page_size, flags | PageFlag::DIRTY, &[]),
+            order,
+        }
+    }
+
+    /// Copies `page`, keeping its key order.
+    pub fn from(page: &DataPage, new_pgno: Pgno) -> Self {
+        DirtyPage {
+            page: Page::from(
+                new_pgno,
+                page.prefix.len() as u16,
+                page.flags | PageFlag::DIRTY,
+                page.lower,
+                page.upper,
+                page.data,
+            ),
+            order: page.order,
+        }
+    }
+
+    /// A page holding `nodes`, which must be in key order; fails with
+    /// `PageFull` if they don't fit.
+    pub fn from_nodes(
+        pgno: Pgno,
+        page_size: usize,
+        flags: PageFlag,
+        order: KeyOrder,
+        nodes: &[DataNode],
+    ) -> Result<Self, DBError> {
+        if DataPage::packed_page_size(nodes, flags) > page_size - PAGE_HEADER_SIZE {
+            return Err(DBError::PageFull);
+        }
+        Ok(DirtyPage {
+            page: DataPage::write_new_page(pgno, page_size, flags | PageFlag::DIRTY, nodes),
+            order,
+        })
+    }
+
+    pub fn get_pgno(&self) -> Pgno {
+        self.page.get_pgno()
+    }
+
+    pub fn as_data_page(&self) -> Result<DataPage<'_>, DBError> {
+        Ok(DataPage::from(&self.page)?.with_order(self.order))
+    }
+
+    /// The finished page, no longer flagged dirty.
+    pub fn into_page(mut self) -> Page {
+        self.page.set_flag(self.page.get_flag() - PageFlag::DIRTY);
+        self.page.update_checksum();
+        self.page
+    }
+
+    pub fn put(&mut self, key: &[u8], data: &[u8]) -> Result<(), DBError> {
+        self.put_with_flags(key, data, PutFlag::OVERWRITE)
+    }
+
+    /// See `DataPage::put_with_flags`.
+    pub fn put_with_flags(
+        &mut self,
+        key: &[u8],
+        data: &[u8],
+        put_flags: PutFlag,
+    ) -> Result<(), DBError> {
+        self.put_node_with_flags(DataNode::from(key, data), put_flags)
+    }
+
+    /// Like `put_with_flags`, but stores `node` with its own flags, e.g.
+    /// `NodeFlag::COMPRESSED`.
+    pub fn put_node_with_flags(
+        &mut self,
+        node: DataNode,
+        put_flags: PutFlag,
+    ) -> Result<(), DBError> {
+        let view = self.as_data_page()?;
+        let slot = view.search(&node.get_key())?;
+        check_put(slot, view.num_nodes(), put_flags)?;
+        self.put_at(slot, node)
+    }
+
+    /// Hides `key` from reads by clearing its node's `ALIVE` flag, leaving the
+    /// node in place so it can be restored with `undelete`.
+    pub fn soft_delete(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.set_alive(key, false)
+    }
+
+    pub fn undelete(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.set_alive(key, true)
+    }
+
+    /// Takes `key`'s node off the page for good. Only its slot is removed;
+    /// the node's bytes are garbage until the next compaction.
+    pub fn remove(&mut self, key: &[u8]) -> Result<(), DBError> {
+        let idx = self.as_data_page()?.search(key)?.map_err(|_| DBError::KeyNotFound)?;
+        self.remove_at(idx);
+        Ok(())
+    }
+
+    pub fn remove_at(&mut self, idx: usize) {
+        let lower = self.page.get_lower() as usize;
+        let slot_start = idx * U16_N;
+        assert!(slot_start < lower, "node {idx} is past the end of the page");
+        self.page.get_data_mut().copy_within(slot_start + U16_N..lower, slot_start);
+        self.page.set_lower((lower - U16_N) as u16);
+    }
+
+    /// Replaces the data of the node at `idx`, keeping its key.
+    pub fn replace_data(&mut self, idx: usize, data: &[u8]) -> Result<(), DBError> {
+        let key = self.as_data_page()?.read_node(idx)?.get_key().into_owned();
+        self.put_at(Ok(idx), DataNode::from(&key, data))
+    }
+
+    fn set_alive(&mut self, key: &[u8], alive: bool) -> Result<(), DBError> {
+        let view = self.as_data_page()?;
+        let idx = view.search(key)?.map_err(|_| DBError::KeyNotFound)?;
+        let offset = view.offset(idx);
+        let mut flags = view.read_node_from_offset(offset)?.flags;
+        // deleting a key that is already deleted is as missing as any other
+        if !alive && !flags.contains(NodeFlag::ALIVE) {
+            return Err(DBError::KeyNotFound);
+        }
+        flags.set(NodeFlag::ALIVE, alive);
+        self.page.get_data_mut()[offset..offset + U16_N]
+            .copy_from_slice(&flags.bits().to_le_bytes());
+        Ok(())
+    }
+
+    fn put_at(&mut self, slot: Result<usize, usize>, node: DataNode) -> Result<(), DBError> {
+        let (idx, upsert) = match slot {
+            Ok(idx) => (idx, true),
+            Err(idx) => (idx, false),
+        };
+
+        let fixed = self.page.get_flag().contains(PageFlag::FIXED_KEY);
+        check_key_size(fixed, &node)?;
+        let mut lower = self.page.get_lower() as usize;
+        let upper = self.page.get_upper() as usize;
+        let prefix_len = self.page.get_pad() as usize;
+        let data = self.page.get_data();
+        let prefix = &data[data.len() - prefix_len..];
+        // a key outside the page's prefix means the prefix has to shrink,
+        // which rewrites every node
+        if !node.starts_with(prefix) {
+            return self.compact_with(idx, upsert, node);
+        }
+        // an upsert that doesn't change the node's size overwrites it in place
+        if upsert {
+            let view = self.as_data_page()?;
+            let offset = view.offset(idx);
+            let size = node.packed_size(prefix_len, fixed);
+            if view.read_node_from_offset(offset)?.get_size() == size {
+                let node_bytes = node.pack_without_prefix(prefix_len, fixed);
+                self.page.get_data_mut()[offset..offset + node_bytes.len()]
+                    .copy_from_slice(&node_bytes);
+                return Ok(());
+            }
+        }
+        let needed = node.packed_size(prefix_len, fixed) + if upsert { 0 } else { U16_N };
+        if upper - lower < needed {
+            return self.compact_with(idx, upsert, node);
+        }
+
+        // an upserted node's old bytes are left behind as garbage until the
+        // next compaction
+        let node_bytes = node.pack_without_prefix(prefix_len, fixed);
+        let new_upper = upper - node_bytes.len();
+        let buf = self.page.get_data_mut();
+        buf[new_upper..upper].copy_from_slice(&node_bytes);
+
+        let slot_start = idx * U16_N;
+        if !upsert {
+            buf.copy_within(slot_start..lower, slot_start + U16_N);
+            lower += U16_N;
+        }
+        buf[slot_start..slot_start + U16_N].copy_from_slice(&(new_upper as u16).to_le_bytes());
+
+        self.page.set_lower(lower as u16);
+        self.page.set_upper(new_upper as u16);
+        Ok(())
+    }
+
+    /// Puts `node` after every node on the page without searching for its
+    /// place; its key must sort after all of theirs.
+    pub fn append_node(&mut self, node: DataNode) -> Result<(), DBError> {
+        let num_nodes = self.as_data_page()?.num_nodes();
+        self.put_at(Err(num_nodes), node)
+    }
+
+    /// Puts `key` into a copy of the page split in two, for when it no longer
+    /// fits: the left half keeps this page's number and the right half gets
+    /// `pgno_right`. The split point balances the halves by size rather than
+    /// by count, so a large node still finds room next to small ones. Also
+    /// returns the right half's first key, which a branch page's right half
+    /// doesn't store (see `DataPage::first_searchable`).
+    pub fn split_insert(
+        &self,
+        pgno_right: Pgno,
+        key: &[u8],
+        data: &[u8],
+    ) -> Result<(DirtyPage, DirtyPage, Vec<u8>), DBError> {
+        self.split_insert_node(pgno_right, DataNode::from(key, data))
+    }
+
+    /// Like `split_insert`, but inserts `node` with its own flags.
+    pub fn split_insert_node(
+        &self,
+        pgno_right: Pgno,
+        node: DataNode,
+    ) -> Result<(DirtyPage, DirtyPage, Vec<u8>), DBError> {
+        self.split_insert_with_bias(pgno_right, node, SplitBias::Middle)
+    }
+
+    /// Like `split_insert_node`, splitting where `bias` says.
+    pub fn split_insert_with_bias(
+        &self,
+        pgno_right: Pgno,
+        node: DataNode,
+        bias: SplitBias,
+    ) -> Result<(DirtyPage, DirtyPage, Vec<u8>), DBError> {
+        let view = self.as_data_page()?;
+        check_key_size(view.has_fixed_keys(), &node)?;
+        let mut nodes = view.read_nodes()?;
+        let appended = match view.search(&node.get_key())? {
+            Ok(idx) => {
+                nodes[idx] = node;
+                false
+            }
+            Err(idx) => {
+                nodes.insert(idx, node);
+                idx == view.num_nodes()
+            }
+        };
+        if nodes.len() < 2 {
+            return Err(DBError::PageFull);
+        }
+
+        let mid = match bias {
+            SplitBias::Right if appended => nodes.len() - 1,
+            _ => DataPage::split_point(&nodes),
+        };
+        let separator = nodes[mid].get_key().into_owned();
+        if view.flags.contains(PageFlag::BRANCH) {
+            nodes[mid] = DataNode::from(&[], nodes[mid].data);
+        }
+        let (left, right) = nodes.split_at(mid);
+        let capacity = view.data.len();
+        if DataPage::packed_page_size(left, view.flags) > capacity
+            || DataPage::packed_page_size(right, view.flags) > capacity
+        {
+            return Err(DBError::PageFull);
+        }
+
+        let page_size = view.get_page_size();
+        let half = |pgno, nodes| DirtyPage {
+            page: DataPage::write_new_page(pgno, page_size, view.flags, nodes),
+            order: self.order,
+        };
+        Ok((half(self.page.get_pgno(), left), half(pgno_right, right), separator))
+    }
+
+    fn compact_with(&mut self, idx: usize, upsert: bool, node: DataNode) -> Result<(), DBError> {
+        let view = self.as_data_page()?;
+        let mut nodes = view.read_nodes()?;
+        if upsert {
+            nodes[idx] = node;
+        } else {
+            nodes.insert(idx, node);
+        }
+
+        if DataPage::packed_page_size(&nodes, view.flags) > view.data.len() {
+            return Err(DBError::PageFull);
+        }
+        let page = DataPage::write_new_page(
+            self.page.get_pgno(),
+            view.get_page_size(),
+            view.flags,
+            &nodes,
+        );
+        self.page = page;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use rand::distr::Alphanumeric;
-    use rand::Rng;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
     use std::collections::HashMap;
 
     #[test]
     fn test_puts() {
-        let mut page = DataPage::write_new_page(0, &[]);
+        let mut page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
         let mut leaf_page = DataPage::from(&page).unwrap();
 
         let test_key_values = generate_key_values(100);
@@ -232,9 +1151,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_search_matches_binary_search() {
+        // pages small enough for every bisection to end at either edge
+        for num_keys in 0..=9 {
+            let keys: Vec<_> = (0..num_keys).map(|i| format!("key-{:03}", i * 2)).collect();
+            let mut dirty =
+                DirtyPage::new(0, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, KeyOrder::default());
+            for key in &keys {
+                dirty.put(key.as_bytes(), b"value").unwrap();
+            }
+            let page = dirty.into_page();
+            let leaf_page = DataPage::from(&page).unwrap();
+            for i in 0..num_keys * 2 + 1 {
+                let key = format!("key-{:03}", i);
+                assert_eq!(leaf_page.search(key.as_bytes()).unwrap(), keys.binary_search(&key));
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_key_matches_node() {
+        let fixed_keys: Vec<_> = (0..40u64).map(|i| (0xab00 + i).to_be_bytes()).collect();
+        let sized_keys: Vec<_> = (0..40).map(|i| format!("shared/{i:03}")).collect();
+        // some values long enough that their sizes take two varint bytes
+        let value = |i: usize| vec![7; if i.is_multiple_of(8) { 300 } else { 1 }];
+        let values: Vec<_> = (0..40).map(value).collect();
+        let fixed_nodes: Vec<_> =
+            fixed_keys.iter().zip(&values).map(|(k, v)| DataNode::from(k, v)).collect();
+        let sized_nodes: Vec<_> =
+            sized_keys.iter().zip(&values).map(|(k, v)| DataNode::from(k.as_bytes(), v)).collect();
+        let flags = PageFlag::ALIVE | PageFlag::FIXED_KEY;
+        let fixed = DataPage::write_new_page(0, DEFAULT_PAGE_SIZE, flags, &fixed_nodes);
+        let sized = DataPage::write_new_page(1, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, &sized_nodes);
+        assert!(DataPage::from(&fixed).unwrap().has_fixed_keys());
+        for page in [&fixed, &sized] {
+            let data_page = DataPage::from(page).unwrap();
+            assert!(!data_page.get_prefix().is_empty());
+            for offset in data_page.get_offsets().iter() {
+                let node = data_page.read_node_from_offset(offset as usize).unwrap();
+                let suffix = data_page.read_key_at_offset(offset as usize).unwrap();
+                assert_eq!(suffix, node.get_key_suffix());
+            }
+            assert!(matches!(data_page.read_key_at_offset(0), Err(DBError::CorruptPage { .. })));
+        }
+    }
+
     #[test]
     fn test_node_offsets_are_ordered() {
-        let mut page = DataPage::write_new_page(0, &[]);
+        let mut page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
         let mut leaf_page = DataPage::from(&page).unwrap();
 
         let test_key_values = generate_key_values(100);
@@ -248,7 +1213,7 @@ mod tests {
         let nodes: Vec<DataNode> = leaf_page
             .offsets
             .iter()
-            .map(|offset| leaf_page.read_node_from_offset(*offset as usize))
+            .map(|offset| leaf_page.read_node_from_offset(offset as usize).unwrap())
             .collect();
 
         assert!(is_sorted_by_key(&nodes));
@@ -256,7 +1221,7 @@ mod tests {
 
     #[test]
     fn test_data_page_split() {
-        let mut page = DataPage::write_new_page(0, &[]);
+        let mut page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
         let mut leaf_page = DataPage::from(&page).unwrap();
 
         let test_key_values = generate_key_values(100);
@@ -269,7 +1234,7 @@ mod tests {
         let mut sorted_key_values: Vec<DataNode> = test_key_values.iter()
             .map(|(k, v)| DataNode::from(k.as_bytes(), v.as_bytes()))
             .collect();
-        sorted_key_values.sort_by(|n1, n2| n1.key.cmp(n2.key));
+        sorted_key_values.sort_by(|n1, n2| n1.get_key().cmp(&n2.get_key()));
         let (expected_left, expected_right) = sorted_key_values.split_at(sorted_key_values.len() / 2);
 
         let (left_split, right_split) = leaf_page.split(0, 0).unwrap();
@@ -282,11 +1247,441 @@ mod tests {
         assert_eq!(right_nodes, expected_right);
     }
 
+    #[test]
+    fn test_corrupt_node_returns_error() {
+        let node = DataNode::from(b"key", b"value");
+        let page = DataPage::write_new_page(7, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, &[node]);
+        let leaf_page = DataPage::from(&page).unwrap();
+        let offset = leaf_page.offset(0);
+
+        // overwrite the node's key size with something far larger than the page
+        let mut data = page.get_data().to_vec();
+        let mut huge = Vec::new();
+        write_varint_u64(&mut huge, u64::MAX);
+        data[offset + U16_N..offset + U16_N + huge.len()].copy_from_slice(&huge);
+        let corrupt = Page::from(7, 0, PageFlag::ALIVE, page.get_lower(), page.get_upper(), &data);
+        let corrupt_page = DataPage::from(&corrupt).unwrap();
+
+        assert!(matches!(
+            corrupt_page.get(b"key"),
+            Err(DBError::CorruptPage { pgno: 7, .. })
+        ));
+        assert!(matches!(
+            corrupt_page.put(8, b"other", b"value"),
+            Err(DBError::CorruptPage { pgno: 7, .. })
+        ));
+    }
+
+    // the exact bytes a page is written as, which are the same on every host
+    // whatever its byte order or the width of its usize
+    #[test]
+    fn test_page_image_is_host_independent() {
+        let nodes = [DataNode::from(b"key/a", b"1"), DataNode::from(b"key/b", b"22")];
+        let page = DataPage::write_new_page(7, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, &nodes);
+        let image = page.as_bytes();
+        // pgno, pad (the prefix length), flags, lower and upper
+        assert_eq!(image[..16], [7, 0, 0, 0, 0, 0, 0, 0, 4, 0, 1, 0, 4, 0, 0xdb, 0x0f]);
+        let data = &image[PAGE_HEADER_SIZE..];
+        assert_eq!(data[..4], [0xe2, 0x0f, 0xdb, 0x0f]);
+        assert_eq!(data[4059..4066], [1, 0, 1, 2, b'b', b'2', b'2']);
+        assert_eq!(data[4066..4072], [1, 0, 1, 1, b'a', b'1']);
+        assert_eq!(data[4072..], *b"key/");
+
+        let copy = Page::from_bytes(image).unwrap();
+        let data_page = DataPage::from(&copy).unwrap();
+        assert_eq!(data_page.get_offsets().iter().collect::<Vec<_>>(), [4066, 4059]);
+        assert_eq!(data_page.get(b"key/b").unwrap(), b"22");
+
+        // a size past u32::MAX doesn't fit a 32-bit usize, and runs past the
+        // end of the page on a 64-bit host; both reject the node
+        let mut data = page.get_data().to_vec();
+        let mut size = Vec::new();
+        write_varint_u64(&mut size, u32::MAX as u64 + 2);
+        let data_size = 4066 + U16_N + 1;
+        data.splice(data_size..data_size + 1, size);
+        data.truncate(page.get_data().len());
+        let page = Page::from(7, 4, PageFlag::ALIVE, 4, 4059, &data);
+        assert!(matches!(
+            DataPage::from(&page).unwrap().read_node(0),
+            Err(DBError::CorruptPage { pgno: 7, .. })
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_header_returns_error() {
+        let data = [0u8; DEFAULT_PAGE_SIZE - PAGE_HEADER_SIZE];
+        let lower_past_upper = Page::from(3, 0, PageFlag::ALIVE, 64, 32, &data);
+        assert!(matches!(
+            DataPage::from(&lower_past_upper),
+            Err(DBError::CorruptPage { pgno: 3, .. })
+        ));
+
+        let upper_past_end = Page::from(3, 0, PageFlag::ALIVE, 0, u16::MAX, &data);
+        assert!(matches!(
+            DataPage::from(&upper_past_end),
+            Err(DBError::CorruptPage { pgno: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_validated_read_rejects_unordered_keys() {
+        let nodes = [DataNode::from(b"a", b"1"), DataNode::from(b"b", b"2")];
+        let page = DataPage::write_new_page(4, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, &nodes);
+        DataPage::from_validated(&page).unwrap();
+
+        let mut data = page.get_data().to_vec();
+        data.copy_within(0..U16_N, 2 * U16_N);
+        data.copy_within(U16_N..2 * U16_N, 0);
+        data.copy_within(2 * U16_N..3 * U16_N, U16_N);
+        let swapped = Page::from(4, 0, PageFlag::ALIVE, page.get_lower(), page.get_upper(), &data);
+
+        assert!(DataPage::from(&swapped).is_ok());
+        assert!(matches!(
+            DataPage::from_validated(&swapped),
+            Err(DBError::CorruptPage { pgno: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn test_dirty_page_upserts_compact_in_place() {
+        let page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 1);
+        dirty.put(b"stable", b"value").unwrap();
+
+        // each upsert changes the value's size, leaving the previous node
+        // behind as garbage, so this only succeeds if the page compacts once
+        // the gap runs out
+        for i in 0..1000 {
+            let value = format!("value-{}", "x".repeat(i % 7));
+            dirty.put(b"counter", value.as_bytes()).unwrap();
+        }
+
+        let data_page = dirty.as_data_page().unwrap();
+        assert_eq!(data_page.offsets.len(), 2);
+        assert_eq!(data_page.get(b"counter").unwrap(), b"value-xxxxx");
+        assert_eq!(data_page.get(b"stable").unwrap(), b"value");
+
+        // an upsert of the same size takes no extra space
+        let upper = dirty.page.get_upper();
+        dirty.put(b"counter", b"value-yyyyy").unwrap();
+        assert_eq!(dirty.page.get_upper(), upper);
+        assert_eq!(dirty.as_data_page().unwrap().get(b"counter").unwrap(), b"value-yyyyy");
+    }
+
+    #[test]
+    fn test_split_insert_balances_by_size() {
+        let page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 0);
+        dirty.put(b"a", &[1u8; 2500]).unwrap();
+        for key in [b"b", b"c", b"d"] {
+            dirty.put(key, b"small").unwrap();
+        }
+        assert!(matches!(dirty.put(b"e", &[2u8; 2500]), Err(DBError::PageFull)));
+
+        // by count this would put both large values on one page
+        let (left, right, separator) = dirty.split_insert(1, b"e", &[2u8; 2500]).unwrap();
+        assert_eq!(separator, b"c");
+        let (left, right) = (left.as_data_page().unwrap(), right.as_data_page().unwrap());
+        assert_eq!((left.get_pgno(), right.get_pgno()), (0, 1));
+        assert_eq!(left.get(b"a").unwrap(), [1u8; 2500]);
+        assert_eq!(right.get(b"e").unwrap(), [2u8; 2500]);
+        assert_eq!(left.num_nodes() + right.num_nodes(), 5);
+    }
+
+    #[test]
+    fn test_split_bias() {
+        let page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 0);
+        let value = [0u8; 500];
+        let mut n = 0u32;
+        while dirty.append_node(DataNode::from(&n.to_be_bytes(), &value)).is_ok() {
+            n += 1;
+        }
+        let split = |key: u32| {
+            let key = key.to_be_bytes();
+            let node = DataNode::from(&key, &value);
+            let (left, right, _) = dirty.split_insert_with_bias(1, node, SplitBias::Right).unwrap();
+            (left.as_data_page().unwrap().num_nodes(), right.as_data_page().unwrap().num_nodes())
+        };
+        // only a node put after the rest starts the right page on its own
+        assert_eq!(split(n), (n as usize, 1));
+        let (left, right) = split(n / 2);
+        assert!(left.abs_diff(right) <= 1);
+    }
+
+    #[test]
+    fn test_dirty_page_full() {
+        let page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 0);
+        let value = [0u8; 1000];
+
+        let mut i = 0u32;
+        let err = loop {
+            match dirty.put(&i.to_be_bytes(), &value) {
+                Ok(()) => i += 1,
+                Err(err) => break err,
+            }
+        };
+
+        assert!(matches!(err, DBError::PageFull));
+        assert_eq!(dirty.as_data_page().unwrap().offsets.len(), i as usize);
+    }
+
+    #[test]
+    fn test_put_flags() {
+        let page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let page = DataPage::from(&page).unwrap().put(0, b"b", b"first").unwrap();
+        let data_page = DataPage::from(&page).unwrap();
+        let put = |key: &[u8], put_flags| {
+            data_page.put_with_flags(0, key, b"second", put_flags)
+        };
+
+        assert!(matches!(put(b"b", PutFlag::NO_OVERWRITE), Err(DBError::KeyExists)));
+        let page = put(b"c", PutFlag::NO_OVERWRITE).unwrap();
+        assert_eq!(DataPage::from(&page).unwrap().get(b"c").unwrap(), b"second");
+        let page = put(b"b", PutFlag::OVERWRITE).unwrap();
+        assert_eq!(DataPage::from(&page).unwrap().get(b"b").unwrap(), b"second");
+
+        // appends only go past the last key
+        for key in [&b"a"[..], b"b"] {
+            assert!(matches!(put(key, PutFlag::APPEND), Err(DBError::KeyExists)));
+        }
+        let mut dirty = DirtyPage::from(&data_page, 0);
+        dirty.put_with_flags(b"c", b"third", PutFlag::APPEND).unwrap();
+        assert!(matches!(
+            dirty.put_with_flags(b"c", b"fourth", PutFlag::APPEND),
+            Err(DBError::KeyExists)
+        ));
+    }
+
+    #[test]
+    fn test_shared_key_prefix_is_stored_once() {
+        let key = |i: u32| format!("tenant-00042/orders/{i:06}");
+        let page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 0);
+
+        let mut count = 0;
+        while dirty.put(key(count).as_bytes(), b"v").is_ok() {
+            count += 1;
+        }
+        let node_size = DataNode::from(key(0).as_bytes(), b"v").get_size();
+        let uncompressed = (DEFAULT_PAGE_SIZE - PAGE_HEADER_SIZE) / (node_size + U16_N);
+        assert!(count as usize > uncompressed, "{count} <= {uncompressed}");
+
+        let data_page = dirty.as_data_page().unwrap();
+        assert!(data_page.get_prefix().starts_with(b"tenant-00042/orders/"));
+        for i in 0..count {
+            assert_eq!(data_page.get(key(i).as_bytes()).unwrap(), b"v");
+        }
+    }
+
+    #[test]
+    fn test_key_outside_prefix_shrinks_it() {
+        let nodes = [DataNode::from(b"prefix-a", b"1"), DataNode::from(b"prefix-b", b"2")];
+        let page = DataPage::write_new_page(0, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, &nodes);
+        let data_page = DataPage::from(&page).unwrap();
+        assert_eq!(data_page.get_prefix(), b"prefix-");
+
+        let page = data_page.put(0, b"pre", b"3").unwrap();
+        let data_page = DataPage::from(&page).unwrap();
+        assert_eq!(data_page.get_prefix(), b"pre");
+        assert_eq!(data_page.get(b"pre").unwrap(), b"3");
+        assert_eq!(data_page.get(b"prefix-a").unwrap(), b"1");
+        assert_eq!(data_page.get(b"prefix-b").unwrap(), b"2");
+        assert!(matches!(data_page.get(b"prefix-"), Err(DBError::KeyNotFound)));
+
+        let (left, right) = data_page.split(1, 2).unwrap();
+        let (left, right) = (DataPage::from(&left).unwrap(), DataPage::from(&right).unwrap());
+        assert_eq!(left.get(b"pre").unwrap(), b"3");
+        assert_eq!(right.get_prefix(), b"prefix-");
+        assert_eq!(get_nodes(&right), nodes[..]);
+    }
+
+    #[test]
+    fn test_small_nodes_use_short_headers() {
+        let page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 0);
+        let mut i = 0u32;
+        while dirty.put(&i.to_be_bytes(), &i.to_le_bytes()).is_ok() {
+            i += 1;
+        }
+        // flags + one byte per size + key + data, plus the offset slot; the
+        // shared key prefix only improves on that
+        assert_eq!(DataNode::from(&[0; 4], &[0; 4]).get_size(), 12);
+        assert!(i as usize >= (DEFAULT_PAGE_SIZE - PAGE_HEADER_SIZE) / 14);
+
+        let value = [0u8; 300];
+        let node = DataNode::from(b"k", &value);
+        assert_eq!(node.get_size(), U16_N + 1 + 2 + 1 + 300);
+        let page = DataPage::write_new_page(0, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, &[node]);
+        assert_eq!(DataPage::from(&page).unwrap().get(b"k").unwrap(), value);
+    }
+
+    #[test]
+    fn test_large_pages_hold_more() {
+        let count = |page_size| {
+            let page = DataPage::new_page(0, page_size);
+            let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 0);
+            let mut i = 0u32;
+            while dirty.put(&i.to_be_bytes(), &[0u8; 100]).is_ok() {
+                i += 1;
+            }
+            let (left, right) = dirty.as_data_page().unwrap().split(1, 2).unwrap();
+            assert_eq!(left.get_page_size(), page_size);
+            let right = DataPage::from(&right).unwrap();
+            assert_eq!(right.get(&(i - 1).to_be_bytes()).unwrap(), [0; 100]);
+            i
+        };
+
+        assert!(count(MAX_PAGE_SIZE) > 15 * count(DEFAULT_PAGE_SIZE));
+    }
+
+    #[test]
+    fn test_u64_le_order() {
+        let page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let page = DataPage::from(&page).unwrap().with_order(KeyOrder::U64LE);
+        let mut dirty = DirtyPage::from(&page, 0);
+        for i in [300u64, 2, 70_000, 1, 256] {
+            dirty.put(&i.to_le_bytes(), &i.to_be_bytes()).unwrap();
+        }
+
+        let data_page = dirty.as_data_page().unwrap();
+        data_page.validate().unwrap();
+        let keys: Vec<u64> = data_page
+            .nodes()
+            .map(|n| u64::from_le_bytes(n.unwrap().get_key()[..].try_into().unwrap()))
+            .collect();
+        assert_eq!(keys, [1, 2, 256, 300, 70_000]);
+        assert_eq!(data_page.get(&256u64.to_le_bytes()).unwrap(), 256u64.to_be_bytes());
+        // read back in byte order, the same page is out of order
+        let page = dirty.into_page();
+        assert!(DataPage::from_validated(&page).is_err());
+    }
+
+    #[test]
+    fn test_byte_based_orders_on_prefixed_pages() {
+        // every key shares "tenant-", so the page stores it as a prefix and the
+        // in-place comparisons have to see across it
+        let keys = ["tenant-b:9", "tenant-a:1", "tenant-c:5", "tenant-", "tenant-a"];
+        for order in [KeyOrder::BytesReversed, KeyOrder::BytesAfter(8), KeyOrder::BytesAfter(3)] {
+            let mut sorted: Vec<&[u8]> = keys.iter().map(|key| key.as_bytes()).collect();
+            sorted.sort_by(|a, b| order.compare(a, b));
+            let nodes: Vec<_> = sorted.iter().map(|key| DataNode::from(key, key)).collect();
+            let page = DataPage::write_new_page(0, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, &nodes);
+            let data_page = DataPage::from(&page).unwrap().with_order(order);
+            assert_eq!(data_page.get_prefix(), b"tenant-");
+            data_page.validate().unwrap();
+            for key in &sorted {
+                assert_eq!(data_page.get(key).unwrap(), *key);
+            }
+            assert!(matches!(data_page.get(b"tenant-b"), Err(DBError::KeyNotFound)));
+
+            // and inserts land where the order puts them
+            let mut dirty = DirtyPage::from(&data_page, 0);
+            dirty.put(b"tenant-b", b"new").unwrap();
+            dirty.as_data_page().unwrap().validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_soft_delete_and_undelete() {
+        let page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 0);
+        for key in [b"a", b"b", b"c"] {
+            dirty.put(key, b"value").unwrap();
+        }
+
+        dirty.soft_delete(b"b").unwrap();
+        assert!(matches!(dirty.soft_delete(b"b"), Err(DBError::KeyNotFound)));
+        assert!(matches!(dirty.undelete(b"z"), Err(DBError::KeyNotFound)));
+        // compaction must carry the flag over
+        dirty.put(b"d", &[0u8; 3000]).unwrap();
+        dirty.put(b"d", &[1u8; 3000]).unwrap();
+
+        let data_page = dirty.as_data_page().unwrap();
+        assert!(matches!(data_page.get(b"b"), Err(DBError::KeyNotFound)));
+        assert!(!data_page.get_node(b"b").unwrap().is_alive());
+        let live: Vec<_> = data_page.nodes().map(|n| n.unwrap().get_key().to_vec()).collect();
+        assert_eq!(live, [b"a", b"c", b"d"]);
+        assert_eq!(data_page.nodes().include_deleted().count(), 4);
+
+        dirty.undelete(b"b").unwrap();
+        assert_eq!(dirty.as_data_page().unwrap().get(b"b").unwrap(), b"value");
+    }
+
+    #[test]
+    fn test_damaged_pages_fail_cleanly() {
+        let mut dirty = DirtyPage::new(0, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, KeyOrder::default());
+        for i in 0..60 {
+            dirty.put(format!("shared-{i:03}").as_bytes(), &vec![i as u8; i]).unwrap();
+        }
+        dirty.soft_delete(b"shared-007").unwrap();
+        let page = dirty.into_page();
+
+        // a few bytes at a time, half of them in the header and offset array,
+        // where one bad value steers every read after it
+        let mut rng = StdRng::seed_from_u64(1041);
+        for _ in 0..5000 {
+            let mut bytes = page.as_bytes().to_vec();
+            for _ in 0..rng.random_range(1..=4) {
+                let at = match rng.random_bool(0.5) {
+                    true => rng.random_range(0..PAGE_HEADER_SIZE + 128),
+                    false => rng.random_range(0..bytes.len()),
+                };
+                bytes[at] = rng.random();
+            }
+            let damaged = Page::from_bytes(&bytes).unwrap();
+            let Ok(data_page) = DataPage::from(&damaged) else {
+                continue;
+            };
+            let _ = data_page.validate();
+            for node in data_page.nodes().include_deleted().flatten() {
+                let _ = (node.get_key(), node.get_data());
+            }
+            let _ = data_page.get(b"shared-030");
+            let _ = data_page.put(0, b"shared-999", b"value");
+            let _ = data_page.split(0, 1);
+        }
+    }
+
+    #[test]
+    fn test_fixed_keys() {
+        let flags = PageFlag::ALIVE | PageFlag::FIXED_KEY;
+        let mut fixed = DirtyPage::new(0, DEFAULT_PAGE_SIZE, flags, KeyOrder::Bytes);
+        let mut sized = DirtyPage::new(1, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, KeyOrder::Bytes);
+        // big-endian keys sharing their high bytes, so the page stores a prefix
+        let keys: Vec<u64> = (0..200).map(|i| 0x1234_5600_0000 + i * 3).collect();
+        for page in [&mut fixed, &mut sized] {
+            for key in keys.iter().rev() {
+                page.put(&key.to_be_bytes(), &key.to_le_bytes()).unwrap();
+            }
+        }
+        let (fixed, sized) = (fixed.into_page(), sized.into_page());
+        let (fixed, sized) = (DataPage::from(&fixed).unwrap(), DataPage::from(&sized).unwrap());
+        assert!(fixed.has_fixed_keys() && !sized.has_fixed_keys());
+        assert_eq!(fixed.get_prefix().len(), 6);
+        fixed.validate().unwrap();
+        // the key size was the one byte left out of every node
+        assert_eq!(fixed.get_upper() - sized.get_upper(), 200);
+
+        for probe in (0..600u64).map(|i| 0x1234_5600_0000 + i) {
+            let probe = probe.to_be_bytes();
+            assert_eq!(fixed.search(&probe).unwrap(), sized.search(&probe).unwrap());
+        }
+        let node = fixed.read_node(7).unwrap();
+        assert_eq!(node.get_key(), &keys[7].to_be_bytes()[..]);
+        assert_eq!(node.get_data(), keys[7].to_le_bytes());
+        assert!(matches!(
+            fixed.put(0, b"short", b"value"),
+            Err(DBError::KeySizeMismatch { size: 5, expected: FIXED_KEY_SIZE })
+        ));
+    }
+
     fn get_nodes<'a>(page: &'a DataPage) -> Vec<DataNode<'a>> {
         page
             .offsets
             .iter()
-            .map(|offset| page.read_node_from_offset(*offset as usize))
+            .map(|offset| page.read_node_from_offset(offset as usize).unwrap())
             .collect()
     }
 
@@ -302,6 +1697,6 @@ mod tests {
     }
 
     fn is_sorted_by_key(nodes: &[DataNode]) -> bool {
-        nodes.windows(2).all(|w| w[0].key <= w[1].key)
+        nodes.windows(2).all(|w| w[0].get_key() <= w[1].get_key())
     }
 }
@@ -0,0 +1,135 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::constants::*;
+use crate::data_page::{DataNode, DataPage};
+
+/// One difference between two versions of a data page, keyed by node key.
+#[derive(Debug, Eq, PartialEq)]
+pub enum NodeDiff {
+    Added { key: Vec<u8>, data: Vec<u8> },
+    Removed { key: Vec<u8>, data: Vec<u8> },
+    Changed { key: Vec<u8>, old: Vec<u8>, new: Vec<u8> },
+    FlagsChanged { key: Vec<u8>, old: NodeFlag, new: NodeFlag },
+}
+
+impl fmt::Display for NodeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeDiff::Added { key, data } => {
+                write!(f, "+ {} = {}", key.escape_ascii(), data.escape_ascii())
+            }
+            NodeDiff::Removed { key, data } => {
+                write!(f, "- {} = {}", key.escape_ascii(), data.escape_ascii())
+            }
+            NodeDiff::Changed { key, old, new } => write!(
+                f,
+                "~ {}: {} -> {}",
+                key.escape_ascii(),
+                old.escape_ascii(),
+                new.escape_ascii()
+            ),
+            NodeDiff::FlagsChanged { key, old, new } => {
+                write!(f, "~ {}: flags {:?} -> {:?}", key.escape_ascii(), old, new)
+            }
+        }
+    }
+}
+
+/// Compares the nodes of two page versions (e.g. before and after a
+/// copy-on-write update) in key order. Soft-deleted nodes are included, so
+/// deleting or restoring a key shows up as a flags change.
+pub fn diff_pages(old: &DataPage, new: &DataPage) -> Result<Vec<NodeDiff>, DBError> {
+    let mut old_nodes = old.nodes().include_deleted().peekable();
+    let mut new_nodes = new.nodes().include_deleted().peekable();
+    let mut diffs = Vec::new();
+    loop {
+        let ordering = match (old_nodes.peek(), new_nodes.peek()) {
+            (None, None) => break,
+            (Some(Err(_)), _) => return Err(old_nodes.next().unwrap().unwrap_err()),
+            (_, Some(Err(_))) => return Err(new_nodes.next().unwrap().unwrap_err()),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(Ok(a)), Some(Ok(b))) => a.get_key().cmp(&b.get_key()),
+        };
+        match ordering {
+            Ordering::Less => {
+                let node = old_nodes.next().unwrap()?;
+                diffs.push(NodeDiff::Removed {
+                    key: node.get_key().to_vec(),
+                    data: node.get_data().to_vec(),
+                });
+            }
+            Ordering::Greater => {
+                let node = new_nodes.next().unwrap()?;
+                diffs.push(NodeDiff::Added {
+                    key: node.get_key().to_vec(),
+                    data: node.get_data().to_vec(),
+                });
+            }
+            Ordering::Equal => {
+                let (a, b) = (old_nodes.next().unwrap()?, new_nodes.next().unwrap()?);
+                diff_node(&a, &b, &mut diffs);
+            }
+        }
+    }
+
+    Ok(diffs)
+}
+
+fn diff_node(old: &DataNode, new: &DataNode, diffs: &mut Vec<NodeDiff>) {
+    if old.get_data() != new.get_data() {
+        diffs.push(NodeDiff::Changed {
+            key: old.get_key().to_vec(),
+            old: old.get_data().to_vec(),
+            new: new.get_data().to_vec(),
+        });
+    }
+    if old.get_flags() != new.get_flags() {
+        diffs.push(NodeDiff::FlagsChanged {
+            key: old.get_key().to_vec(),
+            old: old.get_flags(),
+            new: new.get_flags(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_page::DirtyPage;
+
+    #[test]
+    fn test_diff_pages() {
+        let page = DataPage::new_page(0, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 0);
+        for key in [b"a", b"b", b"c", b"d"] {
+            dirty.put(key, b"1").unwrap();
+        }
+        let before = dirty.as_data_page().unwrap();
+        let mut after = DirtyPage::from(&before, 1);
+        after.put(b"b", b"2").unwrap();
+        after.soft_delete(b"c").unwrap();
+        after.put(b"e", b"1").unwrap();
+        let after = after.into_page();
+        let after = DataPage::from(&after).unwrap();
+
+        assert_eq!(
+            diff_pages(&before, &after).unwrap(),
+            vec![
+                NodeDiff::Changed { key: b"b".to_vec(), old: b"1".to_vec(), new: b"2".to_vec() },
+                NodeDiff::FlagsChanged {
+                    key: b"c".to_vec(),
+                    old: NodeFlag::ALIVE,
+                    new: NodeFlag::empty(),
+                },
+                NodeDiff::Added { key: b"e".to_vec(), data: b"1".to_vec() },
+            ]
+        );
+        assert!(matches!(
+            diff_pages(&after, &before).unwrap().last(),
+            Some(NodeDiff::Removed { .. })
+        ));
+        assert!(diff_pages(&before, &before).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+use std::io::{Read, Write};
+
+use crate::buf::{read_varint_u64, write_varint_u64, VarintError, MAX_VARINT_LEN};
+use crate::constants::*;
+use crate::key_order::{KeyOrder, UNRECORDED_ID};
+
+// Dump layout:
+//   magic (8 bytes) + version (u32) + key order (u16)
+//   records: tag (u8) followed by the record body
+//     TAG_DATABASE: name_len (varint) + name
+//     TAG_ENTRY:    key_len (varint) + key + value_len (varint) + value
+//     TAG_END:      entry count (u64) + crc32 of every record byte before it
+// All fixed-width integers are little-endian.
+// The key order is recorded as in the meta pages, as the id of a built-in
+// `KeyOrder` or UNRECORDED_ID, so that a dump loads into a file in the order
+// its entries are in. Version 1 had no key order.
+pub const DUMP_MAGIC: &[u8; 8] = b"MMDBDUMP";
+pub const DUMP_VERSION: u32 = 2;
+const KEY_ORDER_VERSION: u32 = 2;
+
+const TAG_END: u8 = 0;
+const TAG_ENTRY: u8 = 1;
+const TAG_DATABASE: u8 = 2;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum DumpRecord {
+    /// Subsequent entries belong to the named database.
+    Database(Vec<u8>),
+    Entry(Vec<u8>, Vec<u8>),
+}
+
+pub struct DumpWriter<W: Write> {
+    writer: W,
+    hasher: crc32fast::Hasher,
+    count: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> DumpWriter<W> {
+    /// Starts a dump of entries in `order`, which they must be written in.
+    pub fn new(mut writer: W, order: KeyOrder) -> Result<Self, DBError> {
+        writer.write_all(DUMP_MAGIC)?;
+        writer.write_all(&DUMP_VERSION.to_le_bytes())?;
+        writer.write_all(&order.recorded_id().to_le_bytes())?;
+        Ok(DumpWriter {
+            writer,
+            hasher: crc32fast::Hasher::new(),
+            count: 0,
+            buf: Vec::new(),
+        })
+    }
+
+    fn write_record(&mut self, tag: u8, fields: &[&[u8]]) -> Result<(), DBError> {
+        self.buf.clear();
+        self.buf.push(tag);
+        for field in fields {
+            write_varint_u64(&mut self.buf, field.len() as u64);
+            self.buf.extend_from_slice(field);
+        }
+        self.hasher.update(&self.buf);
+        self.writer.write_all(&self.buf)?;
+        Ok(())
+    }
+
+    pub fn begin_database(&mut self, name: &[u8]) -> Result<(), DBError> {
+        self.write_record(TAG_DATABASE, &[name])
+    }
+
+    pub fn write_entry(&mut self, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        self.write_record(TAG_ENTRY, &[key, value])?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Writes the trailer and returns the underlying writer. A dump without a
+    /// trailer is rejected by `DumpReader` as truncated.
+    pub fn finish(mut self) -> Result<W, DBError> {
+        let crc = self.hasher.clone().finalize();
+        self.writer.write_all(&[TAG_END])?;
+        self.writer.write_all(&self.count.to_le_bytes())?;
+        self.writer.write_all(&crc.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+pub struct DumpReader<R: Read> {
+    reader: R,
+    key_order: Option<KeyOrder>,
+    hasher: crc32fast::Hasher,
+    count: u64,
+    done: bool,
+}
+
+fn corrupt(reason: &'static str) -> DBError {
+    DBError::CorruptValue { reason }
+}
+
+impl<R: Read> DumpReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, DBError> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != DUMP_MAGIC {
+            return Err(corrupt("not an mmdb dump"));
+        }
+        let version = u32::from_le_bytes(header[8..].try_into().unwrap());
+        if version == 0 || version > DUMP_VERSION {
+            return Err(DBError::VersionMismatch {
+                expected: DUMP_VERSION,
+                found: version,
+            });
+        }
+        let mut key_order = None;
+        if version >= KEY_ORDER_VERSION {
+            let mut id = [0u8; 2];
+            reader.read_exact(&mut id)?;
+            let id = u16::from_le_bytes(id);
+            key_order = u8::try_from(id).ok().and_then(KeyOrder::from_id);
+            if key_order.is_none() && id != UNRECORDED_ID {
+                return Err(corrupt("unrecognized key order"));
+            }
+        }
+        Ok(DumpReader {
+            reader,
+            key_order,
+            hasher: crc32fast::Hasher::new(),
+            count: 0,
+            done: false,
+        })
+    }
+
+    /// The order the dump's entries are in, if it is a built-in one; `None`
+    /// for any other order, and for dumps of version 1, which didn't record
+    /// it.
+    pub const fn get_key_order(&self) -> Option<KeyOrder> {
+        self.key_order
+    }
+
+    fn read_hashed(&mut self, buf: &mut [u8]) -> Result<(), DBError> {
+        self.reader
+            .read_exact(buf)
+            .map_err(|_| corrupt("dump is truncated"))?;
+        self.hasher.update(buf);
+        Ok(())
+    }
+
+    // read a byte at a time, up to the last one, then decoded as in a buffer
+    fn read_varint(&mut self) -> Result<u64, DBError> {
+        let mut bytes = [0u8; MAX_VARINT_LEN];
+        for len in 1..=MAX_VARINT_LEN {
+            self.read_hashed(&mut bytes[len - 1..len])?;
+            if bytes[len - 1] & 0x80 == 0 {
+                let (value, _) =
+                    read_varint_u64(&bytes[..len]).map_err(|err| corrupt(err.reason()))?;
+                return Ok(value);
+            }
+        }
+        Err(corrupt(VarintError::Overflow.reason()))
+    }
+
+    fn read_field(&mut self) -> Result<Vec<u8>, DBError> {
+        let len = self.read_varint()?;
+        // read through take() so a corrupt length can't force a huge allocation
+        let mut field = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut field)?;
+        if field.len() as u64 != len {
+            return Err(corrupt("dump is truncated"));
+        }
+        self.hasher.update(&field);
+        Ok(field)
+    }
+
+    fn read_trailer(&mut self) -> Result<(), DBError> {
+        let mut trailer = [0u8; 12];
+        self.reader
+            .read_exact(&mut trailer)
+            .map_err(|_| corrupt("dump is truncated"))?;
+        let count = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        let crc = u32::from_le_bytes(trailer[8..].try_into().unwrap());
+        if count != self.count {
+            return Err(corrupt("dump entry count does not match trailer"));
+        }
+        if crc != self.hasher.clone().finalize() {
+            return Err(corrupt("dump checksum mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Returns the next record, or `None` once the trailer has been read and
+    /// verified.
+    pub fn next_record(&mut self) -> Result<Option<DumpRecord>, DBError> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut tag = [0u8; 1];
+        self.reader
+            .read_exact(&mut tag)
+            .map_err(|_| corrupt("dump is truncated"))?;
+        if tag[0] == TAG_END {
+            self.done = true;
+            self.read_trailer()?;
+            return Ok(None);
+        }
+        self.hasher.update(&tag);
+
+        match tag[0] {
+            TAG_DATABASE => Ok(Some(DumpRecord::Database(self.read_field()?))),
+            TAG_ENTRY => {
+                let key = self.read_field()?;
+                let value = self.read_field()?;
+                self.count += 1;
+                Ok(Some(DumpRecord::Entry(key, value)))
+            }
+            _ => Err(corrupt("unrecognized dump record")),
+        }
+    }
+}
+
+impl<R: Read> Iterator for DumpReader<R> {
+    type Item = Result<DumpRecord, DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_sample() -> Vec<u8> {
+        let mut writer = DumpWriter::new(Vec::new(), KeyOrder::Bytes).unwrap();
+        writer.begin_database(b"users").unwrap();
+        for i in 0..100u32 {
+            writer.write_entry(&i.to_be_bytes(), format!("user-{i}").as_bytes()).unwrap();
+        }
+        writer.begin_database(b"empty-values").unwrap();
+        writer.write_entry(b"flag", b"").unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dump = write_sample();
+        let reader = DumpReader::new(dump.as_slice()).unwrap();
+        assert!(matches!(reader.get_key_order(), Some(KeyOrder::Bytes)));
+        let records = reader.collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(records.len(), 103);
+        assert_eq!(records[0], DumpRecord::Database(b"users".to_vec()));
+        assert_eq!(
+            records[5],
+            DumpRecord::Entry(4u32.to_be_bytes().to_vec(), b"user-4".to_vec())
+        );
+        assert_eq!(records[102], DumpRecord::Entry(b"flag".to_vec(), vec![]));
+
+        // version 1 went straight from the version to the records
+        let mut old = [&DUMP_MAGIC[..], &1u32.to_le_bytes()].concat();
+        old.extend_from_slice(&dump[14..]);
+        let reader = DumpReader::new(old.as_slice()).unwrap();
+        assert!(reader.get_key_order().is_none());
+        assert_eq!(reader.count(), 103);
+    }
+
+    #[test]
+    fn test_rejects_damaged_dumps() {
+        let dump = write_sample();
+
+        let truncated = &dump[..dump.len() - 20];
+        let result: Result<Vec<_>, _> = DumpReader::new(truncated).unwrap().collect();
+        assert!(matches!(result, Err(DBError::CorruptValue { .. })));
+
+        let mut flipped = dump.clone();
+        flipped[40] ^= 0x01;
+        let result: Result<Vec<_>, _> = DumpReader::new(flipped.as_slice()).unwrap().collect();
+        assert!(matches!(result, Err(DBError::CorruptValue { .. })));
+
+        let mut future = dump.clone();
+        future[8] = 3;
+        assert!(matches!(
+            DumpReader::new(future.as_slice()),
+            Err(DBError::VersionMismatch { expected: 2, found: 3 })
+        ));
+
+        let mut unknown_order = dump.clone();
+        unknown_order[12] = 100;
+        assert!(matches!(
+            DumpReader::new(unknown_order.as_slice()),
+            Err(DBError::CorruptValue { .. })
+        ));
+    }
+}
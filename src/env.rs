@@ -0,0 +1,3276 @@
+use memmap2::{Mmap, MmapMut};
+use std::collections::{HashSet, VecDeque};
+use std::ffi::{CString, OsString};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ops::{Range, RangeBounds};
+use std::ptr;
+use std::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicU64, AtomicUsize};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Once, OnceLock, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::btree::{BTree, TreeStat};
+use crate::btree_page::DEFAULT_MIN_FILL;
+use crate::check::{check_commit_with, check_sample, salvage_commit, CheckReport};
+use crate::conflict::ConflictTable;
+use crate::constants::*;
+use crate::data_page::SplitBias;
+use crate::events::{Event, EventBus, Subscriber};
+use crate::export::{self, IncrementalBackup, Partition};
+use crate::key_filter::{KeyFilter, KeyFilterStat};
+use crate::key_order::KeyOrder;
+use crate::merge::MergeFn;
+use crate::meta::{Meta, META_VERSION, NUM_META_PAGES};
+use crate::metrics::{Metrics, MetricsStat, Recorder};
+use crate::page::Page;
+use crate::page_changes::{PageChanges, RecordedCommit};
+use crate::page_alloc::{DirtySet, PageAllocator};
+use crate::page_cache::{PageCache, PageCacheStat, DEFAULT_PAGE_CACHE_ENTRIES};
+use crate::page_io::{AccessPattern, BufferPool, FileWriter, IoBackend, PageFile};
+use crate::pin::{key_range, PinTable, PinnedPage};
+use crate::progress::{no_progress, report, ProgressFn, Stage, READER_POLL_INTERVAL};
+use crate::reader_table::{ReaderTable, DEFAULT_MAX_READERS};
+use crate::replication::ChangeStream;
+use crate::txn::{PageChecks, ReadTxn, Snapshot, WriteTxn};
+use crate::wal::{Wal, WalOptions, WalStat};
+
+// crash marker file: magic (8 bytes) + txnid of the last commit (u64)
+const CRASH_MAGIC: &[u8; 8] = b"MMDBCRSH";
+const CRASH_MARKER_SIZE: usize = 16;
+
+/// How many of the most recent commits the flush percentiles cover.
+pub const FLUSH_SAMPLES: usize = 1024;
+
+type SampleHook = Arc<dyn Fn(CheckReport) + Send + Sync>;
+type Runs = Vec<(Pgno, Vec<u8>)>;
+
+/// Options for opening an environment; the page size only applies when the
+/// file is created.
+#[derive(Clone)]
+pub struct EnvOptions {
+    page_size: usize,
+    max_readers: usize,
+    merge: Option<MergeFn>,
+    min_fill: f64,
+    max_dirty_pages: usize,
+    fixed_keys: bool,
+    split_bias: SplitBias,
+    key_order: KeyOrder,
+    compress_values: usize,
+    verify_checksums: bool,
+    paranoid_reads: bool,
+    wait_for_lock: bool,
+    sample: Option<(usize, SampleHook)>,
+    map_size: u64,
+    growth: GrowthPolicy,
+    page_cache: usize,
+    key_filter: usize,
+    io_backend: IoBackend,
+    wal: Option<WalOptions>,
+    subscribers: Vec<Subscriber>,
+    metrics: Vec<Arc<dyn Metrics>>,
+}
+
+impl Default for EnvOptions {
+    fn default() -> Self {
+        EnvOptions {
+            page_size: DEFAULT_PAGE_SIZE,
+            max_readers: DEFAULT_MAX_READERS,
+            merge: None,
+            min_fill: DEFAULT_MIN_FILL,
+            max_dirty_pages: 0,
+            fixed_keys: false,
+            split_bias: SplitBias::default(),
+            key_order: KeyOrder::default(),
+            compress_values: 0,
+            verify_checksums: false,
+            paranoid_reads: false,
+            wait_for_lock: false,
+            sample: None,
+            map_size: 0,
+            growth: GrowthPolicy::default(),
+            page_cache: DEFAULT_PAGE_CACHE_ENTRIES,
+            key_filter: 0,
+            io_backend: IoBackend::default(),
+            wal: None,
+            subscribers: Vec::new(),
+            metrics: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for EnvOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvOptions")
+            .field("page_size", &self.page_size)
+            .field("max_readers", &self.max_readers)
+            .field("merge", &self.merge.is_some())
+            .field("min_fill", &self.min_fill)
+            .field("max_dirty_pages", &self.max_dirty_pages)
+            .field("fixed_keys", &self.fixed_keys)
+            .field("split_bias", &self.split_bias)
+            .field("key_order", &self.key_order)
+            .field("compress_values", &self.compress_values)
+            .field("verify_checksums", &self.verify_checksums)
+            .field("paranoid_reads", &self.paranoid_reads)
+            .field("wait_for_lock", &self.wait_for_lock)
+            .field("sample_pages", &self.sample.as_ref().map(|(count, _)| count))
+            .field("map_size", &self.map_size)
+            .field("growth", &self.growth)
+            .field("page_cache", &self.page_cache)
+            .field("key_filter", &self.key_filter)
+            .field("io_backend", &self.io_backend)
+            .field("wal", &self.wal)
+            .field("subscribers", &self.subscribers.len())
+            .field("metrics", &self.metrics.len())
+            .finish()
+    }
+}
+
+impl EnvOptions {
+    pub fn new() -> Self {
+        EnvOptions::default()
+    }
+
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// How many read transactions can be open at once across every process
+    /// using the file. Like the page size, only applies when the lock file is
+    /// created.
+    pub fn max_readers(mut self, max_readers: usize) -> Self {
+        self.max_readers = max_readers;
+        self
+    }
+
+    /// The operator `WriteTxn::merge` combines values with.
+    pub fn merge_operator(mut self, merge: MergeFn) -> Self {
+        self.merge = Some(merge);
+        self
+    }
+
+    /// How full, from 0 to 1, a page that lost entries to deletes must stay;
+    /// below it, the page is merged with a neighbour or takes some of its
+    /// entries. 0 turns rebalancing off, leaving emptied pages in the tree.
+    pub fn min_fill(mut self, min_fill: f64) -> Self {
+        self.min_fill = min_fill;
+        self
+    }
+
+    /// How many dirty pages a write transaction may hold before its writes
+    /// fail with `TxnFull`, so a large batch job notices in time to commit
+    /// what it has and go on in a new transaction; 0, the default, sets no
+    /// cap. It is checked before each write, so the pages one write needs
+    /// can take a transaction a little past it.
+    pub fn max_dirty_pages(mut self, max_dirty_pages: usize) -> Self {
+        self.max_dirty_pages = max_dirty_pages;
+        self
+    }
+
+    /// Every key is exactly `FIXED_KEY_SIZE` (8) bytes, such as a big-endian
+    /// u64 counter or timestamp, and puts of any other size fail with
+    /// `KeySizeMismatch`. Leaves then leave each key's size out of its node,
+    /// and searches read keys straight off the page. Like a merge operator,
+    /// this isn't recorded with the database, so it must be passed again on
+    /// every open; leaves already written keep the layout they have.
+    pub fn fixed_keys(mut self, fixed_keys: bool) -> Self {
+        self.fixed_keys = fixed_keys;
+        self
+    }
+
+    /// Where puts split full pages; `SplitBias::Right` suits keys that mostly
+    /// arrive in increasing order. `WriteTxn::put_append` splits to the right
+    /// whatever this says.
+    pub fn split_bias(mut self, split_bias: SplitBias) -> Self {
+        self.split_bias = split_bias;
+        self
+    }
+
+    /// How keys are ordered. It is recorded when the file is created, and
+    /// opening the file in any other order fails with `IncompatibleFile`;
+    /// orders without an id (see `KeyOrder::id`) are recorded only as such,
+    /// so, like a merge operator, they must be passed again on every open.
+    pub fn key_order(mut self, key_order: KeyOrder) -> Self {
+        self.key_order = key_order;
+        self
+    }
+
+    /// Compresses values of at least `min_size` bytes as they are put, where
+    /// that makes them smaller, and marks their nodes `COMPRESSED`; reads
+    /// decompress them into memory the transaction keeps until it ends. 0,
+    /// the default, compresses nothing. Values already stored stay as they
+    /// are, and files holding compressed values can only be read by builds
+    /// with the `compression` feature.
+    #[cfg(feature = "compression")]
+    pub fn compress_values(mut self, min_size: usize) -> Self {
+        self.compress_values = min_size;
+        self
+    }
+
+    /// Checks the checksum of every committed page as it is read, failing
+    /// the read with `ChecksumMismatch` rather than parsing a page that was
+    /// damaged on disk. Off by default, since it hashes every page on every
+    /// read that doesn't find it in the page cache; `Env::check` and
+    /// `sample_pages` catch the same damage out of band.
+    pub fn verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+
+    /// Walks every node of each committed page as it is read, failing the
+    /// read with `CorruptPage` on the first one that doesn't parse, is out of
+    /// key order or overlaps another, as `DataPage::from_validated` does.
+    /// Catches damage a checksum can't, such as a buggy writer's, before a
+    /// search is steered by it, at the cost of reading the whole page on
+    /// every read that doesn't find it in the page cache. Off by default.
+    pub fn paranoid_reads(mut self, paranoid_reads: bool) -> Self {
+        self.paranoid_reads = paranoid_reads;
+        self
+    }
+
+    /// Whether `open` waits for another process that has the file open for
+    /// writing to close it, rather than failing with `WriterLocked`, the
+    /// default. Read-only opens never wait.
+    pub fn wait_for_lock(mut self, wait_for_lock: bool) -> Self {
+        self.wait_for_lock = wait_for_lock;
+        self
+    }
+
+    /// Size in bytes the file and its writable map are grown to on open, so
+    /// commits can fill it without remapping; like LMDB's `mdb_env_set_mapsize`,
+    /// but the file grows past it as needed. A larger file is never shrunk.
+    pub fn map_size(mut self, map_size: u64) -> Self {
+        self.map_size = map_size;
+        self
+    }
+
+    /// How much the file grows by when a commit runs past its end, rounded up
+    /// to whole steps; 0, the default, grows it only as far as the commit
+    /// needs. Short for `growth_policy(GrowthPolicy::Step(growth_step))`.
+    pub fn growth_step(self, growth_step: u64) -> Self {
+        self.growth_policy(GrowthPolicy::Step(growth_step))
+    }
+
+    /// How the file grows when a commit runs past its end. Growing by more at
+    /// a time means fewer remaps and less fragmentation on filesystems that
+    /// allocate as the file extends, at the cost of space not yet used.
+    pub fn growth_policy(mut self, growth: GrowthPolicy) -> Self {
+        self.growth = growth;
+        self
+    }
+
+    /// How many parsed branch pages read transactions share, so lookups skip
+    /// decoding the pages every one of them passes through; 0 turns the cache
+    /// off. `EnvStat::page_cache` reports how often it is hit.
+    pub fn page_cache(mut self, entries: usize) -> Self {
+        self.page_cache = entries;
+        self
+    }
+
+    /// Keeps a bloom filter of the keys in memory, `bits_per_key` bits for
+    /// each, so `ReadTxn::get` answers most lookups of keys that aren't there
+    /// without reading the tree; 10 bits let about 1% of them through. It is
+    /// built from every key when the environment is opened, takes the keys of
+    /// each commit, and is built again, twice the size, by the commit that
+    /// fills it, each taking time in proportion to the tree. 0, the default,
+    /// keeps none. Write transactions don't use it, and environments opened
+    /// read-only don't build one, as the keys the writer adds don't pass
+    /// through them. `EnvStat::key_filter` reports how well it does.
+    pub fn key_filter(mut self, bits_per_key: usize) -> Self {
+        self.key_filter = bits_per_key;
+        self
+    }
+
+    /// Whether the file is mapped or read and written with plain file IO
+    /// through a cache of pages; see `IoBackend`.
+    pub fn io_backend(mut self, io_backend: IoBackend) -> Self {
+        self.io_backend = io_backend;
+        self
+    }
+
+    /// Commits append their pages and meta page to a log next to the file,
+    /// `<path>-wal`, and share syncs of it, rather than each syncing the file
+    /// twice, for many small transactions from several threads. A commit
+    /// returns once a sync covers it, and new readers see it before that.
+    /// `Env::checkpoint` writes the latest commit's meta page to the file and
+    /// empties the log, as commits do once it passes
+    /// `WalOptions::checkpoint_bytes`; until then, environments opened
+    /// read-only, which read the meta pages from the file, see only the last
+    /// checkpoint. Opening the file for writing again, with or without a log,
+    /// replays what the log holds past it.
+    pub fn wal(mut self, wal: WalOptions) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    /// Subscribes `on_event` from the start, so it also sees events raised
+    /// while opening, such as those of `sample_pages`; see `Env::subscribe`.
+    pub fn on_event(mut self, on_event: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        self.subscribers.push(Arc::new(on_event));
+        self
+    }
+
+    /// Has the environment call the hooks of `metrics` as it reads, writes,
+    /// splits pages, commits and syncs, on top of the counters `Env::metrics`
+    /// reports. Hooks registered more than once are all called.
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics.push(metrics);
+        self
+    }
+
+    /// After each open, checks `count` pages picked at random (checksums and
+    /// key order) on a background thread and passes the findings to
+    /// `on_report`. Catches silent corruption early without paying for a full
+    /// check before the environment can be used.
+    pub fn sample_pages(
+        mut self,
+        count: usize,
+        on_report: impl Fn(CheckReport) + Send + Sync + 'static,
+    ) -> Self {
+        self.sample = Some((count, Arc::new(on_report)));
+        self
+    }
+
+    /// Opens `path` for reading and writing, creating it if it doesn't
+    /// exist. Only one environment at a time, in any process, can have a
+    /// file open this way: the file is locked with `flock` for as long as
+    /// the environment is open, and other opens fail with `WriterLocked` (or
+    /// wait, see `wait_for_lock`) until it is closed.
+    pub fn open(&self, path: impl AsRef<Path>) -> Result<Env, DBError> {
+        Env::open_with(path.as_ref(), self, false)
+    }
+
+    /// Opens an existing file for reading only, alongside any number of
+    /// other readers and the one writer, in this process or others. Each
+    /// read transaction begins at the most recent commit on disk, whoever
+    /// made it. Writes fail with `TxnReadOnly`, and the options that only
+    /// concern writes are ignored. The file is only mapped for reading and
+    /// nothing is created beside it, not even the lock file (see
+    /// `ReaderTable::open_read_only`), so this works on read-only media.
+    pub fn open_read_only(&self, path: impl AsRef<Path>) -> Result<Env, DBError> {
+        Env::open_with(path.as_ref(), self, true)
+    }
+
+    /// Opens `path`, then checks every page of the current commit. If any
+    /// fails, the tree is rebuilt in a new commit from the entries that can
+    /// still be read, and the report says which pages were given up on; a
+    /// clean report means nothing was lost. Still fails, like `open`, if
+    /// neither meta page can be read.
+    pub fn open_recover(&self, path: impl AsRef<Path>) -> Result<(Env, CheckReport), DBError> {
+        self.open_recover_with(path, &mut no_progress)
+    }
+
+    /// Like `open_recover`, reporting progress to `progress`: pages checked,
+    /// then entries written if the tree has to be rebuilt. The rebuild
+    /// commits only once every entry is written, so cancelling it leaves the
+    /// file as it was.
+    pub fn open_recover_with(
+        &self,
+        path: impl AsRef<Path>,
+        progress: &mut ProgressFn,
+    ) -> Result<(Env, CheckReport), DBError> {
+        let env = self.open(path)?;
+        let report = env.recover(progress)?;
+        Ok((env, report))
+    }
+
+    /// Creates a new file at `path` holding `entries`, which must be in
+    /// strictly increasing key order, building the tree bottom-up in a single
+    /// commit. Fails if `path` already exists.
+    pub fn bulk_load<K, V>(
+        &self,
+        path: impl AsRef<Path>,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Env, DBError>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let path = path.as_ref();
+        OpenOptions::new().write(true).create_new(true).open(path)?;
+        let env = self.open(path)?;
+        let mut txn = env.begin_write();
+        txn.write_batch(entries)?;
+        txn.commit()?;
+        Ok(env)
+    }
+
+    /// Puts the entries of a dump from `Env::dump` into the file at `path`,
+    /// creating it if need be, in a single commit, overwriting entries it
+    /// already holds under the same keys. The file is opened in the order the
+    /// dump records, in place of `key_order`, so opening a file in another
+    /// order fails with `IncompatibleFile`; only a dump in an order without
+    /// an id, or from version 1, which didn't record one, is loaded in
+    /// `key_order`. Nothing is committed if the dump turns out damaged.
+    pub fn load(&self, path: impl AsRef<Path>, mut dump: impl Read) -> Result<Env, DBError> {
+        export::load(self, path.as_ref(), &mut dump)
+    }
+}
+
+/// Left behind by `Env::emergency_sync` and reported by the next open.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CrashMarker {
+    /// The last commit made before the crash.
+    pub txnid: TxnId,
+}
+
+/// How far the file grows when a commit runs past its end; each growth raises
+/// `Event::MapGrown` and is counted in `EnvStat::map_growths`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GrowthPolicy {
+    /// As far as the commit needs, rounded up to a whole number of steps of
+    /// this many bytes; 0 doesn't round.
+    Step(u64),
+    /// By the file's current size, so it doubles, but by no more than
+    /// `max_step` bytes at a time; `u64::MAX` leaves it uncapped.
+    Doubling { max_step: u64 },
+    /// By this percentage of the file's current size.
+    Percent(u32),
+}
+
+impl Default for GrowthPolicy {
+    fn default() -> Self {
+        GrowthPolicy::Step(0)
+    }
+}
+
+impl GrowthPolicy {
+    /// The size a `size`-byte file is grown to so it holds `needed` bytes:
+    /// never less than that, and a whole number of pages unless a step says
+    /// otherwise.
+    pub fn grow(&self, size: u64, needed: u64, page_size: usize) -> u64 {
+        let page_size = page_size as u64;
+        let target = match *self {
+            GrowthPolicy::Step(step) => {
+                let step = step.max(1);
+                return needed.div_ceil(step) * step;
+            }
+            GrowthPolicy::Doubling { max_step } => size.saturating_add(size.min(max_step)),
+            GrowthPolicy::Percent(percent) => {
+                size.saturating_add((size as u128 * percent as u128 / 100) as u64)
+            }
+        };
+        target.max(needed).div_ceil(page_size) * page_size
+    }
+}
+
+/// Page counts for the most recent commit, from `Env::stat`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnvStat {
+    pub page_size: usize,
+    pub file_size: u64,
+    /// Pages allocated so far, the meta pages included.
+    pub total_pages: u64,
+    pub meta_pages: u64,
+    /// Allocated pages the tree no longer reaches: older copies of pages a
+    /// write transaction has since replaced.
+    pub free_pages: u64,
+    /// Times the file has grown since the environment was opened.
+    pub map_growths: u64,
+    pub flush: FlushStat,
+    pub page_cache: PageCacheStat,
+    /// `None` if the environment keeps no key filter.
+    pub key_filter: Option<KeyFilterStat>,
+    /// `None` unless the environment was opened with `EnvOptions::wal`.
+    pub wal: Option<WalStat>,
+    /// Pages served from memory and read from the file by the cache of
+    /// `IoBackend::Buffered`; `None` when the file is mapped.
+    pub buffer_pool: Option<PageCacheStat>,
+    pub tree: TreeStat,
+}
+
+/// What a file records about itself in its meta pages, from `Env::info`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EnvInfo {
+    pub format_version: u16,
+    pub page_size: usize,
+    /// `meta::BYTE_ORDER_MARKER`, as every field is stored little-endian.
+    pub byte_order_marker: u32,
+    /// In milliseconds since the Unix epoch; `None` for files created before
+    /// format version 5, which didn't record it.
+    pub created_at: Option<u64>,
+    pub last_txnid: TxnId,
+    pub entries: u64,
+    /// Whatever the application set with `WriteTxn::set_user_meta`.
+    pub user_meta: Vec<u8>,
+    /// The last id `WriteTxn::next_id` handed out.
+    pub sequence: u64,
+}
+
+/// How long commits spent syncing to disk: their pages first, then the meta
+/// page that makes them current. Sync costs dominate commit latency, so this
+/// is what to look at when choosing how durable commits need to be. The
+/// percentiles cover the last `FLUSH_SAMPLES` commits, and are zero until the
+/// first one. Prepared transactions (see `WriteTxn::prepare`) aren't counted.
+/// With `EnvOptions::wal`, a commit's data time is how long it waited for the
+/// sync of the log that covered it, shared with the rest of its group, and
+/// its meta time that of the checkpoint it ran, zero for most commits.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FlushStat {
+    /// Commits since the environment was opened.
+    pub commits: u64,
+    pub data_p50: Duration,
+    pub data_p99: Duration,
+    pub meta_p50: Duration,
+    pub meta_p99: Duration,
+}
+
+#[derive(Default)]
+struct FlushTimes {
+    commits: u64,
+    // (data, meta) flush times of the most recent commits, oldest first
+    recent: VecDeque<(Duration, Duration)>,
+}
+
+impl FlushTimes {
+    fn record(&mut self, data: Duration, meta: Duration) {
+        if self.recent.len() == FLUSH_SAMPLES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((data, meta));
+        self.commits += 1;
+    }
+
+    fn stat(&self) -> FlushStat {
+        // nearest-rank percentiles
+        let percentiles = |time: fn(&(Duration, Duration)) -> Duration| {
+            let mut times: Vec<Duration> = self.recent.iter().map(time).collect();
+            times.sort_unstable();
+            let rank = |p: usize| (times.len() * p).div_ceil(100).saturating_sub(1);
+            let at = |p| times.get(rank(p)).copied().unwrap_or_default();
+            (at(50), at(99))
+        };
+        let (data_p50, data_p99) = percentiles(|times| times.0);
+        let (meta_p50, meta_p99) = percentiles(|times| times.1);
+        FlushStat {
+            commits: self.commits,
+            data_p50,
+            data_p99,
+            meta_p50,
+            meta_p99,
+        }
+    }
+}
+
+/// File sizes in bytes before and after `Env::compact`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CompactReport {
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// Everything `emergency_sync` needs, prepared up front so that the sync
+/// itself makes only async-signal-safe calls: no allocation and no locks.
+struct Emergency {
+    file: File,
+    marker_path: CString,
+    txnid: AtomicU64,
+    // set once a panic hook has left a marker, which a clean close takes back
+    panicked: AtomicBool,
+    // set as the environment is dropped, after which nothing is written
+    closed: AtomicBool,
+}
+
+impl Emergency {
+    fn sync(&self) {
+        if self.closed.load(atomic::Ordering::Acquire) {
+            return;
+        }
+        let mut marker = [0u8; CRASH_MARKER_SIZE];
+        marker[..8].copy_from_slice(CRASH_MAGIC);
+        marker[8..].copy_from_slice(&self.txnid.load(atomic::Ordering::Acquire).to_le_bytes());
+        // errors are ignored: there is nothing left to report them to
+        unsafe {
+            libc::fsync(self.file.as_raw_fd());
+            let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC;
+            let fd = libc::open(self.marker_path.as_ptr(), flags, 0o644);
+            if fd >= 0 {
+                libc::write(fd, marker.as_ptr().cast(), marker.len());
+                libc::fsync(fd);
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// How many environments at a time `Env::install_panic_hook` can cover.
+pub const MAX_HOOKED_ENVS: usize = 64;
+
+// The environments synced on a panic or fatal signal, each slot holding a
+// reference taken with `Arc::into_raw` until the environment is dropped. A
+// fixed array of atomics, as the signal handler can neither lock nor
+// allocate.
+static HOOK_TARGETS: [AtomicPtr<Emergency>; MAX_HOOKED_ENVS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_HOOKED_ENVS];
+static INSTALL_HOOKS: Once = Once::new();
+// how many hooks are going through the slots, so that an environment being
+// dropped can tell whether one might still be using it
+static HOOKS_RUNNING: AtomicUsize = AtomicUsize::new(0);
+// the actions the signal handler replaced, which it hands each signal on to
+static PREVIOUS_ACTIONS: OnceLock<[libc::sigaction; FATAL_SIGNALS.len()]> = OnceLock::new();
+
+fn for_each_hook_target(f: impl Fn(&Emergency)) {
+    HOOKS_RUNNING.fetch_add(1, atomic::Ordering::SeqCst);
+    for slot in &HOOK_TARGETS {
+        let target = slot.load(atomic::Ordering::SeqCst);
+        if !target.is_null() {
+            f(unsafe { &*target });
+        }
+    }
+    HOOKS_RUNNING.fetch_sub(1, atomic::Ordering::SeqCst);
+}
+
+// once per process, whichever environment asks first
+fn install_hooks() {
+    INSTALL_HOOKS.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            for_each_hook_target(|emergency| {
+                emergency.panicked.store(true, atomic::Ordering::Release);
+                emergency.sync();
+            });
+            previous(info);
+        }));
+        // saved before any is replaced, so the handler always has one to go on to
+        let actions = FATAL_SIGNALS.map(|signum| unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(signum, ptr::null(), &mut action);
+            action
+        });
+        let _ = PREVIOUS_ACTIONS.set(actions);
+        for signum in FATAL_SIGNALS {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = on_fatal_signal as SigInfoHandler as usize;
+                // on the thread's alternate stack, so an overflowed stack is
+                // handled too
+                action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signum, &action, ptr::null_mut());
+            }
+        }
+    });
+}
+
+const FATAL_SIGNALS: [libc::c_int; 5] =
+    [libc::SIGSEGV, libc::SIGBUS, libc::SIGILL, libc::SIGFPE, libc::SIGABRT];
+
+type SigInfoHandler = extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void);
+
+extern "C" fn on_fatal_signal(
+    signum: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    for_each_hook_target(Emergency::sync);
+    let index = FATAL_SIGNALS.iter().position(|&fatal| fatal == signum);
+    let previous = index.and_then(|index| Some(PREVIOUS_ACTIONS.get()?[index]));
+    // a zeroed action is the default one
+    let previous = previous.unwrap_or_else(|| unsafe { std::mem::zeroed() });
+    unsafe {
+        match previous.sa_sigaction {
+            // put back and raised again, so the process still dies as it would have
+            libc::SIG_DFL | libc::SIG_IGN => {
+                libc::sigaction(signum, &previous, ptr::null_mut());
+                libc::raise(signum);
+            }
+            // called as the signal would have called it, with what the signal
+            // carries, as the standard library's stack overflow report needs
+            handler if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+                std::mem::transmute::<usize, SigInfoHandler>(handler)(signum, info, context);
+            }
+            handler => {
+                std::mem::transmute::<usize, extern "C" fn(libc::c_int)>(handler)(signum);
+            }
+        }
+    }
+}
+
+// the most recent commit, as seen by transactions that begin now; a map
+// covers every page that commit references, and the key filter, if there is
+// one, every key
+struct Current {
+    meta: Meta,
+    file: PageFile,
+    key_filter: Option<Arc<KeyFilter>>,
+}
+
+/// An open database file, shared between threads by reference or in an `Arc`.
+/// Any number of read transactions run alongside the single write transaction:
+/// a reader only takes a lock in `begin_read` to pick up the current commit,
+/// and reads pages straight from the map after that. A write transaction holds
+/// the writer lock until it commits or is dropped, so it can't leave the
+/// thread that began it.
+pub struct Env {
+    path: PathBuf,
+    file: File,
+    page_size: usize,
+    current: RwLock<Current>,
+    // writable map of the whole file, or the buffer pool, only written
+    // through by the writer; a map is replaced when the file grows, while
+    // readers keep the maps they have
+    map: Mutex<FileWriter>,
+    growth: GrowthPolicy,
+    map_growths: AtomicU64,
+    flushes: Mutex<FlushTimes>,
+    readers: Arc<ReaderTable>,
+    merge: Option<MergeFn>,
+    min_fill: f64,
+    max_dirty_pages: usize,
+    fixed_keys: bool,
+    split_bias: SplitBias,
+    key_order: KeyOrder,
+    compress_values: usize,
+    page_checks: PageChecks,
+    // opened with `open_read_only`: no writer lock, and commits made by the
+    // writer are picked up from the file
+    read_only: bool,
+    // held by the one write transaction allowed at a time
+    writer: Mutex<()>,
+    emergency: Arc<Emergency>,
+    last_crash: Option<CrashMarker>,
+    // the commit `WriteTxn::prepare` recorded, until it is resolved
+    prepared: Mutex<Option<Meta>>,
+    events: Arc<EventBus>,
+    pins: Mutex<PinTable>,
+    // what commits replaced, for open optimistic transactions
+    conflicts: Mutex<ConflictTable>,
+    // advised on every new map of the file
+    access: Mutex<AccessPattern>,
+    page_cache: Arc<PageCache>,
+    wal: Option<Wal>,
+    metrics: Arc<Recorder>,
+    // the next page number and root of each commit, kept by the writer
+    changes: Option<Mutex<PageChanges>>,
+    // signalled as each commit is recorded in `changes`
+    recorded: Condvar,
+}
+
+// a clean close leaves the file holding every commit without the log, for
+// environments that open it read-only and tools that read it directly
+impl Drop for Env {
+    fn drop(&mut self) {
+        if self.wal.is_some() {
+            // on failure, the next open replays the log instead
+            let _ = self.checkpoint();
+        }
+        self.emergency.closed.store(true, atomic::Ordering::Release);
+        let target = Arc::as_ptr(&self.emergency).cast_mut();
+        for slot in &HOOK_TARGETS {
+            let taken = slot.compare_exchange(
+                target,
+                ptr::null_mut(),
+                atomic::Ordering::SeqCst,
+                atomic::Ordering::Acquire,
+            );
+            // a hook that loaded the slot before it was emptied may still be
+            // using it, in which case the slot's reference is left behind
+            // rather than dropped under the hook; `closed` keeps it inert
+            if taken.is_ok() && HOOKS_RUNNING.load(atomic::Ordering::SeqCst) == 0 {
+                drop(unsafe { Arc::from_raw(target) });
+            }
+        }
+        // the process got past the panic, caught or confined to a thread, so
+        // it didn't crash with the file open
+        if self.emergency.panicked.load(atomic::Ordering::Acquire) {
+            let _ = fs::remove_file(Self::sibling_path(&self.path, "-crash"));
+        }
+    }
+}
+
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Env>();
+    assert_send_sync::<ReadTxn>();
+    assert_send_sync::<Snapshot>();
+};
+
+impl Env {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DBError> {
+        EnvOptions::default().open(path)
+    }
+
+    /// See `EnvOptions::open_read_only`.
+    pub fn open_read_only(path: impl AsRef<Path>) -> Result<Self, DBError> {
+        EnvOptions::default().open_read_only(path)
+    }
+
+    /// See `EnvOptions::open_recover`.
+    pub fn open_recover(path: impl AsRef<Path>) -> Result<(Self, CheckReport), DBError> {
+        EnvOptions::default().open_recover(path)
+    }
+
+    fn recover(&self, progress: &mut ProgressFn) -> Result<CheckReport, DBError> {
+        let txn = self.begin_read()?;
+        let order = txn.tree().get_order();
+        let mmap = txn.get_mmap()?;
+        let (entries, checked) = salvage_commit(mmap, txn.get_meta(), order, progress)?;
+        drop(txn);
+        if checked.is_ok() {
+            return Ok(checked);
+        }
+        let total = entries.len() as u64;
+        let mut cancelled = None;
+        let entries = entries.into_iter().zip(1..).map_while(|(entry, done)| {
+            match report(progress, Stage::Rebuilding, done, total) {
+                Ok(()) => Some(entry),
+                Err(err) => {
+                    cancelled = Some(err);
+                    None
+                }
+            }
+        });
+        let mut txn = self.begin_write();
+        txn.clear();
+        txn.write_batch(entries)?;
+        if let Some(err) = cancelled {
+            return Err(err);
+        }
+        txn.commit()?;
+        Ok(checked)
+    }
+
+    /// Checks every page the current commit reaches; see `check_with`.
+    pub fn check(&self) -> Result<CheckReport, DBError> {
+        self.check_with(&mut no_progress)
+    }
+
+    /// Checks every page the current commit reaches, as `mmdb verify` does,
+    /// reporting progress to `progress`. It reads through a read transaction
+    /// like any other, so it blocks neither readers nor writers, and only
+    /// holds on to the commit it checks.
+    pub fn check_with(&self, progress: &mut ProgressFn) -> Result<CheckReport, DBError> {
+        let txn = self.begin_read()?;
+        let (mmap, meta, order) = (txn.get_mmap()?, txn.get_meta(), Some(self.key_order));
+        check_commit_with(mmap, meta, order, &mut HashSet::new(), progress)
+    }
+
+    fn open_with(path: &Path, options: &EnvOptions, read_only: bool) -> Result<Self, DBError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .create(!read_only)
+            .truncate(false)
+            .open(path)?;
+        // taken before the file is initialized, so two writers can't both
+        // find it empty
+        if !read_only {
+            Self::lock_writer(&file, options.wait_for_lock)?;
+            if file.metadata()?.len() == 0 {
+                Self::init_file(&mut file, options.page_size, options.key_order)?;
+            }
+        }
+        let metrics = Arc::new(Recorder::new(options.metrics.clone()));
+        let (meta, pages) = match options.io_backend {
+            IoBackend::Mmap => {
+                let mmap = unsafe { Mmap::map(&file)? };
+                (Meta::read(&mmap)?, PageFile::Mapped(Arc::new(mmap)))
+            }
+            IoBackend::Buffered { cache_pages } => {
+                let meta = Meta::read(&Self::read_meta_pages(&file)?)?;
+                let pool = BufferPool::new(file.try_clone()?, meta.get_page_size(), cache_pages);
+                let pool = pool.with_metrics(Arc::clone(&metrics) as Arc<dyn Metrics>);
+                (meta, PageFile::Buffered(Arc::new(pool)))
+            }
+        };
+        if !meta.has_key_order(options.key_order) {
+            return Err(DBError::IncompatibleFile { reason: "keys are in another order" });
+        }
+        if !read_only && file.metadata()?.len() < options.map_size {
+            file.set_len(options.map_size)?;
+        }
+        let map = match &pages {
+            _ if read_only => FileWriter::ReadOnly { len: file.metadata()?.len() },
+            PageFile::Mapped(_) => FileWriter::Mapped(unsafe { MmapMut::map_mut(&file)? }),
+            PageFile::Buffered(pool) => {
+                FileWriter::Buffered { pool: Arc::clone(pool), len: file.metadata()?.len() }
+            }
+        };
+
+        let events = Arc::new(EventBus::new(options.subscribers.clone()));
+        let lock_path = Self::sibling_path(path, "-lock");
+        let readers = match read_only {
+            true => ReaderTable::open_read_only(&lock_path, options.max_readers)?,
+            false => ReaderTable::open(&lock_path, options.max_readers)?,
+        };
+        let readers = Arc::new(readers.with_events(Arc::clone(&events)));
+        let marker_path = Self::sibling_path(path, "-crash");
+        // left for the writer to report
+        let last_crash = match read_only {
+            true => None,
+            false => Self::take_crash_marker(&marker_path)?,
+        };
+        // resolving a prepared commit is up to the writer, and reading its
+        // record could mean removing it
+        let prepared = match read_only {
+            true => None,
+            false => Self::read_prepared(&Self::sibling_path(path, "-prepared"), &meta)?,
+        };
+        let emergency = Arc::new(Emergency {
+            // opened again rather than cloned, so that a panic hook keeping
+            // it past the environment doesn't keep the writer lock too
+            file: File::open(path)?,
+            marker_path: CString::new(marker_path.as_os_str().as_bytes()).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte")
+            })?,
+            txnid: AtomicU64::new(meta.get_txnid()),
+            panicked: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        });
+
+        if let Some((count, on_report)) = options.sample.clone() {
+            let mmap = Arc::clone(pages.view().as_mmap()?);
+            let order = options.key_order;
+            Self::spawn_sample(meta, mmap, order, count, on_report, Arc::clone(&events))?;
+        }
+
+        let mut env = Env {
+            path: path.to_path_buf(),
+            file,
+            page_size: meta.get_page_size(),
+            current: RwLock::new(Current { meta, file: pages, key_filter: None }),
+            map: Mutex::new(map),
+            growth: options.growth,
+            map_growths: AtomicU64::new(0),
+            flushes: Mutex::new(FlushTimes::default()),
+            readers,
+            merge: options.merge,
+            min_fill: options.min_fill,
+            max_dirty_pages: options.max_dirty_pages,
+            fixed_keys: options.fixed_keys,
+            split_bias: options.split_bias,
+            key_order: options.key_order,
+            compress_values: options.compress_values,
+            page_checks: PageChecks {
+                checksums: options.verify_checksums,
+                nodes: options.paranoid_reads,
+            },
+            read_only,
+            writer: Mutex::new(()),
+            emergency,
+            last_crash,
+            prepared: Mutex::new(prepared),
+            events,
+            pins: Mutex::new(PinTable::new()),
+            conflicts: Mutex::new(ConflictTable::new()),
+            access: Mutex::new(AccessPattern::Normal),
+            page_cache: Arc::new(
+                PageCache::new(options.page_cache).with_metrics(Arc::clone(&metrics) as _),
+            ),
+            wal: None,
+            metrics,
+            changes: None,
+            recorded: Condvar::new(),
+        };
+        let wal_path = Self::sibling_path(path, "-wal");
+        if !read_only && (options.wal.is_some() || wal_path.exists()) {
+            let wal = Wal::open(&wal_path, options.wal.unwrap_or_default(), meta)?
+                .with_metrics(Arc::clone(&env.metrics) as _);
+            env.replay_wal(&wal)?;
+            match options.wal {
+                Some(_) => env.wal = Some(wal),
+                None => fs::remove_file(&wal_path)?,
+            }
+        }
+        if !read_only {
+            let changes = PageChanges::open(&Self::sibling_path(path, "-changes"), &meta)?;
+            env.changes = Some(Mutex::new(changes));
+        }
+        if options.key_filter > 0 && !read_only {
+            let filter = env.load_key_filter(options.key_filter)?;
+            env.current.get_mut().unwrap_or_else(PoisonError::into_inner).key_filter =
+                Some(Arc::new(filter));
+        }
+        Ok(env)
+    }
+
+    // the sample only covers pages of the commit found on open, which are
+    // never written again, so it can run alongside later commits
+    fn spawn_sample(
+        meta: Meta,
+        mmap: Arc<Mmap>,
+        order: KeyOrder,
+        count: usize,
+        on_report: SampleHook,
+        events: Arc<EventBus>,
+    ) -> Result<(), DBError> {
+        let pgnos = NUM_META_PAGES..meta.get_next_pgno();
+        thread::Builder::new().name("mmdb-sample".into()).spawn(move || {
+            let report = check_sample(&mmap, meta.get_page_size(), pgnos, count, Some(order));
+            for problem in &report.problems {
+                events.emit(Event::CorruptionDetected(problem.clone()));
+            }
+            on_report(report);
+        })?;
+        Ok(())
+    }
+
+    /// Loads a dump from `Env::dump` into the file at `path`; see
+    /// `EnvOptions::load`.
+    pub fn load(path: impl AsRef<Path>, mut dump: impl Read) -> Result<Self, DBError> {
+        EnvOptions::default().load(path, &mut dump)
+    }
+
+    /// Creates a new file holding `entries`; see `EnvOptions::bulk_load`.
+    pub fn bulk_load<K, V>(
+        path: impl AsRef<Path>,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, DBError>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        EnvOptions::default().bulk_load(path, entries)
+    }
+
+    fn init_file(file: &mut File, page_size: usize, order: KeyOrder) -> Result<(), DBError> {
+        for page in Meta::new(page_size)?.with_key_order(order).write_slots() {
+            file.write_all(page.as_bytes())?;
+        }
+        file.sync_all()?;
+        Ok(())
+    }
+
+    // Takes the exclusive `flock` on the file that marks its one writer; the
+    // lock goes with the last descriptor of the file, when the environment
+    // is dropped or the process exits.
+    fn lock_writer(file: &File, wait: bool) -> Result<(), DBError> {
+        let operation = match wait {
+            true => libc::LOCK_EX,
+            false => libc::LOCK_EX | libc::LOCK_NB,
+        };
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            match err.kind() {
+                io::ErrorKind::Interrupted => continue,
+                io::ErrorKind::WouldBlock => return Err(DBError::WriterLocked),
+                _ => return Err(err.into()),
+            }
+        }
+    }
+
+    // enough of the start of the file for `Meta::read` to find both meta
+    // pages, whatever the page size
+    fn read_meta_pages(file: &File) -> Result<Vec<u8>, DBError> {
+        let len = file.metadata()?.len().min(2 * MAX_PAGE_SIZE as u64);
+        let mut head = vec![0; len as usize];
+        file.read_exact_at(&mut head, 0)?;
+        Ok(head)
+    }
+
+    fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+        let mut sibling = OsString::from(path.as_os_str());
+        sibling.push(suffix);
+        PathBuf::from(sibling)
+    }
+
+    // A record of a commit that is already current was committed before a
+    // crash kept it from being removed, and one that doesn't parse was never
+    // fully written, so `prepare` never returned; both are dropped.
+    fn read_prepared(path: &Path, current: &Meta) -> Result<Option<Meta>, DBError> {
+        let record = match fs::read(path) {
+            Ok(record) => record,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let meta = Page::from_bytes(&record).and_then(|page| {
+            page.as_page_ref().verify_checksum()?;
+            Meta::from(page.as_page_ref())
+        });
+        match meta {
+            Ok(meta) if meta.get_txnid() == current.get_txnid() + 1 => Ok(Some(meta)),
+            _ => {
+                fs::remove_file(path)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn take_crash_marker(marker_path: &Path) -> Result<Option<CrashMarker>, DBError> {
+        let marker = match fs::read(marker_path) {
+            Ok(marker) => marker,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        fs::remove_file(marker_path)?;
+        if marker.len() != CRASH_MARKER_SIZE || &marker[..8] != CRASH_MAGIC {
+            return Err(DBError::CorruptValue {
+                reason: "malformed crash marker",
+            });
+        }
+        Ok(Some(CrashMarker {
+            txnid: TxnId::from_le_bytes(marker[8..].try_into().unwrap()),
+        }))
+    }
+
+    /// Writes the current commit out as up to `n` self-contained files in
+    /// `dir`, one per disjoint key range, in parallel. The ranges are split at
+    /// keys from the top of the tree, so they are only roughly even. If this
+    /// fails, files already written are left in `dir`.
+    pub fn export_partitions(
+        &self,
+        n: usize,
+        dir: impl AsRef<Path>,
+    ) -> Result<Vec<Partition>, DBError> {
+        export::export_partitions(self, n, dir.as_ref())
+    }
+
+    /// Writes a consistent copy of the current commit to a new file at
+    /// `path`, page for page, while writers carry on. Fails if `path` already
+    /// exists.
+    pub fn copy_to(&self, path: impl AsRef<Path>) -> Result<(), DBError> {
+        export::copy_to(self, path.as_ref())
+    }
+
+    /// Writes every entry of the current commit to `dump`, in key order, in
+    /// the format of `DumpWriter`, along with the key order, while writers
+    /// carry on; `EnvOptions::load` reads it back into a file.
+    pub fn dump(&self, mut dump: impl Write) -> Result<(), DBError> {
+        export::dump(self, &mut dump)
+    }
+
+    /// Like `copy_to`, but rebuilds the tree from its entries, leaving out
+    /// the pages older commits left behind and soft-deleted entries, with
+    /// every page filled.
+    pub fn copy_to_compacted(&self, path: impl AsRef<Path>) -> Result<(), DBError> {
+        export::copy_to_compacted(self, path.as_ref())
+    }
+
+    /// The pages the commits after `txnid` wrote, up to the current one.
+    /// Committed pages are never written again, so they are the pages past
+    /// the end of `txnid`'s commit. Since a commit from before the last
+    /// compaction, or before the file was opened by a version that records
+    /// commits, every page counts as changed, as it does since any commit in
+    /// an environment opened read-only.
+    pub fn pages_changed_since(&self, txnid: TxnId) -> Range<Pgno> {
+        let next_pgno = self.get_meta().get_next_pgno();
+        let first = match &self.changes {
+            Some(changes) => {
+                changes.lock().unwrap_or_else(PoisonError::into_inner).first_changed(txnid)
+            }
+            None => NUM_META_PAGES,
+        };
+        first.min(next_pgno)..next_pgno
+    }
+
+    /// The changes of every commit after `txnid`, key by key, in commit order
+    /// and then in key order, followed by those of each commit made from
+    /// then on as it is made: the stream blocks waiting for the next one, but
+    /// see `ChangeStream::try_next`. It finds them by comparing each commit's
+    /// tree with the one before, reading only the pages the commit wrote, so
+    /// while it lasts it holds a reader slot on the last commit it handed
+    /// out, which keeps compaction from running. Waits for the write
+    /// transaction open, if any. Fails with `Unsupported` for a commit from
+    /// before the last compaction or before the file was opened by a version
+    /// that records commits, and in an environment opened read-only.
+    pub fn subscribe_changes(&self, txnid: TxnId) -> Result<ChangeStream<'_>, DBError> {
+        // no compaction is under way while the writer lock is held, so the
+        // pages of `txnid` are still there once the slot holds them
+        let _writer = self.lock_writer_txn();
+        let slot = self.readers.register_snapshot(txnid)?;
+        self.next_recorded(txnid, false)?;
+        Ok(ChangeStream::new(self, txnid, slot))
+    }
+
+    // Commit `txnid`, as `subscribe_changes` needs it to be recorded, and the
+    // first one recorded after it, waiting for it if `wait` is set.
+    pub(crate) fn next_recorded(
+        &self,
+        txnid: TxnId,
+        wait: bool,
+    ) -> Result<(RecordedCommit, Option<RecordedCommit>), DBError> {
+        let Some(changes) = &self.changes else {
+            return Err(DBError::Unsupported { reason: "no commits recorded read-only" });
+        };
+        let mut changes = changes.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            let Some((commit, next)) = changes.next_after(txnid) else {
+                return Err(DBError::Unsupported { reason: "commit not recorded" });
+            };
+            if next.is_some() || !wait {
+                return Ok((commit, next));
+            }
+            changes = self.recorded.wait(changes).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    pub(crate) fn get_readers(&self) -> &Arc<ReaderTable> {
+        &self.readers
+    }
+
+    /// Writes the pages changed since commit `txnid`, as `pages_changed_since`
+    /// finds them, and the meta pages of the current commit to `backup`,
+    /// while writers carry on. `apply_incremental_backup` brings a copy of
+    /// commit `txnid` or later, from `copy_to` or an earlier backup, up to
+    /// the current commit with them, far more cheaply than a new copy.
+    pub fn incremental_backup(
+        &self,
+        txnid: TxnId,
+        mut backup: impl Write,
+    ) -> Result<IncrementalBackup, DBError> {
+        export::incremental_backup(self, txnid, &mut backup)
+    }
+
+    /// Writes the pages of a backup from `incremental_backup` into the copy at
+    /// `path`, which no environment may have open, then its meta pages, and
+    /// returns the meta of the commit the copy is now at. Fails with
+    /// `IncompatibleFile` if the backup starts past the copy's commit.
+    pub fn apply_incremental_backup(
+        path: impl AsRef<Path>,
+        mut backup: impl Read,
+    ) -> Result<Meta, DBError> {
+        export::apply_incremental_backup(path.as_ref(), &mut backup)
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub const fn get_page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub const fn get_merge_operator(&self) -> Option<MergeFn> {
+        self.merge
+    }
+
+    pub const fn get_min_fill(&self) -> f64 {
+        self.min_fill
+    }
+
+    pub const fn get_max_dirty_pages(&self) -> usize {
+        self.max_dirty_pages
+    }
+
+    pub const fn get_fixed_keys(&self) -> bool {
+        self.fixed_keys
+    }
+
+    /// What reads of committed pages check; see `EnvOptions::verify_checksums`
+    /// and `EnvOptions::paranoid_reads`.
+    pub const fn get_page_checks(&self) -> PageChecks {
+        self.page_checks
+    }
+
+    pub const fn get_split_bias(&self) -> SplitBias {
+        self.split_bias
+    }
+
+    pub const fn get_key_order(&self) -> KeyOrder {
+        self.key_order
+    }
+
+    pub(crate) fn get_metrics(&self) -> &Recorder {
+        &self.metrics
+    }
+
+    pub(crate) fn get_conflicts(&self) -> MutexGuard<'_, ConflictTable> {
+        self.conflicts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// See `EnvOptions::compress_values`.
+    pub const fn get_compress_values(&self) -> usize {
+        self.compress_values
+    }
+
+    /// Whether the environment was opened with `EnvOptions::open_read_only`.
+    pub const fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Meta of the most recent commit.
+    pub fn get_meta(&self) -> Meta {
+        self.current().0
+    }
+
+    /// Number of live entries as of the most recent commit, kept in its meta
+    /// page, so this takes no reads.
+    pub fn len(&self) -> u64 {
+        self.get_meta().get_entries()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn current(&self) -> (Meta, PageFile) {
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        (current.meta, current.file.clone())
+    }
+
+    /// The filter `EnvOptions::key_filter` keeps, which holds every key of
+    /// the current commit.
+    pub fn get_key_filter(&self) -> Option<Arc<KeyFilter>> {
+        self.current.read().unwrap_or_else(PoisonError::into_inner).key_filter.clone()
+    }
+
+    // A filter holding every key of the current commit, and of the prepared
+    // one, if there is one, so `commit_prepared` can make it current later.
+    fn load_key_filter(&self, bits_per_key: usize) -> Result<KeyFilter, DBError> {
+        let txn = self.begin_read()?;
+        let prepared = *self.prepared.lock().unwrap_or_else(PoisonError::into_inner);
+        let filter = KeyFilter::new(bits_per_key, txn.len().saturating_mul(2));
+        let order = txn.tree().get_order();
+        let roots = prepared.map(|meta| meta.get_root());
+        for root in [txn.get_meta().get_root()].into_iter().chain(roots) {
+            for entry in BTree::new(&txn, root, order).cursor()? {
+                filter.insert(&entry?.0);
+            }
+        }
+        Ok(filter)
+    }
+
+    /// A read-only view of the most recent commit, unaffected by later ones.
+    /// Fails with `ReadersFull` if too many are already open.
+    pub fn begin_read(&self) -> Result<ReadTxn, DBError> {
+        if self.read_only {
+            self.refresh()?;
+        }
+        // registered before the current commit can be replaced, so a writer never
+        // sees fewer readers of it than there are
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        let slot = self.readers.register(current.meta.get_txnid())?;
+        let cache = Arc::clone(&self.page_cache);
+        let txn = ReadTxn::new(current.meta, current.file.view(), self.key_order, cache, slot);
+        let txn = txn.with_metrics(Arc::clone(&self.metrics) as _).with_checks(self.page_checks);
+        Ok(txn.with_key_filter(current.key_filter.clone()))
+    }
+
+    /// A read-only view of the current commit that, unlike a `ReadTxn`, is
+    /// cheap to clone and share between threads: every clone reads the same
+    /// commit, for as long as any of them is alive, while writes go on. Like
+    /// an open read transaction, it keeps the pages of its commit from being
+    /// reused, so a file written to grows while a snapshot is held.
+    pub fn snapshot(&self) -> Result<Snapshot, DBError> {
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        let slot = self.readers.register_snapshot(current.meta.get_txnid())?;
+        let cache = Arc::clone(&self.page_cache);
+        let txn = ReadTxn::new(current.meta, current.file.view(), self.key_order, cache, slot);
+        let txn = txn.with_metrics(Arc::clone(&self.metrics) as _).with_checks(self.page_checks);
+        Ok(Snapshot::new(txn.with_key_filter(current.key_filter.clone())))
+    }
+
+    /// The oldest commit an open read transaction or snapshot is looking at,
+    /// in this or any other process.
+    pub fn oldest_reader(&self) -> Option<TxnId> {
+        self.readers.oldest_reader()
+    }
+
+    /// The oldest commit a snapshot is holding on to, in this or any other
+    /// process; a long-lived snapshot stops compaction as far back as this.
+    pub fn oldest_snapshot(&self) -> Option<TxnId> {
+        self.readers.oldest_snapshot()
+    }
+
+    /// Tells the OS that reads will mostly be long scans, so it reads further
+    /// ahead and lets pages go soon after they are passed; for cold-cache
+    /// scans of large files. Cursors ask for the next few leaves of a scan
+    /// either way. Lasts until `advise_random` is called.
+    pub fn advise_sequential(&self) -> Result<(), DBError> {
+        self.advise(AccessPattern::Sequential)
+    }
+
+    /// Tells the OS that reads will mostly be point lookups, so it reads only
+    /// the pages asked for rather than the ones around them too. Lasts until
+    /// `advise_sequential` is called.
+    pub fn advise_random(&self) -> Result<(), DBError> {
+        self.advise(AccessPattern::Random)
+    }
+
+    fn advise(&self, pattern: AccessPattern) -> Result<(), DBError> {
+        let mut access = self.access.lock().unwrap_or_else(PoisonError::into_inner);
+        self.current().1.advise(pattern)?;
+        *access = pattern;
+        Ok(())
+    }
+
+    // A new map of the whole file, advised like the ones before it. The
+    // pattern stays locked until the map is made current, so an advice given
+    // meanwhile can't miss it.
+    fn map_file(&self) -> Result<(PageFile, MutexGuard<'_, AccessPattern>), DBError> {
+        let access = self.access.lock().unwrap_or_else(PoisonError::into_inner);
+        let file = PageFile::Mapped(Arc::new(unsafe { Mmap::map(&self.file)? }));
+        if *access != AccessPattern::Normal {
+            // only a hint, which a commit already made shouldn't fail over
+            let _ = file.advise(*access);
+        }
+        Ok((file, access))
+    }
+
+    /// Holds page `pgno` of the current commit for as long as the returned
+    /// handle or a clone of it; see `PinnedPage`. Takes a reader slot like
+    /// `snapshot`, and fails with `PageOutOfBounds` for a page number the
+    /// commit doesn't have.
+    pub fn pin_page(&self, pgno: Pgno) -> Result<PinnedPage, DBError> {
+        PinnedPage::new(self.snapshot()?, pgno)
+    }
+
+    /// Locks the pages that lookups of keys in `range` read, from the root
+    /// down to the leaves, into memory with `mlock`, so they never wait on the
+    /// disk; for a small working set with strict latency needs. The pages
+    /// are those of the most recent commit, and later commits write the pages
+    /// they change elsewhere, so a range that is written to should be pinned
+    /// again. Fails with `Io` if the process may not lock that much memory
+    /// (see `RLIMIT_MEMLOCK`), and with `Unsupported` under
+    /// `IoBackend::Buffered`, which has no map to lock. Returns the number of
+    /// pages pinned.
+    pub fn pin_range<'k>(&self, range: impl RangeBounds<&'k [u8]>) -> Result<usize, DBError> {
+        if let (_, PageFile::Buffered(_)) = self.current() {
+            return Err(DBError::Unsupported { reason: "pinning pages of an unmapped file" });
+        }
+        let txn = self.begin_read()?;
+        let key_range = key_range(&range);
+        let pages = txn.tree().pages_in_range(range)?;
+        let mut pins = self.pins.lock().unwrap_or_else(PoisonError::into_inner);
+        pins.pin(key_range, Arc::clone(txn.get_mmap()?), self.page_size, &pages)?;
+        Ok(pages.len())
+    }
+
+    /// Releases a pin `pin_range` took with the same bounds, and returns
+    /// whether there was one. Pages another pin also covers stay locked.
+    pub fn unpin_range<'k>(&self, range: impl RangeBounds<&'k [u8]>) -> Result<bool, DBError> {
+        self.pins.lock().unwrap_or_else(PoisonError::into_inner).unpin(&key_range(&range))
+    }
+
+    /// Takes the next id of the sequence in a commit of its own; see
+    /// `WriteTxn::next_id` to take ids along with the writes using them.
+    pub fn next_id(&self) -> Result<u64, DBError> {
+        let mut txn = self.begin_write();
+        let id = txn.next_id()?;
+        txn.commit()?;
+        Ok(id)
+    }
+
+    /// Describes the file from the meta page of the most recent commit, which,
+    /// unlike `stat`, doesn't read the tree.
+    pub fn info(&self) -> Result<EnvInfo, DBError> {
+        let txn = self.begin_read()?;
+        let meta = txn.get_meta();
+        Ok(EnvInfo {
+            format_version: META_VERSION,
+            page_size: meta.get_page_size(),
+            byte_order_marker: meta.get_byte_order_marker(),
+            created_at: meta.get_created_at(),
+            last_txnid: meta.get_txnid(),
+            entries: meta.get_entries(),
+            user_meta: meta.get_user_meta().to_vec(),
+            sequence: meta.get_sequence(),
+        })
+    }
+
+    /// What the environment has done since it was opened, as counted by the
+    /// hooks of `Metrics`; cheap enough to export on every scrape, unlike
+    /// `stat`.
+    pub fn metrics(&self) -> MetricsStat {
+        self.metrics.stat()
+    }
+
+    /// Walks the most recent commit to count its pages by type, like
+    /// `mdb_stat`. Takes time in proportion to the size of the tree.
+    pub fn stat(&self) -> Result<EnvStat, DBError> {
+        let txn = self.begin_read()?;
+        let tree = txn.tree().stat()?;
+        let total_pages = txn.get_meta().get_next_pgno();
+        let tree_pages = tree.branch_pages + tree.leaf_pages + tree.overflow_pages;
+        Ok(EnvStat {
+            page_size: self.page_size,
+            file_size: self.file.metadata()?.len(),
+            total_pages,
+            meta_pages: NUM_META_PAGES,
+            free_pages: total_pages - NUM_META_PAGES - tree_pages,
+            map_growths: self.map_growths.load(atomic::Ordering::Relaxed),
+            flush: self.flushes.lock().unwrap_or_else(PoisonError::into_inner).stat(),
+            page_cache: self.page_cache.stat(),
+            key_filter: self.get_key_filter().map(|filter| filter.stat()),
+            wal: self.wal.as_ref().map(Wal::stat),
+            buffer_pool: match self.current().1 {
+                PageFile::Mapped(_) => None,
+                PageFile::Buffered(pool) => Some(pool.stat()),
+            },
+            tree,
+        })
+    }
+
+    /// Starts a write transaction, waiting for the current one (if any) to
+    /// finish first.
+    pub fn begin_write(&self) -> WriteTxn<'_> {
+        let writer = self.lock_writer_txn();
+        let (meta, file) = self.current();
+        WriteTxn::new(self, writer, meta, file.view())
+    }
+
+    // a writer that panicked never published anything, so the lock is safe
+    // to take over
+    pub(crate) fn lock_writer_txn(&self) -> MutexGuard<'_, ()> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// A write transaction that, unlike `begin_write`, doesn't wait for the
+    /// one writer at a time: any number can be open at once, each building
+    /// its writes on the commit that was current when it began. The first
+    /// to commit wins. One committing after others have fails with
+    /// `Conflict` if any of them replaced a leaf it read, its writes
+    /// included, or took an id while it did too; otherwise its writes are
+    /// made again on top of the current commit. Like a read transaction, it
+    /// holds a reader slot, and so fails with `ReadersFull` if none is free;
+    /// `ingest_file` isn't supported in one.
+    pub fn begin_optimistic(&self) -> Result<WriteTxn<'_>, DBError> {
+        self.check_writable()?;
+        // counted before the current commit can be replaced, so every commit
+        // after its base is recorded
+        let mut conflicts = self.get_conflicts();
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        let slot = self.readers.register(current.meta.get_txnid())?;
+        conflicts.begin(current.meta.get_txnid());
+        Ok(WriteTxn::optimistic(self, current.meta, current.file.view(), slot))
+    }
+
+    // Writes a transaction's runs of consecutive pages, each starting at the
+    // given page number, then the meta page that makes them current, syncing
+    // after each so the meta never refers to pages that aren't on disk. None
+    // of the pages overwrite anything a reader can still see. With a log, the
+    // commit is appended to it instead, and the pages are written unsynced;
+    // the returned position is where the caller, once it has let the next
+    // writer go, waits for the log to be synced to, passing on how long the
+    // checkpoint the commit ran, if any, took.
+    pub(crate) fn write_commit(
+        &self,
+        runs: impl IntoIterator<Item = (Pgno, Vec<u8>)>,
+        meta: Meta,
+        rebuilt_filter: Option<KeyFilter>,
+    ) -> Result<Option<(u64, Duration)>, DBError> {
+        self.check_writable()?;
+        self.check_not_prepared()?;
+        let Some(wal) = &self.wal else {
+            self.write_pages(runs, meta)?;
+            self.publish_with(meta, rebuilt_filter)?;
+            return Ok(None);
+        };
+        let runs: Runs = runs.into_iter().collect();
+        let end = wal.append(&runs, &meta)?;
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        self.copy_data(&mut map, runs, meta.get_next_pgno())?;
+        drop(map);
+        self.publish_with(meta, rebuilt_filter)?;
+        let checkpoint_bytes = wal.get_options().checkpoint_bytes;
+        let mut checkpointed = Duration::ZERO;
+        if checkpoint_bytes > 0 && wal.len() >= checkpoint_bytes {
+            let started = Instant::now();
+            self.checkpoint_locked()?;
+            checkpointed = started.elapsed();
+        }
+        Ok(Some((end, checkpointed)))
+    }
+
+    // waits for the log to be synced up to where `write_commit` left commit
+    // `txnid`, then counts the wait as the time its pages took to flush, and
+    // its checkpoint as that of its meta page
+    pub(crate) fn wait_for_wal(
+        &self,
+        txnid: TxnId,
+        end: u64,
+        checkpointed: Duration,
+    ) -> Result<(), DBError> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        let started = Instant::now();
+        wal.wait_synced(end)?;
+        self.flushed(txnid, started.elapsed(), checkpointed);
+        Ok(())
+    }
+
+    /// With `EnvOptions::wal`, syncs the pages of the commits made since the
+    /// last checkpoint, writes the meta page of the latest one to the file
+    /// and empties the log, so the file holds them without it. Waits for the
+    /// write transaction, if there is one, to finish first. Does nothing
+    /// without a log, where every commit writes its meta page.
+    pub fn checkpoint(&self) -> Result<(), DBError> {
+        self.check_writable()?;
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        self.checkpoint_locked()
+    }
+
+    // `checkpoint`, for a caller holding the writer lock, so no commit can be
+    // appended to the log between the meta page written and its truncation
+    fn checkpoint_locked(&self) -> Result<(), DBError> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        let (meta, checkpoint) = (self.get_meta(), wal.get_checkpoint());
+        if meta.get_txnid() == checkpoint.get_txnid() {
+            return Ok(());
+        }
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        self.write_checkpoint(&mut map, &checkpoint, meta)?;
+        drop(map);
+        wal.reset(meta)?;
+        self.events.emit(Event::CheckpointCompleted { txnid: meta.get_txnid() });
+        Ok(())
+    }
+
+    // syncs the pages written since `checkpoint` (pages are never written
+    // below the end of the commit before them, short of a compaction, which
+    // checkpoints first), then writes the meta page of `meta` after them
+    fn write_checkpoint(
+        &self,
+        map: &mut FileWriter,
+        checkpoint: &Meta,
+        meta: Meta,
+    ) -> Result<(), DBError> {
+        let start = checkpoint.get_next_pgno().min(meta.get_next_pgno()) as usize;
+        let end = meta.get_next_pgno() as usize;
+        let range = (start * self.page_size, (end - start) * self.page_size);
+        self.timed_sync(|| map.flush(&[range]))?;
+        self.write_meta(map, meta)?;
+        Ok(())
+    }
+
+    // Writes the commits `wal` holds past the file's meta page into the file
+    // again, as a crash may have lost them, and checkpoints the last one. A
+    // commit is replayed whole or not at all: the log ends at the first
+    // record that wasn't fully written, which was never synced.
+    fn replay_wal(&self, wal: &Wal) -> Result<(), DBError> {
+        let checkpoint = self.get_meta();
+        let mut meta = checkpoint;
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        for record in wal.read_records(self.page_size)? {
+            // the log isn't emptied until the meta page written before it is
+            // synced, so it may still hold commits the file already has
+            if record.meta.get_txnid() <= meta.get_txnid() {
+                continue;
+            }
+            self.copy_data(&mut map, record.runs, record.meta.get_next_pgno())?;
+            meta = record.meta;
+        }
+        if meta.get_txnid() > checkpoint.get_txnid() {
+            self.write_checkpoint(&mut map, &checkpoint, meta)?;
+            drop(map);
+            self.publish(meta)?;
+        } else if wal.len() == 0 {
+            return Ok(());
+        }
+        wal.reset(meta)
+    }
+
+    // Catches a read-only environment up with the commits the writer has
+    // made since, which means reading the meta pages from the file again.
+    // Later commits write only past the end of the last one seen, so what is
+    // cached stays good, but for compaction, which hands page numbers back:
+    // it isn't meant to run while other processes have the file open (see
+    // `compact_with`), but a commit ending before the last one seen can only
+    // follow one, so the cache is emptied then. A compacted file that has
+    // grown past the old end again by the time it is seen isn't told apart.
+    fn refresh(&self) -> Result<(), DBError> {
+        let meta = Meta::read(&Self::read_meta_pages(&self.file)?)?;
+        let (current, file) = self.current();
+        if meta.get_txnid() <= current.get_txnid() {
+            return Ok(());
+        }
+        let (file, _access) = match file {
+            PageFile::Mapped(_) => self.map_file().map(|(file, access)| (file, Some(access)))?,
+            buffered => (buffered, None),
+        };
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        // another thread may have caught up further meanwhile
+        if meta.get_txnid() > current.meta.get_txnid() {
+            if meta.get_next_pgno() < current.meta.get_next_pgno() {
+                self.page_cache.invalidate(0..current.meta.get_next_pgno());
+            }
+            *current = Current { meta, file, key_filter: None };
+        }
+        Ok(())
+    }
+
+    // fails for an environment opened with `open_read_only`
+    fn check_writable(&self) -> Result<(), DBError> {
+        match self.read_only {
+            true => Err(DBError::TxnReadOnly),
+            false => Ok(()),
+        }
+    }
+
+    // makes `meta`, already on disk, the commit new readers see
+    fn publish(&self, meta: Meta) -> Result<(), DBError> {
+        self.publish_with(meta, None)
+    }
+
+    // `publish`, replacing the key filter with `rebuilt_filter` if there is
+    // one; either way, the filter new readers get holds every key of `meta`
+    fn publish_with(&self, meta: Meta, rebuilt_filter: Option<KeyFilter>) -> Result<(), DBError> {
+        let (file, _access) = match self.current().1 {
+            PageFile::Mapped(_) => self.map_file().map(|(file, access)| (file, Some(access)))?,
+            // the pool reads whatever the file holds now
+            buffered => (buffered, None),
+        };
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        let key_filter = rebuilt_filter.map(Arc::new).or_else(|| current.key_filter.take());
+        *current = Current { meta, file, key_filter };
+        drop(current);
+        if let Some(changes) = &self.changes {
+            let mut changes = changes.lock().unwrap_or_else(PoisonError::into_inner);
+            changes.record(&meta)?;
+            self.recorded.notify_all();
+        }
+        self.emergency.txnid.store(meta.get_txnid(), atomic::Ordering::Release);
+        Ok(())
+    }
+
+    // Writes the pages of a prepared transaction like `write_commit`, but in
+    // place of the meta page, records the meta in a file of its own. Its pages
+    // lie past the end of the current commit, where a rollback leaves them for
+    // later commits to overwrite.
+    pub(crate) fn write_prepared(
+        &self,
+        runs: impl IntoIterator<Item = (Pgno, Vec<u8>)>,
+        meta: Meta,
+    ) -> Result<(), DBError> {
+        self.check_writable()?;
+        // the pages a prepared commit builds on have to be durable by the
+        // time `commit_prepared` writes its meta page
+        self.checkpoint_locked()?;
+        let mut prepared = self.prepared.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(pending) = &*prepared {
+            return Err(DBError::PreparedTxnPending { txnid: pending.get_txnid() });
+        }
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        self.write_data(&mut map, runs, meta.get_next_pgno())?;
+        drop(map);
+
+        let path = Self::sibling_path(&self.path, "-prepared");
+        let mut record = File::create(&path)?;
+        record.write_all(meta.write_page().as_bytes())?;
+        record.sync_all()?;
+        // the record's directory entry has to last too
+        let dir = self.path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        File::open(dir.unwrap_or(Path::new(".")))?.sync_all()?;
+        *prepared = Some(meta);
+        Ok(())
+    }
+
+    /// Commit id of the transaction prepared and not yet committed or rolled
+    /// back, including one prepared before the environment was last opened.
+    pub fn get_prepared(&self) -> Option<TxnId> {
+        let prepared = self.prepared.lock().unwrap_or_else(PoisonError::into_inner);
+        prepared.map(|meta| meta.get_txnid())
+    }
+
+    /// Makes the transaction `WriteTxn::prepare` returned `txnid` for the
+    /// current commit, as its `commit` would have. Fails with
+    /// `PreparedTxnNotFound` if that isn't the one prepared, e.g. because it
+    /// was already resolved.
+    pub fn commit_prepared(&self, txnid: TxnId) -> Result<(), DBError> {
+        self.check_writable()?;
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut prepared = self.find_prepared(txnid)?;
+        let meta = prepared.unwrap();
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        self.write_meta(&mut map, meta)?;
+        drop(map);
+        self.checkpointed(meta)?;
+        self.publish(meta)?;
+        *prepared = None;
+        fs::remove_file(Self::sibling_path(&self.path, "-prepared"))?;
+        Ok(())
+    }
+
+    /// Discards the transaction `WriteTxn::prepare` returned `txnid` for.
+    /// Fails with `PreparedTxnNotFound` if that isn't the one prepared.
+    pub fn rollback_prepared(&self, txnid: TxnId) -> Result<(), DBError> {
+        self.check_writable()?;
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut prepared = self.find_prepared(txnid)?;
+        fs::remove_file(Self::sibling_path(&self.path, "-prepared"))?;
+        *prepared = None;
+        Ok(())
+    }
+
+    // the prepared transaction, which has to be `txnid`
+    fn find_prepared(&self, txnid: TxnId) -> Result<MutexGuard<'_, Option<Meta>>, DBError> {
+        let prepared = self.prepared.lock().unwrap_or_else(PoisonError::into_inner);
+        match *prepared {
+            Some(meta) if meta.get_txnid() == txnid => Ok(prepared),
+            _ => Err(DBError::PreparedTxnNotFound { txnid }),
+        }
+    }
+
+    // a prepared transaction's pages lie past the end of the current commit,
+    // so no other commit can go ahead of it
+    fn check_not_prepared(&self) -> Result<(), DBError> {
+        match self.get_prepared() {
+            Some(txnid) => Err(DBError::PreparedTxnPending { txnid }),
+            None => Ok(()),
+        }
+    }
+
+    // the pages reach the disk before the meta that refers to them
+    fn write_pages(
+        &self,
+        runs: impl IntoIterator<Item = (Pgno, Vec<u8>)>,
+        meta: Meta,
+    ) -> Result<(), DBError> {
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        let data_flush = self.write_data(&mut map, runs, meta.get_next_pgno())?;
+        let meta_flush = self.write_meta(&mut map, meta)?;
+        drop(map);
+        self.checkpointed(meta)?;
+        self.flushed(meta.get_txnid(), data_flush, meta_flush);
+        Ok(())
+    }
+
+    fn flushed(&self, txnid: TxnId, data: Duration, meta: Duration) {
+        self.flushes.lock().unwrap_or_else(PoisonError::into_inner).record(data, meta);
+        self.events.emit(Event::CommitFlushed { txnid, data, meta });
+    }
+
+    // a meta page written and synced outside of the log makes the file hold
+    // every commit up to `meta`, so the log starts over from it
+    fn checkpointed(&self, meta: Meta) -> Result<(), DBError> {
+        match &self.wal {
+            Some(wal) => wal.reset(meta),
+            None => Ok(()),
+        }
+    }
+
+    // copies `runs` into the file, growing it to hold `next_pgno` pages first,
+    // and syncs them; returns the time the sync took
+    fn write_data(
+        &self,
+        map: &mut FileWriter,
+        runs: impl IntoIterator<Item = (Pgno, Vec<u8>)>,
+        next_pgno: Pgno,
+    ) -> Result<Duration, DBError> {
+        let written = self.copy_data(map, runs, next_pgno)?;
+        self.timed_sync(|| map.flush(&written))
+    }
+
+    // `write_data` without the sync; returns the ranges written
+    fn copy_data(
+        &self,
+        map: &mut FileWriter,
+        runs: impl IntoIterator<Item = (Pgno, Vec<u8>)>,
+        next_pgno: Pgno,
+    ) -> Result<Vec<(usize, usize)>, DBError> {
+        let end = next_pgno * self.page_size as u64;
+        if map.len() < end {
+            let len = self.growth.grow(map.len(), end, self.page_size);
+            self.remap(map, len)?;
+        }
+        let mut written = Vec::new();
+        for (pgno, bytes) in runs {
+            let pages = (bytes.len() / self.page_size) as Pgno;
+            self.page_cache.invalidate(pgno..pgno + pages);
+            let offset = pgno as usize * self.page_size;
+            map.write_at(offset, &bytes)?;
+            written.push((offset, bytes.len()));
+            self.metrics.pages_written(pages);
+        }
+        Ok(written)
+    }
+
+    fn write_meta(&self, map: &mut FileWriter, meta: Meta) -> Result<Duration, DBError> {
+        let offset = meta.get_pgno() as usize * self.page_size;
+        map.write_at(offset, meta.write_page().as_bytes())?;
+        self.timed_sync(|| map.flush(&[(offset, self.page_size)]))
+    }
+
+    // runs `sync` and returns the time it took, which goes to the metrics
+    fn timed_sync(&self, sync: impl FnOnce() -> Result<(), DBError>) -> Result<Duration, DBError> {
+        let started = Instant::now();
+        sync()?;
+        let elapsed = started.elapsed();
+        self.metrics.fsync(elapsed);
+        Ok(elapsed)
+    }
+
+    // resizes the file to `len`, mapping it again if it's mapped
+    fn remap(&self, map: &mut FileWriter, len: u64) -> Result<(), DBError> {
+        let old_size = map.len();
+        map.resize(&self.file, len)?;
+        if len > old_size {
+            self.map_growths.fetch_add(1, atomic::Ordering::Relaxed);
+            self.events.emit(Event::MapGrown { old_size, new_size: len });
+        }
+        Ok(())
+    }
+
+    /// Current size in bytes of the file and its writable map.
+    pub fn get_map_size(&self) -> u64 {
+        self.map.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    /// Grows the file and its map to `map_size` bytes now, like LMDB's
+    /// `mdb_env_set_mapsize`; a smaller size than the current one is ignored.
+    pub fn set_map_size(&self, map_size: u64) -> Result<(), DBError> {
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        if map.len() < map_size {
+            self.remap(&mut map, map_size)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the pages the current commit reaches to the front of the file
+    /// and truncates the rest, giving back the space older commits left
+    /// behind; see `compact_with`.
+    pub fn compact(&self) -> Result<CompactReport, DBError> {
+        self.compact_with(&mut no_progress)
+    }
+
+    /// Like `compact`, reporting progress to `progress` as it goes. Fails with
+    /// `ReadersActive` if a read transaction is open when it starts. Readers
+    /// are never blocked: those that begin meanwhile see the commits it makes,
+    /// and before pages an older reader may still use are overwritten or cut
+    /// off, it waits for that reader to finish. Writers are, for as long as it
+    /// takes; cancelling through `progress` bounds that. A cancelled
+    /// compaction leaves a consistent file behind, possibly with a copy of the
+    /// tree committed past its end, so that it is larger than before until
+    /// the next compaction. Other processes must not have the file open, since
+    /// the pages they have mapped may be cut off.
+    pub fn compact_with(&self, progress: &mut ProgressFn) -> Result<CompactReport, DBError> {
+        self.check_writable()?;
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        self.check_not_prepared()?;
+        if let Some(oldest) = self.readers.oldest_reader() {
+            return Err(DBError::ReadersActive { oldest });
+        }
+        // the copies overwrite pages, which a log replayed later mustn't undo
+        self.checkpoint_locked()?;
+        let size_before = self.file.metadata()?.len();
+        let txn = self.begin_read()?;
+        let reachable = txn.tree().stat()?;
+        let reachable = reachable.branch_pages + reachable.leaf_pages;
+        let total = 2 * reachable;
+        let mut copied = 0;
+        // the reachable pages renumbered from `first`, as the meta after `meta`
+        let mut copy_tree = |meta: &Meta, first: Pgno| -> Result<(Meta, Runs), DBError> {
+            let (mut alloc, mut dirty) = (PageAllocator::new(first), DirtySet::new());
+            let tree = txn.tree();
+            let mut on_copy = || {
+                copied += 1;
+                report(progress, Stage::Copying, copied, total)
+            };
+            let root = match tree.get_root() {
+                Some(root) => Some(dirty.copy_subtree(&mut alloc, &tree, root, 0, &mut on_copy)?),
+                None => None,
+            };
+            Ok((meta.next_commit(root, alloc.get_next_pgno()), dirty.into_runs()))
+        };
+
+        // the copy at the front overwrites pages of the current commit, so a
+        // copy past the end is committed first for a crash to fall back to
+        let mut meta = *txn.get_meta();
+        if NUM_META_PAGES + reachable < meta.get_next_pgno() {
+            let (tail, runs) = copy_tree(&meta, meta.get_next_pgno())?;
+            self.write_pages(runs, tail)?;
+            self.publish(tail)?;
+            let (front, runs) = copy_tree(&tail, NUM_META_PAGES)?;
+            drop(txn);
+            self.wait_for_readers(tail.get_txnid(), progress)?;
+            self.write_pages(runs, front)?;
+            self.publish(front)?;
+            // both meta slots must point to the front before the tail goes
+            meta = front.next_commit(front.get_root(), front.get_next_pgno());
+            self.write_pages([], meta)?;
+            self.publish(meta)?;
+            self.wait_for_readers(front.get_txnid(), progress)?;
+        } else {
+            drop(txn);
+        }
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        self.remap(&mut map, meta.get_next_pgno() * self.page_size as u64)?;
+        drop(map);
+        self.file.sync_all()?;
+        self.publish(meta)?;
+
+        let report = CompactReport {
+            size_before,
+            size_after: self.file.metadata()?.len(),
+        };
+        self.events.emit(Event::CompactionFinished(report));
+        Ok(report)
+    }
+
+    // Waits for every read transaction of a commit before `txnid` to end. New
+    // readers start on the current commit, so once it is `txnid` or later, no
+    // more can come along.
+    fn wait_for_readers(&self, txnid: TxnId, progress: &mut ProgressFn) -> Result<(), DBError> {
+        while self.readers.oldest_reader().is_some_and(|oldest| oldest < txnid) {
+            report(progress, Stage::WaitingForReaders, 0, 0)?;
+            thread::sleep(READER_POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    /// The crash marker found by this open, if the previous process using the
+    /// file called `emergency_sync` before dying.
+    pub const fn last_crash(&self) -> Option<CrashMarker> {
+        self.last_crash
+    }
+
+    /// Flushes everything written to the file so far to stable storage.
+    pub fn sync(&self) -> Result<(), DBError> {
+        let txnid = self.get_meta().get_txnid();
+        self.timed_sync(|| Ok(self.file.sync_all()?))?;
+        self.events.emit(Event::CheckpointCompleted { txnid });
+        Ok(())
+    }
+
+    /// Calls `on_event` with every lifecycle event from now on, such as the
+    /// file growing or a corrupt page being found; see `EventBus` for where
+    /// it runs.
+    pub fn subscribe(&self, on_event: impl Fn(&Event) + Send + Sync + 'static) {
+        self.events.subscribe(on_event);
+    }
+
+    /// Like `subscribe`, but sends the events to the returned channel.
+    pub fn subscribe_channel(&self) -> Receiver<Event> {
+        self.events.subscribe_channel()
+    }
+
+    /// Best-effort flush for a process that is about to die: syncs the file
+    /// and leaves a crash marker for the next open to find. Only makes
+    /// async-signal-safe calls, so it is safe to call from a signal handler.
+    pub fn emergency_sync(&self) {
+        self.emergency.sync();
+    }
+
+    /// Runs `emergency_sync` on panic (before the previously installed hook)
+    /// and on fatal signals (SIGSEGV, SIGBUS, SIGILL, SIGFPE and SIGABRT,
+    /// each then handed on to the action it replaced), until the environment
+    /// is dropped. The hooks are process-wide and
+    /// installed with the first call, and every environment that asked is
+    /// synced, up to `MAX_HOOKED_ENVS` of them; calls past that, or for an
+    /// environment already covered, do nothing. A panic the process gets
+    /// past, caught or confined to a thread, isn't a crash: closing the
+    /// environment takes back the marker its hook left.
+    pub fn install_panic_hook(&self) {
+        install_hooks();
+        let target = Arc::as_ptr(&self.emergency).cast_mut();
+        if HOOK_TARGETS.iter().any(|slot| slot.load(atomic::Ordering::Acquire) == target) {
+            return;
+        }
+        let raw = Arc::into_raw(Arc::clone(&self.emergency)).cast_mut();
+        let placed = HOOK_TARGETS.iter().any(|slot| {
+            slot.compare_exchange(
+                ptr::null_mut(),
+                raw,
+                atomic::Ordering::AcqRel,
+                atomic::Ordering::Relaxed,
+            )
+            .is_ok()
+        });
+        if !placed {
+            drop(unsafe { Arc::from_raw(raw) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree::PageSource;
+    use crate::cursor::PREFETCH_LEAVES;
+    use crate::metrics::Counters;
+    use crate::progress::Progress;
+    use std::ops::ControlFlow;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_create_and_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let env = EnvOptions::new().page_size(16384).open(&path).unwrap();
+        assert_eq!(env.get_page_size(), 16384);
+        assert_eq!(env.get_meta().get_root(), None);
+        drop(env);
+
+        // the page size recorded at creation wins over the options
+        let env = EnvOptions::new().page_size(8192).open(&path).unwrap();
+        assert_eq!(env.get_page_size(), 16384);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 2 * 16384);
+        assert!(matches!(
+            EnvOptions::new().page_size(1000).open(dir.path().join("bad")),
+            Err(DBError::InvalidPageSize { size: 1000 })
+        ));
+    }
+
+    #[test]
+    fn test_map_grows_in_steps() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let page = DEFAULT_PAGE_SIZE as u64;
+        let options = EnvOptions::new().map_size(8 * page).growth_step(16 * page);
+        let env = options.open(&path).unwrap();
+        assert_eq!(env.get_map_size(), 8 * page);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 8 * page);
+
+        // a reader keeps its own map while commits grow the file under it
+        let mut txn = env.begin_write();
+        txn.put(b"first", b"value").unwrap();
+        txn.commit().unwrap();
+        let reader = env.begin_read().unwrap();
+        let mut txn = env.begin_write();
+        for i in 0..5000u32 {
+            txn.put(&i.to_be_bytes(), &[0u8; 100]).unwrap();
+        }
+        txn.commit().unwrap();
+        let used = env.get_meta().get_next_pgno() * page;
+        assert!(env.get_map_size() >= used && env.get_map_size().is_multiple_of(16 * page));
+        assert_eq!(reader.get(b"first").unwrap(), b"value");
+        assert!(matches!(reader.get(&7u32.to_be_bytes()), Err(DBError::KeyNotFound)));
+
+        env.set_map_size(env.get_map_size() + 4 * page).unwrap();
+        let before = env.get_map_size();
+        env.set_map_size(page).unwrap();
+        assert_eq!(env.get_map_size(), before);
+        drop((reader, env));
+
+        // reopening keeps the larger file
+        let env = Env::open(&path).unwrap();
+        assert_eq!(env.get_map_size(), before);
+        assert_eq!(env.begin_read().unwrap().get(&4999u32.to_be_bytes()).unwrap(), [0u8; 100]);
+    }
+
+    #[test]
+    fn test_growth_policy() {
+        let page = DEFAULT_PAGE_SIZE as u64;
+        let grow = |policy: GrowthPolicy, size, needed| policy.grow(size, needed, page as usize);
+        assert_eq!(grow(GrowthPolicy::Step(0), 4 * page, 5 * page), 5 * page);
+        assert_eq!(grow(GrowthPolicy::Step(8 * page), 4 * page, 9 * page), 16 * page);
+        let doubling = GrowthPolicy::Doubling { max_step: 16 * page };
+        assert_eq!(grow(doubling, 4 * page, 5 * page), 8 * page);
+        assert_eq!(grow(doubling, 64 * page, 65 * page), 80 * page);
+        // never less than the commit needs
+        assert_eq!(grow(doubling, 4 * page, 20 * page), 20 * page);
+        assert_eq!(grow(GrowthPolicy::Percent(50), 10 * page, 11 * page), 15 * page);
+        assert_eq!(grow(GrowthPolicy::Percent(10), 10 * page, 11 * page), 11 * page);
+
+        let dir = tempdir().unwrap();
+        let uncapped = GrowthPolicy::Doubling { max_step: u64::MAX };
+        let env = EnvOptions::new().growth_policy(uncapped).open(dir.path().join("db")).unwrap();
+        let events = env.subscribe_channel();
+        for i in 0..2000u32 {
+            let mut txn = env.begin_write();
+            txn.put(&i.to_be_bytes(), &[0u8; 100]).unwrap();
+            txn.commit().unwrap();
+        }
+        let sizes: Vec<u64> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::MapGrown { new_size, .. } => Some(new_size),
+                _ => None,
+            })
+            .collect();
+        assert!(sizes.windows(2).all(|pair| pair[1] == 2 * pair[0]), "{sizes:?}");
+        assert_eq!(env.stat().unwrap().map_growths, sizes.len() as u64);
+        assert_eq!(env.get_map_size(), *sizes.last().unwrap());
+    }
+
+    #[test]
+    fn test_metrics() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let hook = Arc::new(Counters::default());
+        let options = EnvOptions::new().page_size(MIN_PAGE_SIZE).metrics(hook.clone());
+        let env = options.open(&path).unwrap();
+        let mut txn = env.begin_write();
+        for i in 0..200u32 {
+            txn.put(&i.to_be_bytes(), &[0; 100]).unwrap();
+        }
+        txn.commit().unwrap();
+        let txn = env.begin_read().unwrap();
+        for i in 0..200u32 {
+            txn.get(&i.to_be_bytes()).unwrap();
+        }
+        drop(txn);
+
+        let stat = env.metrics();
+        assert_eq!(stat, hook.stat());
+        assert_eq!(stat.commits, 1);
+        assert!(stat.page_splits > 0);
+        // the pages were written, then synced before the meta page
+        assert!(stat.pages_written > stat.page_splits);
+        assert_eq!(stat.fsyncs, 2);
+        assert!(stat.page_reads >= 400);
+        // the root is parsed once, then found on every other lookup
+        assert_eq!((stat.cache_hits, stat.cache_misses), (199, 1));
+    }
+
+    #[test]
+    fn test_events() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let page = DEFAULT_PAGE_SIZE as u64;
+        let env = EnvOptions::new().growth_step(4 * page).open(&path).unwrap();
+        let events = env.subscribe_channel();
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"value").unwrap();
+        txn.commit().unwrap();
+        assert_eq!(events.try_recv().unwrap(), Event::MapGrown {
+            old_size: 2 * page,
+            new_size: 4 * page
+        });
+        assert!(matches!(events.try_recv().unwrap(), Event::CommitFlushed { txnid: 1, .. }));
+        env.sync().unwrap();
+        assert_eq!(events.try_recv().unwrap(), Event::CheckpointCompleted { txnid: 1 });
+        let report = env.compact().unwrap();
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(events.last(), Some(&Event::CompactionFinished(report)));
+        assert!(events[..events.len() - 1]
+            .iter()
+            .all(|event| matches!(event, Event::CommitFlushed { .. })));
+        drop(env);
+
+        // subscribed through the options, corruption found on open is seen too
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[2 * DEFAULT_PAGE_SIZE + 100] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let options = EnvOptions::new().sample_pages(usize::MAX, |_| {}).on_event(move |event| {
+            sender.send(event.clone()).unwrap();
+        });
+        let _env = options.open(&path).unwrap();
+        match receiver.recv().unwrap() {
+            Event::CorruptionDetected(problem) => assert_eq!(problem.pgno, 2),
+            event => panic!("unexpected {event:?}"),
+        }
+    }
+
+    #[test]
+    fn test_sample_pages_on_open() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let entries = (0..2000u32).map(|i| (i.to_be_bytes(), [0u8; 100]));
+        let num_pages = Env::bulk_load(&path, entries).unwrap().get_meta().get_next_pgno();
+
+        let open_sampled = |count| {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let options = EnvOptions::new().sample_pages(count, move |report| {
+                sender.send(report).unwrap();
+            });
+            let _env = options.open(&path).unwrap();
+            receiver.recv().unwrap()
+        };
+        let report = open_sampled(10);
+        assert_eq!(report.pages_checked, 10);
+        assert!(report.is_ok(), "{:?}", report.problems);
+
+        // flip a byte in the middle of a data page
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[3 * DEFAULT_PAGE_SIZE + 100] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        let report = open_sampled(usize::MAX);
+        assert_eq!(report.pages_checked as Pgno, num_pages - NUM_META_PAGES);
+        assert!(report.problems.iter().any(|problem| problem.pgno == 3));
+    }
+
+    #[test]
+    fn test_open_recover() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let entries = (0..2000u32).map(|i| (i.to_be_bytes(), [0u8; 100]));
+        let env = Env::bulk_load(&path, entries).unwrap();
+        let txnid = env.get_meta().get_txnid();
+        drop(env);
+
+        let (env, report) = Env::open_recover(&path).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(env.get_meta().get_txnid(), txnid);
+        let txn = env.begin_read().unwrap();
+        let leaf = *txn.tree().descend(&1000u32.to_be_bytes()).unwrap().leaf.as_data_page();
+        let leaf_pgno = leaf.get_pgno();
+        let lost: Vec<u32> = leaf
+            .nodes()
+            .map(|node| u32::from_be_bytes(node.unwrap().get_key()[..].try_into().unwrap()))
+            .collect();
+        drop(txn);
+        drop(env);
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[leaf_pgno as usize * DEFAULT_PAGE_SIZE + 100] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        let (env, report) = Env::open_recover(&path).unwrap();
+        assert!(report.problems.iter().any(|problem| problem.pgno == leaf_pgno));
+        assert_eq!(env.get_meta().get_txnid(), txnid + 1);
+        let txn = env.begin_read().unwrap();
+        for i in 0..2000u32 {
+            let found = txn.get(&i.to_be_bytes());
+            assert_eq!(found.is_ok(), !lost.contains(&i), "key {i}");
+        }
+        assert_eq!(txn.tree().cursor().unwrap().count(), 2000 - lost.len());
+        drop(txn);
+        drop(env);
+
+        // the damaged page is no longer part of the tree
+        let (_env, report) = Env::open_recover(&path).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+    }
+
+    #[test]
+    fn test_verify_checksums() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let entries = (0..2000u32).map(|i| (i.to_be_bytes(), [0u8; 100]));
+        let env = Env::bulk_load(&path, entries).unwrap();
+        let txn = env.begin_read().unwrap();
+        let leaf = txn.tree().descend(&1000u32.to_be_bytes()).unwrap().leaf;
+        let leaf = leaf.as_data_page().get_pgno();
+        drop(txn);
+        drop(env);
+
+        // parsing the page doesn't notice a byte of an entry changing
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[(leaf as usize + 1) * DEFAULT_PAGE_SIZE - 1] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        let mismatch = |found: Result<&[u8], DBError>| {
+            matches!(found, Err(DBError::ChecksumMismatch { pgno }) if pgno == leaf)
+        };
+        let env = Env::open(&path).unwrap();
+        assert!(!mismatch(env.begin_read().unwrap().get(&1000u32.to_be_bytes())));
+        drop(env);
+        let env = EnvOptions::new().verify_checksums(true).open(&path).unwrap();
+        assert!(mismatch(env.begin_read().unwrap().get(&1000u32.to_be_bytes())));
+        assert!(mismatch(env.begin_write().get(&1000u32.to_be_bytes())));
+        assert!(env.begin_read().unwrap().get(&0u32.to_be_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_paranoid_reads() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let entries = (0..2000u32).map(|i| (i.to_be_bytes(), [0u8; 100]));
+        let env = Env::bulk_load(&path, entries).unwrap();
+        let txn = env.begin_read().unwrap();
+        let leaf = txn.tree().descend(&1000u32.to_be_bytes()).unwrap().leaf;
+        let leaf = leaf.as_data_page().get_pgno();
+        drop(txn);
+        drop(env);
+
+        // the leaf's first two nodes swapped, under a checksum that matches
+        let mut bytes = fs::read(&path).unwrap();
+        let at = leaf as usize * DEFAULT_PAGE_SIZE;
+        let mut page = Page::from_bytes(&bytes[at..at + DEFAULT_PAGE_SIZE]).unwrap();
+        page.get_data_mut()[..4].rotate_left(2);
+        page.update_checksum();
+        bytes[at..at + DEFAULT_PAGE_SIZE].copy_from_slice(page.as_bytes());
+        fs::write(&path, bytes).unwrap();
+        let options = EnvOptions::new().verify_checksums(true).paranoid_reads(true);
+        let env = options.open(&path).unwrap();
+        let corrupt = |found: Result<&[u8], DBError>| {
+            matches!(found, Err(DBError::CorruptPage { pgno, .. }) if pgno == leaf)
+        };
+        assert!(corrupt(env.begin_read().unwrap().get(&1000u32.to_be_bytes())));
+        assert!(corrupt(env.begin_write().get(&1000u32.to_be_bytes())));
+        assert!(env.begin_read().unwrap().get(&0u32.to_be_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_key_order() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = EnvOptions::new().key_order(KeyOrder::U64LE).paranoid_reads(true);
+        let env = options.open(&path).unwrap();
+        let mut txn = env.begin_write();
+        // 1, 256 and 65536 all sort before 2 in byte order
+        for i in (0..3000u64).map(|i| i * 7919 % 3000) {
+            txn.put(&(i << 4).to_le_bytes(), &[0; 50]).unwrap();
+        }
+        txn.commit().unwrap();
+
+        let numbers = |txn: &ReadTxn| -> Vec<u64> {
+            let keys = txn.tree().cursor().unwrap().keys();
+            keys.map(|key| u64::from_le_bytes(key.unwrap()[..].try_into().unwrap())).collect()
+        };
+        let txn = env.begin_read().unwrap();
+        assert!(txn.tree().height().unwrap() > 1);
+        assert_eq!(numbers(&txn), (0..3000).map(|i| i << 4).collect::<Vec<_>>());
+        let (start, end) = (16u64.to_le_bytes(), 80u64.to_le_bytes());
+        let range = txn.tree().page(&start[..]..&end[..], 0, 100).unwrap();
+        assert_eq!(range.len(), 4);
+        drop(txn);
+        assert!(env.check().unwrap().is_ok());
+        drop(env);
+
+        // the order is recorded with the file
+        assert!(matches!(Env::open(&path), Err(DBError::IncompatibleFile { .. })));
+        let reverse = EnvOptions::new().key_order(KeyOrder::Custom(|a, b| b.cmp(a)));
+        assert!(matches!(reverse.open(&path), Err(DBError::IncompatibleFile { .. })));
+        let env = options.open(&path).unwrap();
+        assert_eq!(numbers(&env.begin_read().unwrap()).len(), 3000);
+    }
+
+    #[test]
+    fn test_reversed_and_header_orders() {
+        let dir = tempdir().unwrap();
+        // a two-byte shard id ahead of each key
+        let keys: Vec<Vec<u8>> = (0..3000u32)
+            .map(|i| [&(i % 7).to_be_bytes()[2..], &(i * 7919 % 3000).to_be_bytes()[..]].concat())
+            .collect();
+        let orders = [("reversed", KeyOrder::BytesReversed), ("after", KeyOrder::BytesAfter(2))];
+        for (name, order) in orders {
+            let path = dir.path().join(name);
+            let options = EnvOptions::new().key_order(order);
+            let env = options.open(&path).unwrap();
+            let mut txn = env.begin_write();
+            for key in &keys {
+                txn.put(key, key).unwrap();
+            }
+            txn.commit().unwrap();
+            let mut sorted = keys.clone();
+            sorted.sort_by(|a, b| order.compare(a, b));
+
+            let check_reads = |txn: &ReadTxn| {
+                let tree = txn.tree();
+                assert!(tree.height().unwrap() > 1);
+                let found: Vec<Vec<u8>> =
+                    tree.cursor().unwrap().keys().map(|key| key.unwrap().to_vec()).collect();
+                assert_eq!(found, sorted, "{order:?}");
+                let (start, end) = (&sorted[1000][..], &sorted[1010][..]);
+                let range = tree.page(start..end, 0, 100).unwrap();
+                let range: Vec<&[u8]> = range.iter().map(|(key, _)| &key[..]).collect();
+                assert_eq!(range, sorted[1000..1010].iter().map(Vec::as_slice).collect::<Vec<_>>());
+                let mut cursor = tree.cursor().unwrap();
+                cursor.seek(end).unwrap();
+                assert_eq!(cursor.prev().unwrap().unwrap().0, &sorted[1009][..]);
+            };
+            check_reads(&env.begin_read().unwrap());
+            assert!(env.check().unwrap().is_ok());
+            drop(env);
+            assert!(matches!(Env::open(&path), Err(DBError::IncompatibleFile { .. })));
+            let env = options.open(&path).unwrap();
+            check_reads(&env.begin_read().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_prepare() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"v1").unwrap();
+        txn.commit().unwrap();
+
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"v2").unwrap();
+        let txnid = txn.prepare().unwrap();
+        assert_eq!((txnid, env.get_prepared()), (2, Some(2)));
+        assert_eq!(env.begin_read().unwrap().get(b"key").unwrap(), b"v1");
+        let mut txn = env.begin_write();
+        txn.put(b"other", b"value").unwrap();
+        assert!(matches!(txn.commit(), Err(DBError::PreparedTxnPending { txnid: 2 })));
+        let mut txn = env.begin_write();
+        txn.put(b"other", b"value").unwrap();
+        assert!(matches!(txn.prepare(), Err(DBError::PreparedTxnPending { txnid: 2 })));
+        assert!(matches!(env.compact(), Err(DBError::PreparedTxnPending { txnid: 2 })));
+        assert!(matches!(env.commit_prepared(3), Err(DBError::PreparedTxnNotFound { txnid: 3 })));
+
+        // still prepared, and still invisible, after a restart
+        drop(env);
+        let env = Env::open(&path).unwrap();
+        assert_eq!(env.get_prepared(), Some(2));
+        assert_eq!(env.begin_read().unwrap().get(b"key").unwrap(), b"v1");
+        env.commit_prepared(2).unwrap();
+        assert_eq!(env.get_prepared(), None);
+        assert_eq!(env.begin_read().unwrap().get(b"key").unwrap(), b"v2");
+        assert!(matches!(env.commit_prepared(2), Err(DBError::PreparedTxnNotFound { .. })));
+        drop(env);
+        let env = Env::open(&path).unwrap();
+        assert_eq!((env.get_prepared(), env.get_meta().get_txnid()), (None, 2));
+
+        // a rollback leaves the pages it wrote for the next commit to reuse
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"v3").unwrap();
+        let txnid = txn.prepare().unwrap();
+        drop(env);
+        let env = Env::open(&path).unwrap();
+        env.rollback_prepared(txnid).unwrap();
+        assert!(matches!(env.rollback_prepared(txnid), Err(DBError::PreparedTxnNotFound { .. })));
+        let mut txn = env.begin_write();
+        txn.put(b"other", b"value").unwrap();
+        txn.commit().unwrap();
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get(b"key").unwrap(), b"v2");
+        assert_eq!(txn.get(b"other").unwrap(), b"value");
+        drop(txn);
+        drop(env);
+        assert!(Env::open(&path).unwrap().get_prepared().is_none());
+    }
+
+    #[test]
+    fn test_readers_are_tracked() {
+        let dir = tempdir().unwrap();
+        let env = EnvOptions::new().max_readers(2).open(dir.path().join("db")).unwrap();
+        assert_eq!(env.oldest_reader(), None);
+
+        let old = env.begin_read().unwrap();
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"value").unwrap();
+        txn.commit().unwrap();
+        let new = env.begin_read().unwrap();
+        assert_eq!(env.oldest_reader(), Some(0));
+        assert!(matches!(env.begin_read(), Err(DBError::ReadersFull { max: 2 })));
+
+        drop(old);
+        assert_eq!(env.oldest_reader(), Some(new.get_meta().get_txnid()));
+        drop(new);
+        assert_eq!(env.oldest_reader(), None);
+    }
+
+    #[test]
+    fn test_snapshot_outlives_writes() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"old").unwrap();
+        txn.commit().unwrap();
+        let snapshot = env.snapshot().unwrap();
+        let txnid = snapshot.get_meta().get_txnid();
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let snapshot = snapshot.clone();
+                scope.spawn(move || {
+                    for _ in 0..50 {
+                        assert_eq!(snapshot.get(b"key").unwrap(), b"old");
+                    }
+                });
+            }
+            for i in 0..20u32 {
+                let mut txn = env.begin_write();
+                txn.put(b"key", &i.to_be_bytes()).unwrap();
+                txn.commit().unwrap();
+            }
+        });
+        assert_eq!(env.begin_read().unwrap().get(b"key").unwrap(), 19u32.to_be_bytes());
+        assert_eq!((env.oldest_snapshot(), env.oldest_reader()), (Some(txnid), Some(txnid)));
+        let read = env.begin_read().unwrap();
+        drop(snapshot);
+        assert_eq!(env.oldest_snapshot(), None);
+        assert_eq!(env.oldest_reader(), Some(read.get_meta().get_txnid()));
+    }
+
+    #[test]
+    fn test_stat() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let stat = env.stat().unwrap();
+        assert_eq!((stat.total_pages, stat.free_pages), (NUM_META_PAGES, 0));
+        assert_eq!(stat.tree, TreeStat::default());
+
+        let entries = (0..2000u32).map(|i| (i.to_be_bytes(), [0u8; 100]));
+        let env = Env::bulk_load(dir.path().join("bulk"), entries).unwrap();
+        let stat = env.stat().unwrap();
+        assert_eq!(stat.tree.entries, 2000);
+        assert_eq!(stat.tree.depth, 2);
+        assert_eq!(stat.tree.branch_pages, 1);
+        assert_eq!(stat.free_pages, 0);
+        assert_eq!(stat.file_size, stat.total_pages * DEFAULT_PAGE_SIZE as u64);
+        assert!(stat.tree.fill_factor() > 0.5 && stat.tree.fill_factor() <= 1.0);
+
+        // a commit leaves the pages it copied behind, unreachable
+        let mut txn = env.begin_write();
+        txn.put(&5u32.to_be_bytes(), b"changed").unwrap();
+        txn.delete(&6u32.to_be_bytes()).unwrap();
+        txn.commit().unwrap();
+        let after = env.stat().unwrap();
+        assert_eq!(after.tree.entries, 1999);
+        assert_eq!(after.free_pages, 2);
+        assert_eq!(after.tree.leaf_pages, stat.tree.leaf_pages);
+    }
+
+    #[test]
+    fn test_flush_stat() {
+        let mut times = FlushTimes::default();
+        assert_eq!(times.stat(), FlushStat::default());
+        for ms in (1..=FLUSH_SAMPLES as u64 + 100).rev() {
+            times.record(Duration::from_millis(ms), Duration::from_micros(ms));
+        }
+        // only the most recent commits count: the ones that took 1..=1024ms
+        let stat = times.stat();
+        assert_eq!(stat.commits, FLUSH_SAMPLES as u64 + 100);
+        assert_eq!(stat.data_p50, Duration::from_millis(512));
+        assert_eq!(stat.data_p99, Duration::from_millis(1014));
+        assert_eq!(stat.meta_p99, Duration::from_micros(1014));
+
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        for i in 0..10u32 {
+            let mut txn = env.begin_write();
+            txn.put(&i.to_be_bytes(), b"value").unwrap();
+            txn.commit().unwrap();
+        }
+        let stat = env.stat().unwrap().flush;
+        assert_eq!(stat.commits, 10);
+        assert!(stat.data_p50 <= stat.data_p99 && stat.meta_p50 <= stat.meta_p99);
+    }
+
+    // memory this process has locked, in kB
+    fn locked_kb() -> u64 {
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|line| line.starts_with("VmLck:")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn test_buffered_io() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = EnvOptions::new().io_backend(IoBackend::Buffered { cache_pages: 16 });
+        let env = options.open(&path).unwrap();
+        let write = |round: u8| {
+            let mut txn = env.begin_write();
+            for i in 0..2000u32 {
+                txn.put(&i.to_be_bytes(), &[round; 100]).unwrap();
+            }
+            txn.commit().unwrap();
+        };
+        write(0);
+        let old = env.begin_read().unwrap();
+        write(1);
+        // far more leaves than the pool keeps, and an older reader that still
+        // sees its own commit
+        assert_eq!(old.get(&7u32.to_be_bytes()).unwrap(), [0; 100]);
+        let txn = env.begin_read().unwrap();
+        for i in 0..2000u32 {
+            assert_eq!(txn.get(&i.to_be_bytes()).unwrap(), [1; 100]);
+        }
+        let stat = env.stat().unwrap().buffer_pool.unwrap();
+        assert!(stat.entries <= 16 && stat.hits > 0 && stat.misses > 0);
+        let report = env.check().unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert!(matches!(env.pin_range(..), Err(DBError::Unsupported { .. })));
+
+        // compaction writes over page numbers the pool has read
+        drop((old, txn));
+        env.compact().unwrap();
+        let txn = env.begin_read().unwrap();
+        for i in 0..2000u32 {
+            assert_eq!(txn.get(&i.to_be_bytes()).unwrap(), [1; 100]);
+        }
+        drop((txn, env));
+
+        // the file is the same either way
+        let env = Env::open(&path).unwrap();
+        assert_eq!(env.begin_read().unwrap().get(&1999u32.to_be_bytes()).unwrap(), [1; 100]);
+        assert!(env.stat().unwrap().buffer_pool.is_none());
+    }
+
+    #[test]
+    fn test_pin_page() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        for i in 0..2000u32 {
+            txn.put(&i.to_be_bytes(), &[1; 100]).unwrap();
+        }
+        txn.commit().unwrap();
+        let root = env.get_meta().get_root().unwrap();
+        let pinned = env.pin_page(root).unwrap();
+        let held = pinned.clone();
+        drop(pinned);
+        assert_eq!(env.oldest_reader(), Some(1));
+
+        // a later commit copies the root elsewhere; the pinned one stays
+        let mut txn = env.begin_write();
+        txn.clear();
+        txn.put(b"key", b"value").unwrap();
+        txn.commit().unwrap();
+        assert_ne!(env.get_meta().get_root(), Some(root));
+        let page = held.page().unwrap();
+        assert!(page.get_flags().contains(PageFlag::BRANCH));
+        assert_eq!((held.get_pgno(), held.get_txnid()), (root, 1));
+        drop(held);
+        assert_eq!(env.oldest_reader(), None);
+
+        assert!(matches!(env.pin_page(0), Err(DBError::PageOutOfBounds { pgno: 0 })));
+        let end = env.get_meta().get_next_pgno();
+        assert!(matches!(env.pin_page(end), Err(DBError::PageOutOfBounds { .. })));
+    }
+
+    #[test]
+    fn test_pin_range() {
+        let dir = tempdir().unwrap();
+        let entries = (0..5000u32).map(|i| (i.to_be_bytes(), [0u8; 100]));
+        let env = Env::bulk_load(dir.path().join("db"), entries).unwrap();
+        let before = locked_kb();
+
+        let (start, end) = (1000u32.to_be_bytes(), 1200u32.to_be_bytes());
+        let range = &start[..]..&end[..];
+        let txn = env.begin_read().unwrap();
+        let tree = txn.tree();
+        let pages = tree.pages_in_range(range.clone()).unwrap();
+        // the root, and each leaf a key in the range is found in
+        assert_eq!(pages[0], tree.get_root().unwrap());
+        for i in 1000..1200u32 {
+            let leaf = tree.descend(&i.to_be_bytes()).unwrap().leaf;
+            assert!(pages.contains(&leaf.as_data_page().get_pgno()));
+        }
+        assert!(pages.len() < tree.stat().unwrap().leaf_pages as usize / 2);
+
+        assert_eq!(env.pin_range(range.clone()).unwrap(), pages.len());
+        let pinned = locked_kb();
+        assert!(pinned > before);
+        // overlapping pins share the root, which stays locked until both go
+        assert!(env.pin_range(..).unwrap() > pages.len());
+        assert!(env.unpin_range(..).unwrap());
+        assert_eq!(locked_kb(), pinned);
+        assert!(env.unpin_range(range.clone()).unwrap());
+        assert!(!env.unpin_range(range).unwrap());
+        assert_eq!(locked_kb(), before);
+    }
+
+    #[test]
+    fn test_page_cache() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let write = |round: u8| {
+            let mut txn = env.begin_write();
+            for i in 0..2000u32 {
+                txn.put(&i.to_be_bytes(), &[round; 100]).unwrap();
+            }
+            txn.commit().unwrap();
+        };
+        write(0);
+        for i in 0..100u32 {
+            env.begin_read().unwrap().get(&i.to_be_bytes()).unwrap();
+        }
+        let stat = env.stat().unwrap().page_cache;
+        assert!(stat.entries > 0 && stat.hits >= 100 - stat.misses);
+        assert!(stat.hit_rate() > 0.9);
+
+        // compaction writes the tree back over lower page numbers, some of them
+        // cached, which have to be parsed again
+        write(1);
+        env.compact().unwrap();
+        let txn = env.begin_read().unwrap();
+        for i in 0..2000u32 {
+            assert_eq!(txn.get(&i.to_be_bytes()).unwrap(), [1; 100]);
+        }
+        drop(txn);
+
+        let dir = tempdir().unwrap();
+        let env = EnvOptions::new().page_cache(0).open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        for i in 0..2000u32 {
+            txn.put(&i.to_be_bytes(), b"value").unwrap();
+        }
+        txn.commit().unwrap();
+        assert_eq!(env.begin_read().unwrap().get(&7u32.to_be_bytes()).unwrap(), b"value");
+        assert_eq!(env.stat().unwrap().page_cache, PageCacheStat::default());
+    }
+
+    #[test]
+    fn test_key_filter() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = EnvOptions::new().key_filter(10).open(&path).unwrap();
+        assert_eq!(env.stat().unwrap().key_filter.unwrap().bits, 10240);
+        let mut txn = env.begin_write();
+        for i in (0..10_000u32).step_by(2) {
+            txn.put(&i.to_be_bytes(), b"value").unwrap();
+        }
+        txn.commit().unwrap();
+        // rebuilt for twice the keys by the commit that filled it
+        let stat = env.stat().unwrap().key_filter.unwrap();
+        assert!(stat.bits >= 100_000, "{stat:?}");
+
+        let txn = env.begin_read().unwrap();
+        for i in 0..10_000u32 {
+            assert_eq!(txn.get(&i.to_be_bytes()).is_ok(), i % 2 == 0);
+        }
+        let stat = env.stat().unwrap().key_filter.unwrap();
+        assert_eq!((stat.lookups, stat.skipped + stat.false_positives), (10_000, 5_000));
+        assert!(stat.false_positive_rate() < 0.05, "{stat:?}");
+
+        // the reader began before the delete still finds the key
+        let mut write = env.begin_write();
+        write.delete(&0u32.to_be_bytes()).unwrap();
+        write.put(b"new", b"value").unwrap();
+        write.commit().unwrap();
+        assert!(txn.get(&0u32.to_be_bytes()).is_ok());
+        assert_eq!(env.begin_read().unwrap().get(b"new").unwrap(), b"value");
+        let mut write = env.begin_write();
+        write.put(b"prepared", b"value").unwrap();
+        env.commit_prepared(write.prepare().unwrap()).unwrap();
+        assert!(env.begin_read().unwrap().get(b"prepared").is_ok());
+        drop((txn, env));
+
+        // built again from the tree, and on no read-only environment
+        let env = EnvOptions::new().key_filter(10).open(&path).unwrap();
+        let txn = env.begin_read().unwrap();
+        assert!(txn.get(b"new").is_ok() && txn.get(&2u32.to_be_bytes()).is_ok());
+        assert!(matches!(txn.get(&0u32.to_be_bytes()), Err(DBError::KeyNotFound)));
+        drop((txn, env));
+        let env = EnvOptions::new().key_filter(10).open_read_only(&path).unwrap();
+        assert!(env.stat().unwrap().key_filter.is_none());
+    }
+
+    #[test]
+    fn test_info() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = EnvOptions::new().page_size(16384).open(&path).unwrap();
+        let created = env.info().unwrap();
+        assert_eq!((created.format_version, created.page_size), (META_VERSION, 16384));
+        assert_eq!(created.byte_order_marker, crate::meta::BYTE_ORDER_MARKER);
+        assert!(created.created_at.is_some() && created.user_meta.is_empty());
+
+        // a commit of nothing but the metadata still commits
+        let mut txn = env.begin_write();
+        txn.set_user_meta(b"schema=3").unwrap();
+        assert!(matches!(txn.set_user_meta(&[0; 65]), Err(DBError::ValueTooLarge { .. })));
+        txn.commit().unwrap();
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"value").unwrap();
+        txn.commit().unwrap();
+        drop(env);
+
+        let env = Env::open_read_only(&path).unwrap();
+        let info = env.info().unwrap();
+        assert_eq!((info.last_txnid, info.entries), (2, 1));
+        assert_eq!(info.user_meta, b"schema=3");
+        assert_eq!(info.created_at, created.created_at);
+    }
+
+    #[test]
+    fn test_wal() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = WalOptions { checkpoint_bytes: 0, ..WalOptions::default() };
+        let env = EnvOptions::new().wal(options).open(&path).unwrap();
+        for i in 0..20u32 {
+            let mut txn = env.begin_write();
+            txn.put(&i.to_be_bytes(), b"value").unwrap();
+            txn.commit().unwrap();
+        }
+        let wal = env.stat().unwrap().wal.unwrap();
+        assert_eq!((wal.commits, wal.syncs, wal.checkpoints), (20, 20, 0));
+        // the file's meta pages are as they were when it was created
+        assert_eq!(Env::open_read_only(&path).unwrap().len(), 0);
+
+        // a copy taken now is what a crash would leave: replaying its log
+        // brings back every commit
+        let copy = dir.path().join("copy");
+        fs::copy(&path, &copy).unwrap();
+        fs::copy(Env::sibling_path(&path, "-wal"), Env::sibling_path(&copy, "-wal")).unwrap();
+        let replayed = Env::open(&copy).unwrap();
+        assert_eq!((replayed.get_meta().get_txnid(), replayed.len()), (20, 20));
+        assert!(replayed.check().unwrap().is_ok());
+        drop(replayed);
+        assert_eq!(Env::open_read_only(&copy).unwrap().len(), 20);
+        // opened without a log, the one it had is gone once replayed
+        assert!(!Env::sibling_path(&copy, "-wal").exists());
+
+        let events = env.subscribe_channel();
+        env.checkpoint().unwrap();
+        assert_eq!(events.try_recv().unwrap(), Event::CheckpointCompleted { txnid: 20 });
+        assert_eq!(env.stat().unwrap().wal.unwrap().bytes, 0);
+        assert_eq!(Env::open_read_only(&path).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn test_wal_groups_commits() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions {
+            group_interval: Duration::from_millis(20),
+            // each commit's record is several pages, so this checkpoints
+            // along the way
+            checkpoint_bytes: 50 * DEFAULT_PAGE_SIZE as u64,
+            ..WalOptions::default()
+        };
+        let env = Arc::new(EnvOptions::new().wal(options).open(dir.path().join("db")).unwrap());
+        let threads: Vec<_> = (0..4u32)
+            .map(|thread| {
+                let env = Arc::clone(&env);
+                thread::spawn(move || {
+                    for i in 0..25u32 {
+                        let mut txn = env.begin_write();
+                        txn.put(&(thread * 1000 + i).to_be_bytes(), b"value").unwrap();
+                        txn.commit().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let wal = env.stat().unwrap().wal.unwrap();
+        assert_eq!(wal.commits, 100);
+        assert!(wal.syncs < wal.commits, "{wal:?}");
+        assert!(wal.checkpoints > 1, "{wal:?}");
+        // every commit waited on the log, and the few that checkpointed on
+        // the file's meta page too
+        let flush = env.stat().unwrap().flush;
+        assert_eq!(flush.commits, 100);
+        assert!(flush.data_p50 > Duration::ZERO, "{flush:?}");
+        assert_eq!(flush.meta_p50, Duration::ZERO);
+        assert!(flush.meta_p99 > Duration::ZERO, "{flush:?}");
+        assert_eq!(env.len(), 100);
+        assert!(env.check().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_compact() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        let report = env.compact().unwrap();
+        assert_eq!(report.size_after, NUM_META_PAGES * DEFAULT_PAGE_SIZE as u64);
+        for round in 0..20u32 {
+            let mut txn = env.begin_write();
+            for i in (round..2000).step_by(20) {
+                txn.put(&i.to_be_bytes(), &[round as u8; 100]).unwrap();
+            }
+            txn.commit().unwrap();
+        }
+        let before = env.stat().unwrap();
+        assert!(before.free_pages > 0);
+
+        let reader = env.begin_read().unwrap();
+        let oldest = reader.get_meta().get_txnid();
+        assert!(matches!(env.compact(), Err(DBError::ReadersActive { oldest: o }) if o == oldest));
+        drop(reader);
+        let report = env.compact().unwrap();
+        assert_eq!(report.size_before, before.file_size);
+        let after = env.stat().unwrap();
+        assert_eq!(report.size_after, after.file_size);
+        assert_eq!(after.free_pages, 0);
+        assert_eq!(after.tree, before.tree);
+        let tree_pages = before.tree.branch_pages + before.tree.leaf_pages;
+        assert_eq!(after.total_pages, NUM_META_PAGES + tree_pages);
+
+        // still usable, and the same after a reopen
+        let mut txn = env.begin_write();
+        txn.put(b"new", b"value").unwrap();
+        txn.commit().unwrap();
+        drop(env);
+        let env = Env::open(&path).unwrap();
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get(&1999u32.to_be_bytes()).unwrap(), [19u8; 100]);
+        assert_eq!(txn.get(b"new").unwrap(), b"value");
+        assert_eq!(txn.tree().cursor().unwrap().count(), 2001);
+    }
+
+    #[test]
+    fn test_compact_progress() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        for round in 0..10u32 {
+            let mut txn = env.begin_write();
+            for i in (round..1000).step_by(10) {
+                txn.put(&i.to_be_bytes(), &[round as u8; 100]).unwrap();
+            }
+            txn.commit().unwrap();
+        }
+        let tree = env.stat().unwrap().tree;
+        let tree_pages = tree.branch_pages + tree.leaf_pages;
+
+        // cancelled partway through the first copy, nothing has changed
+        let txnid = env.get_meta().get_txnid();
+        let cancel = env.compact_with(&mut |progress| match progress.done {
+            3 => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        });
+        assert!(matches!(cancel, Err(DBError::Cancelled)));
+        assert_eq!(env.get_meta().get_txnid(), txnid);
+
+        // a reader that starts during the compaction isn't blocked, and the
+        // compaction waits for it before overwriting the pages it reads
+        let mut reader = None;
+        let mut last = None;
+        let mut waited = false;
+        env.compact_with(&mut |progress| {
+            match progress.stage {
+                Stage::Copying if progress.done == 1 => reader = Some(env.begin_read().unwrap()),
+                Stage::WaitingForReaders => {
+                    let txn = reader.take().unwrap();
+                    assert_eq!(txn.get(&999u32.to_be_bytes()).unwrap(), [9u8; 100]);
+                    waited = true;
+                }
+                _ => last = Some(progress),
+            }
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert!(waited);
+        let total = 2 * tree_pages;
+        assert_eq!(last, Some(Progress { stage: Stage::Copying, done: total, total }));
+        assert_eq!(env.stat().unwrap().free_pages, 0);
+    }
+
+    #[test]
+    fn test_check_and_rebuild_progress() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let entries = (0..2000u32).map(|i| (i.to_be_bytes(), [0u8; 100]));
+        let env = Env::bulk_load(&path, entries).unwrap();
+        let mut checks = Vec::new();
+        let report = env
+            .check_with(&mut |progress| {
+                checks.push(progress);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(checks.len(), report.pages_checked);
+        assert!(checks.iter().all(|progress| progress.stage == Stage::Checking));
+        let cancel = env.check_with(&mut |_| ControlFlow::Break(()));
+        assert!(matches!(cancel, Err(DBError::Cancelled)));
+        let txnid = env.get_meta().get_txnid();
+        let txn = env.begin_read().unwrap();
+        let descent = txn.tree().descend(&1000u32.to_be_bytes()).unwrap();
+        let leaf = descent.leaf.as_data_page().get_pgno();
+        drop(txn);
+        drop(env);
+
+        // a cancelled rebuild leaves the damage in place, for the next try
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[leaf as usize * DEFAULT_PAGE_SIZE + 100] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        let cancel = EnvOptions::new().open_recover_with(&path, &mut |progress| {
+            match (progress.stage, progress.done) {
+                (Stage::Rebuilding, 100) => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        });
+        assert!(matches!(cancel, Err(DBError::Cancelled)));
+        let mut rebuilt = 0;
+        let (env, report) = EnvOptions::new()
+            .open_recover_with(&path, &mut |progress| {
+                if progress.stage == Stage::Rebuilding {
+                    rebuilt = progress.done;
+                }
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(env.get_meta().get_txnid(), txnid + 1);
+        assert_eq!(rebuilt, env.stat().unwrap().tree.entries);
+    }
+
+    #[test]
+    fn test_readers_alongside_writer() {
+        const NUM_KEYS: u32 = 500;
+        const NUM_COMMITS: u64 = 40;
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        // every commit rewrites every key with its own version, spread over
+        // enough pages that a torn commit would show up as mixed versions
+        let write_version = |version: u64| {
+            let mut txn = env.begin_write();
+            let value = [version.to_le_bytes(); 8].concat();
+            for i in 0..NUM_KEYS {
+                txn.put(&i.to_be_bytes(), &value).unwrap();
+            }
+            txn.commit().unwrap();
+        };
+        write_version(0);
+
+        thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut last = 0;
+                        while last < NUM_COMMITS {
+                            let txn = env.begin_read().unwrap();
+                            let mut versions = txn.tree().cursor().unwrap().map(|entry| {
+                                let (_, value) = entry.unwrap();
+                                assert!(value.chunks(8).all(|chunk| chunk == &value[..8]));
+                                u64::from_le_bytes(value[..8].try_into().unwrap())
+                            });
+                            let version = versions.next().unwrap();
+                            assert!(versions.all(|v| v == version), "torn commit");
+                            assert!(version >= last, "went back from {last} to {version}");
+                            last = version;
+                        }
+                    })
+                })
+                .collect();
+            for version in 1..=NUM_COMMITS {
+                write_version(version);
+            }
+            for reader in readers {
+                reader.join().unwrap();
+            }
+        });
+        assert_eq!(env.oldest_reader(), None);
+    }
+
+    #[test]
+    fn test_crash_marker_reported_once() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let env = Env::open(&path).unwrap();
+        assert_eq!(env.last_crash(), None);
+        env.emergency_sync();
+        drop(env);
+
+        let env = Env::open(&path).unwrap();
+        assert_eq!(env.last_crash(), Some(CrashMarker { txnid: 0 }));
+        drop(env);
+        assert_eq!(Env::open(&path).unwrap().last_crash(), None);
+    }
+
+    #[test]
+    fn test_one_writer_at_a_time() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        assert!(matches!(Env::open(&path), Err(DBError::WriterLocked)));
+
+        let waiting = thread::spawn({
+            let path = path.clone();
+            move || EnvOptions::new().wait_for_lock(true).open(path).map(|env| env.get_meta())
+        });
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"value").unwrap();
+        txn.commit().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        drop(env);
+        assert_eq!(waiting.join().unwrap().unwrap().get_txnid(), 1);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        assert!(matches!(Env::open_read_only(&path), Err(DBError::Io(_))));
+        let writer = Env::open(&path).unwrap();
+        let reader = Env::open_read_only(&path).unwrap();
+        assert!(reader.is_read_only());
+
+        // commits made after the reader opened, including ones that grow the
+        // file past what it mapped
+        let mut txn = writer.begin_write();
+        for i in 0..2000u32 {
+            txn.put(&i.to_be_bytes(), &[7; 100]).unwrap();
+        }
+        txn.commit().unwrap();
+        let txn = reader.begin_read().unwrap();
+        assert_eq!(txn.get(&1999u32.to_be_bytes()).unwrap(), [7; 100]);
+        assert_eq!(txn.get_meta().get_txnid(), writer.get_meta().get_txnid());
+        assert_eq!(writer.oldest_reader(), Some(1));
+        drop(txn);
+
+        let mut txn = reader.begin_write();
+        assert!(matches!(txn.put(b"key", b"value"), Err(DBError::TxnReadOnly)));
+        txn.abort();
+        assert!(matches!(reader.compact(), Err(DBError::TxnReadOnly)));
+        // the reader doesn't stand in the way of the next writer
+        drop(writer);
+        Env::open(&path).unwrap();
+        drop(reader);
+
+        // nothing is created beside the file, as if it were on read-only
+        // media, where the slots are kept in memory
+        fs::remove_file(Env::sibling_path(&path, "-lock")).unwrap();
+        let listing = || fs::read_dir(dir.path()).unwrap().count();
+        let files = listing();
+        let reader = Env::open_read_only(&path).unwrap();
+        let (first, second) = (reader.begin_read().unwrap(), reader.begin_read().unwrap());
+        assert_eq!(second.get(&1999u32.to_be_bytes()).unwrap(), [7; 100]);
+        assert_eq!(reader.oldest_reader(), Some(first.get_meta().get_txnid()));
+        assert_eq!(listing(), files);
+        drop((first, second));
+
+        // a compaction hands page numbers back, so the branches cached from
+        // before it are dropped rather than read through
+        let writer = Env::open(&path).unwrap();
+        let mut txn = writer.begin_write();
+        for i in 0..2000u32 {
+            txn.put(&i.to_be_bytes(), &[8; 100]).unwrap();
+        }
+        txn.commit().unwrap();
+        assert_eq!(reader.begin_read().unwrap().get(&1999u32.to_be_bytes()).unwrap(), [8; 100]);
+        assert!(reader.stat().unwrap().page_cache.entries > 0);
+        writer.compact().unwrap();
+        let txn = reader.begin_read().unwrap();
+        assert_eq!(reader.stat().unwrap().page_cache.entries, 0);
+        assert_eq!(txn.get(&1999u32.to_be_bytes()).unwrap(), [8; 100]);
+    }
+
+    #[test]
+    fn test_access_advice() {
+        let dir = tempdir().unwrap();
+        for (name, backend) in [("mapped", IoBackend::Mmap), ("buffered", IoBackend::buffered())] {
+            let env = EnvOptions::new().io_backend(backend).open(dir.path().join(name)).unwrap();
+            env.advise_sequential().unwrap();
+            // enough leaves for scans to prefetch more than one batch, in a map
+            // taken after the advice
+            let mut txn = env.begin_write();
+            for i in 0..20_000u32 {
+                txn.put(&i.to_be_bytes(), &[7; 40]).unwrap();
+            }
+            txn.commit().unwrap();
+            let txn = env.begin_read().unwrap();
+            assert!(txn.tree().stat().unwrap().leaf_pages as usize > 2 * PREFETCH_LEAVES);
+            assert_eq!(txn.tree().cursor().unwrap().count(), 20_000);
+            let mut cursor = txn.tree().cursor().unwrap();
+            cursor.seek(&15_000u32.to_be_bytes()).unwrap();
+            assert_eq!(cursor.count(), 5_000);
+            // past the end of the file, which the hint leaves out
+            txn.prefetch(&[u64::MAX - 1, u64::MAX]);
+            drop(txn);
+
+            env.advise_random().unwrap();
+            let txn = env.begin_read().unwrap();
+            assert_eq!(txn.get(&19_999u32.to_be_bytes()).unwrap(), [7; 40]);
+        }
+    }
+
+    #[test]
+    fn test_panic_hook_leaves_marker() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        let env = Env::open(&path).unwrap();
+        env.install_panic_hook();
+        env.install_panic_hook();
+        // the handler runs on the alternate stack, and has what it replaced
+        // to hand signals on to
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        unsafe { libc::sigaction(libc::SIGSEGV, ptr::null(), &mut action) };
+        assert_eq!(action.sa_sigaction, on_fatal_signal as SigInfoHandler as usize);
+        assert_ne!(action.sa_flags & libc::SA_ONSTACK, 0);
+        assert!(PREVIOUS_ACTIONS.get().is_some());
+        assert!(std::thread::spawn(|| panic!("boom")).join().is_err());
+        // there for the next open, had the panic taken the process down
+        assert!(Env::sibling_path(&path, "-crash").exists());
+        drop(env);
+        assert_eq!(Env::open(&path).unwrap().last_crash(), None);
+    }
+
+    #[test]
+    fn test_caught_panic_then_clean_close() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        env.install_panic_hook();
+        assert!(std::panic::catch_unwind(|| panic!("caught")).is_err());
+        drop(env);
+        assert_eq!(Env::open(&path).unwrap().last_crash(), None);
+
+        // nor does a panic after the environment is closed
+        let env = Env::open(&path).unwrap();
+        env.install_panic_hook();
+        drop(env);
+        assert!(std::panic::catch_unwind(|| panic!("after close")).is_err());
+        assert_eq!(Env::open(&path).unwrap().last_crash(), None);
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use crate::check::Problem;
+use crate::constants::*;
+use crate::env::CompactReport;
+
+/// Something notable the storage engine did, for alerting without parsing
+/// logs; see `Env::subscribe`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// The file and its writable map grew, sizes in bytes.
+    MapGrown { old_size: u64, new_size: u64 },
+    /// Commit `txnid` reached the disk, after `data` syncing its pages and
+    /// `meta` syncing the meta page; see `EnvStat::flush` for percentiles,
+    /// and for what the two are with a log.
+    CommitFlushed {
+        txnid: TxnId,
+        data: Duration,
+        meta: Duration,
+    },
+    /// `Env::sync` flushed everything up to commit `txnid` to stable storage,
+    /// or `Env::checkpoint` wrote it to the file from the log.
+    CheckpointCompleted { txnid: TxnId },
+    CompactionFinished(CompactReport),
+    /// The reader slot of a process that exited without releasing it was
+    /// reclaimed; it was reading commit `txnid`.
+    ReaderEvicted { pid: u32, txnid: TxnId },
+    /// A page check, such as the one `EnvOptions::sample_pages` runs, found
+    /// a problem.
+    CorruptionDetected(Problem),
+}
+
+pub type Subscriber = Arc<dyn Fn(&Event) + Send + Sync>;
+
+/// Hands each event to every subscriber, on the thread that caused it and
+/// possibly while the environment holds a lock, so subscribers should return
+/// quickly and must not use the environment themselves; a channel subscriber
+/// leaves the handling to another thread.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: RwLock<Vec<Subscriber>>,
+}
+
+impl EventBus {
+    pub fn new(subscribers: Vec<Subscriber>) -> Self {
+        EventBus {
+            subscribers: RwLock::new(subscribers),
+        }
+    }
+
+    pub fn subscribe(&self, on_event: impl Fn(&Event) + Send + Sync + 'static) {
+        let mut subscribers = self.subscribers.write().unwrap_or_else(PoisonError::into_inner);
+        subscribers.push(Arc::new(on_event));
+    }
+
+    /// Subscribes a channel, and returns its receiving end.
+    pub fn subscribe_channel(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribe(move |event| {
+            // a receiver that was dropped just stops listening
+            let _ = sender.send(event.clone());
+        });
+        receiver
+    }
+
+    pub fn emit(&self, event: Event) {
+        let subscribers = self.subscribers.read().unwrap_or_else(PoisonError::into_inner);
+        for on_event in subscribers.iter() {
+            on_event(&event);
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crate::constants::*;
+use crate::dump::{DumpReader, DumpRecord, DumpWriter};
+use crate::env::{Env, EnvOptions};
+use crate::meta::{Meta, NUM_META_PAGES};
+use crate::txn::ReadTxn;
+
+/// One file written by `Env::export_partitions`, holding the keys from
+/// `start` (inclusive) to `end` (exclusive); `None` leaves that end open.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Partition {
+    pub path: PathBuf,
+    pub start: Option<Vec<u8>>,
+    pub end: Option<Vec<u8>>,
+}
+
+pub fn copy_to(env: &Env, path: &Path) -> Result<(), DBError> {
+    let txn = env.begin_read()?;
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    for page in txn.get_meta().write_slots() {
+        file.write_all(page.as_bytes())?;
+    }
+    // committed pages are never written again, so they're copied straight out
+    // of the map while later commits go on past them
+    file.write_all(txn.data_pages()?)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// What `Env::incremental_backup` wrote.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IncrementalBackup {
+    /// The commit the backup brings a copy up to, for the next backup to
+    /// start from.
+    pub txnid: TxnId,
+    pub pages: Range<Pgno>,
+}
+
+// An incremental backup is the meta pages of its commit, the number of the
+// first page after them (u64), then every page from that one up to the
+// commit's next page number.
+pub fn incremental_backup(
+    env: &Env,
+    since: TxnId,
+    backup: &mut dyn Write,
+) -> Result<IncrementalBackup, DBError> {
+    let txn = env.begin_read()?;
+    let meta = txn.get_meta();
+    // taken once the reader holds off compaction
+    let pages = env.pages_changed_since(since);
+    let pages = pages.start..meta.get_next_pgno();
+    for page in meta.write_slots() {
+        backup.write_all(page.as_bytes())?;
+    }
+    backup.write_all(&pages.start.to_le_bytes())?;
+    let skipped = (pages.start - NUM_META_PAGES) as usize * meta.get_page_size();
+    backup.write_all(&txn.data_pages()?[skipped..])?;
+    backup.flush()?;
+    Ok(IncrementalBackup { txnid: meta.get_txnid(), pages })
+}
+
+pub fn apply_incremental_backup(path: &Path, backup: &mut dyn Read) -> Result<Meta, DBError> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut meta_pages = vec![0; NUM_META_PAGES as usize * MAX_PAGE_SIZE];
+    let read = file.read_at(&mut meta_pages, 0)?;
+    let base = Meta::read(&meta_pages[..read])?;
+    let page_size = base.get_page_size();
+    let mut meta_pages = vec![0; NUM_META_PAGES as usize * page_size];
+    backup.read_exact(&mut meta_pages)?;
+    let meta = Meta::read(&meta_pages)?;
+    let mut first = [0; 8];
+    backup.read_exact(&mut first)?;
+    let first = Pgno::from_le_bytes(first);
+    if meta.get_page_size() != page_size {
+        return Err(DBError::IncompatibleFile { reason: "page sizes differ" });
+    }
+    if first > base.get_next_pgno() || meta.get_txnid() < base.get_txnid() {
+        return Err(DBError::IncompatibleFile { reason: "backup starts past the file's commit" });
+    }
+    let mut page = vec![0; page_size];
+    for pgno in first..meta.get_next_pgno() {
+        backup.read_exact(&mut page)?;
+        file.write_all_at(&page, pgno * page_size as u64)?;
+    }
+    // the meta pages go last, once every page they refer to is on disk
+    file.sync_data()?;
+    file.write_all_at(&meta_pages, 0)?;
+    file.sync_all()?;
+    Ok(meta)
+}
+
+pub fn dump(env: &Env, writer: &mut dyn Write) -> Result<(), DBError> {
+    let txn = env.begin_read()?;
+    let mut dump = DumpWriter::new(writer, env.get_key_order())?;
+    for entry in txn.tree().cursor()? {
+        let (key, value) = entry?;
+        dump.write_entry(&key, value)?;
+    }
+    dump.finish()?;
+    Ok(())
+}
+
+pub fn load(options: &EnvOptions, path: &Path, reader: &mut dyn Read) -> Result<Env, DBError> {
+    let dump = DumpReader::new(reader)?;
+    let options = match dump.get_key_order() {
+        Some(order) => options.clone().key_order(order),
+        None => options.clone(),
+    };
+    let env = options.open(path)?;
+    let mut txn = env.begin_write();
+
+    // as in `write_partition`, a bad record ends the entries early and is
+    // reported once the batch returns
+    let mut error = None;
+    let mut databases = 0;
+    let entries = dump
+        .map_while(|record| match record {
+            Ok(DumpRecord::Entry(key, value)) => Some(Some((key, value))),
+            // a file holds a single tree, so one database is all it takes
+            Ok(DumpRecord::Database(_)) if databases == 0 => {
+                databases += 1;
+                Some(None)
+            }
+            Ok(DumpRecord::Database(_)) => {
+                error = Some(DBError::IncompatibleFile {
+                    reason: "dump holds more than one database",
+                });
+                None
+            }
+            Err(err) => {
+                error = Some(err);
+                None
+            }
+        })
+        .flatten();
+    let written = txn.write_batch(entries);
+    if let Some(err) = error {
+        return Err(err);
+    }
+    written?;
+    txn.commit()?;
+    Ok(env)
+}
+
+pub fn copy_to_compacted(env: &Env, path: &Path) -> Result<(), DBError> {
+    let txn = env.begin_read()?;
+    let whole = Partition {
+        path: path.to_path_buf(),
+        start: None,
+        end: None,
+    };
+    let options = EnvOptions::new().page_size(env.get_page_size()).key_order(env.get_key_order());
+    write_partition(&txn, &options, &whole)
+}
+
+pub fn export_partitions(env: &Env, n: usize, dir: &Path) -> Result<Vec<Partition>, DBError> {
+    assert!(n > 0, "at least one partition is needed");
+    let txn = env.begin_read()?;
+    let splits = txn.tree().split_points(n)?;
+    fs::create_dir_all(dir)?;
+
+    let starts = [None].into_iter().chain(splits.clone().into_iter().map(Some));
+    let ends = splits.into_iter().map(Some).chain([None]);
+    let partitions: Vec<Partition> = starts
+        .zip(ends)
+        .enumerate()
+        .map(|(i, (start, end))| Partition {
+            path: dir.join(format!("partition-{:04}", i)),
+            start,
+            end,
+        })
+        .collect();
+
+    let options = EnvOptions::new().page_size(env.get_page_size()).key_order(env.get_key_order());
+    thread::scope(|scope| {
+        let writers: Vec<_> = partitions
+            .iter()
+            .map(|partition| scope.spawn(|| write_partition(&txn, &options, partition)))
+            .collect();
+        writers
+            .into_iter()
+            .try_for_each(|writer| {
+                writer.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+    })?;
+    Ok(partitions)
+}
+
+fn write_partition(
+    txn: &ReadTxn,
+    options: &EnvOptions,
+    partition: &Partition,
+) -> Result<(), DBError> {
+    let tree = txn.tree();
+    let mut cursor = tree.cursor()?;
+    if let Some(start) = &partition.start {
+        cursor.seek(start)?;
+    }
+    let in_range = |key: &[u8]| match &partition.end {
+        Some(end) => tree.get_order().compare(key, end).is_lt(),
+        None => true,
+    };
+
+    // bulk loading takes plain entries, so a read error ends the input early
+    // and is reported once the load returns
+    let mut error = None;
+    let entries = cursor
+        .map_while(|entry| entry.map_err(|err| error = Some(err)).ok())
+        .take_while(|(key, _)| in_range(key));
+    let loaded = options.bulk_load(&partition.path, entries);
+    match error {
+        Some(err) => Err(err),
+        None => loaded.map(drop),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_order::KeyOrder;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_incremental_backup() {
+        let dir = tempdir().unwrap();
+        let (path, copy) = (dir.path().join("db"), dir.path().join("copy"));
+        let key = |i: u32| format!("key-{i:06}").into_bytes();
+        let env = Env::bulk_load(&path, (0..5000).map(|i| (key(i), key(i)))).unwrap();
+        env.copy_to(&copy).unwrap();
+        let copied = env.get_meta();
+        assert!(env.pages_changed_since(copied.get_txnid()).is_empty());
+
+        for i in 0..3 {
+            let mut txn = env.begin_write();
+            txn.put(&key(i * 2000), b"changed").unwrap();
+            txn.commit().unwrap();
+        }
+        let changed = env.pages_changed_since(copied.get_txnid());
+        assert_eq!(changed, copied.get_next_pgno()..env.get_meta().get_next_pgno());
+        let mut backup = Vec::new();
+        let written = env.incremental_backup(copied.get_txnid(), &mut backup).unwrap();
+        assert_eq!(written, IncrementalBackup { txnid: copied.get_txnid() + 3, pages: changed });
+        let meta = Env::apply_incremental_backup(&copy, &backup[..]).unwrap();
+        assert_eq!(meta.get_txnid(), written.txnid);
+        let restored = Env::open(&copy).unwrap();
+        assert!(restored.check().unwrap().is_ok());
+        assert_eq!(restored.begin_read().unwrap().get(&key(4000)).unwrap(), b"changed");
+        drop(restored);
+        // a copy of an older commit can't take it
+        let mut older = Vec::new();
+        env.incremental_backup(written.txnid, &mut older).unwrap();
+        let first = dir.path().join("first");
+        Env::open(&first).unwrap();
+        let applied = Env::apply_incremental_backup(&first, &older[..]);
+        assert!(matches!(applied, Err(DBError::IncompatibleFile { .. })));
+
+        // recorded across a reopen, until compaction moves every page
+        drop(env);
+        let env = Env::open(&path).unwrap();
+        assert_eq!(env.pages_changed_since(copied.get_txnid()).start, copied.get_next_pgno());
+        env.compact().unwrap();
+        assert_eq!(env.pages_changed_since(written.txnid).start, NUM_META_PAGES);
+    }
+
+    #[test]
+    fn test_copy_while_writing() {
+        let dir = tempdir().unwrap();
+        let key = |i: u32| format!("key-{i:06}").into_bytes();
+        let env = Env::bulk_load(dir.path().join("db"), (0..5000).map(|i| (key(i), key(i))))
+            .unwrap();
+        let mut txn = env.begin_write();
+        txn.delete(&key(7)).unwrap();
+        txn.commit().unwrap();
+
+        // a write transaction in progress isn't part of either copy
+        let mut txn = env.begin_write();
+        txn.put(b"uncommitted", b"1").unwrap();
+        env.copy_to(dir.path().join("copy")).unwrap();
+        env.copy_to_compacted(dir.path().join("compact")).unwrap();
+        txn.commit().unwrap();
+        assert!(env.copy_to(dir.path().join("copy")).is_err());
+
+        let stat = env.stat().unwrap();
+        for name in ["copy", "compact"] {
+            let copy = Env::open(dir.path().join(name)).unwrap();
+            let txn = copy.begin_read().unwrap();
+            assert_eq!(txn.get(&key(4999)).unwrap(), key(4999));
+            assert!(matches!(txn.get(&key(7)), Err(DBError::KeyNotFound)));
+            assert!(matches!(txn.get(b"uncommitted"), Err(DBError::KeyNotFound)));
+            assert_eq!(copy.stat().unwrap().tree.entries, 4999);
+        }
+        let copy = Env::open(dir.path().join("copy")).unwrap().stat().unwrap();
+        assert_eq!(copy.total_pages, stat.total_pages - 2);
+        let compact = Env::open(dir.path().join("compact")).unwrap().stat().unwrap();
+        assert_eq!(compact.free_pages, 0);
+        assert!(compact.total_pages < copy.total_pages);
+    }
+
+    #[test]
+    fn test_dump_and_load() {
+        let dir = tempdir().unwrap();
+        let key = |i: u64| i.to_le_bytes().to_vec();
+        let options = EnvOptions::new().key_order(KeyOrder::U64LE);
+        let env = options.bulk_load(dir.path().join("db"), (0..3000).map(|i| (key(i), key(i))))
+            .unwrap();
+        let mut dump = Vec::new();
+        env.dump(&mut dump).unwrap();
+
+        // a new file takes the order of the dump, which byte order would
+        // have re-sorted by the lowest byte first
+        let loaded = Env::load(dir.path().join("loaded"), &dump[..]).unwrap();
+        assert!(matches!(loaded.get_key_order(), KeyOrder::U64LE));
+        let txn = loaded.begin_read().unwrap();
+        let keys: Vec<_> = txn.tree().cursor().unwrap().keys().map(Result::unwrap).collect();
+        assert!(keys.iter().map(|key| key.to_vec()).eq((0..3000).map(key)));
+        assert_eq!(txn.get(&key(2999)).unwrap(), key(2999));
+        drop(txn);
+
+        // a file in another order can't take it
+        let other = dir.path().join("other");
+        Env::bulk_load(&other, [(b"a", b"1")]).unwrap();
+        let result = Env::load(&other, &dump[..]);
+        assert!(matches!(result, Err(DBError::IncompatibleFile { .. })));
+
+        // nor is anything of a damaged dump committed
+        let damaged = dir.path().join("damaged");
+        let result = Env::load(&damaged, &dump[..dump.len() - 4]);
+        assert!(matches!(result, Err(DBError::CorruptValue { .. })));
+        assert_eq!(options.open(&damaged).unwrap().stat().unwrap().tree.entries, 0);
+    }
+
+    #[test]
+    fn test_partitions_cover_every_key_once() {
+        let dir = tempdir().unwrap();
+        let key = |i: u32| format!("key-{i:06}").into_bytes();
+        let env = Env::bulk_load(dir.path().join("db"), (0..30_000).map(|i| (key(i), key(i))))
+            .unwrap();
+
+        let partitions = env.export_partitions(4, dir.path().join("out")).unwrap();
+        assert_eq!(partitions.len(), 4);
+        let mut next = 0;
+        for partition in &partitions {
+            let part = Env::open(&partition.path).unwrap();
+            let txn = part.begin_read().unwrap();
+            let count = txn.tree().cursor().unwrap().count() as u32;
+            // the split is taken from the top of the tree, so it's only roughly even
+            assert!((3000..12_000).contains(&count), "{count} entries");
+            for i in [next, next + count - 1] {
+                assert_eq!(txn.get(&key(i)).unwrap(), key(i));
+            }
+            assert_eq!(partition.start.as_deref(), (next > 0).then(|| key(next)).as_deref());
+            next += count;
+        }
+        assert_eq!(next, 30_000);
+
+        // a tree smaller than the partition count yields fewer partitions
+        let small = Env::bulk_load(dir.path().join("small"), [(b"a", b"1"), (b"b", b"2")]).unwrap();
+        let partitions = small.export_partitions(8, dir.path().join("small-out")).unwrap();
+        assert_eq!(partitions.len(), 2);
+        assert_eq!(partitions[1].start.as_deref(), Some(&b"b"[..]));
+    }
+}
@@ -0,0 +1,327 @@
+use crate::constants::*;
+
+const CURVE_BITS: u32 = 32;
+
+/// Cap on the number of key ranges `query_bbox` scans by default. Cells left
+/// partially covered once the cap is reached are scanned whole and filtered.
+pub const DEFAULT_MAX_RANGES: usize = 64;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Curve {
+    ZOrder,
+    Hilbert,
+}
+
+/// Inclusive bounding box on the 2^32 x 2^32 grid.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BBox {
+    pub min_x: u32,
+    pub min_y: u32,
+    pub max_x: u32,
+    pub max_y: u32,
+}
+
+impl BBox {
+    pub fn new(min_x: u32, min_y: u32, max_x: u32, max_y: u32) -> Self {
+        BBox {
+            min_x: min_x.min(max_x),
+            min_y: min_y.min(max_y),
+            max_x: min_x.max(max_x),
+            max_y: min_y.max(max_y),
+        }
+    }
+
+    pub fn from_lat_lon(min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> Self {
+        let (min_x, min_y) = lat_lon_to_xy(min_lat, min_lon);
+        let (max_x, max_y) = lat_lon_to_xy(max_lat, max_lon);
+        BBox::new(min_x, min_y, max_x, max_y)
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        (self.min_x..=self.max_x).contains(&x) && (self.min_y..=self.max_y).contains(&y)
+    }
+}
+
+/// Quantizes a coordinate onto the grid: longitude maps to x and latitude to y.
+pub fn lat_lon_to_xy(lat: f64, lon: f64) -> (u32, u32) {
+    fn quantize(value: f64, min: f64, max: f64) -> u32 {
+        let unit = ((value - min) / (max - min)).clamp(0.0, 1.0);
+        (unit * u32::MAX as f64).round() as u32
+    }
+    (quantize(lon, -180.0, 180.0), quantize(lat, -90.0, 90.0))
+}
+
+pub fn xy_to_lat_lon(x: u32, y: u32) -> (f64, f64) {
+    let lat = y as f64 / u32::MAX as f64 * 180.0 - 90.0;
+    let lon = x as f64 / u32::MAX as f64 * 360.0 - 180.0;
+    (lat, lon)
+}
+
+// spreads the 32 bits of v out to the even bits of a u64
+fn spread_bits(v: u32) -> u64 {
+    let mut v = v as u64;
+    v = (v | (v << 16)) & 0x0000_FFFF_0000_FFFF;
+    v = (v | (v << 8)) & 0x00FF_00FF_00FF_00FF;
+    v = (v | (v << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+    v = (v | (v << 1)) & 0x5555_5555_5555_5555;
+    v
+}
+
+fn compact_bits(v: u64) -> u32 {
+    let mut v = v & 0x5555_5555_5555_5555;
+    v = (v | (v >> 1)) & 0x3333_3333_3333_3333;
+    v = (v | (v >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+    v = (v | (v >> 4)) & 0x00FF_00FF_00FF_00FF;
+    v = (v | (v >> 8)) & 0x0000_FFFF_0000_FFFF;
+    v = (v | (v >> 16)) & 0x0000_0000_FFFF_FFFF;
+    v as u32
+}
+
+pub fn z_encode(x: u32, y: u32) -> u64 {
+    spread_bits(x) | (spread_bits(y) << 1)
+}
+
+pub fn z_decode(z: u64) -> (u32, u32) {
+    (compact_bits(z), compact_bits(z >> 1))
+}
+
+fn hilbert_rotate(n: u64, x: &mut u64, y: &mut u64, rx: u64, ry: u64) {
+    if ry == 0 {
+        if rx == 1 {
+            *x = n.wrapping_sub(1).wrapping_sub(*x);
+            *y = n.wrapping_sub(1).wrapping_sub(*y);
+        }
+        std::mem::swap(x, y);
+    }
+}
+
+// index of (x, y) along a Hilbert curve covering a 2^order x 2^order grid
+fn hilbert_index(order: u32, x: u64, y: u64) -> u64 {
+    let (mut x, mut y) = (x, y);
+    let mut d = 0u64;
+    let mut s = 1u64 << (order - 1);
+    while s > 0 {
+        let rx = ((x & s) > 0) as u64;
+        let ry = ((y & s) > 0) as u64;
+        d += s * s * ((3 * rx) ^ ry);
+        hilbert_rotate(s, &mut x, &mut y, rx, ry);
+        s >>= 1;
+    }
+    d
+}
+
+pub fn hilbert_encode(x: u32, y: u32) -> u64 {
+    hilbert_index(CURVE_BITS, x as u64, y as u64)
+}
+
+pub fn hilbert_decode(d: u64) -> (u32, u32) {
+    let (mut x, mut y) = (0u64, 0u64);
+    let mut t = d;
+    let mut s = 1u64;
+    while s < 1 << CURVE_BITS {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        hilbert_rotate(s, &mut x, &mut y, rx, ry);
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s <<= 1;
+    }
+    (x as u32, y as u32)
+}
+
+impl Curve {
+    pub fn encode(&self, x: u32, y: u32) -> u64 {
+        match self {
+            Curve::ZOrder => z_encode(x, y),
+            Curve::Hilbert => hilbert_encode(x, y),
+        }
+    }
+
+    pub fn decode(&self, d: u64) -> (u32, u32) {
+        match self {
+            Curve::ZOrder => z_decode(d),
+            Curve::Hilbert => hilbert_decode(d),
+        }
+    }
+
+    /// Curve key for a point, big-endian so byte order matches curve order.
+    pub fn key(&self, x: u32, y: u32) -> [u8; 8] {
+        self.encode(x, y).to_be_bytes()
+    }
+
+    pub fn lat_lon_key(&self, lat: f64, lon: f64) -> [u8; 8] {
+        let (x, y) = lat_lon_to_xy(lat, lon);
+        self.key(x, y)
+    }
+
+    // Both curves visit every quadtree cell contiguously, so a cell at `level`
+    // covers the inclusive index range below.
+    fn cell_range(&self, level: u32, cx: u32, cy: u32) -> (u64, u64) {
+        if level == 0 {
+            return (0, u64::MAX);
+        }
+        let d = match self {
+            Curve::ZOrder => z_encode(cx, cy),
+            Curve::Hilbert => hilbert_index(level, cx as u64, cy as u64),
+        };
+        let shift = 2 * (CURVE_BITS - level);
+        let start = d << shift;
+        (start, start | ((1u64 << shift) - 1))
+    }
+
+    /// Decomposes `bbox` into at most roughly `max_ranges` sorted, disjoint,
+    /// inclusive index ranges whose union covers the box. Ranges may cover
+    /// points outside the box once the cap forces coarse cells.
+    pub fn bbox_ranges(&self, bbox: &BBox, max_ranges: usize) -> Vec<(u64, u64)> {
+        let mut ranges = Vec::new();
+        let mut partial = vec![(0u32, 0u32)];
+        let mut level = 0;
+
+        while !partial.is_empty() {
+            if level == CURVE_BITS || ranges.len() + partial.len() * 4 > max_ranges.max(1) {
+                ranges.extend(partial.iter().map(|&(cx, cy)| self.cell_range(level, cx, cy)));
+                break;
+            }
+            level += 1;
+            let side_shift = CURVE_BITS - level;
+
+            let mut next = Vec::new();
+            for &(cx, cy) in &partial {
+                for (qx, qy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let (x, y) = ((cx << 1) | qx, (cy << 1) | qy);
+                    let min_x = (x as u64) << side_shift;
+                    let min_y = (y as u64) << side_shift;
+                    let max_x = min_x + (1u64 << side_shift) - 1;
+                    let max_y = min_y + (1u64 << side_shift) - 1;
+
+                    let disjoint = min_x > bbox.max_x as u64
+                        || max_x < bbox.min_x as u64
+                        || min_y > bbox.max_y as u64
+                        || max_y < bbox.min_y as u64;
+                    let covered = min_x >= bbox.min_x as u64
+                        && max_x <= bbox.max_x as u64
+                        && min_y >= bbox.min_y as u64
+                        && max_y <= bbox.max_y as u64;
+                    if covered {
+                        ranges.push(self.cell_range(level, x, y));
+                    } else if !disjoint {
+                        next.push((x, y));
+                    }
+                }
+            }
+            partial = next;
+        }
+
+        ranges.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last_end)) if last_end.checked_add(1) == Some(start) => *last_end = end,
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+}
+
+/// Returns the entries whose 8-byte curve key falls inside `bbox`, in key
+/// order. `scan` is called once per key range with inclusive big-endian bounds
+/// and must return the entries stored in that range.
+pub fn query_bbox<F>(
+    curve: Curve,
+    bbox: &BBox,
+    max_ranges: usize,
+    mut scan: F,
+) -> Result<Vec<KeyValue>, DBError>
+where
+    F: FnMut(&[u8], &[u8]) -> Result<Vec<KeyValue>, DBError>,
+{
+    let mut results = Vec::new();
+    for (start, end) in curve.bbox_ranges(bbox, max_ranges) {
+        for (key, value) in scan(&start.to_be_bytes(), &end.to_be_bytes())? {
+            let d: [u8; 8] = key.as_slice().try_into().map_err(|_| DBError::CorruptValue {
+                reason: "spatial key is not 8 bytes",
+            })?;
+            let (x, y) = curve.decode(u64::from_be_bytes(d));
+            if bbox.contains(x, y) {
+                results.push((key, value));
+            }
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_curve_round_trip() {
+        let mut rng = rand::rng();
+        for _ in 0..1000 {
+            let (x, y) = (rng.random::<u32>(), rng.random::<u32>());
+            assert_eq!(z_decode(z_encode(x, y)), (x, y));
+            assert_eq!(hilbert_decode(hilbert_encode(x, y)), (x, y));
+        }
+        assert_eq!(z_encode(u32::MAX, u32::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_hilbert_neighbours_are_adjacent() {
+        for d in 0..4096u64 {
+            let (x0, y0) = hilbert_decode(d);
+            let (x1, y1) = hilbert_decode(d + 1);
+            assert_eq!(x0.abs_diff(x1) + y0.abs_diff(y1), 1);
+        }
+    }
+
+    #[test]
+    fn test_query_bbox_matches_brute_force() {
+        let mut rng = rand::rng();
+        let points: Vec<(u32, u32)> = (0..2000)
+            .map(|_| (rng.random_range(0..1 << 20), rng.random_range(0..1 << 20)))
+            .collect();
+        let bbox = BBox::new(100_000, 200_000, 600_000, 450_000);
+
+        for curve in [Curve::ZOrder, Curve::Hilbert] {
+            let store: BTreeMap<Vec<u8>, Vec<u8>> = points
+                .iter()
+                .map(|&(x, y)| (curve.key(x, y).to_vec(), vec![]))
+                .collect();
+            let found = query_bbox(curve, &bbox, DEFAULT_MAX_RANGES, |start, end| {
+                Ok(store
+                    .range(start.to_vec()..=end.to_vec())
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect())
+            })
+            .unwrap();
+
+            let mut expected: Vec<Vec<u8>> = store
+                .keys()
+                .filter(|k| {
+                    let (x, y) = curve.decode(u64::from_be_bytes(k.as_slice().try_into().unwrap()));
+                    bbox.contains(x, y)
+                })
+                .cloned()
+                .collect();
+            expected.sort();
+            let found: Vec<Vec<u8>> = found.into_iter().map(|(k, _)| k).collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_lat_lon_keys() {
+        let (x, y) = lat_lon_to_xy(37.7749, -122.4194);
+        let (lat, lon) = xy_to_lat_lon(x, y);
+        assert!((lat - 37.7749).abs() < 1e-6);
+        assert!((lon + 122.4194).abs() < 1e-6);
+
+        let bbox = BBox::from_lat_lon(37.0, -123.0, 38.0, -122.0);
+        assert!(bbox.contains(x, y));
+    }
+}
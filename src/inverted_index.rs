@@ -0,0 +1,217 @@
+use crate::buf::{read_varint_u64, write_varint_u64, VarintError};
+use crate::constants::*;
+use crate::txn::{ReadTxn, WriteTxn};
+
+/// Backing storage for posting lists, keyed by token.
+pub trait PostingStore {
+    fn get_postings(&self, token: &[u8]) -> Result<Option<Vec<u8>>, DBError>;
+
+    fn put_postings(&mut self, token: &[u8], postings: &[u8]) -> Result<(), DBError>;
+}
+
+/// Posting lists as entries of a write transaction, under their tokens as
+/// keys, so the index commits along with whatever else it writes.
+impl PostingStore for WriteTxn<'_> {
+    fn get_postings(&self, token: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        match self.get_owned(token) {
+            Err(DBError::KeyNotFound) => Ok(None),
+            found => found.map(Some),
+        }
+    }
+
+    fn put_postings(&mut self, token: &[u8], postings: &[u8]) -> Result<(), DBError> {
+        self.put(token, postings)?;
+        Ok(())
+    }
+}
+
+/// Posting lists as a write transaction left them, for queries; adding a
+/// document fails with `TxnReadOnly`.
+impl PostingStore for ReadTxn {
+    fn get_postings(&self, token: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        match self.get_owned(token) {
+            Err(DBError::KeyNotFound) => Ok(None),
+            found => found.map(Some),
+        }
+    }
+
+    fn put_postings(&mut self, _token: &[u8], _postings: &[u8]) -> Result<(), DBError> {
+        Err(DBError::TxnReadOnly)
+    }
+}
+
+/// Maps tokens to sorted lists of document ids. Each posting list is stored as
+/// a single value: the number of ids followed by the delta between consecutive
+/// ids, all as varints.
+pub struct InvertedIndex<S: PostingStore> {
+    store: S,
+}
+
+pub fn encode_postings(ids: &[u64]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(ids.len() + 1);
+    write_varint_u64(&mut buf, ids.len() as u64);
+    let mut prev = 0;
+    for &id in ids {
+        debug_assert!(id >= prev, "posting list must be sorted");
+        write_varint_u64(&mut buf, id - prev);
+        prev = id;
+    }
+    buf
+}
+
+pub fn decode_postings(buf: &[u8]) -> Result<Vec<u64>, DBError> {
+    let malformed = |err: VarintError| DBError::CorruptValue {
+        reason: match err {
+            VarintError::Truncated => "truncated posting list",
+            err => err.reason(),
+        },
+    };
+    let (count, mut pos) = read_varint_u64(buf).map_err(malformed)?;
+
+    // every id takes at least one byte, which bounds the allocation
+    if count > (buf.len() - pos) as u64 {
+        return Err(DBError::CorruptValue {
+            reason: "posting list count exceeds its length",
+        });
+    }
+    let mut ids = Vec::with_capacity(count as usize);
+    let mut prev = 0u64;
+    for _ in 0..count {
+        let (delta, n) = read_varint_u64(&buf[pos..]).map_err(malformed)?;
+        prev = prev.checked_add(delta).ok_or(DBError::CorruptValue {
+            reason: "posting list id overflows u64",
+        })?;
+        ids.push(prev);
+        pos += n;
+    }
+
+    Ok(ids)
+}
+
+impl<S: PostingStore> InvertedIndex<S> {
+    pub fn new(store: S) -> Self {
+        InvertedIndex { store }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    pub fn postings(&self, token: &[u8]) -> Result<Vec<u64>, DBError> {
+        match self.store.get_postings(token)? {
+            Some(buf) => decode_postings(&buf),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn add_document(&mut self, id: u64, tokens: &[&[u8]]) -> Result<(), DBError> {
+        let mut tokens = tokens.to_vec();
+        tokens.sort_unstable();
+        tokens.dedup();
+
+        for token in tokens {
+            let mut ids = self.postings(token)?;
+            if let Err(idx) = ids.binary_search(&id) {
+                ids.insert(idx, id);
+                self.store.put_postings(token, &encode_postings(&ids))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ids of documents containing every token.
+    pub fn query_and(&self, tokens: &[&[u8]]) -> Result<Vec<u64>, DBError> {
+        let mut lists = tokens
+            .iter()
+            .map(|token| self.postings(token))
+            .collect::<Result<Vec<_>, _>>()?;
+        // intersect starting from the shortest list to keep the candidate set small
+        lists.sort_by_key(|ids| ids.len());
+
+        let mut lists = lists.into_iter();
+        let mut result = match lists.next() {
+            Some(ids) => ids,
+            None => return Ok(Vec::new()),
+        };
+        for ids in lists {
+            result.retain(|id| ids.binary_search(id).is_ok());
+            if result.is_empty() {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Ids of documents containing at least one of the tokens.
+    pub fn query_or(&self, tokens: &[&[u8]]) -> Result<Vec<u64>, DBError> {
+        let mut result = Vec::new();
+        for token in tokens {
+            result.extend(self.postings(token)?);
+        }
+        result.sort_unstable();
+        result.dedup();
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::EnvOptions;
+    use std::collections::BTreeMap;
+    use tempfile::tempdir;
+
+    impl PostingStore for BTreeMap<Vec<u8>, Vec<u8>> {
+        fn get_postings(&self, token: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+            Ok(self.get(token).cloned())
+        }
+
+        fn put_postings(&mut self, token: &[u8], postings: &[u8]) -> Result<(), DBError> {
+            self.insert(token.to_vec(), postings.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_postings_round_trip() {
+        let ids = vec![0, 1, 7, 300, 301, 1 << 40, u64::MAX];
+        let encoded = encode_postings(&ids);
+        assert_eq!(decode_postings(&encoded).unwrap(), ids);
+
+        assert!(matches!(
+            decode_postings(&encoded[..encoded.len() - 1]),
+            Err(DBError::CorruptValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut index = InvertedIndex::new(BTreeMap::new());
+        index.add_document(1, &[b"red", b"apple"]).unwrap();
+        index.add_document(2, &[b"green", b"apple"]).unwrap();
+        index.add_document(3, &[b"red", b"car", b"red"]).unwrap();
+
+        assert_eq!(index.query_and(&[b"red", b"apple"]).unwrap(), vec![1]);
+        assert_eq!(index.query_and(&[b"apple"]).unwrap(), vec![1, 2]);
+        assert_eq!(index.query_and(&[b"red", b"blue"]).unwrap(), Vec::<u64>::new());
+        assert_eq!(index.query_or(&[b"green", b"car"]).unwrap(), vec![2, 3]);
+        assert_eq!(index.query_or(&[b"blue"]).unwrap(), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_env_backed() {
+        let dir = tempdir().unwrap();
+        let env = EnvOptions::new().open(dir.path().join("db")).unwrap();
+        let mut index = InvertedIndex::new(env.begin_write());
+        index.add_document(1, &[b"red", b"apple"]).unwrap();
+        index.add_document(2, &[b"green", b"apple"]).unwrap();
+        index.into_inner().commit().unwrap();
+
+        let mut index = InvertedIndex::new(env.begin_read().unwrap());
+        assert_eq!(index.query_and(&[b"red", b"apple"]).unwrap(), vec![1]);
+        assert_eq!(index.query_or(&[b"green", b"red"]).unwrap(), vec![1, 2]);
+        assert_eq!(index.into_inner().get(b"apple").unwrap(), encode_postings(&[1, 2]));
+        index = InvertedIndex::new(env.begin_read().unwrap());
+        assert!(matches!(index.add_document(3, &[b"car"]), Err(DBError::TxnReadOnly)));
+    }
+}
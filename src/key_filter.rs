@@ -0,0 +1,173 @@
+use std::hash::{DefaultHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// never sized for fewer keys than this, so a filter built for a small tree
+// isn't rebuilt on every other commit
+const MIN_KEYS: u64 = 1024;
+
+/// Lookups answered by an environment's `KeyFilter` since it was opened,
+/// across rebuilds.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct KeyFilterStat {
+    pub bits_per_key: usize,
+    pub bits: u64,
+    /// Bits set by the keys put in so far; the filter is rebuilt larger
+    /// once about as many are set as the keys it was sized for set.
+    pub set_bits: u64,
+    pub lookups: u64,
+    /// Lookups of absent keys answered without reading the tree.
+    pub skipped: u64,
+    /// Lookups the filter let through for keys that then weren't found.
+    pub false_positives: u64,
+}
+
+impl KeyFilterStat {
+    /// Share of lookups of absent keys the filter failed to catch, from 0
+    /// to 1.
+    pub fn false_positive_rate(&self) -> f64 {
+        match self.skipped + self.false_positives {
+            0 => 0.0,
+            misses => self.false_positives as f64 / misses as f64,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    lookups: AtomicU64,
+    skipped: AtomicU64,
+    false_positives: AtomicU64,
+}
+
+/// A bloom filter over the keys of an environment's commits, so lookups of
+/// keys that aren't there can mostly skip the tree. Keys are only ever put
+/// in, never taken out, so a filter holding every key of a commit still
+/// holds them once later commits have deleted some: readers of any commit
+/// since the filter was built can use it. Bits are set atomically, so the
+/// writer puts in the keys of a commit while readers go on using the filter.
+pub struct KeyFilter {
+    words: Box<[AtomicU64]>,
+    bits_per_key: usize,
+    num_hashes: u32,
+    set_bits: AtomicU64,
+    counters: Arc<Counters>,
+}
+
+impl KeyFilter {
+    /// An empty filter with room for `keys` keys at `bits_per_key` bits each,
+    /// which has about a 1% false positive rate at 10 bits and 0.1% at 15.
+    pub fn new(bits_per_key: usize, keys: u64) -> Self {
+        Self::with_counters(bits_per_key, keys, Arc::default())
+    }
+
+    fn with_counters(bits_per_key: usize, keys: u64, counters: Arc<Counters>) -> Self {
+        let bits_per_key = bits_per_key.max(1);
+        let bits = keys.max(MIN_KEYS).saturating_mul(bits_per_key as u64);
+        let words = (0..bits.div_ceil(64)).map(|_| AtomicU64::new(0)).collect();
+        // the number of hashes that gives the fewest false positives
+        let num_hashes = (bits_per_key as f64 * std::f64::consts::LN_2).round() as u32;
+        KeyFilter {
+            words,
+            bits_per_key,
+            num_hashes: num_hashes.clamp(1, 30),
+            set_bits: AtomicU64::new(0),
+            counters,
+        }
+    }
+
+    /// An empty filter sized for `keys` keys that carries on counting
+    /// lookups where this one leaves off.
+    pub fn resized(&self, keys: u64) -> Self {
+        Self::with_counters(self.bits_per_key, keys, Arc::clone(&self.counters))
+    }
+
+    fn num_bits(&self) -> u64 {
+        self.words.len() as u64 * 64
+    }
+
+    // the bits of `key`, by double hashing
+    fn bits_of(&self, key: &[u8]) -> impl Iterator<Item = u64> {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(key);
+        let hash = hasher.finish();
+        let (num_bits, step) = (self.num_bits(), hash.rotate_left(32) | 1);
+        (0..u64::from(self.num_hashes))
+            .map(move |i| hash.wrapping_add(i.wrapping_mul(step)) % num_bits)
+    }
+
+    pub fn insert(&self, key: &[u8]) {
+        for bit in self.bits_of(key) {
+            let mask = 1 << (bit % 64);
+            let old = self.words[(bit / 64) as usize].fetch_or(mask, Ordering::Relaxed);
+            if old & mask == 0 {
+                self.set_bits.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Whether `key` may have been put in; `false` means it certainly wasn't.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.counters.lookups.fetch_add(1, Ordering::Relaxed);
+        let found = self.bits_of(key).all(|bit| {
+            self.words[(bit / 64) as usize].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0
+        });
+        if !found {
+            self.counters.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+        found
+    }
+
+    /// Counts a key `may_contain` let through that turned out not to be there.
+    pub fn record_false_positive(&self) {
+        self.counters.false_positives.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether as many bits are set as the keys the filter was sized for
+    /// would set, past which false positives quickly grow more common.
+    pub fn is_full(&self) -> bool {
+        let sized_fill = 1.0 - (-f64::from(self.num_hashes) / self.bits_per_key as f64).exp();
+        self.set_bits.load(Ordering::Relaxed) as f64 > sized_fill * self.num_bits() as f64
+    }
+
+    pub fn stat(&self) -> KeyFilterStat {
+        KeyFilterStat {
+            bits_per_key: self.bits_per_key,
+            bits: self.num_bits(),
+            set_bits: self.set_bits.load(Ordering::Relaxed),
+            lookups: self.counters.lookups.load(Ordering::Relaxed),
+            skipped: self.counters.skipped.load(Ordering::Relaxed),
+            false_positives: self.counters.false_positives.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_false_negatives() {
+        let filter = KeyFilter::new(10, 12_000);
+        for i in 0..10_000u32 {
+            filter.insert(&i.to_be_bytes());
+        }
+        assert!(!filter.is_full());
+        assert!((0..10_000u32).all(|i| filter.may_contain(&i.to_be_bytes())));
+        let passed = (10_000..110_000u32).filter(|i| filter.may_contain(&i.to_be_bytes())).count();
+        // about 1% at 10 bits a key, with room to spare
+        assert!(passed < 2_000, "{passed}");
+        let stat = filter.stat();
+        assert_eq!((stat.lookups, stat.skipped), (110_000, 100_000 - passed as u64));
+
+        // twice the keys it was sized for
+        for i in 10_000..24_000u32 {
+            filter.insert(&i.to_be_bytes());
+        }
+        assert!(filter.is_full());
+        let resized = filter.resized(40_000);
+        assert!(!resized.is_full() && resized.stat().bits > stat.bits);
+        resized.record_false_positive();
+        assert_eq!(filter.stat().false_positives, 1);
+    }
+}
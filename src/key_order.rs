@@ -0,0 +1,248 @@
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
+
+// ids of the built-in orders, as recorded in a file's meta pages
+const BYTES_ID: u8 = 0;
+const U64_LE_ID: u8 = 1;
+const U64_LE_DESCENDING_ID: u8 = 2;
+const BYTES_REVERSED_ID: u8 = 3;
+// BytesAfter(n) is recorded as BYTES_AFTER_ID + n, for the headers short
+// enough to fit
+const BYTES_AFTER_ID: u8 = 128;
+// recorded, in meta pages and dumps, in place of an id for an order that has
+// none; past every id a built-in order can have
+pub(crate) const UNRECORDED_ID: u16 = 0x100;
+
+/// How keys of a database are ordered, fixed when the database is created.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum KeyOrder {
+    /// Lexicographic byte order.
+    #[default]
+    Bytes,
+    /// Keys are little-endian u64s compared numerically. Keys that aren't 8
+    /// bytes long sort after every 8-byte key, in byte order.
+    U64LE,
+    /// `U64LE` reversed, largest first, as for newest-first timestamps. Keys
+    /// that aren't 8 bytes long still sort last.
+    U64LEDescending,
+    /// Byte order reversed, largest first.
+    BytesReversed,
+    /// Byte order of what follows a fixed-length header, such as a hash or
+    /// shard id, with ties broken by the header. Keys no longer than the
+    /// header sort first, by the header. Only headers of up to 127 bytes are
+    /// recorded with the database.
+    BytesAfter(usize),
+    /// A user-supplied order; it isn't recorded with the database, so it must
+    /// be passed again on every open.
+    Custom(fn(&[u8], &[u8]) -> Ordering),
+}
+
+impl KeyOrder {
+    pub fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match self {
+            KeyOrder::Bytes => a.cmp(b),
+            KeyOrder::U64LE => compare_u64_le(a, b, false),
+            KeyOrder::U64LEDescending => compare_u64_le(a, b, true),
+            KeyOrder::BytesReversed => b.cmp(a),
+            &KeyOrder::BytesAfter(header) => {
+                let (a_header, a_rest) = a.split_at(header.min(a.len()));
+                let (b_header, b_rest) = b.split_at(header.min(b.len()));
+                a_rest.cmp(b_rest).then_with(|| a_header.cmp(b_header))
+            }
+            KeyOrder::Custom(compare) => compare(a, b),
+        }
+    }
+
+    /// Where `key` falls relative to `range`: `Less` before its start,
+    /// `Greater` past its end, and `Equal` inside it.
+    pub fn compare_to_range<'k>(&self, key: &[u8], range: &impl RangeBounds<&'k [u8]>) -> Ordering {
+        let before = match range.start_bound() {
+            Bound::Included(start) => self.compare(key, start).is_lt(),
+            Bound::Excluded(start) => self.compare(key, start).is_le(),
+            Bound::Unbounded => false,
+        };
+        let after = match range.end_bound() {
+            Bound::Included(end) => self.compare(key, end).is_gt(),
+            Bound::Excluded(end) => self.compare(key, end).is_ge(),
+            Bound::Unbounded => false,
+        };
+        match (before, after) {
+            (true, _) => Ordering::Less,
+            (_, true) => Ordering::Greater,
+            _ => Ordering::Equal,
+        }
+    }
+
+    /// How the order relates to the natural order of what it compares.
+    pub const fn direction(&self) -> Direction {
+        match self {
+            KeyOrder::Bytes | KeyOrder::U64LE | KeyOrder::BytesAfter(_) => Direction::Ascending,
+            KeyOrder::U64LEDescending | KeyOrder::BytesReversed => Direction::Descending,
+            KeyOrder::Custom(_) => Direction::Unspecified,
+        }
+    }
+
+    pub const fn is_bytes(&self) -> bool {
+        matches!(self, KeyOrder::Bytes)
+    }
+
+    /// Id recorded for built-in orders; custom orders have none.
+    pub const fn id(&self) -> Option<u8> {
+        match self {
+            KeyOrder::Bytes => Some(BYTES_ID),
+            KeyOrder::U64LE => Some(U64_LE_ID),
+            KeyOrder::U64LEDescending => Some(U64_LE_DESCENDING_ID),
+            KeyOrder::BytesReversed => Some(BYTES_REVERSED_ID),
+            KeyOrder::BytesAfter(header) if *header <= (u8::MAX - BYTES_AFTER_ID) as usize => {
+                Some(BYTES_AFTER_ID + *header as u8)
+            }
+            KeyOrder::BytesAfter(_) => None,
+            KeyOrder::Custom(_) => None,
+        }
+    }
+
+    // the id widened to the 16 bits it's recorded in, so an order without
+    // one has a value of its own
+    pub(crate) const fn recorded_id(&self) -> u16 {
+        match self.id() {
+            Some(id) => id as u16,
+            None => UNRECORDED_ID,
+        }
+    }
+
+    pub const fn from_id(id: u8) -> Option<Self> {
+        match id {
+            BYTES_ID => Some(KeyOrder::Bytes),
+            U64_LE_ID => Some(KeyOrder::U64LE),
+            U64_LE_DESCENDING_ID => Some(KeyOrder::U64LEDescending),
+            BYTES_REVERSED_ID => Some(KeyOrder::BytesReversed),
+            BYTES_AFTER_ID..=u8::MAX => Some(KeyOrder::BytesAfter((id - BYTES_AFTER_ID) as usize)),
+            _ => None,
+        }
+    }
+}
+
+/// Whether an order sorts smallest first by the bytes or numbers it compares
+/// (keys that aren't 8 bytes, for the u64 orders, sort last either way).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Ascending,
+    Descending,
+    /// A custom order, which may be anything.
+    Unspecified,
+}
+
+/// How the values stored under one key are ordered.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DuplicateOrder {
+    /// Each key holds a single value.
+    Unique,
+    /// Values are kept sorted by the `KeyOrder` with this id. Nothing keeps
+    /// more than one value under a key yet, so no tree reports this.
+    Sorted { comparator: u8, direction: Direction },
+}
+
+/// The order a tree's cursors yield entries in, for callers that depend on
+/// it. Every scan, seek and page of a tree visits keys in strictly increasing
+/// order under its `KeyOrder`, whatever order they were written in, and the
+/// same keys always come back in the same order, across commits and reopens.
+/// Soft-deleted entries are only yielded by cursors that ask for them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IterationOrder {
+    /// Id of the key order as recorded with the database, or `None` for an
+    /// order that isn't recorded and must be passed again on every open.
+    pub comparator: Option<u8>,
+    pub direction: Direction,
+    pub duplicates: DuplicateOrder,
+}
+
+impl IterationOrder {
+    pub const fn of(order: KeyOrder) -> Self {
+        IterationOrder {
+            comparator: order.id(),
+            direction: order.direction(),
+            duplicates: DuplicateOrder::Unique,
+        }
+    }
+}
+
+fn compare_u64_le(a: &[u8], b: &[u8], descending: bool) -> Ordering {
+    match (<[u8; 8]>::try_from(a), <[u8; 8]>::try_from(b)) {
+        (Ok(a), Ok(b)) if descending => u64::from_le_bytes(b).cmp(&u64::from_le_bytes(a)),
+        (Ok(a), Ok(b)) => u64::from_le_bytes(a).cmp(&u64::from_le_bytes(b)),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orders() {
+        let (one, two_fifty_six) = (1u64.to_le_bytes(), 256u64.to_le_bytes());
+        assert_eq!(KeyOrder::Bytes.compare(&one, &two_fifty_six), Ordering::Greater);
+        assert_eq!(KeyOrder::U64LE.compare(&one, &two_fifty_six), Ordering::Less);
+        assert_eq!(KeyOrder::U64LE.compare(b"short", &one), Ordering::Greater);
+        assert_eq!(KeyOrder::U64LEDescending.compare(&one, &two_fifty_six), Ordering::Greater);
+        assert_eq!(KeyOrder::U64LEDescending.compare(b"short", &one), Ordering::Greater);
+
+        let range = &b"b"[..]..&b"d"[..];
+        let place = |key: &[u8]| KeyOrder::Bytes.compare_to_range(key, &range);
+        assert_eq!([place(b"a"), place(b"b"), place(b"c"), place(b"d")], [
+            Ordering::Less,
+            Ordering::Equal,
+            Ordering::Equal,
+            Ordering::Greater
+        ]);
+
+        assert_eq!(KeyOrder::BytesReversed.compare(b"a", b"b"), Ordering::Greater);
+        let after_shard = KeyOrder::BytesAfter(2);
+        assert_eq!(after_shard.compare(b"zza", b"aab"), Ordering::Less);
+        assert_eq!(after_shard.compare(b"bba", b"aaa"), Ordering::Greater);
+        assert_eq!(after_shard.compare(b"z", b"aaa"), Ordering::Less);
+        for order in [KeyOrder::BytesReversed, KeyOrder::BytesAfter(0), KeyOrder::BytesAfter(127)] {
+            let id = order.id().unwrap();
+            assert_eq!(KeyOrder::from_id(id).unwrap().id(), Some(id));
+        }
+        assert_eq!(KeyOrder::BytesAfter(128).id(), None);
+
+        let reverse = KeyOrder::Custom(|a, b| b.cmp(a));
+        assert_eq!(reverse.compare(b"a", b"b"), Ordering::Greater);
+        assert_eq!(reverse.id(), None);
+        assert!(matches!(KeyOrder::from_id(KeyOrder::U64LE.id().unwrap()), Some(KeyOrder::U64LE)));
+    }
+
+    #[test]
+    fn test_iteration_order_matches_compare() {
+        // every built-in order's reported direction agrees with how it sorts
+        // the natural order of its keys
+        let numbers: Vec<[u8; 8]> = [0u64, 1, 255, 256, 70_000].map(u64::to_le_bytes).to_vec();
+        let strings: Vec<[u8; 8]> =
+            [*b"aaaaaaaa", *b"aaaaaaab", *b"abaaaaaa", *b"baaaaaaa"].to_vec();
+        let orders = [
+            (KeyOrder::Bytes, &strings),
+            (KeyOrder::U64LE, &numbers),
+            (KeyOrder::U64LEDescending, &numbers),
+            (KeyOrder::BytesReversed, &strings),
+            (KeyOrder::BytesAfter(0), &strings),
+        ];
+        for (order, keys) in orders {
+            let contract = IterationOrder::of(order);
+            assert_eq!(contract.comparator, order.id());
+            assert_eq!(contract.duplicates, DuplicateOrder::Unique);
+            let expected = match contract.direction {
+                Direction::Ascending => Ordering::Less,
+                Direction::Descending => Ordering::Greater,
+                Direction::Unspecified => unreachable!("{order:?}"),
+            };
+            for pair in keys.windows(2) {
+                assert_eq!(order.compare(&pair[0], &pair[1]), expected, "{order:?}");
+            }
+        }
+        let custom = IterationOrder::of(KeyOrder::Custom(|a, b| b.cmp(a)));
+        assert_eq!((custom.comparator, custom.direction), (None, Direction::Unspecified));
+    }
+}
@@ -1,5 +1,42 @@
+#[cfg(feature = "async")]
+pub mod r#async;
 pub mod buf;
+pub mod btree;
 pub mod btree_page;
+pub mod check;
+#[cfg(feature = "compression")]
+pub mod compress;
+pub mod conflict;
 pub mod constants;
+pub mod cursor;
 pub mod data_page;
+pub mod debug;
+pub mod dump;
+pub mod env;
+pub mod events;
+pub mod export;
+pub mod geo;
+pub mod inverted_index;
+pub mod key_filter;
+pub mod key_order;
+pub mod merge;
+pub mod migrate;
+pub mod meta;
+pub mod metrics;
 pub mod page;
+pub mod page_alloc;
+pub mod page_cache;
+pub mod page_changes;
+pub mod page_io;
+pub mod pin;
+pub mod progress;
+pub mod raw;
+pub mod reader_table;
+pub mod replication;
+#[cfg(feature = "roaring")]
+pub mod roaring_value;
+pub mod txn;
+pub mod ttl;
+#[cfg(feature = "serde")]
+pub mod typed;
+pub mod wal;
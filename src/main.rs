@@ -0,0 +1,337 @@
+use memmap2::Mmap;
+use std::collections::HashSet;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+
+use mmdb::check::{check_commit, check_metas, CheckReport, Problem};
+use mmdb::constants::*;
+use mmdb::data_page::DataPage;
+use mmdb::debug::diff_pages;
+use mmdb::env::Env;
+use mmdb::meta::Meta;
+use mmdb::page::PageRef;
+
+const USAGE: &str = "usage:
+  mmdb get <file> <key>
+  mmdb put <file> <key> <value>
+  mmdb del <file> <key>
+  mmdb scan <file> [<prefix>]
+  mmdb stat <file>
+  mmdb info <file>
+  mmdb dump <file> <dump-file>|-
+  mmdb load <file> <dump-file>|-
+  mmdb check <file>
+  mmdb diff-page <file> <pgno> <pgno>
+  mmdb diff-page <file> <pgno> <other-file> <pgno>
+  mmdb verify [--watch] [--interval <ms>] <file>
+
+Keys and values are taken as given and printed with non-ASCII and control
+bytes escaped; `-` reads a dump from stdin or writes it to stdout.";
+
+const DEFAULT_INTERVAL_MS: u64 = 1000;
+// a commit being written can be caught halfway, so a problem only counts if
+// it is still there a moment later
+const RECHECK_DELAY: Duration = Duration::from_millis(100);
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("get") => get(&args[1..]),
+        Some("put") => put(&args[1..]),
+        Some("del") => del(&args[1..]),
+        Some("scan") => scan(&args[1..]),
+        Some("stat") => stat(&args[1..]),
+        Some("info") => info(&args[1..]),
+        Some("dump") => dump(&args[1..]),
+        Some("load") => load(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("diff-page") => diff_page(&args[1..]),
+        Some("verify") => verify(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn open(path: &str) -> Result<(Mmap, usize), String> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+    // the file may change underneath the map; this is a debugging tool
+    let mmap = unsafe { Mmap::map(&file) }.map_err(|err| format!("{}: {}", path, err))?;
+    let page_size = Meta::read(&mmap).map_err(|err| format!("{}: {}", path, err))?.get_page_size();
+    Ok((mmap, page_size))
+}
+
+// unlike `open`, goes through the environment like any other user of the
+// file, taking a reader slot and, for writes, the writer's lock
+fn open_env(path: &str) -> Result<Env, String> {
+    Env::open(path).map_err(|err| format!("{}: {}", path, err))
+}
+
+// for commands that only read, which can then run while another process
+// has the file open for writing
+fn open_env_read_only(path: &str) -> Result<Env, String> {
+    Env::open_read_only(path).map_err(|err| format!("{}: {}", path, err))
+}
+
+fn get(args: &[String]) -> Result<(), String> {
+    let [path, key] = args else {
+        return Err(USAGE.to_string());
+    };
+    let env = open_env_read_only(path)?;
+    let txn = env.begin_read().map_err(|err| err.to_string())?;
+    match txn.get(key.as_bytes()) {
+        Ok(value) => {
+            println!("{}", value.escape_ascii());
+            Ok(())
+        }
+        Err(DBError::KeyNotFound) => Err(format!("{}: key not found", key)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn put(args: &[String]) -> Result<(), String> {
+    let [path, key, value] = args else {
+        return Err(USAGE.to_string());
+    };
+    let env = open_env(path)?;
+    let mut txn = env.begin_write();
+    txn.put(key.as_bytes(), value.as_bytes())
+        .and_then(|_| txn.commit())
+        .map_err(|err| err.to_string())
+}
+
+fn del(args: &[String]) -> Result<(), String> {
+    let [path, key] = args else {
+        return Err(USAGE.to_string());
+    };
+    let env = open_env(path)?;
+    let mut txn = env.begin_write();
+    match txn.delete(key.as_bytes()) {
+        Ok(()) => txn.commit().map_err(|err| err.to_string()),
+        Err(DBError::KeyNotFound) => Err(format!("{}: key not found", key)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn scan(args: &[String]) -> Result<(), String> {
+    let (path, prefix) = match args {
+        [path] => (path, ""),
+        [path, prefix] => (path, prefix.as_str()),
+        _ => return Err(USAGE.to_string()),
+    };
+    let env = open_env_read_only(path)?;
+    let txn = env.begin_read().map_err(|err| err.to_string())?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    for entry in txn.scan_prefix(prefix.as_bytes()).map_err(|err| err.to_string())? {
+        let (key, value) = entry.map_err(|err| err.to_string())?;
+        writeln!(out, "{}\t{}", key.escape_ascii(), value.escape_ascii())
+            .map_err(|err| err.to_string())?;
+    }
+    out.flush().map_err(|err| err.to_string())
+}
+
+fn stat(args: &[String]) -> Result<(), String> {
+    let [path] = args else {
+        return Err(USAGE.to_string());
+    };
+    let env = open_env_read_only(path)?;
+    let stat = env.stat().map_err(|err| err.to_string())?;
+    let txnid = env.begin_read().map_err(|err| err.to_string())?.get_meta().get_txnid();
+    println!("commit: {}", txnid);
+    println!("page size: {}", stat.page_size);
+    println!("file size: {}", stat.file_size);
+    println!("pages: {} ({} free)", stat.total_pages, stat.free_pages);
+    println!("depth: {}", stat.tree.depth);
+    println!("branch pages: {}", stat.tree.branch_pages);
+    println!("leaf pages: {}", stat.tree.leaf_pages);
+    println!("entries: {}", stat.tree.entries);
+    println!("fill factor: {:.2}", stat.tree.fill_factor());
+    println!(
+        "compressed values: {} ({:.2}x)",
+        stat.tree.compressed_values,
+        stat.tree.compression_ratio()
+    );
+    Ok(())
+}
+
+fn info(args: &[String]) -> Result<(), String> {
+    let [path] = args else {
+        return Err(USAGE.to_string());
+    };
+    let env = open_env_read_only(path)?;
+    let info = env.info().map_err(|err| err.to_string())?;
+    println!("format version: {}", info.format_version);
+    println!("page size: {}", info.page_size);
+    println!("byte order: little-endian ({:#010x})", info.byte_order_marker);
+    match info.created_at {
+        Some(created_at) => println!("created: {} ms after the Unix epoch", created_at),
+        None => println!("created: not recorded"),
+    }
+    println!("last commit: {}", info.last_txnid);
+    println!("entries: {}", info.entries);
+    println!("user metadata: {}", info.user_meta.escape_ascii());
+    println!("sequence: {}", info.sequence);
+    Ok(())
+}
+
+fn dump(args: &[String]) -> Result<(), String> {
+    let [path, out_path] = args else {
+        return Err(USAGE.to_string());
+    };
+    let env = open_env_read_only(path)?;
+    let out: Box<dyn Write> = match out_path.as_str() {
+        "-" => Box::new(io::stdout().lock()),
+        _ => Box::new(File::create(out_path).map_err(|err| format!("{}: {}", out_path, err))?),
+    };
+    env.dump(BufWriter::new(out)).map_err(|err| err.to_string())
+}
+
+fn load(args: &[String]) -> Result<(), String> {
+    let [path, in_path] = args else {
+        return Err(USAGE.to_string());
+    };
+    let input: Box<dyn Read> = match in_path.as_str() {
+        "-" => Box::new(io::stdin().lock()),
+        _ => Box::new(File::open(in_path).map_err(|err| format!("{}: {}", in_path, err))?),
+    };
+    Env::load(path, BufReader::new(input)).map_err(|err| format!("{}: {}", path, err))?;
+    Ok(())
+}
+
+fn check(args: &[String]) -> Result<(), String> {
+    let [path] = args else {
+        return Err(USAGE.to_string());
+    };
+    let env = open_env_read_only(path)?;
+    let report = env.check().map_err(|err| err.to_string())?;
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    if !report.is_ok() {
+        return Err(format!("{}: {} problems found", path, report.problems.len()));
+    }
+    println!("ok, {} pages checked", report.pages_checked);
+    Ok(())
+}
+
+fn parse_pgno(arg: &str) -> Result<usize, String> {
+    arg.parse().map_err(|_| format!("invalid page number: {}", arg))
+}
+
+fn diff_page(args: &[String]) -> Result<(), String> {
+    let (path_a, pgno_a, path_b, pgno_b) = match args {
+        [path, a, b] => (path, a, path, b),
+        [path_a, a, path_b, b] => (path_a, a, path_b, b),
+        _ => return Err(USAGE.to_string()),
+    };
+    let (mmap_a, page_size_a) = open(path_a)?;
+    let (mmap_b, page_size_b) = open(path_b)?;
+    let read = |mmap, page_size, pgno| -> Result<DataPage, String> {
+        PageRef::from_mmap(mmap, page_size, pgno)
+            .and_then(DataPage::from)
+            .map_err(|err: DBError| err.to_string())
+    };
+    let old = read(&mmap_a, page_size_a, parse_pgno(pgno_a)?)?;
+    let new = read(&mmap_b, page_size_b, parse_pgno(pgno_b)?)?;
+
+    for (label, page) in [("old", &old), ("new", &new)] {
+        println!(
+            "{} page {}: {} nodes, lower {}, upper {}, prefix {}",
+            label,
+            page.get_pgno(),
+            page.num_nodes(),
+            page.get_lower(),
+            page.get_upper(),
+            page.get_prefix().escape_ascii()
+        );
+    }
+    for diff in diff_pages(&old, &new).map_err(|err| err.to_string())? {
+        println!("{}", diff);
+    }
+    Ok(())
+}
+
+fn verify(args: &[String]) -> Result<(), String> {
+    let (mut watch, mut interval, mut path) = (false, DEFAULT_INTERVAL_MS, None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => watch = true,
+            "--interval" => {
+                let ms = args.next().ok_or(USAGE)?;
+                interval = ms.parse().map_err(|_| format!("invalid interval: {}", ms))?;
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+
+    // pages already checked, valid for as long as the file doesn't shrink
+    let mut verified = HashSet::new();
+    let mut last = None;
+    loop {
+        let (meta, mut report) = check_latest(path, last.as_ref(), &mut verified)?;
+        if last.as_ref().is_none_or(|last: &Meta| meta.get_txnid() != last.get_txnid()) {
+            if !report.is_ok() {
+                thread::sleep(RECHECK_DELAY);
+                report = check_latest(path, last.as_ref(), &mut verified)?.1;
+            }
+            if !report.is_ok() {
+                for problem in &report.problems {
+                    println!("{}", problem);
+                }
+                return Err(format!("{}: commit {} failed verification", path, meta.get_txnid()));
+            }
+            println!(
+                "commit {}: ok, {} new pages checked",
+                meta.get_txnid(),
+                report.pages_checked
+            );
+        }
+        last = Some(meta);
+        if !watch {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(interval));
+    }
+}
+
+// checks the newest commit in `path` against the one seen before it
+fn check_latest(
+    path: &str,
+    last: Option<&Meta>,
+    verified: &mut HashSet<Pgno>,
+) -> Result<(Meta, CheckReport), String> {
+    let (mmap, page_size) = open(path)?;
+    let meta = Meta::read(&mmap).map_err(|err| format!("{}: {}", path, err))?;
+    if last.is_some_and(|last| meta.get_next_pgno() < last.get_next_pgno()) {
+        // compacted, so the pages verified so far may have been rewritten
+        verified.clear();
+    }
+    if last.is_some_and(|last| meta.get_txnid() == last.get_txnid()) {
+        return Ok((meta, CheckReport::default()));
+    }
+    let mut report = check_metas(&mmap, page_size);
+    if let Some(last) = last.filter(|last| meta.get_txnid() < last.get_txnid()) {
+        report.problems.push(Problem {
+            pgno: meta.get_pgno(),
+            description: format!(
+                "commit {} went back from {}",
+                meta.get_txnid(),
+                last.get_txnid()
+            ),
+        });
+    }
+    report.merge(check_commit(&mmap, &meta, verified));
+    Ok((meta, report))
+}
@@ -0,0 +1,19 @@
+/// Combines the value stored under `key` (if any) with a merge operand into
+/// the new value. Registered with `EnvOptions::merge_operator`; like a custom
+/// key order, it isn't recorded with the database, so it must be passed again
+/// on every open.
+pub type MergeFn = fn(key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8>;
+
+/// Treats values and operands as little-endian u64 counters and adds them,
+/// wrapping on overflow. A stored value that isn't 8 bytes counts as 0.
+pub fn add_u64_le(_key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+    let read = |bytes: Option<&[u8]>| {
+        bytes.and_then(|bytes| <[u8; 8]>::try_from(bytes).ok()).map_or(0, u64::from_le_bytes)
+    };
+    read(existing).wrapping_add(read(Some(operand))).to_le_bytes().to_vec()
+}
+
+/// Appends each operand to the stored value, for append-only lists.
+pub fn append(_key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+    [existing.unwrap_or_default(), operand].concat()
+}
@@ -0,0 +1,492 @@
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::buf::ByteBuf;
+use crate::constants::*;
+use crate::key_order::{KeyOrder, UNRECORDED_ID};
+use crate::page::{check_page_size, Page, PageRef};
+
+// Meta page layout, in the data area of pages 0 and 1:
+//   magic (u16) + version (u16) + page_size (u32) + txnid (u64) + root (u64)
+//   + next_pgno (u64) + entries (u64) + byte_order (u32) + user_meta_len (u16)
+//   + key_order (u16) + created_at (u64) + user_meta (USER_META_SIZE bytes)
+//   + sequence (u64)
+// The page size sits at a fixed file offset so it can be read before the size
+// of page 0 itself is known. Commits alternate between the two meta pages, and
+// the valid one with the higher txnid is current, so a torn meta write falls
+// back to the previous commit.
+//
+// Version 2 stores data page node sizes as varints instead of usizes.
+// Version 3 adds the second meta page and the txnid, root and next_pgno fields.
+// Version 4 adds entries, and branch nodes count the entries under each child.
+// Version 5 adds byte_order, created_at and user_meta, so a file says what it
+// is without the reader having to guess.
+// Version 6 adds sequence, the last id `WriteTxn::next_id` handed out.
+// key_order holds the id of the file's built-in `KeyOrder`, or
+// UNRECORDED_ID for one that has none; it took a field every file of
+// versions 5 and 6 left 0, the id of `KeyOrder::Bytes`, so they need no
+// upgrade.
+// Versions 1 and 2 had a single meta page holding only the first three fields;
+// `migrate::upgrade_file` brings older files up to date.
+pub const META_VERSION: u16 = 6;
+pub const NUM_META_PAGES: Pgno = 2;
+
+const MAGIC_OFFSET: usize = 0;
+const VERSION_OFFSET: usize = 2;
+const PAGE_SIZE_OFFSET: usize = 4;
+const TXNID_OFFSET: usize = 8;
+const ROOT_OFFSET: usize = 16;
+const NEXT_PGNO_OFFSET: usize = 24;
+const ENTRIES_OFFSET: usize = 32;
+const BYTE_ORDER_OFFSET: usize = 40;
+const USER_META_LEN_OFFSET: usize = 44;
+const KEY_ORDER_OFFSET: usize = 46;
+const CREATED_AT_OFFSET: usize = 48;
+const USER_META_OFFSET: usize = 56;
+const SEQUENCE_OFFSET: usize = USER_META_OFFSET + USER_META_SIZE;
+const META_SIZE: usize = SEQUENCE_OFFSET + 8;
+const LEGACY_META_SIZE: usize = 8;
+
+/// Most bytes of metadata of its own an application can keep in the meta
+/// pages; see `WriteTxn::set_user_meta`.
+pub const USER_META_SIZE: usize = 64;
+/// Written little-endian like every other field, so a tool that reads it back
+/// as anything else is reading the file in the wrong byte order.
+pub const BYTE_ORDER_MARKER: u32 = 0x0102_0304;
+
+/// File-wide settings fixed when the file is created, plus the state of the
+/// most recent commit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Meta {
+    page_size: u32,
+    txnid: TxnId,
+    root: Pgno,
+    next_pgno: Pgno,
+    entries: u64,
+    byte_order: u32,
+    // a `KeyOrder` id, or UNRECORDED_ID
+    key_order: u16,
+    // milliseconds since the Unix epoch, 0 if not recorded
+    created_at: u64,
+    user_meta_len: u8,
+    user_meta: [u8; USER_META_SIZE],
+    sequence: u64,
+}
+
+/// The format version and page size recorded at the start of `file`. Every
+/// version keeps them at the same place in page 0, so they can be read before
+/// knowing how to parse the rest.
+pub fn read_format(file: &[u8]) -> Result<(u16, usize), DBError> {
+    let corrupt = |reason| DBError::CorruptPage { pgno: 0, reason };
+    let page_size = file
+        .read_u32_le(PAGE_HEADER_SIZE + PAGE_SIZE_OFFSET)
+        .ok_or(DBError::PageOutOfBounds { pgno: 0 })? as usize;
+    check_page_size(page_size)?;
+    let page = file.get(..page_size).ok_or(DBError::PageOutOfBounds { pgno: 0 })?;
+    let page = Page::from_bytes(page)?;
+    page.as_page_ref().verify_checksum()?;
+    if !page.get_flag().contains(PageFlag::META) {
+        return Err(corrupt("not a meta page"));
+    }
+    let data = page.get_data();
+    if data.read_u16_le(MAGIC_OFFSET) != Some(MAGIC_NUMBER) {
+        return Err(corrupt("bad magic number"));
+    }
+    match data.read_u16_le(VERSION_OFFSET) {
+        Some(0) | None => Err(corrupt("bad format version")),
+        Some(version) => Ok((version, page_size)),
+    }
+}
+
+impl Meta {
+    /// Meta for a new, empty file, created now.
+    pub fn new(page_size: usize) -> Result<Self, DBError> {
+        check_page_size(page_size)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH);
+        Ok(Meta {
+            page_size: page_size as u32,
+            txnid: 0,
+            root: INVALID_PGNO,
+            next_pgno: NUM_META_PAGES,
+            entries: 0,
+            byte_order: BYTE_ORDER_MARKER,
+            key_order: KeyOrder::Bytes.recorded_id(),
+            created_at: now.map_or(0, |now| now.as_millis() as u64).max(1),
+            user_meta_len: 0,
+            user_meta: [0; USER_META_SIZE],
+            sequence: 0,
+        })
+    }
+
+    pub const fn get_page_size(&self) -> usize {
+        self.page_size as usize
+    }
+
+    pub const fn get_txnid(&self) -> TxnId {
+        self.txnid
+    }
+
+    /// Root page of the tree, or `None` while the tree is empty.
+    pub fn get_root(&self) -> Option<Pgno> {
+        (self.root != INVALID_PGNO).then_some(self.root)
+    }
+
+    /// First page number past the end of the allocated file.
+    pub const fn get_next_pgno(&self) -> Pgno {
+        self.next_pgno
+    }
+
+    /// Number of live entries in the tree.
+    pub const fn get_entries(&self) -> u64 {
+        self.entries
+    }
+
+    pub const fn with_entries(self, entries: u64) -> Self {
+        Meta { entries, ..self }
+    }
+
+    /// The byte order marker as read from the file, `BYTE_ORDER_MARKER`, or
+    /// 0 for a file written before version 5.
+    pub const fn get_byte_order_marker(&self) -> u32 {
+        self.byte_order
+    }
+
+    /// The order the file's keys were written in, or `None` for one that
+    /// isn't recorded (see `KeyOrder::id`), which only its writer knows.
+    pub fn get_key_order(&self) -> Option<KeyOrder> {
+        u8::try_from(self.key_order).ok().and_then(KeyOrder::from_id)
+    }
+
+    pub const fn with_key_order(self, order: KeyOrder) -> Self {
+        Meta { key_order: order.recorded_id(), ..self }
+    }
+
+    /// Whether the file's keys were written in `order`, as far as the file
+    /// records: every order without an id matches one that isn't recorded.
+    pub const fn has_key_order(&self, order: KeyOrder) -> bool {
+        self.key_order == order.recorded_id()
+    }
+
+    /// When the file was created, in milliseconds since the Unix epoch;
+    /// `None` for files created before version 5, which didn't record it.
+    pub fn get_created_at(&self) -> Option<u64> {
+        (self.created_at != 0).then_some(self.created_at)
+    }
+
+    pub fn get_user_meta(&self) -> &[u8] {
+        &self.user_meta[..usize::from(self.user_meta_len)]
+    }
+
+    /// Fails with `ValueTooLarge` if `user_meta` is over `USER_META_SIZE`.
+    pub fn with_user_meta(self, user_meta: &[u8]) -> Result<Self, DBError> {
+        if user_meta.len() > USER_META_SIZE {
+            return Err(DBError::ValueTooLarge { size: user_meta.len(), max: USER_META_SIZE });
+        }
+        let mut meta = Meta { user_meta_len: user_meta.len() as u8, ..self };
+        meta.user_meta = [0; USER_META_SIZE];
+        meta.user_meta[..user_meta.len()].copy_from_slice(user_meta);
+        Ok(meta)
+    }
+
+    /// The meta for the commit after this one, with the same entry count
+    /// until `with_entries` sets the new one.
+    pub fn next_commit(&self, root: Option<Pgno>, next_pgno: Pgno) -> Self {
+        Meta {
+            txnid: self.txnid + 1,
+            root: root.unwrap_or(INVALID_PGNO),
+            next_pgno,
+            ..*self
+        }
+    }
+
+    pub fn from(page: PageRef) -> Result<Self, DBError> {
+        Self::from_version(page, META_VERSION)
+    }
+
+    /// Like `from`, for a meta page written in format `version`, 3 or later,
+    /// so an upgrade can read the commit it starts from. Version 3 didn't
+    /// count entries, so its meta reads as holding none, and neither it nor
+    /// version 4 described the file, so theirs reads as undescribed. Before
+    /// version 6, the sequence reads as 0.
+    pub fn from_version(page: PageRef, expected: u16) -> Result<Self, DBError> {
+        let corrupt = |reason| DBError::CorruptPage {
+            pgno: page.get_pgno(),
+            reason,
+        };
+        if !page.get_flag().contains(PageFlag::META) {
+            return Err(corrupt("not a meta page"));
+        }
+        let data = page.get_data();
+        if data.read_u16_le(MAGIC_OFFSET) != Some(MAGIC_NUMBER) {
+            return Err(corrupt("bad magic number"));
+        }
+        let version = data
+            .read_u16_le(VERSION_OFFSET)
+            .ok_or_else(|| corrupt("truncated meta page"))?;
+        if version != expected {
+            return Err(DBError::VersionMismatch {
+                expected: expected as u32,
+                found: version as u32,
+            });
+        }
+        let truncated = || corrupt("truncated meta page");
+        let page_size = data.read_u32_le(PAGE_SIZE_OFFSET).ok_or_else(truncated)?;
+        if page_size as usize != page.get_page_size() {
+            return Err(corrupt("recorded page size does not match the meta page"));
+        }
+        let mut meta = Meta {
+            page_size,
+            txnid: data.read_u64_le(TXNID_OFFSET).ok_or_else(truncated)?,
+            root: data.read_u64_le(ROOT_OFFSET).ok_or_else(truncated)?,
+            next_pgno: data.read_u64_le(NEXT_PGNO_OFFSET).ok_or_else(truncated)?,
+            entries: match version {
+                3 => 0,
+                _ => data.read_u64_le(ENTRIES_OFFSET).ok_or_else(truncated)?,
+            },
+            byte_order: 0,
+            key_order: KeyOrder::Bytes.recorded_id(),
+            created_at: 0,
+            user_meta_len: 0,
+            user_meta: [0; USER_META_SIZE],
+            sequence: 0,
+        };
+        if version >= 5 {
+            meta.byte_order = data.read_u32_le(BYTE_ORDER_OFFSET).ok_or_else(truncated)?;
+            if meta.byte_order != BYTE_ORDER_MARKER {
+                return Err(corrupt("unrecognized byte order marker"));
+            }
+            meta.key_order = data.read_u16_le(KEY_ORDER_OFFSET).ok_or_else(truncated)?;
+            let known = u8::try_from(meta.key_order).ok().and_then(KeyOrder::from_id);
+            if known.is_none() && meta.key_order != UNRECORDED_ID {
+                return Err(corrupt("unrecognized key order"));
+            }
+            meta.created_at = data.read_u64_le(CREATED_AT_OFFSET).ok_or_else(truncated)?;
+            let len = data.read_u16_le(USER_META_LEN_OFFSET).ok_or_else(truncated)? as usize;
+            let user_meta = data.get(USER_META_OFFSET..SEQUENCE_OFFSET).ok_or_else(truncated)?;
+            if len > USER_META_SIZE {
+                return Err(corrupt("user metadata longer than its space"));
+            }
+            meta.user_meta_len = len as u8;
+            meta.user_meta.copy_from_slice(user_meta);
+        }
+        if version >= 6 {
+            meta.sequence = data.read_u64_le(SEQUENCE_OFFSET).ok_or_else(truncated)?;
+        }
+        if meta.next_pgno < NUM_META_PAGES
+            || (meta.root != INVALID_PGNO && meta.root >= meta.next_pgno)
+        {
+            return Err(corrupt("root past the end of the file"));
+        }
+
+        Ok(meta)
+    }
+
+    /// Reads the current meta from the start of the file, peeking at the
+    /// recorded page size first to learn how large the meta pages are.
+    pub fn read(file: &[u8]) -> Result<Self, DBError> {
+        Self::read_version(file, META_VERSION)
+    }
+
+    /// Like `read`, for a file in format `version`; see `from_version`.
+    pub fn read_version(file: &[u8], version: u16) -> Result<Self, DBError> {
+        let page_size = file
+            .read_u32_le(PAGE_HEADER_SIZE + PAGE_SIZE_OFFSET)
+            .ok_or(DBError::PageOutOfBounds { pgno: 0 })? as usize;
+        check_page_size(page_size)?;
+
+        let read = |pgno| {
+            Self::from_version(PageRef::from_mmap_verified(file, page_size, pgno)?, version)
+        };
+        match (read(0), read(1)) {
+            // a newer build committed to one slot; falling back to the other
+            // would quietly lose that commit, and the next one would
+            // overwrite it
+            (Err(err @ DBError::VersionMismatch { expected, found }), _)
+            | (_, Err(err @ DBError::VersionMismatch { expected, found }))
+                if found > expected =>
+            {
+                Err(err)
+            }
+            (Ok(a), Ok(b)) => Ok(if b.txnid > a.txnid { b } else { a }),
+            (Ok(meta), Err(_)) | (Err(_), Ok(meta)) => Ok(meta),
+            (Err(err), Err(_)) => Err(err),
+        }
+    }
+
+    /// Meta page slot this commit is written to.
+    /// The last id `WriteTxn::next_id` handed out, 0 if none has been.
+    pub const fn get_sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub const fn with_sequence(self, sequence: u64) -> Self {
+        Meta { sequence, ..self }
+    }
+
+    pub const fn get_pgno(&self) -> Pgno {
+        self.txnid % NUM_META_PAGES
+    }
+
+    pub fn write_page(&self) -> Page {
+        let mut data = vec![0u8; self.get_page_size() - PAGE_HEADER_SIZE];
+        data[MAGIC_OFFSET..VERSION_OFFSET].copy_from_slice(&MAGIC_NUMBER.to_le_bytes());
+        data[VERSION_OFFSET..PAGE_SIZE_OFFSET].copy_from_slice(&META_VERSION.to_le_bytes());
+        data[PAGE_SIZE_OFFSET..TXNID_OFFSET].copy_from_slice(&self.page_size.to_le_bytes());
+        data[TXNID_OFFSET..ROOT_OFFSET].copy_from_slice(&self.txnid.to_le_bytes());
+        data[ROOT_OFFSET..NEXT_PGNO_OFFSET].copy_from_slice(&self.root.to_le_bytes());
+        data[NEXT_PGNO_OFFSET..ENTRIES_OFFSET].copy_from_slice(&self.next_pgno.to_le_bytes());
+        data[ENTRIES_OFFSET..BYTE_ORDER_OFFSET].copy_from_slice(&self.entries.to_le_bytes());
+        data[BYTE_ORDER_OFFSET..USER_META_LEN_OFFSET]
+            .copy_from_slice(&BYTE_ORDER_MARKER.to_le_bytes());
+        data[USER_META_LEN_OFFSET..KEY_ORDER_OFFSET]
+            .copy_from_slice(&u16::from(self.user_meta_len).to_le_bytes());
+        data[KEY_ORDER_OFFSET..CREATED_AT_OFFSET].copy_from_slice(&self.key_order.to_le_bytes());
+        data[CREATED_AT_OFFSET..USER_META_OFFSET].copy_from_slice(&self.created_at.to_le_bytes());
+        data[USER_META_OFFSET..SEQUENCE_OFFSET].copy_from_slice(&self.user_meta);
+        data[SEQUENCE_OFFSET..META_SIZE].copy_from_slice(&self.sequence.to_le_bytes());
+        Page::from(
+            self.get_pgno(),
+            0x0,
+            PageFlag::ALIVE | PageFlag::META,
+            META_SIZE as u16,
+            data.len() as u16,
+            &data,
+        )
+    }
+
+    /// The single meta page of a version 1 or 2 file, to build such files
+    /// for testing upgrades.
+    pub fn write_legacy_page(page_size: usize, version: u16) -> Page {
+        let mut data = vec![0u8; page_size - PAGE_HEADER_SIZE];
+        data[MAGIC_OFFSET..VERSION_OFFSET].copy_from_slice(&MAGIC_NUMBER.to_le_bytes());
+        data[VERSION_OFFSET..PAGE_SIZE_OFFSET].copy_from_slice(&version.to_le_bytes());
+        data[PAGE_SIZE_OFFSET..LEGACY_META_SIZE]
+            .copy_from_slice(&(page_size as u32).to_le_bytes());
+        Page::from(
+            0,
+            0x0,
+            PageFlag::ALIVE | PageFlag::META,
+            LEGACY_META_SIZE as u16,
+            data.len() as u16,
+            &data,
+        )
+    }
+
+    /// The meta page for each slot, for a new file; both hold this meta, so
+    /// either one is valid on open.
+    pub fn write_slots(&self) -> Vec<Page> {
+        let page = self.write_page();
+        (0..NUM_META_PAGES)
+            .map(|pgno| {
+                let mut page = page.clone();
+                page.set_pgno(pgno);
+                page.update_checksum();
+                page
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memmap2::MmapMut;
+
+    fn map_pages(pages: &[Page]) -> MmapMut {
+        let page_size = pages[0].get_page_size();
+        let mut mmap = MmapMut::map_anon(pages.len() * page_size).unwrap();
+        for (i, page) in pages.iter().enumerate() {
+            mmap[i * page_size..(i + 1) * page_size].copy_from_slice(page.as_bytes());
+        }
+        mmap
+    }
+
+    fn meta_pages(page_size: usize) -> [Page; 2] {
+        let first = Meta::new(page_size).unwrap();
+        let second = first.next_commit(Some(5), 6).with_entries(40);
+        [first.write_page(), second.write_page()]
+    }
+
+    #[test]
+    fn test_read_page_size() {
+        for page_size in [MIN_PAGE_SIZE, 16384, MAX_PAGE_SIZE] {
+            let mmap = map_pages(&meta_pages(page_size)).make_read_only().unwrap();
+            assert_eq!(Meta::read(&mmap).unwrap().get_page_size(), page_size);
+        }
+        assert!(matches!(Meta::new(1000), Err(DBError::InvalidPageSize { size: 1000 })));
+    }
+
+    #[test]
+    fn test_newest_valid_meta_wins() {
+        let mmap = map_pages(&meta_pages(DEFAULT_PAGE_SIZE)).make_read_only().unwrap();
+        let meta = Meta::read(&mmap).unwrap();
+        assert_eq!((meta.get_txnid(), meta.get_root(), meta.get_next_pgno()), (1, Some(5), 6));
+        assert_eq!(meta.get_entries(), 40);
+
+        // a torn write of the newer meta falls back to the older commit
+        let mut mmap = map_pages(&meta_pages(DEFAULT_PAGE_SIZE));
+        mmap[DEFAULT_PAGE_SIZE + PAGE_HEADER_SIZE + ROOT_OFFSET] ^= 0xff;
+        let mmap = mmap.make_read_only().unwrap();
+        let meta = Meta::read(&mmap).unwrap();
+        assert_eq!((meta.get_txnid(), meta.get_root()), (0, None));
+    }
+
+    #[test]
+    fn test_refuses_newer_version() {
+        let [first, mut second] = meta_pages(DEFAULT_PAGE_SIZE);
+        let version = META_VERSION + 1;
+        second.get_data_mut()[VERSION_OFFSET..PAGE_SIZE_OFFSET]
+            .copy_from_slice(&version.to_le_bytes());
+        second.update_checksum();
+        // even though the other slot holds a commit this build can read
+        let mmap = map_pages(&[first, second.clone()]).make_read_only().unwrap();
+        assert!(matches!(Meta::read(&mmap), Err(DBError::VersionMismatch { found: 7, .. })));
+
+        let mmap = map_pages(&[second]).make_read_only().unwrap();
+        assert_eq!(read_format(&mmap).unwrap(), (version, DEFAULT_PAGE_SIZE));
+    }
+
+    #[test]
+    fn test_describes_file() {
+        let meta = Meta::new(DEFAULT_PAGE_SIZE).unwrap().with_user_meta(b"app v2").unwrap();
+        let next = meta.next_commit(Some(5), 6).with_sequence(u64::MAX - 1);
+        let mmap = map_pages(&[meta.write_page(), next.write_page()]).make_read_only().unwrap();
+        let read = Meta::read(&mmap).unwrap();
+        assert_eq!(read, next);
+        assert_eq!(read.get_user_meta(), b"app v2");
+        assert_eq!(read.get_sequence(), u64::MAX - 1);
+        assert_eq!(read.get_byte_order_marker(), BYTE_ORDER_MARKER);
+        assert!(read.get_created_at().is_some());
+        assert_eq!(mmap[PAGE_HEADER_SIZE + BYTE_ORDER_OFFSET], 0x04);
+        assert!(matches!(read.get_key_order(), Some(KeyOrder::Bytes)));
+
+        for order in [KeyOrder::U64LE, KeyOrder::BytesAfter(4), KeyOrder::Custom(|a, b| b.cmp(a))] {
+            let ordered = Meta::new(DEFAULT_PAGE_SIZE).unwrap().with_key_order(order);
+            let mmap = map_pages(&ordered.write_slots()).make_read_only().unwrap();
+            let read = Meta::read(&mmap).unwrap();
+            assert_eq!(read.get_key_order().and_then(|order| order.id()), order.id());
+            assert!(read.has_key_order(order) && !read.has_key_order(KeyOrder::Bytes));
+        }
+
+        let too_long = [0; USER_META_SIZE + 1];
+        assert!(matches!(meta.with_user_meta(&too_long), Err(DBError::ValueTooLarge { .. })));
+        let cleared = read.with_user_meta(b"").unwrap();
+        assert_eq!(cleared.get_user_meta(), b"");
+    }
+
+    #[test]
+    fn test_rejects_bad_meta() {
+        let pages = meta_pages(16384);
+
+        let mut mmap = map_pages(&pages);
+        // claim a smaller page size; the checksums then cover the wrong range
+        mmap[PAGE_HEADER_SIZE + PAGE_SIZE_OFFSET + 1] = 0x10;
+        let mmap = mmap.make_read_only().unwrap();
+        assert!(matches!(Meta::read(&mmap), Err(DBError::ChecksumMismatch { pgno: 0 })));
+
+        let mut mmap = map_pages(&pages);
+        mmap[PAGE_HEADER_SIZE + PAGE_SIZE_OFFSET] = 1;
+        let mmap = mmap.make_read_only().unwrap();
+        assert!(matches!(Meta::read(&mmap), Err(DBError::InvalidPageSize { .. })));
+    }
+}
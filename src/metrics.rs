@@ -0,0 +1,167 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::constants::*;
+
+/// Hooks an environment calls as it works, registered with
+/// `EnvOptions::metrics`, for feeding an exporter such as Prometheus. They
+/// run on the thread doing the work, on paths as hot as every page read, so
+/// they should do little more than bump a counter. Every hook does nothing
+/// unless implemented.
+pub trait Metrics: Send + Sync {
+    /// A transaction read a committed page, from the map or the buffer pool.
+    fn page_read(&self) {}
+    /// Pages written to the file, by a commit or anything else writing them.
+    fn pages_written(&self, _pages: u64) {}
+    /// A write transaction split a full page in two.
+    fn page_split(&self) {}
+    /// Commit `txnid` returned, `elapsed` after `WriteTxn::commit` was called.
+    fn commit(&self, _txnid: TxnId, _elapsed: Duration) {}
+    /// A sync of the file or of the log, which took `elapsed`.
+    fn fsync(&self, _elapsed: Duration) {}
+    /// A page found in the page cache or the buffer pool.
+    fn cache_hit(&self) {}
+    /// A page the page cache or the buffer pool didn't have.
+    fn cache_miss(&self) {}
+}
+
+/// The totals `Counters` kept up to a point, from `Env::metrics`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MetricsStat {
+    pub page_reads: u64,
+    pub pages_written: u64,
+    pub page_splits: u64,
+    pub commits: u64,
+    /// Time the commits took between them.
+    pub commit_time: Duration,
+    pub fsyncs: u64,
+    pub fsync_time: Duration,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+/// `Metrics` that add everything up, which every environment keeps.
+#[derive(Debug, Default)]
+pub struct Counters {
+    page_reads: AtomicU64,
+    pages_written: AtomicU64,
+    page_splits: AtomicU64,
+    commits: AtomicU64,
+    commit_nanos: AtomicU64,
+    fsyncs: AtomicU64,
+    fsync_nanos: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+fn nanos(elapsed: Duration) -> u64 {
+    elapsed.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+impl Counters {
+    pub fn stat(&self) -> MetricsStat {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        MetricsStat {
+            page_reads: load(&self.page_reads),
+            pages_written: load(&self.pages_written),
+            page_splits: load(&self.page_splits),
+            commits: load(&self.commits),
+            commit_time: Duration::from_nanos(load(&self.commit_nanos)),
+            fsyncs: load(&self.fsyncs),
+            fsync_time: Duration::from_nanos(load(&self.fsync_nanos)),
+            cache_hits: load(&self.cache_hits),
+            cache_misses: load(&self.cache_misses),
+        }
+    }
+}
+
+impl Metrics for Counters {
+    fn page_read(&self) {
+        add(&self.page_reads, 1);
+    }
+
+    fn pages_written(&self, pages: u64) {
+        add(&self.pages_written, pages);
+    }
+
+    fn page_split(&self) {
+        add(&self.page_splits, 1);
+    }
+
+    fn commit(&self, _txnid: TxnId, elapsed: Duration) {
+        add(&self.commits, 1);
+        add(&self.commit_nanos, nanos(elapsed));
+    }
+
+    fn fsync(&self, elapsed: Duration) {
+        add(&self.fsyncs, 1);
+        add(&self.fsync_nanos, nanos(elapsed));
+    }
+
+    fn cache_hit(&self) {
+        add(&self.cache_hits, 1);
+    }
+
+    fn cache_miss(&self) {
+        add(&self.cache_misses, 1);
+    }
+}
+
+/// An environment's `Counters`, followed by the hooks it was opened with.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    counters: Counters,
+    hooks: Vec<Arc<dyn Metrics>>,
+}
+
+impl Recorder {
+    pub fn new(hooks: Vec<Arc<dyn Metrics>>) -> Self {
+        Recorder { counters: Counters::default(), hooks }
+    }
+
+    pub fn stat(&self) -> MetricsStat {
+        self.counters.stat()
+    }
+
+    fn each(&self, record: impl Fn(&dyn Metrics)) {
+        record(&self.counters);
+        for hook in &self.hooks {
+            record(hook.as_ref());
+        }
+    }
+}
+
+impl Metrics for Recorder {
+    fn page_read(&self) {
+        self.each(|metrics| metrics.page_read());
+    }
+
+    fn pages_written(&self, pages: u64) {
+        self.each(|metrics| metrics.pages_written(pages));
+    }
+
+    fn page_split(&self) {
+        self.each(|metrics| metrics.page_split());
+    }
+
+    fn commit(&self, txnid: TxnId, elapsed: Duration) {
+        self.each(|metrics| metrics.commit(txnid, elapsed));
+    }
+
+    fn fsync(&self, elapsed: Duration) {
+        self.each(|metrics| metrics.fsync(elapsed));
+    }
+
+    fn cache_hit(&self) {
+        self.each(|metrics| metrics.cache_hit());
+    }
+
+    fn cache_miss(&self) {
+        self.each(|metrics| metrics.cache_miss());
+    }
+}
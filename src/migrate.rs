@@ -0,0 +1,582 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use crate::buf::ByteBuf;
+use crate::constants::*;
+use crate::btree::MAX_TREE_DEPTH;
+use crate::data_page::{DataNode, DataPage, DirtyPage};
+use crate::env::{Env, EnvOptions};
+use crate::key_order::KeyOrder;
+use crate::meta::{read_format, Meta, META_VERSION};
+use crate::page::{Page, PageRef};
+use crate::txn::WriteTxn;
+
+/// Key the applied versions are recorded under by default. There is no
+/// separate namespace for bookkeeping yet, so it lives in the tree alongside
+/// the application's own keys; the leading zero byte keeps it out of the way
+/// of most key schemes.
+pub const DEFAULT_MIGRATIONS_KEY: &[u8] = b"\0mmdb/migrations";
+
+pub type MigrationFn = Box<dyn Fn(&mut WriteTxn) -> Result<(), DBError>>;
+
+/// Brings a database's contents up to date with the application, one
+/// numbered migration at a time. Each migration runs in its own write
+/// transaction, which also records its version, so it is applied exactly once
+/// even if a later one fails or the process dies partway through.
+pub struct Migrator {
+    key: Vec<u8>,
+    migrations: BTreeMap<u64, MigrationFn>,
+}
+
+impl Default for Migrator {
+    fn default() -> Self {
+        Migrator {
+            key: DEFAULT_MIGRATIONS_KEY.to_vec(),
+            migrations: BTreeMap::new(),
+        }
+    }
+}
+
+impl Migrator {
+    pub fn new() -> Self {
+        Migrator::default()
+    }
+
+    /// Records the applied versions under `key` instead of the default.
+    pub fn key(mut self, key: &[u8]) -> Self {
+        self.key = key.to_vec();
+        self
+    }
+
+    /// Registers the migration for `version`; migrations run in increasing
+    /// version order, whatever order they are added in.
+    pub fn migration(
+        mut self,
+        version: u64,
+        migrate: impl Fn(&mut WriteTxn) -> Result<(), DBError> + 'static,
+    ) -> Self {
+        let previous = self.migrations.insert(version, Box::new(migrate));
+        assert!(previous.is_none(), "migration {version} is registered twice");
+        self
+    }
+
+    /// Versions already applied to `env`, in increasing order.
+    pub fn applied(&self, env: &Env) -> Result<Vec<u64>, DBError> {
+        match env.begin_read()?.get(&self.key) {
+            Ok(recorded) => decode_versions(recorded),
+            Err(DBError::KeyNotFound) => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Applies every registered migration not yet recorded in `env`, and
+    /// returns the versions it applied. Stops at the first one that fails,
+    /// leaving it and those after it unapplied. Fails with `IncompatibleFile`
+    /// if `env` has a migration this migrator doesn't know (it was migrated
+    /// by a newer build), and with `MigrationOutOfOrder` if an unapplied
+    /// migration is older than one already applied.
+    pub fn run(&self, env: &Env) -> Result<Vec<u64>, DBError> {
+        let mut ran = Vec::new();
+        for (&version, migrate) in &self.migrations {
+            // checked in the transaction that applies it, so two processes
+            // can't both apply the same migration
+            let mut txn = env.begin_write();
+            let mut applied = match txn.get(&self.key) {
+                Ok(recorded) => decode_versions(recorded)?,
+                Err(DBError::KeyNotFound) => Vec::new(),
+                Err(err) => return Err(err),
+            };
+            if applied.iter().any(|applied| !self.migrations.contains_key(applied)) {
+                return Err(DBError::IncompatibleFile {
+                    reason: "database has a migration this build doesn't know",
+                });
+            }
+            if applied.contains(&version) {
+                continue;
+            }
+            if applied.last().is_some_and(|&last| last > version) {
+                return Err(DBError::MigrationOutOfOrder { version });
+            }
+            migrate(&mut txn)?;
+            applied.push(version);
+            txn.put(&self.key, &encode_versions(&applied))?;
+            txn.commit()?;
+            ran.push(version);
+        }
+        Ok(ran)
+    }
+}
+
+// versions as consecutive little-endian u64s, in increasing order
+fn encode_versions(versions: &[u64]) -> Vec<u8> {
+    versions.iter().flat_map(|version| version.to_le_bytes()).collect()
+}
+
+fn decode_versions(recorded: &[u8]) -> Result<Vec<u64>, DBError> {
+    if !recorded.len().is_multiple_of(8) {
+        return Err(DBError::CorruptValue {
+            reason: "malformed migration record",
+        });
+    }
+    Ok(recorded
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
+
+/// Upgrades the file at `path` to the current on-disk format, `META_VERSION`,
+/// one version at a time, and returns the version it was in. `Env::open`
+/// refuses older files with `VersionMismatch` rather than guess at their
+/// layout; files written by a newer build fail the same way here.
+///
+/// No process may have the file open meanwhile. Version 1 pages are rewritten
+/// where they are, so a crash partway through that step leaves a file neither
+/// version reads: back it up first. Versions 1 and 2 had no tree, only pages,
+/// so the step from version 2 reloads the live entries of every leaf page into
+/// a new tree, as a dump and load would, and only replaces the file once that
+/// is written. Where two leaf pages hold the same key, the later page wins.
+/// Version 3 trees don't count their entries, so the step from version 3
+/// reloads the entries its tree reaches the same way, under byte order and
+/// with values stored uncompressed. Either reload writes the current version.
+/// Version 4 files lack only the description of the file version 5 keeps in
+/// the meta pages, and version 5 files only the sequence of version 6, so the
+/// step from either rewrites the meta pages in place in the current version,
+/// with no creation time for version 4, as it wasn't recorded, and a
+/// sequence of 0.
+pub fn upgrade_file(path: impl AsRef<Path>) -> Result<u16, DBError> {
+    let path = path.as_ref();
+    let (found, page_size) = read_format(&fs::read(path)?)?;
+    if found > META_VERSION {
+        return Err(DBError::VersionMismatch {
+            expected: META_VERSION as u32,
+            found: found as u32,
+        });
+    }
+    for version in found..META_VERSION {
+        match version {
+            1 => reencode_node_sizes(path, page_size)?,
+            2 | 3 => {
+                let entries = match version {
+                    2 => read_v2_entries(path, page_size)?,
+                    _ => read_v3_entries(path, page_size)?,
+                };
+                // a reload writes the current version, leaving no steps
+                reload_into_tree(path, page_size, entries)?;
+                break;
+            }
+            4 | 5 => {
+                rewrite_meta_pages(path, page_size, version)?;
+                break;
+            }
+            _ => unreachable!("format version {version} has no upgrade"),
+        }
+    }
+    Ok(found)
+}
+
+// 1 -> 2: node sizes go from u64s to varints, so every page shrinks and is
+// rewritten in its own place; the version only changes once all of them are
+// on disk
+fn reencode_node_sizes(path: &Path, page_size: usize) -> Result<(), DBError> {
+    let contents = fs::read(path)?;
+    let file = OpenOptions::new().write(true).open(path)?;
+    for page in legacy_data_pages(&contents, page_size) {
+        let page = page?;
+        let entries = read_v1_nodes(&page)?;
+        let nodes: Vec<DataNode> = entries
+            .iter()
+            .map(|(flags, key, data)| DataNode::from(key, data).with_flags(*flags))
+            .collect();
+        let (pgno, flags) = (page.get_pgno(), page.get_flag());
+        let page = DirtyPage::from_nodes(pgno, page_size, flags, KeyOrder::Bytes, &nodes)?;
+        let page = page.into_page();
+        file.write_all_at(page.as_bytes(), pgno * page_size as u64)?;
+    }
+    file.sync_all()?;
+    file.write_all_at(Meta::write_legacy_page(page_size, 2).as_bytes(), 0)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+// a node's flags, full key and data
+type LegacyNode<'a> = (NodeFlag, Vec<u8>, &'a [u8]);
+
+// A version 1 node: flags (u16) + key_size (u64) + data_size (u64) + key
+// suffix + data. Pages already stored their common key prefix as they do now.
+fn read_v1_nodes(page: &Page) -> Result<Vec<LegacyNode<'_>>, DBError> {
+    let corrupt = |reason| DBError::CorruptPage {
+        pgno: page.get_pgno(),
+        reason,
+    };
+    let data = page.get_data();
+    let (lower, upper) = (page.get_lower() as usize, page.get_upper() as usize);
+    let prefix_start = data
+        .len()
+        .checked_sub(page.get_pad() as usize)
+        .filter(|&start| lower <= upper && upper <= start)
+        .ok_or_else(|| corrupt("bad page bounds"))?;
+    let (nodes, prefix) = data.split_at(prefix_start);
+    (0..lower / U16_N)
+        .map(|idx| {
+            let offset = data.read_u16_le(idx * U16_N).unwrap() as usize;
+            let flags = nodes
+                .read_u16_le(offset)
+                .and_then(NodeFlag::from_bits)
+                .ok_or_else(|| corrupt("bad node flags"))?;
+            let size = |pos: usize| {
+                let size = nodes.read_u64_le(pos)?;
+                usize::try_from(size).ok()
+            };
+            let key_size = size(offset + U16_N).ok_or_else(|| corrupt("truncated key size"))?;
+            let data_size =
+                size(offset + U16_N + 8).ok_or_else(|| corrupt("truncated data size"))?;
+            let key_start = offset + U16_N + 16;
+            let key = nodes
+                .read_n_bytes(key_start, key_size)
+                .ok_or_else(|| corrupt("key extends past end of page"))?;
+            let value = key_start
+                .checked_add(key_size)
+                .and_then(|data_start| nodes.read_n_bytes(data_start, data_size))
+                .ok_or_else(|| corrupt("data extends past end of page"))?;
+            Ok((flags, [prefix, key].concat(), value))
+        })
+        .collect()
+}
+
+// the live entries of every leaf page of a version 2 file
+fn read_v2_entries(path: &Path, page_size: usize) -> Result<LoadedEntries, DBError> {
+    let contents = fs::read(path)?;
+    let mut entries = BTreeMap::new();
+    for page in legacy_data_pages(&contents, page_size) {
+        let page = page?;
+        if page.get_flag().contains(PageFlag::BRANCH) {
+            continue;
+        }
+        for node in DataPage::from(&page)?.nodes() {
+            let node = node?;
+            entries.insert(node.get_key().into_owned(), node.get_data().to_vec());
+        }
+    }
+    Ok(entries)
+}
+
+// The live entries of a version 3 file, read down its tree from the current
+// commit; the other pages are older copies. Branch nodes held only the
+// child's page number (u64).
+fn read_v3_entries(path: &Path, page_size: usize) -> Result<LoadedEntries, DBError> {
+    let contents = fs::read(path)?;
+    let meta = Meta::read_version(&contents, 3)?;
+    let mut entries = BTreeMap::new();
+    let mut pending = Vec::from_iter(meta.get_root().map(|root| (root, 0)));
+    while let Some((pgno, depth)) = pending.pop() {
+        if depth == MAX_TREE_DEPTH {
+            return Err(DBError::CorruptPage { pgno, reason: "tree is too deep" });
+        }
+        let page = PageRef::from_mmap_verified(&contents, page_size, pgno as usize)?;
+        let page = DataPage::from(page)?;
+        if !page.get_flags().contains(PageFlag::BRANCH) {
+            for node in page.nodes() {
+                let node = node?;
+                entries.insert(node.get_key().into_owned(), node.read_value()?);
+            }
+            continue;
+        }
+        for idx in 0..page.num_nodes() {
+            let child = <[u8; 8]>::try_from(page.read_node(idx)?.get_data()).map_err(|_| {
+                DBError::CorruptPage { pgno, reason: "branch node is not a child pointer" }
+            })?;
+            pending.push((Pgno::from_le_bytes(child), depth + 1));
+        }
+    }
+    Ok(entries)
+}
+
+type LoadedEntries = BTreeMap<Vec<u8>, Vec<u8>>;
+
+// `entries` are loaded into a tree in a new file beside the one at `path`,
+// which then takes its place
+fn reload_into_tree(path: &Path, page_size: usize, entries: LoadedEntries) -> Result<(), DBError> {
+    let reloaded = sibling_path(path, "-upgrade");
+    // left behind by an upgrade that didn't finish
+    match fs::remove_file(&reloaded) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+    drop(EnvOptions::default().page_size(page_size).bulk_load(&reloaded, entries)?);
+    fs::remove_file(sibling_path(&reloaded, "-lock"))?;
+    fs::rename(&reloaded, path)?;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    File::open(dir.unwrap_or(Path::new(".")))?.sync_all()?;
+    Ok(())
+}
+
+// 4 or 5 -> 6: both meta slots take the current commit, so a crash between the
+// two writes leaves a slot each version can read
+fn rewrite_meta_pages(path: &Path, page_size: usize, version: u16) -> Result<(), DBError> {
+    let meta = Meta::read_version(&fs::read(path)?, version)?;
+    let file = OpenOptions::new().write(true).open(path)?;
+    for page in meta.write_slots() {
+        file.write_all_at(page.as_bytes(), page.get_pgno() * page_size as u64)?;
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+// the data and branch pages of a version 1 or 2 file, which are written
+// pages other than the meta page
+fn legacy_data_pages(
+    contents: &[u8],
+    page_size: usize,
+) -> impl Iterator<Item = Result<Page, DBError>> + '_ {
+    contents
+        .chunks_exact(page_size)
+        .zip(0..)
+        .skip(1)
+        .filter_map(move |(bytes, pgno)| {
+            let page = match Page::from_bytes(bytes) {
+                Ok(page) => page,
+                Err(err) => return Some(Err(err)),
+            };
+            let flags = page.get_flag();
+            if flags.is_empty() || flags.contains(PageFlag::META) {
+                return None;
+            }
+            let checked = page.as_page_ref().verify_checksum().and_then(|()| {
+                if page.get_pgno() == pgno {
+                    Ok(page)
+                } else {
+                    Err(DBError::CorruptPage {
+                        pgno,
+                        reason: "page number does not match its place in the file",
+                    })
+                }
+            });
+            Some(checked)
+        })
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut sibling = OsString::from(path.as_os_str());
+    sibling.push(suffix);
+    PathBuf::from(sibling)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meta::NUM_META_PAGES;
+    use std::io::ErrorKind;
+    use tempfile::tempdir;
+
+    // a leaf page as version 1 wrote it, with `prefix` stored once at the top
+    fn v1_leaf(pgno: Pgno, prefix: &[u8], nodes: &[(NodeFlag, &[u8], &[u8])]) -> Page {
+        let mut data = vec![0u8; DEFAULT_PAGE_SIZE - PAGE_HEADER_SIZE];
+        let mut upper = data.len() - prefix.len();
+        data[upper..].copy_from_slice(prefix);
+        for (idx, (flags, suffix, value)) in nodes.iter().enumerate() {
+            let mut node = flags.bits().to_le_bytes().to_vec();
+            node.extend_from_slice(&(suffix.len() as u64).to_le_bytes());
+            node.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            node.extend_from_slice(suffix);
+            node.extend_from_slice(value);
+            upper -= node.len();
+            data[upper..upper + node.len()].copy_from_slice(&node);
+            data[idx * U16_N..(idx + 1) * U16_N].copy_from_slice(&(upper as u16).to_le_bytes());
+        }
+        let lower = (nodes.len() * U16_N) as u16;
+        Page::from(pgno, prefix.len() as u16, PageFlag::ALIVE, lower, upper as u16, &data)
+    }
+
+    fn write_file(path: &Path, pages: &[Page]) {
+        let contents: Vec<u8> = pages.iter().flat_map(|page| page.as_bytes().to_vec()).collect();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let alive = NodeFlag::ALIVE;
+        write_file(
+            &path,
+            &[
+                Meta::write_legacy_page(DEFAULT_PAGE_SIZE, 1),
+                v1_leaf(
+                    1,
+                    b"user/",
+                    &[
+                        (alive, b"ann", b"1"),
+                        (NodeFlag::empty(), b"bob", b"2"),
+                        (alive, b"cyd", b"3"),
+                    ],
+                ),
+                v1_leaf(2, b"", &[(alive, b"user/cyd", b"4"), (alive, b"zed", b"5")]),
+            ],
+        );
+        assert!(matches!(
+            Env::open(&path),
+            Err(DBError::VersionMismatch { expected: 6, found: 1 })
+        ));
+
+        assert_eq!(upgrade_file(&path).unwrap(), 1);
+        let env = Env::open(&path).unwrap();
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get(b"user/ann").unwrap(), b"1");
+        assert!(matches!(txn.get(b"user/bob"), Err(DBError::KeyNotFound)));
+        assert_eq!(txn.get(b"user/cyd").unwrap(), b"4");
+        assert_eq!(txn.get(b"zed").unwrap(), b"5");
+        drop(txn);
+        drop(env);
+        assert_eq!(upgrade_file(&path).unwrap(), META_VERSION);
+        let leftover = fs::metadata(sibling_path(&path, "-upgrade-lock"));
+        assert_eq!(leftover.unwrap_err().kind(), ErrorKind::NotFound);
+
+        // nothing is touched in a file from a newer build
+        write_file(&path, &[Meta::write_legacy_page(DEFAULT_PAGE_SIZE, META_VERSION + 1)]);
+        assert!(matches!(upgrade_file(&path), Err(DBError::VersionMismatch { found: 7, .. })));
+        assert!(matches!(Env::open(&path), Err(DBError::VersionMismatch { found: 7, .. })));
+    }
+
+    #[test]
+    fn test_upgrade_v3_tree() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let write = |pgno, flags, nodes: &[DataNode]| {
+            let order = KeyOrder::Bytes;
+            DirtyPage::from_nodes(pgno, DEFAULT_PAGE_SIZE, flags, order, nodes).unwrap().into_page()
+        };
+        let leaf = |pgno, keys: &[&[u8]]| {
+            let nodes: Vec<DataNode> = keys.iter().map(|key| DataNode::from(key, b"v")).collect();
+            write(pgno, PageFlag::ALIVE, &nodes)
+        };
+        let children = [(&b""[..], 3u64), (b"m", 4)].map(|(key, child)| (key, child.to_le_bytes()));
+        let nodes: Vec<DataNode> =
+            children.iter().map(|(key, child)| DataNode::from(key, child)).collect();
+        let root = write(2, PageFlag::ALIVE | PageFlag::BRANCH, &nodes);
+        // page 5 is an older copy of page 4, which the tree no longer reaches
+        let meta = Meta::new(DEFAULT_PAGE_SIZE).unwrap().next_commit(Some(2), 6);
+        // the version sits after the magic number
+        let mut pages: Vec<Page> = meta.write_slots();
+        for page in &mut pages {
+            page.get_data_mut()[2..4].copy_from_slice(&3u16.to_le_bytes());
+            page.update_checksum();
+        }
+        pages.push(root);
+        pages.extend([leaf(3, &[b"a", b"b"]), leaf(4, &[b"m", b"n", b"o"]), leaf(5, &[b"x"])]);
+        write_file(&path, &pages);
+
+        assert_eq!(upgrade_file(&path).unwrap(), 3);
+        let env = Env::open(&path).unwrap();
+        assert_eq!(env.len(), 5);
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get(b"o").unwrap(), b"v");
+        assert!(matches!(txn.get(b"x"), Err(DBError::KeyNotFound)));
+    }
+
+    #[test]
+    fn test_upgrade_v4_meta() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        let mut txn = env.begin_write();
+        for i in 0..500u32 {
+            txn.put(&i.to_be_bytes(), b"v").unwrap();
+        }
+        txn.commit().unwrap();
+        drop(env);
+        // a version 4 meta ends with the entry count
+        let mut contents = fs::read(&path).unwrap();
+        for slot in 0..NUM_META_PAGES as usize {
+            let start = slot * DEFAULT_PAGE_SIZE;
+            let mut page = Page::from_bytes(&contents[start..start + DEFAULT_PAGE_SIZE]).unwrap();
+            page.get_data_mut()[2..4].copy_from_slice(&4u16.to_le_bytes());
+            page.get_data_mut()[40..].fill(0);
+            page.update_checksum();
+            contents[start..start + DEFAULT_PAGE_SIZE].copy_from_slice(page.as_bytes());
+        }
+        fs::write(&path, contents).unwrap();
+        assert!(matches!(Env::open(&path), Err(DBError::VersionMismatch { found: 4, .. })));
+
+        assert_eq!(upgrade_file(&path).unwrap(), 4);
+        let env = Env::open(&path).unwrap();
+        let info = env.info().unwrap();
+        assert_eq!((info.format_version, info.entries, info.created_at), (META_VERSION, 500, None));
+        assert_eq!(env.begin_read().unwrap().get(&7u32.to_be_bytes()).unwrap(), b"v");
+    }
+
+    #[test]
+    fn test_upgrade_v5_meta() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"v").unwrap();
+        txn.set_user_meta(b"app").unwrap();
+        txn.commit().unwrap();
+        drop(env);
+        // a version 5 meta ends with the user metadata
+        let mut contents = fs::read(&path).unwrap();
+        for slot in 0..NUM_META_PAGES as usize {
+            let start = slot * DEFAULT_PAGE_SIZE;
+            let mut page = Page::from_bytes(&contents[start..start + DEFAULT_PAGE_SIZE]).unwrap();
+            page.get_data_mut()[2..4].copy_from_slice(&5u16.to_le_bytes());
+            page.get_data_mut()[120..].fill(0xff);
+            page.update_checksum();
+            contents[start..start + DEFAULT_PAGE_SIZE].copy_from_slice(page.as_bytes());
+        }
+        fs::write(&path, contents).unwrap();
+
+        assert_eq!(upgrade_file(&path).unwrap(), 5);
+        let env = Env::open(&path).unwrap();
+        let info = env.info().unwrap();
+        assert_eq!((info.entries, &info.user_meta[..], info.sequence), (1, &b"app"[..], 0));
+        assert_eq!(env.next_id().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_run_in_order_once() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let migrator = || {
+            Migrator::new()
+                .migration(2, |txn| txn.put(b"users", b"v2").map(drop))
+                .migration(1, |txn| txn.put(b"users", b"v1").map(drop))
+        };
+        assert_eq!(migrator().run(&env).unwrap(), [1, 2]);
+        assert_eq!(env.begin_read().unwrap().get(b"users").unwrap(), b"v2");
+        assert_eq!(migrator().run(&env).unwrap(), Vec::<u64>::new());
+        assert_eq!(migrator().applied(&env).unwrap(), [1, 2]);
+
+        // a failing migration leaves no trace, and the ones after it wait
+        let failing = migrator()
+            .migration(3, |txn| {
+                txn.put(b"half", b"done")?;
+                Err(DBError::KeyNotFound)
+            })
+            .migration(4, |txn| txn.put(b"users", b"v4").map(drop));
+        assert!(matches!(failing.run(&env), Err(DBError::KeyNotFound)));
+        let txn = env.begin_read().unwrap();
+        assert!(matches!(txn.get(b"half"), Err(DBError::KeyNotFound)));
+        assert_eq!(txn.get(b"users").unwrap(), b"v2");
+        drop(txn);
+
+        let fixed = migrator()
+            .migration(3, |txn| txn.put(b"half", b"done").map(drop))
+            .migration(4, |txn| txn.put(b"users", b"v4").map(drop));
+        assert_eq!(fixed.run(&env).unwrap(), [3, 4]);
+
+        // an older build, or a migration slotted in below those applied
+        assert!(matches!(migrator().run(&env), Err(DBError::IncompatibleFile { .. })));
+        let late = Migrator::new()
+            .migration(1, |_| Ok(()))
+            .migration(2, |_| Ok(()))
+            .migration(3, |_| Ok(()))
+            .migration(4, |_| Ok(()))
+            .migration(0, |_| Ok(()));
+        assert!(matches!(late.run(&env), Err(DBError::MigrationOutOfOrder { version: 0 })));
+    }
+}
@@ -1,68 +1,313 @@
-use memmap2::Mmap;
-use std::io::{self, ErrorKind, Result};
 
+use crate::buf::ByteBuf;
 use crate::constants::*;
 
-#[repr(C)]
+/// An owned page image. Its length is the page size of the file it belongs
+/// to, fixed when the file is created.
+#[derive(Clone)]
 pub struct Page {
-    pgno: Pgno,
-    pad: u16,
-    flags: PageFlag,
-    lower: u16,
-    upper: u16,
-    data: [u8; PAGE_SIZE - PAGE_HEADER_SIZE],
+    bytes: Box<[u8]>,
+}
+
+/// Rejects page sizes that aren't a power of two in
+/// `MIN_PAGE_SIZE..=MAX_PAGE_SIZE`.
+pub fn check_page_size(page_size: usize) -> Result<(), DBError> {
+    if !page_size.is_power_of_two() || !(MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size) {
+        return Err(DBError::InvalidPageSize { size: page_size });
+    }
+    Ok(())
 }
 
 impl Page {
+    /// Builds a page of `PAGE_HEADER_SIZE + data.len()` bytes.
     pub fn from(
         pgno: Pgno,
         pad: u16,
         flags: PageFlag,
         lower: u16,
         upper: u16,
-        data: [u8; 4080],
+        data: &[u8],
     ) -> Self {
-        Page {
-            pgno,
-            pad,
-            flags,
-            lower,
-            upper,
-            data,
-        }
+        let mut bytes = vec![0u8; PAGE_HEADER_SIZE + data.len()];
+        bytes[PGNO_OFFSET..PAD_OFFSET].copy_from_slice(&pgno.to_le_bytes());
+        bytes[PAD_OFFSET..FLAGS_OFFSET].copy_from_slice(&pad.to_le_bytes());
+        bytes[FLAGS_OFFSET..LOWER_OFFSET].copy_from_slice(&flags.bits().to_le_bytes());
+        bytes[PAGE_HEADER_SIZE..].copy_from_slice(data);
+        let mut page = Page { bytes: bytes.into_boxed_slice() };
+        page.set_lower(lower);
+        page.set_upper(upper);
+        page.update_checksum();
+        page
+    }
+
+    /// Copies a raw page image, e.g. one read from outside the map. Only its
+    /// length is checked, against the supported page sizes; the readers built
+    /// on the page check the rest.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DBError> {
+        check_page_size(bytes.len())?;
+        Ok(Page { bytes: bytes.into() })
+    }
+
+    pub fn get_pgno(&self) -> Pgno {
+        self.as_page_ref().get_pgno()
+    }
+
+    pub fn get_pad(&self) -> u16 {
+        self.as_page_ref().get_pad()
+    }
+
+    pub fn get_flag(&self) -> PageFlag {
+        self.as_page_ref().get_flag()
+    }
+
+    pub fn get_lower(&self) -> u16 {
+        self.as_page_ref().get_lower()
+    }
+
+    pub fn get_upper(&self) -> u16 {
+        self.as_page_ref().get_upper()
+    }
+
+    pub fn get_checksum(&self) -> u32 {
+        self.as_page_ref().get_checksum()
+    }
+
+    pub fn get_page_size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.bytes[PAGE_HEADER_SIZE..]
+    }
+
+    /// Recomputes the stored checksum; must be called after mutating the page
+    /// in place and before it is written out.
+    pub fn update_checksum(&mut self) {
+        let checksum = self.as_page_ref().compute_checksum();
+        self.bytes[CHECKSUM_OFFSET..PAGE_HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
     }
-    pub const fn get_pgno(&self) -> Pgno {
-        self.pgno
+
+    pub fn get_data_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[PAGE_HEADER_SIZE..]
+    }
+
+    pub fn set_pgno(&mut self, pgno: Pgno) {
+        self.bytes[PGNO_OFFSET..PAD_OFFSET].copy_from_slice(&pgno.to_le_bytes());
     }
 
-    pub const fn get_pad(&self) -> u16 {
-        self.pad
+    pub fn set_flag(&mut self, flags: PageFlag) {
+        self.bytes[FLAGS_OFFSET..LOWER_OFFSET].copy_from_slice(&flags.bits().to_le_bytes());
     }
 
-    pub const fn get_flag(&self) -> PageFlag {
-        self.flags
+    pub fn set_lower(&mut self, lower: u16) {
+        self.bytes[LOWER_OFFSET..UPPER_OFFSET].copy_from_slice(&lower.to_le_bytes());
     }
 
-    pub const fn get_lower(&self) -> u16 {
-        self.lower
+    pub fn set_upper(&mut self, upper: u16) {
+        self.bytes[UPPER_OFFSET..CHECKSUM_OFFSET].copy_from_slice(&upper.to_le_bytes());
     }
 
-    pub const fn get_upper(&self) -> u16 {
-        self.upper
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
     }
 
-    pub const fn get_data(&self) -> &[u8] {
-        &self.data
+    pub fn as_page_ref(&self) -> PageRef<'_> {
+        PageRef { bytes: &self.bytes }
     }
 
-    pub fn read_from_mmap(mmap: &Mmap, pgno: usize) -> Result<Self> {
-        let start = pgno * PAGE_SIZE;
-        let end = start + PAGE_SIZE;
-        let page_bytes = mmap
-            .get(start..end)
-            .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "Page out of bounds"))?;
-        let page = { unsafe { std::ptr::read_unaligned(page_bytes.as_ptr().cast::<Page>()) } };
+    pub fn read_from_mmap(file: &[u8], page_size: usize, pgno: usize) -> Result<Self, DBError> {
+        Ok(PageRef::from_mmap(file, page_size, pgno)?.to_page())
+    }
+
+    pub fn read_from_mmap_verified(
+        file: &[u8],
+        page_size: usize,
+        pgno: usize,
+    ) -> Result<Self, DBError> {
+        Ok(PageRef::from_mmap_verified(file, page_size, pgno)?.to_page())
+    }
+}
+
+// header field offsets within the on-disk page image:
+// pgno (u64) + pad (u16) + flags (u16) + lower (u16) + upper (u16) + checksum (u32)
+// Every integer in a file, here and in the page bodies, is either fixed width
+// and little-endian or a LEB128 varint. None is stored as a usize or read by
+// casting bytes in place, so a file reads the same on any host.
+const PGNO_OFFSET: usize = 0;
+const PAD_OFFSET: usize = 8;
+const FLAGS_OFFSET: usize = 10;
+const LOWER_OFFSET: usize = 12;
+const UPPER_OFFSET: usize = 14;
+const CHECKSUM_OFFSET: usize = 16;
 
+/// A borrowed, zero-copy view of a page image, e.g. directly over the mmap.
+#[derive(Copy, Clone)]
+pub struct PageRef<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> From<&'a Page> for PageRef<'a> {
+    fn from(page: &'a Page) -> Self {
+        page.as_page_ref()
+    }
+}
+
+impl<'a> PageRef<'a> {
+    /// Page `pgno` of `file`, the whole file's contents, such as its map.
+    pub fn from_mmap(file: &'a [u8], page_size: usize, pgno: usize) -> Result<Self, DBError> {
+        let bytes = pgno
+            .checked_mul(page_size)
+            .and_then(|start| file.get(start..start.checked_add(page_size)?))
+            .ok_or(DBError::PageOutOfBounds { pgno: pgno as Pgno })?;
+        Self::from_image(bytes, pgno)
+    }
+
+    /// A page read on its own, which should be page `pgno` of its file.
+    pub fn from_image(bytes: &'a [u8], pgno: usize) -> Result<Self, DBError> {
+        let page = PageRef { bytes };
+
+        if page.get_pgno() != pgno as Pgno {
+            return Err(DBError::CorruptPage {
+                pgno: pgno as Pgno,
+                reason: "page number does not match its position in the file",
+            });
+        }
+        if PageFlag::from_bits(page.get_flag().bits()).is_none() {
+            return Err(DBError::CorruptPage {
+                pgno: pgno as Pgno,
+                reason: "unrecognized page flags",
+            });
+        }
+
+        Ok(page)
+    }
+
+    /// Like `from_mmap`, but also rejects pages whose checksum doesn't match.
+    pub fn from_mmap_verified(
+        file: &'a [u8],
+        page_size: usize,
+        pgno: usize,
+    ) -> Result<Self, DBError> {
+        let page = Self::from_mmap(file, page_size, pgno)?;
+        page.verify_checksum()?;
         Ok(page)
     }
+
+    // the slice is always a whole page, so header reads can't fail
+    pub fn get_pgno(&self) -> Pgno {
+        self.bytes.read_u64_le(PGNO_OFFSET).unwrap()
+    }
+
+    pub fn get_pad(&self) -> u16 {
+        self.bytes.read_u16_le(PAD_OFFSET).unwrap()
+    }
+
+    pub fn get_flag(&self) -> PageFlag {
+        PageFlag::from_bits_retain(self.bytes.read_u16_le(FLAGS_OFFSET).unwrap())
+    }
+
+    pub fn get_lower(&self) -> u16 {
+        self.bytes.read_u16_le(LOWER_OFFSET).unwrap()
+    }
+
+    pub fn get_upper(&self) -> u16 {
+        self.bytes.read_u16_le(UPPER_OFFSET).unwrap()
+    }
+
+    pub fn get_checksum(&self) -> u32 {
+        self.bytes.read_u32_le(CHECKSUM_OFFSET).unwrap()
+    }
+
+    pub fn get_page_size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn get_data(&self) -> &'a [u8] {
+        &self.bytes[PAGE_HEADER_SIZE..]
+    }
+
+    /// The whole page image, header included.
+    pub const fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    // CRC32 over the whole page image except the checksum field itself
+    fn compute_checksum(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.bytes[..CHECKSUM_OFFSET]);
+        hasher.update(&self.bytes[PAGE_HEADER_SIZE..]);
+        hasher.finalize()
+    }
+
+    pub fn verify_checksum(&self) -> Result<(), DBError> {
+        if self.compute_checksum() != self.get_checksum() {
+            return Err(DBError::ChecksumMismatch {
+                pgno: self.get_pgno(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn to_page(&self) -> Page {
+        // copy the image as-is rather than recomputing the checksum, so a copy
+        // of a damaged page still fails verification
+        Page { bytes: self.bytes.into() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memmap2::MmapMut;
+
+    fn map_pages(pages: &[Page]) -> MmapMut {
+        let page_size = pages[0].get_page_size();
+        let mut mmap = MmapMut::map_anon(pages.len() * page_size).unwrap();
+        for (i, page) in pages.iter().enumerate() {
+            mmap[i * page_size..(i + 1) * page_size].copy_from_slice(page.as_bytes());
+        }
+        mmap
+    }
+
+    #[test]
+    fn test_checksum_mismatch_detected() {
+        let mut data = [0u8; DEFAULT_PAGE_SIZE - PAGE_HEADER_SIZE];
+        data[..5].copy_from_slice(b"hello");
+        let pages = [
+            Page::from(0, 0, PageFlag::ALIVE, 0, data.len() as u16, &data),
+            Page::from(1, 0, PageFlag::ALIVE, 0, data.len() as u16, &data),
+        ];
+        let mut mmap = map_pages(&pages);
+        // flip a data byte of page 1 behind the checksum's back
+        mmap[DEFAULT_PAGE_SIZE + PAGE_HEADER_SIZE] ^= 0xff;
+        let mmap = mmap.make_read_only().unwrap();
+
+        assert!(PageRef::from_mmap_verified(&mmap, DEFAULT_PAGE_SIZE, 0).is_ok());
+        assert!(matches!(
+            PageRef::from_mmap_verified(&mmap, DEFAULT_PAGE_SIZE, 1),
+            Err(DBError::ChecksumMismatch { pgno: 1 })
+        ));
+        assert!(matches!(
+            Page::read_from_mmap(&mmap, DEFAULT_PAGE_SIZE, 1)
+                .unwrap()
+                .as_page_ref()
+                .verify_checksum(),
+            Err(DBError::ChecksumMismatch { pgno: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_page_sizes() {
+        for size in [MIN_PAGE_SIZE, 16384, MAX_PAGE_SIZE] {
+            check_page_size(size).unwrap();
+            let data = vec![7u8; size - PAGE_HEADER_SIZE];
+            let page = Page::from(3, 0, PageFlag::ALIVE, 0, data.len() as u16, &data);
+            assert_eq!(page.get_page_size(), size);
+            assert_eq!(page.get_upper() as usize, size - PAGE_HEADER_SIZE);
+            page.as_page_ref().verify_checksum().unwrap();
+        }
+        for size in [0, 2048, 5000, 2 * MAX_PAGE_SIZE] {
+            assert!(matches!(check_page_size(size), Err(DBError::InvalidPageSize { .. })));
+        }
+    }
 }
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use crate::btree::{BTree, MAX_TREE_DEPTH};
+use crate::btree_page::{child_data, BranchPage};
+use crate::constants::*;
+use crate::data_page::{DataPage, DirtyPage};
+
+/// Hands out page numbers for a write transaction's new pages. Nothing is
+/// freed yet, so numbers only ever grow past the end of the file.
+pub struct PageAllocator {
+    next_pgno: Pgno,
+}
+
+impl PageAllocator {
+    pub fn new(next_pgno: Pgno) -> Self {
+        PageAllocator { next_pgno }
+    }
+
+    /// First page number past every page handed out so far.
+    pub const fn get_next_pgno(&self) -> Pgno {
+        self.next_pgno
+    }
+
+    pub fn alloc(&mut self) -> Pgno {
+        self.next_pgno += 1;
+        self.next_pgno - 1
+    }
+}
+
+/// The pages a write transaction has changed, by page number. Each one is
+/// flagged `PageFlag::DIRTY` until it is flushed.
+#[derive(Clone, Default)]
+pub struct DirtySet {
+    pages: BTreeMap<Pgno, DirtyPage>,
+}
+
+impl DirtySet {
+    pub fn new() -> Self {
+        DirtySet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub fn contains(&self, pgno: Pgno) -> bool {
+        self.pages.contains_key(&pgno)
+    }
+
+    pub fn get(&self, pgno: Pgno) -> Option<&DirtyPage> {
+        self.pages.get(&pgno)
+    }
+
+    pub fn get_mut(&mut self, pgno: Pgno) -> Option<&mut DirtyPage> {
+        self.pages.get_mut(&pgno)
+    }
+
+    /// Every page, in page number order.
+    pub fn pages(&self) -> impl Iterator<Item = &DirtyPage> {
+        self.pages.values()
+    }
+
+    /// Adds `page` under its own page number, replacing any page already there.
+    pub fn insert(&mut self, page: DirtyPage) {
+        self.pages.insert(page.get_pgno(), page);
+    }
+
+    /// Copies a clean page to a newly allocated number and returns it; the
+    /// original is left for readers of the commit it belongs to.
+    pub fn clone_page(&mut self, alloc: &mut PageAllocator, page: &DataPage) -> Pgno {
+        let pgno = alloc.alloc();
+        self.insert(DirtyPage::from(page, pgno));
+        pgno
+    }
+
+    /// Copies the subtree at `pgno` of `tree` into newly allocated pages,
+    /// pointing each branch at its children's new numbers, and returns the new
+    /// number of its root. Pages are numbered in the order a scan visits them.
+    /// `on_copy` is called after each page, and stops the copy if it fails.
+    pub fn copy_subtree(
+        &mut self,
+        alloc: &mut PageAllocator,
+        tree: &BTree,
+        pgno: Pgno,
+        depth: usize,
+        on_copy: &mut dyn FnMut() -> Result<(), DBError>,
+    ) -> Result<Pgno, DBError> {
+        if depth == MAX_TREE_DEPTH {
+            return Err(DBError::CorruptPage { pgno, reason: "tree is too deep" });
+        }
+        let page = tree.get_page(pgno)?;
+        let new_pgno = alloc.alloc();
+        let mut copy = DirtyPage::from(&page, new_pgno);
+        if page.get_flags().contains(PageFlag::BRANCH) {
+            let branch = BranchPage::from(page)?;
+            for idx in 0..branch.num_children() {
+                let child = branch.child_at(idx)?;
+                let child = self.copy_subtree(alloc, tree, child, depth + 1, on_copy)?;
+                copy.replace_data(idx, &child_data(child, branch.entries_at(idx)?))?;
+            }
+        }
+        self.insert(copy);
+        on_copy()?;
+        Ok(new_pgno)
+    }
+
+    /// Every page ready to be written, in page number order, with consecutive
+    /// pages joined into one buffer so each run takes a single write.
+    pub fn into_runs(self) -> Vec<(Pgno, Vec<u8>)> {
+        let mut runs: Vec<(Pgno, Vec<u8>)> = Vec::new();
+        let mut run_end = None;
+        for (pgno, page) in self.pages {
+            let page = page.into_page();
+            match runs.last_mut() {
+                Some((_, bytes)) if run_end == Some(pgno) => {
+                    bytes.extend_from_slice(page.as_bytes());
+                }
+                _ => runs.push((pgno, page.as_bytes().to_vec())),
+            }
+            run_end = Some(pgno + 1);
+        }
+        runs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_order::KeyOrder;
+    use crate::page::PageRef;
+    use memmap2::Mmap;
+    use std::os::unix::fs::FileExt;
+
+    #[test]
+    fn test_runs_join_consecutive_pages() {
+        let mut alloc = PageAllocator::new(5);
+        let mut dirty = DirtySet::new();
+        for _ in 0..3 {
+            let pgno = alloc.alloc();
+            dirty.insert(DirtyPage::new(pgno, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, KeyOrder::Bytes));
+        }
+        let page = dirty.get(5).unwrap().as_data_page().unwrap();
+        assert!(page.get_flags().contains(PageFlag::DIRTY));
+        dirty.insert(DirtyPage::new(9, DEFAULT_PAGE_SIZE, PageFlag::ALIVE, KeyOrder::Bytes));
+
+        let runs = dirty.into_runs();
+        let starts: Vec<Pgno> = runs.iter().map(|(pgno, _)| *pgno).collect();
+        assert_eq!(starts, [5, 9]);
+        assert_eq!(runs[0].1.len(), 3 * DEFAULT_PAGE_SIZE);
+
+        // flushed pages are clean, with checksums to match
+        let file = tempfile::tempfile().unwrap();
+        for (pgno, bytes) in &runs {
+            file.write_all_at(bytes, pgno * DEFAULT_PAGE_SIZE as u64).unwrap();
+        }
+        let mmap = unsafe { Mmap::map(&file).unwrap() };
+        for pgno in [5, 6, 7, 9] {
+            let page = PageRef::from_mmap_verified(&mmap, DEFAULT_PAGE_SIZE, pgno).unwrap();
+            assert!(!page.get_flag().contains(PageFlag::DIRTY));
+        }
+    }
+}
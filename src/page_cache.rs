@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::btree_page::BranchPage;
+use crate::constants::*;
+use crate::key_order::KeyOrder;
+use crate::metrics::Metrics;
+
+/// How many branch pages an environment keeps parsed by default; see
+/// `EnvOptions::page_cache`.
+pub const DEFAULT_PAGE_CACHE_ENTRIES: usize = 1024;
+
+/// A branch page decoded once: its separator keys, with the page's common
+/// prefix put back, and the child each one leads to.
+pub struct ParsedBranch {
+    keys: Vec<Vec<u8>>,
+    children: Vec<Pgno>,
+}
+
+impl ParsedBranch {
+    pub fn from(branch: &BranchPage) -> Result<Self, DBError> {
+        let nodes = branch.as_data_page().read_nodes()?;
+        Ok(ParsedBranch {
+            keys: nodes.iter().map(|node| node.get_key().into_owned()).collect(),
+            children: (0..nodes.len())
+                .map(|idx| branch.child_at(idx))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn num_children(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Index of the child whose subtree `key` belongs to, as
+    /// `BranchPage::child_index` finds it; the first key is never compared.
+    pub fn child_index(&self, key: &[u8], order: KeyOrder) -> usize {
+        self.keys[1..].partition_point(|separator| order.compare(separator, key).is_le())
+    }
+
+    pub fn child_at(&self, idx: usize) -> Pgno {
+        self.children[idx]
+    }
+}
+
+/// Hits and misses of a `PageCache` since the environment was opened.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PageCacheStat {
+    pub hits: u64,
+    /// Branch pages that had to be parsed, and were then cached.
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl PageCacheStat {
+    /// Share of branch page reads served from the cache, from 0 to 1.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            reads => self.hits as f64 / reads as f64,
+        }
+    }
+}
+
+/// Branch pages parsed by earlier read transactions, shared by all of an
+/// environment's read transactions, so lookups through the hot top of the
+/// tree don't decode the same pages over and over. Committed pages are never
+/// changed in place, so an entry stays good until its page number is written
+/// again, which only happens once compaction has handed the number back;
+/// `Env` drops the entry whenever a commit writes the page, and every entry
+/// when a read-only environment finds the file compacted.
+pub struct PageCache {
+    capacity: usize,
+    branches: RwLock<HashMap<Pgno, Arc<ParsedBranch>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl PageCache {
+    /// A cache of up to `capacity` branch pages; 0 caches none.
+    pub fn new(capacity: usize) -> Self {
+        PageCache {
+            capacity,
+            branches: RwLock::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            metrics: None,
+        }
+    }
+
+    /// Also counts hits and misses in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Branch page `pgno` from the cache, or parsed by `parse` and cached.
+    /// `parse` returns `None` for pages that aren't branches, which are
+    /// neither cached nor counted. A cache of capacity 0 returns `None`
+    /// without calling `parse` at all.
+    pub fn get_or_parse(
+        &self,
+        pgno: Pgno,
+        parse: impl FnOnce() -> Result<Option<ParsedBranch>, DBError>,
+    ) -> Result<Option<Arc<ParsedBranch>>, DBError> {
+        if self.capacity == 0 {
+            return Ok(None);
+        }
+        let branches = self.branches.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(branch) = branches.get(&pgno) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            if let Some(metrics) = &self.metrics {
+                metrics.cache_hit();
+            }
+            return Ok(Some(Arc::clone(branch)));
+        }
+        drop(branches);
+
+        let Some(branch) = parse()? else {
+            return Ok(None);
+        };
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        if let Some(metrics) = &self.metrics {
+            metrics.cache_miss();
+        }
+        let mut branches = self.branches.write().unwrap_or_else(PoisonError::into_inner);
+        if branches.len() >= self.capacity && !branches.contains_key(&pgno) {
+            // any entry will do; the hot pages are read again soon enough
+            if let Some(&victim) = branches.keys().next() {
+                branches.remove(&victim);
+            }
+        }
+        Ok(Some(Arc::clone(branches.entry(pgno).or_insert_with(|| Arc::new(branch)))))
+    }
+
+    /// Drops the entries of pages about to be written.
+    pub fn invalidate(&self, pgnos: Range<Pgno>) {
+        let mut branches = self.branches.write().unwrap_or_else(PoisonError::into_inner);
+        if pgnos.end.saturating_sub(pgnos.start) < branches.len() as u64 {
+            for pgno in pgnos {
+                branches.remove(&pgno);
+            }
+        } else {
+            branches.retain(|pgno, _| !pgnos.contains(pgno));
+        }
+    }
+
+    pub fn stat(&self) -> PageCacheStat {
+        PageCacheStat {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.branches.read().unwrap_or_else(PoisonError::into_inner).len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree_page::child_data;
+    use crate::data_page::{DataPage, DirtyPage};
+
+    #[test]
+    fn test_routes_like_branch_page() {
+        // separators sharing a prefix, which the page stores once
+        let page = BranchPage::new_page(3, DEFAULT_PAGE_SIZE);
+        let mut dirty = DirtyPage::from(&DataPage::from(&page).unwrap(), 3);
+        dirty.put(b"", &child_data(10, 1)).unwrap();
+        for (i, separator) in [&b"user/f"[..], b"user/m", b"user/t"].iter().enumerate() {
+            dirty.put(separator, &child_data(11 + i as u64, 1)).unwrap();
+        }
+        let branch = BranchPage::from(dirty.as_data_page().unwrap()).unwrap();
+        let parsed = ParsedBranch::from(&branch).unwrap();
+
+        assert_eq!(parsed.num_children(), 4);
+        for key in [&b"a"[..], b"user/", b"user/f", b"user/g", b"user/m", b"user/zz", b"zz"] {
+            let idx = parsed.child_index(key, KeyOrder::Bytes);
+            assert_eq!(idx, branch.child_index(key).unwrap());
+            assert_eq!(parsed.child_at(idx), branch.get(key).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_invalidate_and_evict() {
+        let cache = PageCache::new(2);
+        let parsed = || Ok(Some(ParsedBranch { keys: vec![Vec::new()], children: vec![9] }));
+        for pgno in [4, 4, 5] {
+            cache.get_or_parse(pgno, parsed).unwrap();
+        }
+        assert_eq!(cache.stat(), PageCacheStat { hits: 1, misses: 2, entries: 2 });
+
+        // a full cache makes room, and pages that aren't branches stay out
+        cache.get_or_parse(6, parsed).unwrap();
+        assert_eq!(cache.stat().entries, 2);
+        assert!(cache.get_or_parse(7, || Ok(None)).unwrap().is_none());
+        assert_eq!(cache.stat().misses, 3);
+
+        cache.invalidate(0..100);
+        assert_eq!(cache.stat().entries, 0);
+        let off = PageCache::new(0);
+        assert!(off.get_or_parse(4, parsed).unwrap().is_none());
+        assert_eq!(off.stat(), PageCacheStat::default());
+    }
+}
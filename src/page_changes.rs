@@ -0,0 +1,155 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::buf::ByteBuf;
+use crate::constants::*;
+use crate::meta::{Meta, NUM_META_PAGES};
+
+// a record: commit id (u64) + the next page number as of that commit (u64)
+// + its root (u64, INVALID_PGNO for none)
+const RECORD_SIZE: usize = 8 + 8 + 8;
+
+/// A commit as `PageChanges` records it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct RecordedCommit {
+    pub txnid: TxnId,
+    pub next_pgno: Pgno,
+    pub root: Option<Pgno>,
+}
+
+/// The next page number and root of each commit, kept in `<path>-changes`
+/// beside the file, so that `Env::pages_changed_since` can tell the pages
+/// written after a commit from the ones before it, and a `ChangeStream` can
+/// read the tree of every commit. Committed pages are never written again,
+/// so those are simply the ones from that commit's next page number on.
+/// Compaction does write over them, after which none of the commits before
+/// it are known: every page counts as changed since them, as it does since
+/// commits made before the file was first opened with this record.
+///
+/// The file is a log of every commit published, which opening replays: a
+/// commit made again under the id of one dropped (a prepared one rolled
+/// back, or one a crash lost) replaces it, and a commit that moves the next
+/// page number back forgets every commit before it. Records a crash loses
+/// only make more pages count as changed, except the one compaction leaves,
+/// which is synced.
+pub(crate) struct PageChanges {
+    file: File,
+    // by commit id
+    commits: Vec<RecordedCommit>,
+}
+
+impl PageChanges {
+    /// Opens the record at `path`, creating it if need be, for a file whose
+    /// current commit is `current`.
+    pub fn open(path: &Path, current: &Meta) -> Result<Self, DBError> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+        let mut changes = PageChanges { file, commits: Vec::new() };
+        for pos in (0..log.len() / RECORD_SIZE).map(|i| i * RECORD_SIZE) {
+            let field = |offset| log.read_u64_le(pos + offset).expect("a whole record");
+            let root = Some(field(16)).filter(|&root| root != INVALID_PGNO);
+            changes.apply(RecordedCommit { txnid: field(0), next_pgno: field(8), root });
+        }
+        // a record cut short was never fully written
+        changes.file.set_len((log.len() - log.len() % RECORD_SIZE) as u64)?;
+        changes.record(current)?;
+        Ok(changes)
+    }
+
+    // whether the commits recorded before this one were forgotten
+    fn apply(&mut self, commit: RecordedCommit) -> bool {
+        while self.commits.last().is_some_and(|last| last.txnid >= commit.txnid) {
+            self.commits.pop();
+        }
+        let rewritten = self.commits.last().is_some_and(|last| last.next_pgno > commit.next_pgno);
+        if rewritten {
+            self.commits.clear();
+        }
+        self.commits.push(commit);
+        rewritten
+    }
+
+    /// Records the commit of `meta`, once it is current.
+    pub fn record(&mut self, meta: &Meta) -> Result<(), DBError> {
+        let commit = RecordedCommit {
+            txnid: meta.get_txnid(),
+            next_pgno: meta.get_next_pgno(),
+            root: meta.get_root(),
+        };
+        if self.commits.last() == Some(&commit) {
+            return Ok(());
+        }
+        let mut record = [0; RECORD_SIZE];
+        record[..8].copy_from_slice(&commit.txnid.to_le_bytes());
+        record[8..16].copy_from_slice(&commit.next_pgno.to_le_bytes());
+        record[16..].copy_from_slice(&commit.root.unwrap_or(INVALID_PGNO).to_le_bytes());
+        if !self.apply(commit) {
+            self.file.write_all(&record)?;
+            return Ok(());
+        }
+        self.file.set_len(0)?;
+        self.file.write_all(&record)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    /// The first page written after commit `txnid`: every page from it on
+    /// changed since, and none before it did.
+    pub fn first_changed(&self, txnid: TxnId) -> Pgno {
+        match self.commits.partition_point(|commit| commit.txnid <= txnid) {
+            0 => NUM_META_PAGES,
+            idx => self.commits[idx - 1].next_pgno,
+        }
+    }
+
+    /// Commit `txnid`, if it is recorded, and the first recorded after it,
+    /// if there is one yet.
+    pub fn next_after(&self, txnid: TxnId) -> Option<(RecordedCommit, Option<RecordedCommit>)> {
+        let idx = self.commits.binary_search_by_key(&txnid, |commit| commit.txnid).ok()?;
+        Some((self.commits[idx], self.commits.get(idx + 1).copied()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_replayed_on_open() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db-changes");
+        let recorded = |changes: &PageChanges| -> Vec<(TxnId, Pgno)> {
+            changes.commits.iter().map(|commit| (commit.txnid, commit.next_pgno)).collect()
+        };
+        let first = Meta::new(DEFAULT_PAGE_SIZE).unwrap();
+        let mut changes = PageChanges::open(&path, &first).unwrap();
+        let mut meta = first;
+        for next_pgno in [10, 14, 16] {
+            meta = meta.next_commit(Some(next_pgno - 1), next_pgno);
+            changes.record(&meta).unwrap();
+        }
+        assert_eq!([0, 1, 2, 3].map(|txnid| changes.first_changed(txnid)), [2, 10, 14, 16]);
+        let (commit, next) = changes.next_after(1).unwrap();
+        assert_eq!((commit.root, next.map(|next| next.root)), (Some(9), Some(Some(13))));
+        assert_eq!(changes.next_after(3).unwrap().1, None);
+        drop(changes);
+
+        // a crash lost the last commit, and cut the record after it short; a
+        // commit made again under its id replaces it
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[3; 5]).unwrap();
+        let lost = first.next_commit(Some(9), 10).next_commit(Some(13), 14);
+        let mut changes = PageChanges::open(&path, &lost).unwrap();
+        assert_eq!(recorded(&changes), [(0, 2), (1, 10), (2, 14)]);
+        // compaction moved every page
+        let compacted = lost.next_commit(Some(5), 6);
+        changes.record(&compacted).unwrap();
+        assert_eq!([2, 3].map(|txnid| changes.first_changed(txnid)), [NUM_META_PAGES, 6]);
+        assert!(changes.next_after(2).is_none());
+        drop(changes);
+        assert_eq!(recorded(&PageChanges::open(&path, &compacted).unwrap()), [(3, 6)]);
+    }
+}
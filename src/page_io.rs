@@ -0,0 +1,440 @@
+use memmap2::{Advice, Mmap, MmapMut};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+
+use crate::constants::*;
+use crate::metrics::Metrics;
+use crate::page::PageRef;
+use crate::page_cache::PageCacheStat;
+
+/// How many pages `IoBackend::buffered` keeps in memory.
+pub const DEFAULT_BUFFER_PAGES: usize = 4096;
+
+/// How an environment reads and writes its file; see `EnvOptions::io_backend`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum IoBackend {
+    /// Maps the file and reads pages in place, with the OS deciding what
+    /// stays in memory; the default.
+    #[default]
+    Mmap,
+    /// Never maps the file: pages are read with `pread` into an LRU cache of
+    /// up to `cache_pages` pages that all transactions share, and commits
+    /// write with `pwrite`. For filesystems where a mapped file behaves badly,
+    /// such as network filesystems, and for files too large for the address
+    /// space. A transaction holds on to the pages it has read until it ends,
+    /// so a long scan keeps what it has passed in memory. Checks, recovery
+    /// and copies still map the file for as long as they run.
+    Buffered { cache_pages: usize },
+}
+
+impl IoBackend {
+    /// `Buffered` with a cache of `DEFAULT_BUFFER_PAGES` pages.
+    pub const fn buffered() -> Self {
+        IoBackend::Buffered { cache_pages: DEFAULT_BUFFER_PAGES }
+    }
+}
+
+/// How the file is expected to be read, for the OS to plan its readahead
+/// around; see `Env::advise_sequential` and `Env::advise_random`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AccessPattern {
+    /// The OS's own readahead; the default.
+    #[default]
+    Normal,
+    /// Mostly long scans: read further ahead, and let pages go soon after.
+    Sequential,
+    /// Mostly point lookups: read only the pages asked for.
+    Random,
+}
+
+impl AccessPattern {
+    const fn advice(self) -> (Advice, libc::c_int) {
+        match self {
+            AccessPattern::Normal => (Advice::Normal, libc::POSIX_FADV_NORMAL),
+            AccessPattern::Sequential => (Advice::Sequential, libc::POSIX_FADV_SEQUENTIAL),
+            AccessPattern::Random => (Advice::Random, libc::POSIX_FADV_RANDOM),
+        }
+    }
+}
+
+// posix_fadvise returns its error rather than setting errno; a `len` of 0
+// runs to the end of the file
+fn fadvise(file: &File, offset: usize, len: usize, advice: libc::c_int) -> io::Result<()> {
+    let (offset, len) = (offset as libc::off_t, len as libc::off_t);
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), offset, len, advice) } {
+        0 => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+/// Pages read from the file with `pread`, the most recently used of them
+/// kept in memory. As with the map, nothing a reader can still see is ever
+/// written, so a page read once stays good until its page number is written
+/// again, and `write_at` drops it then.
+pub struct BufferPool {
+    file: File,
+    page_size: usize,
+    capacity: usize,
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+#[derive(Default)]
+struct Lru {
+    // pgno -> (page, when it was last used)
+    pages: HashMap<Pgno, (Arc<[u8]>, u64)>,
+    // when used -> pgno, least recently used first
+    by_use: BTreeMap<u64, Pgno>,
+    clock: u64,
+}
+
+impl Lru {
+    fn get(&mut self, pgno: Pgno) -> Option<Arc<[u8]>> {
+        self.clock += 1;
+        let (page, used) = self.pages.get_mut(&pgno)?;
+        self.by_use.remove(used);
+        self.by_use.insert(self.clock, pgno);
+        *used = self.clock;
+        Some(Arc::clone(page))
+    }
+
+    fn insert(&mut self, pgno: Pgno, page: Arc<[u8]>, capacity: usize) {
+        self.remove(pgno);
+        while self.pages.len() >= capacity {
+            let Some((_, victim)) = self.by_use.pop_first() else {
+                return;
+            };
+            self.pages.remove(&victim);
+        }
+        self.clock += 1;
+        self.by_use.insert(self.clock, pgno);
+        self.pages.insert(pgno, (page, self.clock));
+    }
+
+    fn remove(&mut self, pgno: Pgno) {
+        if let Some((_, used)) = self.pages.remove(&pgno) {
+            self.by_use.remove(&used);
+        }
+    }
+}
+
+impl BufferPool {
+    /// A pool over `file`, keeping up to `capacity` pages; 0 reads every
+    /// page from the file.
+    pub fn new(file: File, page_size: usize, capacity: usize) -> Self {
+        BufferPool {
+            file,
+            page_size,
+            capacity,
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            metrics: None,
+        }
+    }
+
+    /// Reports every page found in memory, or not, to `metrics` as well.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub const fn get_page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// The image of page `pgno`, from memory or read from the file. Reading
+    /// past the end of the file fails with `PageOutOfBounds`.
+    pub fn read(&self, pgno: Pgno) -> Result<Arc<[u8]>, DBError> {
+        if let Some(page) = self.lru().get(pgno) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            if let Some(metrics) = &self.metrics {
+                metrics.cache_hit();
+            }
+            return Ok(page);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        if let Some(metrics) = &self.metrics {
+            metrics.cache_miss();
+        }
+        let mut page = vec![0; self.page_size];
+        let offset = pgno.checked_mul(self.page_size as u64);
+        match offset.map(|offset| self.file.read_exact_at(&mut page, offset)) {
+            Some(Ok(())) => {}
+            None => return Err(DBError::PageOutOfBounds { pgno }),
+            Some(Err(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(DBError::PageOutOfBounds { pgno });
+            }
+            Some(Err(err)) => return Err(err.into()),
+        }
+        let page: Arc<[u8]> = page.into();
+        if self.capacity > 0 {
+            self.lru().insert(pgno, Arc::clone(&page), self.capacity);
+        }
+        Ok(page)
+    }
+
+    /// Writes `bytes` at `offset`, dropping the pages they cover from memory.
+    pub fn write_at(&self, offset: u64, bytes: &[u8]) -> Result<(), DBError> {
+        let first = offset / self.page_size as u64;
+        let end = (offset + bytes.len() as u64).div_ceil(self.page_size as u64);
+        let mut lru = self.lru();
+        for pgno in first..end {
+            lru.remove(pgno);
+        }
+        drop(lru);
+        self.file.write_all_at(bytes, offset)?;
+        Ok(())
+    }
+
+    pub fn stat(&self) -> PageCacheStat {
+        PageCacheStat {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.lru().pages.len(),
+        }
+    }
+
+    fn lru(&self) -> MutexGuard<'_, Lru> {
+        self.lru.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Where the pages of one commit are read from: the map taken for it, or the
+/// environment's buffer pool.
+#[derive(Clone)]
+pub enum PageFile {
+    Mapped(Arc<Mmap>),
+    Buffered(Arc<BufferPool>),
+}
+
+impl PageFile {
+    /// Passes `pattern` on to the OS: for a map as advice on all of it, which
+    /// a new map of the file doesn't inherit, and for the buffer pool on the
+    /// open file, which lasts.
+    pub fn advise(&self, pattern: AccessPattern) -> Result<(), DBError> {
+        let (madvice, fadvice) = pattern.advice();
+        match self {
+            PageFile::Mapped(mmap) => mmap.advise(madvice)?,
+            PageFile::Buffered(pool) => fadvise(&pool.file, 0, 0, fadvice)?,
+        }
+        Ok(())
+    }
+
+    /// A view for one transaction to read through.
+    pub fn view(&self) -> FileView {
+        FileView {
+            file: self.clone(),
+            held: Mutex::new(HashMap::new()),
+            mapped: OnceLock::new(),
+        }
+    }
+}
+
+/// A transaction's access to the file. Pages read through a buffer pool are
+/// held until the view is dropped, so the pages borrowed from it stay valid
+/// for as long as the transaction, as they would in a map.
+pub struct FileView {
+    file: PageFile,
+    held: Mutex<HashMap<Pgno, Arc<[u8]>>>,
+    // the whole file, mapped for the operations that need it in one piece
+    mapped: OnceLock<Arc<Mmap>>,
+}
+
+impl FileView {
+    pub fn page(&self, page_size: usize, pgno: Pgno) -> Result<PageRef<'_>, DBError> {
+        let pool = match &self.file {
+            PageFile::Mapped(mmap) => return PageRef::from_mmap(mmap, page_size, pgno as usize),
+            PageFile::Buffered(pool) => pool,
+        };
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        let page = match held.entry(pgno) {
+            Entry::Occupied(entry) => Arc::clone(entry.get()),
+            Entry::Vacant(entry) => Arc::clone(entry.insert(pool.read(pgno)?)),
+        };
+        // entries are never removed or replaced, so the bytes live as long
+        // as `held`, which is as long as the view
+        let bytes = unsafe { &*Arc::as_ptr(&page) };
+        PageRef::from_image(bytes, pgno as usize)
+    }
+
+    /// Hints that pages `pgnos` will be read soon, so the OS can start reading
+    /// them in, one request per run of consecutive page numbers. Pages past
+    /// the end of the file are left out, and a hint the OS turns down
+    /// changes nothing, so it isn't reported.
+    pub fn prefetch(&self, page_size: usize, pgnos: &[Pgno]) {
+        let mut runs: Vec<(Pgno, u64)> = Vec::new();
+        for &pgno in pgnos {
+            match runs.last_mut() {
+                Some((start, count)) if start.checked_add(*count) == Some(pgno) => *count += 1,
+                _ => runs.push((pgno, 1)),
+            }
+        }
+        for (start, count) in runs {
+            let (Some(offset), Some(len)) = (
+                (start as usize).checked_mul(page_size),
+                (count as usize).checked_mul(page_size),
+            ) else {
+                continue;
+            };
+            let _ = match &self.file {
+                PageFile::Mapped(mmap) if offset.saturating_add(len) <= mmap.len() => {
+                    mmap.advise_range(Advice::WillNeed, offset, len)
+                }
+                PageFile::Mapped(_) => continue,
+                PageFile::Buffered(pool) => {
+                    fadvise(&pool.file, offset, len, libc::POSIX_FADV_WILLNEED)
+                }
+            };
+        }
+    }
+
+    /// Like `page`, but also rejects pages whose checksum doesn't match.
+    pub fn page_verified(&self, page_size: usize, pgno: Pgno) -> Result<PageRef<'_>, DBError> {
+        let page = self.page(page_size, pgno)?;
+        page.verify_checksum()?;
+        Ok(page)
+    }
+
+    /// The whole file as one map. Without a map of its own, the file is
+    /// mapped on first use and kept until the view is dropped.
+    pub fn as_mmap(&self) -> Result<&Arc<Mmap>, DBError> {
+        let pool = match &self.file {
+            PageFile::Mapped(mmap) => return Ok(mmap),
+            PageFile::Buffered(pool) => pool,
+        };
+        if let Some(mmap) = self.mapped.get() {
+            return Ok(mmap);
+        }
+        let mmap = Arc::new(unsafe { Mmap::map(&pool.file)? });
+        Ok(self.mapped.get_or_init(|| mmap))
+    }
+}
+
+/// The writer's access to the file: a writable map covering all of it, or
+/// writes through the buffer pool; or none, for a read-only environment,
+/// where every write fails with `TxnReadOnly`.
+pub enum FileWriter {
+    Mapped(MmapMut),
+    Buffered { pool: Arc<BufferPool>, len: u64 },
+    ReadOnly { len: u64 },
+}
+
+impl FileWriter {
+    /// Size of the file as last set with `resize`.
+    pub fn len(&self) -> u64 {
+        match self {
+            FileWriter::Mapped(map) => map.len() as u64,
+            FileWriter::Buffered { len, .. } | FileWriter::ReadOnly { len } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes `bytes` at `offset`, which must lie within the file.
+    pub fn write_at(&mut self, offset: usize, bytes: &[u8]) -> Result<(), DBError> {
+        match self {
+            FileWriter::Mapped(map) => map[offset..offset + bytes.len()].copy_from_slice(bytes),
+            FileWriter::Buffered { pool, .. } => pool.write_at(offset as u64, bytes)?,
+            FileWriter::ReadOnly { .. } => return Err(DBError::TxnReadOnly),
+        }
+        Ok(())
+    }
+
+    /// Syncs the `(offset, len)` ranges written to stable storage.
+    pub fn flush(&self, ranges: &[(usize, usize)]) -> Result<(), DBError> {
+        match self {
+            FileWriter::Mapped(map) => {
+                for &(offset, len) in ranges {
+                    map.flush_range(offset, len)?;
+                }
+            }
+            FileWriter::Buffered { pool, .. } if !ranges.is_empty() => pool.file.sync_data()?,
+            FileWriter::Buffered { .. } | FileWriter::ReadOnly { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Resizes `file`, the file written to, to `len`, mapping it again if
+    /// it's mapped; maps readers hold stay valid as long as it only grows.
+    pub fn resize(&mut self, file: &File, len: u64) -> Result<(), DBError> {
+        if let FileWriter::ReadOnly { .. } = self {
+            return Err(DBError::TxnReadOnly);
+        }
+        file.set_len(len)?;
+        match self {
+            FileWriter::Mapped(map) => *map = unsafe { MmapMut::map_mut(file)? },
+            FileWriter::Buffered { len: size, .. } => *size = len,
+            FileWriter::ReadOnly { .. } => unreachable!("refused above"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::Page;
+    use tempfile::tempfile;
+
+    const PAGE_SIZE: usize = DEFAULT_PAGE_SIZE;
+
+    fn page(pgno: Pgno, flags: PageFlag) -> Page {
+        let (lower, upper) = (PAGE_HEADER_SIZE as u16, PAGE_SIZE as u16);
+        Page::from(pgno, 0, flags, lower, upper, &[0; PAGE_SIZE - PAGE_HEADER_SIZE])
+    }
+
+    fn pool_of(pages: u64, capacity: usize) -> BufferPool {
+        let file = tempfile().unwrap();
+        for pgno in 0..pages {
+            let offset = pgno * PAGE_SIZE as u64;
+            file.write_all_at(page(pgno, PageFlag::ALIVE).as_bytes(), offset).unwrap();
+        }
+        BufferPool::new(file, PAGE_SIZE, capacity)
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let pool = pool_of(4, 2);
+        for pgno in [0, 1, 0, 2] {
+            pool.read(pgno).unwrap();
+        }
+        // 1 was used least recently, so 2 took its place
+        assert_eq!(pool.stat(), PageCacheStat { hits: 1, misses: 3, entries: 2 });
+        pool.read(0).unwrap();
+        pool.read(1).unwrap();
+        assert_eq!(pool.stat().hits, 2);
+        assert!(matches!(pool.read(4), Err(DBError::PageOutOfBounds { pgno: 4 })));
+
+        let uncached = pool_of(1, 0);
+        uncached.read(0).unwrap();
+        uncached.read(0).unwrap();
+        assert_eq!(uncached.stat(), PageCacheStat { hits: 0, misses: 2, entries: 0 });
+    }
+
+    #[test]
+    fn test_views_keep_what_they_read() {
+        let pool = Arc::new(pool_of(3, 1));
+        let view = PageFile::Buffered(Arc::clone(&pool)).view();
+        let first = view.page(PAGE_SIZE, 1).unwrap();
+        assert_eq!(first.get_pgno(), 1);
+
+        // a write drops the pool's copy, not the one the view borrowed
+        let branch = page(1, PageFlag::ALIVE | PageFlag::BRANCH);
+        pool.write_at(PAGE_SIZE as u64, branch.as_bytes()).unwrap();
+        view.page(PAGE_SIZE, 2).unwrap();
+        assert_eq!(first.get_flag(), PageFlag::ALIVE);
+        assert_eq!(pool.read(1).unwrap().as_ref(), branch.as_bytes());
+        assert_eq!(view.as_mmap().unwrap().len(), 3 * PAGE_SIZE);
+    }
+}
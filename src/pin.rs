@@ -0,0 +1,158 @@
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use crate::btree::PageSource;
+use crate::constants::*;
+use crate::data_page::DataPage;
+use crate::meta::NUM_META_PAGES;
+use crate::txn::Snapshot;
+
+/// A key range with owned bounds, as `Env::pin_range` records it.
+pub type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+pub fn key_range<'k>(range: &impl RangeBounds<&'k [u8]>) -> KeyRange {
+    let own = |bound: Bound<&&[u8]>| bound.map(|key| key.to_vec());
+    (own(range.start_bound()), own(range.end_bound()))
+}
+
+// one pinned range, with the map its pages were locked through; holding the
+// map keeps it, and so its locks, alive after newer commits replace it
+struct Pin {
+    range: KeyRange,
+    mmap: Arc<Mmap>,
+    os_pages: Vec<usize>,
+}
+
+/// The ranges pinned with `Env::pin_range`. Memory locks don't nest, and a
+/// database page can share an OS page with its neighbours, so every locked OS
+/// page is counted and only unlocked once no pin in the same map covers it.
+#[derive(Default)]
+pub struct PinTable {
+    pins: Vec<Pin>,
+    // (map address, OS page in the map) -> number of pins covering it
+    locked: HashMap<(usize, usize), usize>,
+}
+
+impl PinTable {
+    pub fn new() -> Self {
+        PinTable::default()
+    }
+
+    /// Locks `pages` of `mmap` into memory for `range`. Either every page is
+    /// locked or, on failure, none of them.
+    pub fn pin(
+        &mut self,
+        range: KeyRange,
+        mmap: Arc<Mmap>,
+        page_size: usize,
+        pages: &[Pgno],
+    ) -> Result<(), DBError> {
+        let os_page_size = os_page_size();
+        let mut os_pages: Vec<usize> = pages
+            .iter()
+            .flat_map(|&pgno| {
+                let start = pgno as usize * page_size;
+                start / os_page_size..(start + page_size).div_ceil(os_page_size)
+            })
+            .collect();
+        os_pages.sort_unstable();
+        os_pages.dedup();
+
+        for (done, &os_page) in os_pages.iter().enumerate() {
+            if let Err(err) = self.lock(&mmap, os_page) {
+                for &os_page in &os_pages[..done] {
+                    self.unlock(&mmap, os_page)?;
+                }
+                return Err(err);
+            }
+        }
+        self.pins.push(Pin { range, mmap, os_pages });
+        Ok(())
+    }
+
+    /// Releases the most recent pin of `range`, and returns whether there was
+    /// one.
+    pub fn unpin(&mut self, range: &KeyRange) -> Result<bool, DBError> {
+        let Some(idx) = self.pins.iter().rposition(|pin| pin.range == *range) else {
+            return Ok(false);
+        };
+        let pin = self.pins.remove(idx);
+        for &os_page in &pin.os_pages {
+            self.unlock(&pin.mmap, os_page)?;
+        }
+        Ok(true)
+    }
+
+    fn lock(&mut self, mmap: &Mmap, os_page: usize) -> Result<(), DBError> {
+        let key = (mmap.as_ptr() as usize, os_page);
+        if !self.locked.contains_key(&key) {
+            let size = os_page_size();
+            // the page lies within the map, which stays mapped while a pin
+            // holds it
+            if unsafe { libc::mlock(mmap.as_ptr().add(os_page * size).cast(), size) } != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+        *self.locked.entry(key).or_insert(0) += 1;
+        Ok(())
+    }
+
+    fn unlock(&mut self, mmap: &Mmap, os_page: usize) -> Result<(), DBError> {
+        let key = (mmap.as_ptr() as usize, os_page);
+        let count = self.locked.get_mut(&key).expect("unlocked pages are locked first");
+        *count -= 1;
+        if *count == 0 {
+            self.locked.remove(&key);
+            let size = os_page_size();
+            if unsafe { libc::munlock(mmap.as_ptr().add(os_page * size).cast(), size) } != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A page of one commit, from `Env::pin_page`, for embedders reading pages
+/// themselves. The handle holds a snapshot of the commit, shared by its
+/// clones, so until the last of them is dropped the commit's pages stay as
+/// they are: compaction waits for it before giving page numbers out again,
+/// as it does for any reader. Unlike `Env::pin_range`, nothing is locked
+/// into memory.
+#[derive(Clone)]
+pub struct PinnedPage {
+    snapshot: Snapshot,
+    pgno: Pgno,
+}
+
+impl PinnedPage {
+    /// Fails with `PageOutOfBounds` unless `pgno` is a page of the commit
+    /// past the meta pages.
+    pub fn new(snapshot: Snapshot, pgno: Pgno) -> Result<Self, DBError> {
+        if !(NUM_META_PAGES..snapshot.get_meta().get_next_pgno()).contains(&pgno) {
+            return Err(DBError::PageOutOfBounds { pgno });
+        }
+        // read once, so a page that doesn't parse fails here
+        snapshot.get_page(pgno)?;
+        Ok(PinnedPage { snapshot, pgno })
+    }
+
+    pub const fn get_pgno(&self) -> Pgno {
+        self.pgno
+    }
+
+    /// The commit the page belongs to.
+    pub fn get_txnid(&self) -> TxnId {
+        self.snapshot.get_meta().get_txnid()
+    }
+
+    pub fn page(&self) -> Result<DataPage<'_>, DBError> {
+        self.snapshot.get_page(self.pgno)
+    }
+}
+
+fn os_page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
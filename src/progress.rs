@@ -0,0 +1,56 @@
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+use crate::constants::*;
+
+/// How often an operation waiting for readers checks on them.
+pub const READER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a maintenance operation is doing; see `Progress`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stage {
+    /// Checking pages, counted against the pages the commit has allocated,
+    /// which is more than it reaches once older commits have left some
+    /// behind.
+    Checking,
+    /// Copying the pages the current commit reaches, each of them twice, as
+    /// `Env::compact` does.
+    Copying,
+    /// Waiting, without counting anything, for read transactions of commits
+    /// whose pages are about to be overwritten or cut off.
+    WaitingForReaders,
+    /// Writing the entries salvaged from a damaged tree into a new one.
+    Rebuilding,
+}
+
+/// How far a long-running maintenance operation has got: `done` units of
+/// work out of `total` in its current stage.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Progress {
+    pub stage: Stage,
+    pub done: u64,
+    pub total: u64,
+}
+
+/// Told about progress after each unit of work (and while waiting, every
+/// `READER_POLL_INTERVAL`); returning `ControlFlow::Break` cancels the
+/// operation, which then fails with `Cancelled`. Each operation documents what
+/// a cancellation leaves behind.
+pub type ProgressFn<'a> = dyn FnMut(Progress) -> ControlFlow<()> + 'a;
+
+/// A `ProgressFn` that lets the operation run to the end.
+pub fn no_progress(_: Progress) -> ControlFlow<()> {
+    ControlFlow::Continue(())
+}
+
+pub fn report(
+    progress: &mut ProgressFn,
+    stage: Stage,
+    done: u64,
+    total: u64,
+) -> Result<(), DBError> {
+    match progress(Progress { stage, done, total }) {
+        ControlFlow::Continue(()) => Ok(()),
+        ControlFlow::Break(()) => Err(DBError::Cancelled),
+    }
+}
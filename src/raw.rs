@@ -0,0 +1,328 @@
+use std::borrow::Cow;
+
+use crate::constants::*;
+use crate::data_page::{DataPage, Offsets};
+use crate::meta::{read_format, Meta, META_VERSION};
+use crate::page::PageRef;
+
+/// The file format the types here read, and the only one: every type is
+/// tagged with it, and anything recorded as another version is refused
+/// rather than misread. It changes only when the format does, and with it
+/// whatever it takes to read the new format; tools built on this module
+/// otherwise keep working from release to release.
+pub const RAW_FORMAT_VERSION: u16 = META_VERSION;
+
+/// Read-only access to the pages of a file, for tools that analyze, export
+/// or recover files outside of an `Env`, e.g. over a map of a copy. Nothing
+/// is cached or trusted: every page is parsed when it is asked for.
+#[derive(Clone, Copy)]
+pub struct RawFile<'a> {
+    bytes: &'a [u8],
+    page_size: usize,
+}
+
+impl<'a> RawFile<'a> {
+    /// Fails with `VersionMismatch` if `bytes` is a file of any version but
+    /// `RAW_FORMAT_VERSION`; `migrate::upgrade_file` brings older ones up to
+    /// date.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, DBError> {
+        let (version, page_size) = read_format(bytes)?;
+        check_version(version)?;
+        Ok(RawFile { bytes, page_size })
+    }
+
+    pub const fn get_version(&self) -> u16 {
+        RAW_FORMAT_VERSION
+    }
+
+    pub const fn get_page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Whole pages in the file; a partial page at its end isn't counted.
+    pub const fn num_pages(&self) -> Pgno {
+        (self.bytes.len() / self.page_size) as Pgno
+    }
+
+    /// Meta of the most recent commit, the one `Env::open` would pick.
+    pub fn meta(&self) -> Result<Meta, DBError> {
+        Meta::read(self.bytes)
+    }
+
+    pub fn page(&self, pgno: Pgno) -> Result<RawPage<'a>, DBError> {
+        let page = PageRef::from_mmap(self.bytes, self.page_size, pgno as usize)?;
+        Ok(RawPage::new(page))
+    }
+
+    /// Every page in the file in order, each with the error it fails to
+    /// parse with, if it does.
+    pub fn pages(&self) -> impl Iterator<Item = (Pgno, Result<RawPage<'a>, DBError>)> + 'a {
+        let file = *self;
+        (0..file.num_pages()).map(move |pgno| (pgno, file.page(pgno)))
+    }
+}
+
+fn check_version(version: u16) -> Result<(), DBError> {
+    if version != RAW_FORMAT_VERSION {
+        return Err(DBError::VersionMismatch {
+            expected: RAW_FORMAT_VERSION as u32,
+            found: version as u32,
+        });
+    }
+    Ok(())
+}
+
+/// What a page holds, going by its flags.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PageKind {
+    Meta,
+    Branch,
+    Leaf,
+}
+
+/// One page, with its header parsed; its body is only parsed when asked for.
+#[derive(Clone, Copy)]
+pub struct RawPage<'a> {
+    page: PageRef<'a>,
+}
+
+impl<'a> RawPage<'a> {
+    fn new(page: PageRef<'a>) -> Self {
+        RawPage { page }
+    }
+
+    /// A page image on its own, such as one salvaged from a damaged file,
+    /// which should be page `pgno` of a file of format `version`.
+    pub fn from_image(bytes: &'a [u8], pgno: Pgno, version: u16) -> Result<Self, DBError> {
+        check_version(version)?;
+        Ok(RawPage::new(PageRef::from_image(bytes, pgno as usize)?))
+    }
+
+    pub const fn get_version(&self) -> u16 {
+        RAW_FORMAT_VERSION
+    }
+
+    pub fn get_pgno(&self) -> Pgno {
+        self.page.get_pgno()
+    }
+
+    pub fn get_kind(&self) -> PageKind {
+        let flags = self.page.get_flag();
+        if flags.contains(PageFlag::META) {
+            PageKind::Meta
+        } else if flags.contains(PageFlag::BRANCH) {
+            PageKind::Branch
+        } else {
+            PageKind::Leaf
+        }
+    }
+
+    pub fn get_flags(&self) -> PageFlag {
+        self.page.get_flag()
+    }
+
+    /// Where the node offsets end and the free space begins.
+    pub fn get_lower(&self) -> u16 {
+        self.page.get_lower()
+    }
+
+    /// Where the free space ends and the nodes begin.
+    pub fn get_upper(&self) -> u16 {
+        self.page.get_upper()
+    }
+
+    pub fn get_checksum(&self) -> u32 {
+        self.page.get_checksum()
+    }
+
+    pub fn checksum_matches(&self) -> bool {
+        self.page.verify_checksum().is_ok()
+    }
+
+    /// The whole page image, header included.
+    pub const fn as_bytes(&self) -> &'a [u8] {
+        self.page.as_bytes()
+    }
+
+    /// The meta a meta page records.
+    pub fn meta(&self) -> Result<Meta, DBError> {
+        Meta::from(self.page)
+    }
+
+    /// Offsets of the nodes of a branch or leaf page, in key order.
+    pub fn offsets(&self) -> Result<Offsets<'a>, DBError> {
+        Ok(self.data_page()?.get_offsets())
+    }
+
+    /// The key prefix every node of a branch or leaf page shares, which the
+    /// page stores once.
+    pub fn prefix(&self) -> Result<&'a [u8], DBError> {
+        Ok(self.data_page()?.get_prefix())
+    }
+
+    /// The nodes of a branch or leaf page in key order, soft-deleted ones
+    /// included; one that doesn't parse leaves the rest readable.
+    pub fn nodes(&self) -> Result<RawNodes<'a>, DBError> {
+        Ok(RawNodes {
+            page: self.data_page()?,
+            idx: 0,
+        })
+    }
+
+    fn data_page(&self) -> Result<DataPage<'a>, DBError> {
+        match self.get_kind() {
+            PageKind::Branch | PageKind::Leaf => DataPage::from(self.page),
+            PageKind::Meta => Err(DBError::CorruptPage {
+                pgno: self.get_pgno(),
+                reason: "not a branch or leaf page",
+            }),
+        }
+    }
+}
+
+/// A node of a branch or leaf page.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RawNode<'a> {
+    offset: u16,
+    flags: NodeFlag,
+    key: Cow<'a, [u8]>,
+    data: &'a [u8],
+    branch: bool,
+}
+
+impl<'a> RawNode<'a> {
+    /// Where in the page's data area the node starts.
+    pub const fn get_offset(&self) -> u16 {
+        self.offset
+    }
+
+    pub const fn get_flags(&self) -> NodeFlag {
+        self.flags
+    }
+
+    pub const fn is_alive(&self) -> bool {
+        self.flags.contains(NodeFlag::ALIVE)
+    }
+
+    /// The whole key, the page's prefix put back.
+    pub fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub const fn get_data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The child page a branch node points to; `None` on a leaf, or if the
+    /// data isn't a child pointer.
+    pub fn get_child(&self) -> Option<Pgno> {
+        self.read_child().map(|(pgno, _)| pgno)
+    }
+
+    /// The number of entries under the child a branch node points to, as of
+    /// the commit it was written in; `None` like `get_child`.
+    pub fn get_entries(&self) -> Option<u64> {
+        self.read_child().map(|(_, entries)| entries)
+    }
+
+    // a page number (u64) followed by an entry count (u64)
+    fn read_child(&self) -> Option<(Pgno, u64)> {
+        let child: &[u8; 16] = self.data.try_into().ok().filter(|_| self.branch)?;
+        let (pgno, entries) = child.split_at(8);
+        Some((
+            Pgno::from_le_bytes(pgno.try_into().unwrap()),
+            u64::from_le_bytes(entries.try_into().unwrap()),
+        ))
+    }
+}
+
+/// The nodes from `RawPage::nodes`.
+pub struct RawNodes<'a> {
+    page: DataPage<'a>,
+    idx: usize,
+}
+
+impl<'a> Iterator for RawNodes<'a> {
+    type Item = Result<RawNode<'a>, DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.page.get_offsets().get(self.idx)?;
+        let node = self.page.read_node(self.idx);
+        self.idx += 1;
+        Some(node.map(|node| RawNode {
+            offset,
+            flags: node.get_flags(),
+            key: node.get_key(),
+            data: node.get_data(),
+            branch: self.page.get_flags().contains(PageFlag::BRANCH),
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.page.num_nodes() - self.idx;
+        (left, Some(left))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::EnvOptions;
+    use crate::meta::NUM_META_PAGES;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_walk_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let entries = (0..2000u32).map(|i| (i.to_be_bytes(), *b"value"));
+        let env = EnvOptions::new().bulk_load(&path, entries).unwrap();
+        let mut txn = env.begin_write();
+        txn.delete(&0u32.to_be_bytes()).unwrap();
+        txn.commit().unwrap();
+        let root = env.get_meta().get_root().unwrap();
+        drop(env);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let file = RawFile::new(&bytes).unwrap();
+        assert_eq!(file.get_version(), RAW_FORMAT_VERSION);
+        assert_eq!(file.meta().unwrap().get_root(), Some(root));
+        assert_eq!(file.page(0).unwrap().get_kind(), PageKind::Meta);
+
+        // walk the tree from the root, as a tool that knows the layout would
+        let (mut pending, mut leaves, mut alive) = (vec![root], 0, 0);
+        while let Some(pgno) = pending.pop() {
+            let page = file.page(pgno).unwrap();
+            assert!(page.checksum_matches());
+            let nodes: Vec<_> = page.nodes().unwrap().map(Result::unwrap).collect();
+            assert_eq!(nodes.len(), page.offsets().unwrap().len());
+            match page.get_kind() {
+                PageKind::Branch => {
+                    pending.extend(nodes.iter().map(|n| n.get_child().unwrap()));
+                    let entries: u64 = nodes.iter().map(|n| n.get_entries().unwrap()).sum();
+                    assert!(pgno != root || entries == 1999);
+                }
+                PageKind::Leaf => {
+                    leaves += 1;
+                    alive += nodes.iter().filter(|node| node.is_alive()).count();
+                    assert!(nodes.iter().all(|node| node.get_child().is_none()));
+                }
+                kind => panic!("unexpected {:?} page in the tree", kind),
+            }
+        }
+        assert!(leaves > 1);
+        assert_eq!(alive, 1999);
+        let parsed = file.pages().filter(|(_, page)| page.is_ok()).count() as Pgno;
+        assert_eq!(parsed, file.num_pages());
+        assert!(file.page(0).unwrap().nodes().is_err());
+        assert!(file.page(NUM_META_PAGES).unwrap().meta().is_err());
+
+        let image = file.page(root).unwrap().as_bytes();
+        assert!(RawPage::from_image(image, root, RAW_FORMAT_VERSION).is_ok());
+        assert!(matches!(
+            RawPage::from_image(image, root, RAW_FORMAT_VERSION - 1),
+            Err(DBError::VersionMismatch { found: 5, .. })
+        ));
+        assert!(RawPage::from_image(image, root + 1, RAW_FORMAT_VERSION).is_err());
+    }
+}
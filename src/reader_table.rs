@@ -0,0 +1,339 @@
+use memmap2::MmapMut;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::constants::*;
+use crate::events::{Event, EventBus};
+
+// Lock file layout: magic (8 bytes) + number of slots (u32) + pad (u32),
+// followed by the slots. Each slot is the pid of the process that holds it
+// (u32, 0 when free) + what holds it (u32: READER or SNAPSHOT) + the txnid
+// its reader is looking at (u64). The kind took the place of padding, which
+// older builds left at 0, so their slots read as plain readers.
+// The file is shared by every process that opens the environment, and the
+// slots are only ever accessed through atomics. A free slot's txnid is kept at
+// 0, so a slot caught halfway through being claimed or released only ever
+// looks older than it is.
+const LOCK_MAGIC: &[u8; 8] = b"MMDBLOCK";
+const HEADER_SIZE: usize = 16;
+const SLOT_SIZE: usize = 16;
+
+// slot kinds
+const READER: u32 = 0;
+const SNAPSHOT: u32 = 1;
+
+pub const DEFAULT_MAX_READERS: usize = 126;
+
+/// The snapshot each open read transaction is looking at, across every
+/// process using the file. A writer may only reuse pages freed by commits
+/// older than `oldest_reader`.
+pub struct ReaderTable {
+    map: MmapMut,
+    num_slots: usize,
+    pid: u32,
+    events: Arc<EventBus>,
+    // None for slots kept in memory, see `open_read_only`
+    _file: Option<File>,
+}
+
+impl ReaderTable {
+    /// Opens the lock file at `path`, creating it with `max_readers` slots if
+    /// it doesn't exist yet; an existing file keeps its own slot count.
+    pub fn open(path: &Path, max_readers: usize) -> Result<Self, DBError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Self::from_file(file, max_readers)
+    }
+
+    /// Like `open`, for an environment opened read-only: joins the lock file
+    /// at `path` if it exists and can be written, so that writers see its
+    /// readers, but never creates it. Otherwise, as on read-only media, the
+    /// slots are kept in memory, where only this process sees them; a writer
+    /// that later opens the file doesn't see them either, which only matters
+    /// to compaction.
+    pub fn open_read_only(path: &Path, max_readers: usize) -> Result<Self, DBError> {
+        let file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::NotFound
+                        | io::ErrorKind::PermissionDenied
+                        | io::ErrorKind::ReadOnlyFilesystem
+                ) =>
+            {
+                let mut map = MmapMut::map_anon(HEADER_SIZE + max_readers * SLOT_SIZE)?;
+                map[..8].copy_from_slice(LOCK_MAGIC);
+                return Ok(ReaderTable {
+                    map,
+                    num_slots: max_readers,
+                    pid: std::process::id(),
+                    events: Arc::default(),
+                    _file: None,
+                });
+            }
+            Err(err) => return Err(err.into()),
+        };
+        Self::from_file(file, max_readers)
+    }
+
+    fn from_file(file: File, max_readers: usize) -> Result<Self, DBError> {
+        // whoever gets the lock first sets the file up; everyone else waits
+        // for it so they never see a half-written header
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let init = Self::init_file(&file, max_readers);
+        unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+        let num_slots = init?;
+
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        if map.len() < HEADER_SIZE + num_slots * SLOT_SIZE {
+            return Err(DBError::CorruptValue {
+                reason: "lock file is too short",
+            });
+        }
+        Ok(ReaderTable {
+            map,
+            num_slots,
+            pid: std::process::id(),
+            events: Arc::default(),
+            _file: Some(file),
+        })
+    }
+
+    /// Reports reclaimed slots to `events`.
+    pub fn with_events(mut self, events: Arc<EventBus>) -> Self {
+        self.events = events;
+        self
+    }
+
+    fn init_file(file: &File, max_readers: usize) -> Result<usize, DBError> {
+        let mut header = [0u8; HEADER_SIZE];
+        if file.metadata()?.len() == 0 {
+            header[..8].copy_from_slice(LOCK_MAGIC);
+            header[8..12].copy_from_slice(&(max_readers as u32).to_le_bytes());
+            file.set_len((HEADER_SIZE + max_readers * SLOT_SIZE) as u64)?;
+            file.write_all_at(&header, 0)?;
+            return Ok(max_readers);
+        }
+        file.read_exact_at(&mut header, 0)?;
+        if &header[..8] != LOCK_MAGIC {
+            return Err(DBError::CorruptValue {
+                reason: "malformed lock file",
+            });
+        }
+        Ok(u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize)
+    }
+
+    pub const fn get_max_readers(&self) -> usize {
+        self.num_slots
+    }
+
+    fn slot(&self, idx: usize) -> (&AtomicU32, &AtomicU64) {
+        let (pid, _, txnid) = self.slot_fields(idx);
+        (pid, txnid)
+    }
+
+    fn slot_fields(&self, idx: usize) -> (&AtomicU32, &AtomicU32, &AtomicU64) {
+        assert!(idx < self.num_slots);
+        // the map is never resized or moved, and every slot is 8-byte aligned
+        // since the map itself is page aligned
+        unsafe {
+            let slot = self.map.as_ptr().add(HEADER_SIZE + idx * SLOT_SIZE);
+            (
+                AtomicU32::from_ptr(slot as *mut u32),
+                AtomicU32::from_ptr(slot.add(4) as *mut u32),
+                AtomicU64::from_ptr(slot.add(8) as *mut u64),
+            )
+        }
+    }
+
+    /// Records a reader of `txnid` in a free slot, until the returned slot is
+    /// dropped. Fails with `ReadersFull` if every slot is taken, even after
+    /// reclaiming those of processes that have died.
+    pub fn register(self: &Arc<Self>, txnid: TxnId) -> Result<ReaderSlot, DBError> {
+        self.claim(txnid, READER)
+    }
+
+    /// Like `register`, for a snapshot: a reader that may be held for a long
+    /// time, which `oldest_snapshot` tells apart from read transactions.
+    pub fn register_snapshot(self: &Arc<Self>, txnid: TxnId) -> Result<ReaderSlot, DBError> {
+        self.claim(txnid, SNAPSHOT)
+    }
+
+    fn claim(self: &Arc<Self>, txnid: TxnId, kind: u32) -> Result<ReaderSlot, DBError> {
+        for attempt in 0..2 {
+            for idx in 0..self.num_slots {
+                let (pid, slot_kind, slot_txnid) = self.slot_fields(idx);
+                if pid.compare_exchange(0, self.pid, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                    slot_kind.store(kind, Ordering::Release);
+                    slot_txnid.store(txnid, Ordering::Release);
+                    return Ok(ReaderSlot {
+                        table: Arc::clone(self),
+                        idx,
+                    });
+                }
+            }
+            if attempt == 0 && self.clear_stale() == 0 {
+                break;
+            }
+        }
+        Err(DBError::ReadersFull { max: self.num_slots })
+    }
+
+    /// The oldest commit any registered reader is looking at, snapshots
+    /// included.
+    pub fn oldest_reader(&self) -> Option<TxnId> {
+        self.held(|_| true).into_iter().min()
+    }
+
+    /// The oldest commit a snapshot is holding on to.
+    pub fn oldest_snapshot(&self) -> Option<TxnId> {
+        self.held(|kind| kind == SNAPSHOT).into_iter().min()
+    }
+
+    /// Snapshots registered, in this or any other process.
+    pub fn num_snapshots(&self) -> usize {
+        self.held(|kind| kind == SNAPSHOT).len()
+    }
+
+    // the txnids of the taken slots whose kind passes `filter`
+    fn held(&self, filter: impl Fn(u32) -> bool) -> Vec<TxnId> {
+        (0..self.num_slots)
+            .filter_map(|idx| {
+                let (pid, kind, txnid) = self.slot_fields(idx);
+                let taken = pid.load(Ordering::Acquire) != 0;
+                let wanted = taken && filter(kind.load(Ordering::Acquire));
+                wanted.then(|| txnid.load(Ordering::Acquire))
+            })
+            .collect()
+    }
+
+    /// Frees the slots left behind by processes that exited without releasing
+    /// them, and returns how many there were.
+    pub fn clear_stale(&self) -> usize {
+        let mut cleared = 0;
+        for idx in 0..self.num_slots {
+            let (pid, txnid) = self.slot(idx);
+            let holder = pid.load(Ordering::Acquire);
+            if holder == 0 || holder == self.pid || process_alive(holder) {
+                continue;
+            }
+            let reading = txnid.swap(0, Ordering::AcqRel);
+            self.slot_fields(idx).1.store(READER, Ordering::Release);
+            if pid.compare_exchange(holder, 0, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                self.events.emit(Event::ReaderEvicted { pid: holder, txnid: reading });
+                cleared += 1;
+            }
+        }
+        cleared
+    }
+}
+
+fn process_alive(pid: u32) -> bool {
+    // signal 0 only checks whether the process exists; EPERM means it does but
+    // belongs to someone else
+    let alive = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// A reader's entry in a `ReaderTable`, released on drop.
+pub struct ReaderSlot {
+    table: Arc<ReaderTable>,
+    idx: usize,
+}
+
+impl ReaderSlot {
+    pub fn get_txnid(&self) -> TxnId {
+        self.table.slot(self.idx).1.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for ReaderSlot {
+    fn drop(&mut self) {
+        let (pid, kind, txnid) = self.table.slot_fields(self.idx);
+        txnid.store(0, Ordering::Release);
+        kind.store(READER, Ordering::Release);
+        pid.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_register_and_release() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("lock");
+        let table = Arc::new(ReaderTable::open(&path, 2).unwrap());
+        assert_eq!(table.oldest_reader(), None);
+
+        let first = table.register(5).unwrap();
+        let second = table.register(3).unwrap();
+        assert_eq!(table.oldest_reader(), Some(3));
+        assert!(matches!(table.register(7), Err(DBError::ReadersFull { max: 2 })));
+        drop(second);
+        assert_eq!(table.oldest_reader(), Some(5));
+        let _third = table.register(7).unwrap();
+        assert_eq!(first.get_txnid(), 5);
+
+        // a second open of the same file shares the slots, whatever it asks for
+        let other = ReaderTable::open(&path, 10).unwrap();
+        assert_eq!(other.get_max_readers(), 2);
+        assert_eq!(other.oldest_reader(), Some(5));
+    }
+
+    #[test]
+    fn test_snapshots_counted_apart() {
+        let dir = tempdir().unwrap();
+        let table = Arc::new(ReaderTable::open(&dir.path().join("lock"), 3).unwrap());
+        let reader = table.register(2).unwrap();
+        let snapshot = table.register_snapshot(4).unwrap();
+        assert_eq!((table.oldest_reader(), table.oldest_snapshot()), (Some(2), Some(4)));
+        assert_eq!(table.num_snapshots(), 1);
+
+        // a released snapshot's slot goes back to being an ordinary one
+        drop(snapshot);
+        assert_eq!(table.num_snapshots(), 0);
+        let _reader = table.register(6).unwrap();
+        assert_eq!(table.oldest_snapshot(), None);
+        drop(reader);
+        assert_eq!(table.oldest_reader(), Some(6));
+    }
+
+    #[test]
+    fn test_clear_stale() {
+        let dir = tempdir().unwrap();
+        let events = Arc::new(EventBus::default());
+        let evicted = events.subscribe_channel();
+        let table = ReaderTable::open(&dir.path().join("lock"), 4).unwrap();
+        let table = Arc::new(table.with_events(events));
+        let _live = table.register(1).unwrap();
+
+        // a slot held by a process that has since exited
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+        let (pid, txnid) = table.slot(3);
+        pid.store(dead_pid, Ordering::Release);
+        txnid.store(0, Ordering::Release);
+
+        assert_eq!(table.oldest_reader(), Some(0));
+        assert_eq!(table.clear_stale(), 1);
+        assert_eq!(table.oldest_reader(), Some(1));
+        let event = evicted.try_recv().unwrap();
+        assert_eq!(event, Event::ReaderEvicted { pid: dead_pid, txnid: 0 });
+    }
+}
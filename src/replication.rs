@@ -0,0 +1,198 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::ops::Bound;
+
+use crate::btree::BTree;
+use crate::constants::*;
+use crate::env::Env;
+use crate::page_changes::RecordedCommit;
+use crate::pin::KeyRange;
+use crate::reader_table::ReaderSlot;
+
+/// A key a commit changed, from a `ChangeStream`: its value before the
+/// commit and after it, None where it wasn't there.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Change {
+    pub txnid: TxnId,
+    pub key: Vec<u8>,
+    pub old: Option<Vec<u8>>,
+    pub new: Option<Vec<u8>>,
+}
+
+/// The changes of one commit after another, from `Env::subscribe_changes`,
+/// for keeping a replica or a cache in step with the environment. As an
+/// iterator, it waits for the next commit once it has handed out the
+/// changes of every commit so far, so it only ends if it fails.
+pub struct ChangeStream<'env> {
+    env: &'env Env,
+    // the last commit whose changes were read, which the slot holds
+    txnid: TxnId,
+    _slot: ReaderSlot,
+    pending: VecDeque<Change>,
+}
+
+impl<'env> ChangeStream<'env> {
+    pub(crate) fn new(env: &'env Env, txnid: TxnId, slot: ReaderSlot) -> Self {
+        ChangeStream { env, txnid, _slot: slot, pending: VecDeque::new() }
+    }
+
+    /// Like `next`, but returns None rather than waiting once the changes of
+    /// every commit so far are handed out.
+    pub fn try_next(&mut self) -> Option<Result<Change, DBError>> {
+        self.advance(false).transpose()
+    }
+
+    fn advance(&mut self, wait: bool) -> Result<Option<Change>, DBError> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Ok(Some(change));
+            }
+            let (commit, next) = self.env.next_recorded(self.txnid, wait)?;
+            let Some(next) = next else {
+                return Ok(None);
+            };
+            // taken before the slot on `commit` is let go
+            let slot = self.env.get_readers().register_snapshot(next.txnid)?;
+            self.pending = commit_changes(self.env, commit, next)?.into();
+            (self.txnid, self._slot) = (next.txnid, slot);
+        }
+    }
+}
+
+impl Iterator for ChangeStream<'_> {
+    type Item = Result<Change, DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.advance(true).transpose()
+    }
+}
+
+// The changes `next` made to the tree of `commit`, from the ranges of the
+// leaves it wrote, read through a transaction on the current commit, which
+// reaches the pages of both.
+fn commit_changes(
+    env: &Env,
+    commit: RecordedCommit,
+    next: RecordedCommit,
+) -> Result<Vec<Change>, DBError> {
+    let txn = env.begin_read()?;
+    let order = txn.tree().get_order();
+    let (old, new) = (BTree::new(&txn, commit.root, order), BTree::new(&txn, next.root, order));
+    let mut changes = Vec::new();
+    let change = |key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>| Change {
+        txnid: next.txnid,
+        key: key.to_vec(),
+        old: old.map(<[u8]>::to_vec),
+        new: new.map(<[u8]>::to_vec),
+    };
+    for range in new.written_ranges(commit.next_pgno)? {
+        let (before, after) = (entries_in(&old, &range)?, entries_in(&new, &range)?);
+        let (mut before, mut after) = (before.iter().peekable(), after.iter().peekable());
+        loop {
+            let ordering = match (before.peek(), after.peek()) {
+                (Some((old_key, _)), Some((new_key, _))) => order.compare(old_key, new_key),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            match ordering {
+                Ordering::Less => {
+                    let (key, old) = before.next().unwrap();
+                    changes.push(change(key, Some(old), None));
+                }
+                Ordering::Greater => {
+                    let (key, new) = after.next().unwrap();
+                    changes.push(change(key, None, Some(new)));
+                }
+                Ordering::Equal => {
+                    let ((key, old), (_, new)) = (before.next().unwrap(), after.next().unwrap());
+                    if old != new {
+                        changes.push(change(key, Some(old), Some(new)));
+                    }
+                }
+            }
+        }
+    }
+    Ok(changes)
+}
+
+// owned, as a change holds them
+type OwnedEntry = (Vec<u8>, Vec<u8>);
+
+fn entries_in(tree: &BTree, (start, end): &KeyRange) -> Result<Vec<OwnedEntry>, DBError> {
+    let mut cursor = tree.cursor()?;
+    if let Bound::Included(start) = start {
+        cursor.seek(start)?;
+    }
+    let mut entries = Vec::new();
+    for entry in cursor {
+        let (key, value) = entry?;
+        if let Bound::Excluded(end) = end {
+            if tree.get_order().compare(&key, end).is_ge() {
+                break;
+            }
+        }
+        entries.push((key.into_owned(), value.to_vec()));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::EnvOptions;
+    use std::thread;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_change_stream() {
+        let dir = tempdir().unwrap();
+        let env = EnvOptions::new().page_size(MIN_PAGE_SIZE).open(dir.path().join("db")).unwrap();
+        let key = |i: u32| format!("key{i:05}").into_bytes();
+        let mut txn = env.begin_write();
+        txn.write_batch((0..2_000).map(|i| (key(i), [7; 40]))).unwrap();
+        txn.commit().unwrap();
+        let mut stream = env.subscribe_changes(env.get_meta().get_txnid()).unwrap();
+        assert!(stream.try_next().is_none());
+
+        let mut txn = env.begin_write();
+        txn.put(&key(1_500), b"new").unwrap();
+        txn.put(&key(5_000), b"added").unwrap();
+        txn.delete(&key(3)).unwrap();
+        // the same value again isn't a change
+        txn.put(&key(700), &[7; 40]).unwrap();
+        txn.commit().unwrap();
+        let change = |i, old: Option<&[u8]>, new: Option<&[u8]>| Change {
+            txnid: env.get_meta().get_txnid(),
+            key: key(i),
+            old: old.map(<[u8]>::to_vec),
+            new: new.map(<[u8]>::to_vec),
+        };
+        let expected = vec![
+            change(3, Some(&[7; 40]), None),
+            change(1_500, Some(&[7; 40]), Some(b"new")),
+            change(5_000, None, Some(b"added")),
+        ];
+        let changes: Vec<_> = std::iter::from_fn(|| stream.try_next()).collect();
+        assert_eq!(changes.into_iter().collect::<Result<Vec<_>, _>>().unwrap(), expected);
+        // a stream holds off compaction
+        assert!(matches!(env.compact(), Err(DBError::ReadersActive { .. })));
+
+        // waits for the next commit
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut txn = env.begin_write();
+                txn.clear();
+                txn.commit().unwrap();
+            });
+            let cleared: Vec<_> = stream.by_ref().take(2_000).map(Result::unwrap).collect();
+            assert!(cleared.iter().all(|change| change.new.is_none()));
+            assert_eq!(cleared[0], change(0, Some(&[7; 40]), None));
+        });
+        assert!(stream.try_next().is_none());
+        drop(stream);
+        let compacted = env.get_meta().get_txnid();
+        env.compact().unwrap();
+        assert!(matches!(env.subscribe_changes(compacted), Err(DBError::Unsupported { .. })));
+    }
+}
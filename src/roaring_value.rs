@@ -0,0 +1,120 @@
+use roaring::RoaringBitmap;
+
+use crate::constants::*;
+
+// operand tags, stored as the first byte of a merge operand
+const OP_UNION: u8 = 0;
+const OP_INTERSECT: u8 = 1;
+const OP_DIFFERENCE: u8 = 2;
+
+/// An update to a bitmap-valued entry, encoded as a merge operand so that the
+/// stored set can be updated by folding operands into it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BitmapOp {
+    Union(RoaringBitmap),
+    Intersect(RoaringBitmap),
+    Difference(RoaringBitmap),
+}
+
+pub fn encode_bitmap(bitmap: &RoaringBitmap) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(bitmap.serialized_size());
+    bitmap
+        .serialize_into(&mut buf)
+        .expect("writing to a Vec cannot fail");
+    buf
+}
+
+pub fn decode_bitmap(buf: &[u8]) -> Result<RoaringBitmap, DBError> {
+    RoaringBitmap::deserialize_from(buf).map_err(|_| DBError::CorruptValue {
+        reason: "malformed roaring bitmap",
+    })
+}
+
+impl BitmapOp {
+    pub fn insert(ids: impl IntoIterator<Item = u32>) -> Self {
+        BitmapOp::Union(ids.into_iter().collect())
+    }
+
+    pub fn remove(ids: impl IntoIterator<Item = u32>) -> Self {
+        BitmapOp::Difference(ids.into_iter().collect())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let (tag, bitmap) = match self {
+            BitmapOp::Union(bitmap) => (OP_UNION, bitmap),
+            BitmapOp::Intersect(bitmap) => (OP_INTERSECT, bitmap),
+            BitmapOp::Difference(bitmap) => (OP_DIFFERENCE, bitmap),
+        };
+        let mut buf = vec![tag];
+        buf.extend(encode_bitmap(bitmap));
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DBError> {
+        let (&tag, rest) = buf.split_first().ok_or(DBError::CorruptValue {
+            reason: "empty bitmap operand",
+        })?;
+        let bitmap = decode_bitmap(rest)?;
+        match tag {
+            OP_UNION => Ok(BitmapOp::Union(bitmap)),
+            OP_INTERSECT => Ok(BitmapOp::Intersect(bitmap)),
+            OP_DIFFERENCE => Ok(BitmapOp::Difference(bitmap)),
+            _ => Err(DBError::CorruptValue {
+                reason: "unrecognized bitmap operand",
+            }),
+        }
+    }
+
+    pub fn apply(&self, bitmap: &mut RoaringBitmap) {
+        match self {
+            BitmapOp::Union(other) => *bitmap |= other,
+            BitmapOp::Intersect(other) => *bitmap &= other,
+            BitmapOp::Difference(other) => *bitmap -= other,
+        }
+    }
+}
+
+/// Merge function for bitmap-valued entries: folds an encoded `BitmapOp`
+/// operand into the existing value, treating a missing value as the empty set.
+pub fn merge_bitmap(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operand: &[u8],
+) -> Result<Vec<u8>, DBError> {
+    let mut bitmap = match existing {
+        Some(buf) => decode_bitmap(buf)?,
+        None => RoaringBitmap::new(),
+    };
+    BitmapOp::decode(operand)?.apply(&mut bitmap);
+    Ok(encode_bitmap(&bitmap))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_ops() {
+        let value = merge_bitmap(b"k", None, &BitmapOp::insert(0..1_000_000).encode()).unwrap();
+        let value = merge_bitmap(b"k", Some(&value), &BitmapOp::remove(10..20).encode()).unwrap();
+        let mask: RoaringBitmap = (0..100).chain(2_000_000..2_000_010).collect();
+        let value =
+            merge_bitmap(b"k", Some(&value), &BitmapOp::Intersect(mask).encode()).unwrap();
+
+        let bitmap = decode_bitmap(&value).unwrap();
+        assert_eq!(bitmap.len(), 90);
+        assert!(bitmap.contains(9) && !bitmap.contains(10) && bitmap.contains(20));
+    }
+
+    #[test]
+    fn test_malformed_operand() {
+        assert!(matches!(
+            merge_bitmap(b"k", None, &[]),
+            Err(DBError::CorruptValue { .. })
+        ));
+        assert!(matches!(
+            merge_bitmap(b"k", None, &[9, 0, 0]),
+            Err(DBError::CorruptValue { .. })
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::constants::*;
+use crate::env::Env;
+
+// Value encoding: the expiration time (u64, little-endian, milliseconds since
+// the Unix epoch, 0 for never) followed by the value itself. Nothing in the
+// file marks a database as holding expiring values, so every value in it has
+// to be written this way, through `ExpiringDb` or `encode_expiring`.
+const EXPIRES_AT_SIZE: usize = 8;
+
+/// Where `ExpiringDb` reads the time from, in milliseconds since the Unix
+/// epoch.
+pub type Clock = fn() -> u64;
+
+/// The system clock as a `Clock`.
+pub fn system_clock() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_millis() as u64)
+}
+
+/// `value` stored so that it expires at `expires_at`, in milliseconds since
+/// the Unix epoch; `None` keeps it forever.
+pub fn encode_expiring(value: &[u8], expires_at: Option<u64>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(EXPIRES_AT_SIZE + value.len());
+    buf.extend_from_slice(&expires_at.unwrap_or(0).to_le_bytes());
+    buf.extend_from_slice(value);
+    buf
+}
+
+/// The expiration time and value of an entry written by `encode_expiring`.
+pub fn decode_expiring(bytes: &[u8]) -> Result<(Option<u64>, &[u8]), DBError> {
+    let Some((expires_at, value)) = bytes.split_first_chunk::<EXPIRES_AT_SIZE>() else {
+        return Err(DBError::CorruptValue {
+            reason: "expiring value shorter than its expiration time",
+        });
+    };
+    let expires_at = u64::from_le_bytes(*expires_at);
+    Ok(((expires_at != 0).then_some(expires_at), value))
+}
+
+fn is_expired(expires_at: Option<u64>, now: u64) -> bool {
+    expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+/// An environment used as a cache: every value carries an expiration time,
+/// reads treat expired entries as missing, and `purge_expired` removes them
+/// from the file. Until it runs, expired entries still take up space. Each
+/// call runs in its own transaction; use the encoding functions with a
+/// `WriteTxn` to group writes.
+pub struct ExpiringDb<'env> {
+    env: &'env Env,
+    clock: Clock,
+}
+
+impl<'env> ExpiringDb<'env> {
+    pub fn new(env: &'env Env) -> Self {
+        Self::with_clock(env, system_clock)
+    }
+
+    /// Reads the time from `clock` instead of the system clock.
+    pub fn with_clock(env: &'env Env, clock: Clock) -> Self {
+        ExpiringDb { env, clock }
+    }
+
+    /// The value of `key`, failing with `KeyNotFound` if it isn't there or
+    /// has expired.
+    pub fn get(&self, key: &[u8]) -> Result<Vec<u8>, DBError> {
+        let txn = self.env.begin_read()?;
+        let (expires_at, value) = decode_expiring(txn.get(key)?)?;
+        if is_expired(expires_at, (self.clock)()) {
+            return Err(DBError::KeyNotFound);
+        }
+        Ok(value.to_vec())
+    }
+
+    /// When `key` expires, `None` for never; fails like `get`.
+    pub fn get_expires_at(&self, key: &[u8]) -> Result<Option<u64>, DBError> {
+        let txn = self.env.begin_read()?;
+        let (expires_at, _) = decode_expiring(txn.get(key)?)?;
+        if is_expired(expires_at, (self.clock)()) {
+            return Err(DBError::KeyNotFound);
+        }
+        Ok(expires_at)
+    }
+
+    /// Stores `value` under `key` for `ttl` from now.
+    pub fn put(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<(), DBError> {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        // an expiration time of 0 would mean never
+        let expires_at = (self.clock)().saturating_add(ttl).max(1);
+        self.put_until(key, value, Some(expires_at))
+    }
+
+    /// Stores `value` under `key` until `expires_at`, or forever for `None`.
+    pub fn put_until(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<(), DBError> {
+        let mut txn = self.env.begin_write();
+        txn.put(key, &encode_expiring(value, expires_at))?;
+        txn.commit()
+    }
+
+    /// Removes `key`, failing with `KeyNotFound` if it isn't there; an
+    /// expired entry not yet purged is removed like any other.
+    pub fn delete(&self, key: &[u8]) -> Result<(), DBError> {
+        let mut txn = self.env.begin_write();
+        txn.delete(key)?;
+        txn.commit()
+    }
+
+    /// Deletes every entry that has expired, in a single commit, and returns
+    /// how many there were. Meant to be called now and then, from whichever
+    /// thread suits the application; nothing calls it on its own.
+    pub fn purge_expired(&self) -> Result<u64, DBError> {
+        let now = (self.clock)();
+        let mut txn = self.env.begin_write();
+        let mut expired = Vec::new();
+        for entry in txn.tree().cursor()? {
+            let (key, data) = entry?;
+            if is_expired(decode_expiring(data)?.0, now) {
+                expired.push(key.into_owned());
+            }
+        }
+        for key in &expired {
+            txn.delete(key)?;
+        }
+        txn.commit()?;
+        Ok(expired.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tempfile::tempdir;
+
+    static NOW: AtomicU64 = AtomicU64::new(1_000);
+
+    fn test_clock() -> u64 {
+        NOW.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn test_expiring_db() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let db = ExpiringDb::with_clock(&env, test_clock);
+        db.put(b"short", b"a", Duration::from_millis(10)).unwrap();
+        db.put(b"long", b"b", Duration::from_secs(10)).unwrap();
+        db.put_until(b"forever", b"c", None).unwrap();
+        assert_eq!(db.get(b"short").unwrap(), b"a");
+        assert_eq!(db.get_expires_at(b"long").unwrap(), Some(11_000));
+        assert_eq!(db.purge_expired().unwrap(), 0);
+
+        NOW.store(1_010, Ordering::Relaxed);
+        assert!(matches!(db.get(b"short"), Err(DBError::KeyNotFound)));
+        assert_eq!(db.get(b"long").unwrap(), b"b");
+        // still in the file until purged
+        assert!(env.begin_read().unwrap().get(b"short").is_ok());
+
+        NOW.store(u64::MAX, Ordering::Relaxed);
+        assert_eq!(db.purge_expired().unwrap(), 2);
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.tree().cursor().unwrap().count(), 1);
+        assert_eq!(db.get(b"forever").unwrap(), b"c");
+
+        assert!(matches!(decode_expiring(b"short"), Err(DBError::CorruptValue { .. })));
+    }
+}
@@ -0,0 +1,2623 @@
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs::File;
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
+use std::path::Path;
+use std::sync::{Arc, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::btree::{BTree, PageSource};
+use crate::btree_page::{self, child_data, BranchPage, Rebalanced};
+#[cfg(feature = "compression")]
+use crate::compress::{self, ValueArena};
+use crate::constants::*;
+use crate::cursor;
+use crate::data_page::{max_value_size, DataNode, DataPage, DirtyPage, SplitBias};
+use crate::env::Env;
+use crate::key_filter::KeyFilter;
+use crate::key_order::KeyOrder;
+use crate::meta::{Meta, NUM_META_PAGES};
+use crate::metrics::Metrics;
+use crate::page::PageRef;
+use crate::page_alloc::{DirtySet, PageAllocator};
+use crate::page_cache::{PageCache, ParsedBranch};
+use crate::page_io::FileView;
+use crate::reader_table::ReaderSlot;
+
+/// What reading a committed page checks on top of parsing its header, as the
+/// environment's options ask.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PageChecks {
+    /// See `EnvOptions::verify_checksums`.
+    pub checksums: bool,
+    /// See `EnvOptions::paranoid_reads`.
+    pub nodes: bool,
+}
+
+// pages the current commit references are never modified in place, so a view
+// taken for that commit stays valid for as long as it is held
+fn committed_page(
+    file: &FileView,
+    page_size: usize,
+    pgno: Pgno,
+    order: KeyOrder,
+    checks: PageChecks,
+) -> Result<DataPage<'_>, DBError> {
+    let page = match checks.checksums {
+        true => file.page_verified(page_size, pgno)?,
+        false => file.page(page_size, pgno)?,
+    };
+    // validated in the tree's order, as `DataPage::from_validated` would in
+    // byte order
+    let page = DataPage::from(page)?.with_order(order);
+    if checks.nodes {
+        page.validate()?;
+    }
+    Ok(page)
+}
+
+/// A consistent view of one commit.
+pub struct ReadTxn {
+    meta: Meta,
+    file: FileView,
+    order: KeyOrder,
+    checks: PageChecks,
+    cache: Arc<PageCache>,
+    key_filter: Option<Arc<KeyFilter>>,
+    metrics: Option<Arc<dyn Metrics>>,
+    #[cfg(feature = "compression")]
+    values: ValueArena,
+    _slot: ReaderSlot,
+}
+
+impl ReadTxn {
+    pub fn new(
+        meta: Meta,
+        file: FileView,
+        order: KeyOrder,
+        cache: Arc<PageCache>,
+        slot: ReaderSlot,
+    ) -> Self {
+        ReadTxn {
+            meta,
+            file,
+            order,
+            checks: PageChecks::default(),
+            cache,
+            key_filter: None,
+            metrics: None,
+            #[cfg(feature = "compression")]
+            values: ValueArena::default(),
+            _slot: slot,
+        }
+    }
+
+    /// Has `get` skip the tree for keys `key_filter`, which must hold every
+    /// key of this commit, rules out.
+    pub fn with_key_filter(mut self, key_filter: Option<Arc<KeyFilter>>) -> Self {
+        self.key_filter = key_filter;
+        self
+    }
+
+    /// Has every page read check what `checks` asks.
+    pub fn with_checks(mut self, checks: PageChecks) -> Self {
+        self.checks = checks;
+        self
+    }
+
+    /// Counts every page read in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub const fn get_meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    pub fn tree(&self) -> BTree<'_> {
+        BTree::new(self, self.meta.get_root(), self.order)
+    }
+
+    /// Number of live entries in this commit, read from its meta page.
+    pub const fn len(&self) -> u64 {
+        self.meta.get_entries()
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the whole file, mapped now if the environment doesn't map it
+    pub(crate) fn get_mmap(&self) -> Result<&Arc<Mmap>, DBError> {
+        self.file.as_mmap()
+    }
+
+    /// This commit's data pages as they are laid out in the file, from the
+    /// first one past the meta pages up to `next_pgno`. With
+    /// `IoBackend::Buffered`, maps the file for as long as the transaction.
+    pub fn data_pages(&self) -> Result<&[u8], DBError> {
+        let page_size = self.meta.get_page_size();
+        let end = self.meta.get_next_pgno() as usize * page_size;
+        Ok(&self.get_mmap()?[NUM_META_PAGES as usize * page_size..end])
+    }
+
+    /// The value is read in place from the map, so it can't outlive the
+    /// transaction: the pages behind it may be reused once no reader holds
+    /// this commit. See `get_owned` to keep it longer.
+    pub fn get<'txn>(&'txn self, key: &[u8]) -> Result<&'txn [u8], DBError> {
+        let Some(filter) = &self.key_filter else {
+            return self.tree().get(key);
+        };
+        if !filter.may_contain(key) {
+            return Err(DBError::KeyNotFound);
+        }
+        let found = self.tree().get(key);
+        if let Err(DBError::KeyNotFound) = found {
+            filter.record_false_positive();
+        }
+        found
+    }
+
+    pub fn get_owned(&self, key: &[u8]) -> Result<Vec<u8>, DBError> {
+        self.get(key).map(<[u8]>::to_vec)
+    }
+
+    /// See `BTree::contains_key`.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool, DBError> {
+        let Some(filter) = &self.key_filter else {
+            return self.tree().contains_key(key);
+        };
+        if !filter.may_contain(key) {
+            return Ok(false);
+        }
+        let found = self.tree().contains_key(key)?;
+        if !found {
+            filter.record_false_positive();
+        }
+        Ok(found)
+    }
+
+    /// See `BTree::get_many`. The key filter isn't consulted, as the keys
+    /// share their descents anyway.
+    pub fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<&[u8]>>, DBError> {
+        self.tree().get_many(keys)
+    }
+
+    /// See `BTree::last_key_value`.
+    pub fn last_key_value(&self) -> Result<Option<cursor::Entry<'_>>, DBError> {
+        self.tree().last_key_value()
+    }
+
+    /// See `BTree::scan_prefix`.
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+    ) -> Result<impl Iterator<Item = Result<cursor::Entry<'_>, DBError>> + '_, DBError> {
+        self.tree().scan_prefix(prefix)
+    }
+}
+
+impl PageSource for ReadTxn {
+    fn get_page(&self, pgno: Pgno) -> Result<DataPage<'_>, DBError> {
+        if let Some(metrics) = &self.metrics {
+            metrics.page_read();
+        }
+        committed_page(&self.file, self.meta.get_page_size(), pgno, self.order, self.checks)
+    }
+
+    #[cfg(feature = "compression")]
+    fn decompress(&self, data: &[u8]) -> Result<&[u8], DBError> {
+        Ok(self.values.hold(compress::decompress_value(data)?))
+    }
+
+    fn prefetch(&self, pgnos: &[Pgno]) {
+        self.file.prefetch(self.meta.get_page_size(), pgnos);
+    }
+
+    fn get_parsed_branch(&self, pgno: Pgno) -> Result<Option<Arc<ParsedBranch>>, DBError> {
+        self.cache.get_or_parse(pgno, || {
+            let page = self.get_page(pgno)?;
+            if !page.get_flags().contains(PageFlag::BRANCH) {
+                return Ok(None);
+            }
+            ParsedBranch::from(&BranchPage::from(page)?).map(Some)
+        })
+    }
+}
+
+/// A read transaction shared by however many threads, from `Env::snapshot`;
+/// its commit stays readable until the last clone is dropped.
+#[derive(Clone)]
+pub struct Snapshot {
+    txn: Arc<ReadTxn>,
+}
+
+impl Snapshot {
+    pub fn new(txn: ReadTxn) -> Self {
+        Snapshot { txn: Arc::new(txn) }
+    }
+}
+
+impl Deref for Snapshot {
+    type Target = ReadTxn;
+
+    fn deref(&self) -> &ReadTxn {
+        &self.txn
+    }
+}
+
+/// The single write transaction. Pages are copied on first write to new page
+/// numbers past the end of the file, so readers of the previous commit never
+/// see a change; nothing is written to the file until `commit`.
+pub struct WriteTxn<'env> {
+    env: &'env Env,
+    // None for an optimistic transaction until it commits
+    _writer: Option<MutexGuard<'env, ()>>,
+    base: Meta,
+    file: FileView,
+    order: KeyOrder,
+    root: Option<Pgno>,
+    alloc: PageAllocator,
+    dirty: DirtySet,
+    started: Instant,
+    keys_written: u64,
+    pages_copied: u64,
+    // live entries, this transaction's writes included
+    entries: u64,
+    // replaces the base's user metadata when set
+    user_meta: Option<Vec<u8>>,
+    // the last id `next_id` handed out
+    sequence: u64,
+    // the committed pages copied to be rewritten, or None once `clear` has
+    // left every one behind, for the optimistic transactions open alongside
+    replaced: Option<HashSet<Pgno>>,
+    optimistic: Option<Optimistic<'env>>,
+    #[cfg(feature = "compression")]
+    values: ValueArena,
+}
+
+// What an optimistic transaction keeps for committing over the commits made
+// since its base: the committed leaves it read, which none of them may have
+// replaced, and its writes, to make again on top of the last of them.
+struct Optimistic<'env> {
+    env: &'env Env,
+    base: TxnId,
+    _slot: ReaderSlot,
+    // the meta page's number stands for the sequence, once `next_id` is used
+    reads: RefCell<HashSet<Pgno>>,
+    redo: Vec<Redo>,
+}
+
+// a write of an optimistic transaction, with each value as it is stored
+enum Redo {
+    Put(Vec<u8>, Vec<u8>, NodeFlag),
+    Delete(Vec<u8>),
+    SoftDelete(Vec<u8>),
+    Undelete(Vec<u8>),
+    Clear,
+}
+
+impl Drop for Optimistic<'_> {
+    fn drop(&mut self) {
+        self.env.get_conflicts().end(self.base);
+    }
+}
+
+/// What a write transaction has done so far, from `WriteTxn::stats`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TxnStats {
+    /// Puts and deletes, counting a key again each time it is written.
+    pub keys_written: u64,
+    /// Pages of the previous commit copied to be written.
+    pub pages_copied: u64,
+    /// New page numbers taken past the end of the file, copies included.
+    pub pages_allocated: u64,
+    /// Bytes of dirty pages that `commit` will write.
+    pub bytes_pending: u64,
+    pub elapsed: Duration,
+}
+
+/// Whether a put added its key or replaced the value already under it, for
+/// callers keeping counts or enforcing uniqueness themselves.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PutOutcome {
+    Inserted,
+    Replaced,
+}
+
+// the branch pages leading to a leaf (as in `Descent::path`), and the leaf
+type LeafPath = (Vec<(Pgno, usize)>, Pgno);
+
+impl<'env> WriteTxn<'env> {
+    pub fn new(env: &'env Env, writer: MutexGuard<'env, ()>, base: Meta, file: FileView) -> Self {
+        Self::begin(env, Some(writer), base, file, None)
+    }
+
+    /// See `Env::begin_optimistic`, which has counted the transaction in its
+    /// conflict table; `slot` keeps `base` readable until it ends.
+    pub(crate) fn optimistic(env: &'env Env, base: Meta, file: FileView, slot: ReaderSlot) -> Self {
+        let optimistic = Optimistic {
+            env,
+            base: base.get_txnid(),
+            _slot: slot,
+            reads: RefCell::default(),
+            redo: Vec::new(),
+        };
+        Self::begin(env, None, base, file, Some(optimistic))
+    }
+
+    fn begin(
+        env: &'env Env,
+        writer: Option<MutexGuard<'env, ()>>,
+        base: Meta,
+        file: FileView,
+        optimistic: Option<Optimistic<'env>>,
+    ) -> Self {
+        WriteTxn {
+            env,
+            _writer: writer,
+            base,
+            file,
+            order: env.get_key_order(),
+            root: base.get_root(),
+            alloc: PageAllocator::new(base.get_next_pgno()),
+            dirty: DirtySet::new(),
+            started: Instant::now(),
+            keys_written: 0,
+            pages_copied: 0,
+            entries: base.get_entries(),
+            user_meta: None,
+            sequence: base.get_sequence(),
+            replaced: Some(HashSet::new()),
+            optimistic,
+            #[cfg(feature = "compression")]
+            values: ValueArena::default(),
+        }
+    }
+
+    // makes a write again when an optimistic transaction commits over others
+    fn redo(&mut self, redo: Redo) -> Result<(), DBError> {
+        match redo {
+            Redo::Put(key, data, node_flags) => {
+                self.check_writable()?;
+                self.put_checked(&key, &data, node_flags, None, false)?;
+            }
+            // `Conflict` should have caught the key going missing
+            Redo::Delete(key) => match self.delete(&key) {
+                Err(DBError::KeyNotFound) => {
+                    return Err(DBError::Conflict { txnid: self.base.get_txnid() })
+                }
+                result => result?,
+            },
+            Redo::SoftDelete(key) => self.soft_delete(&key)?,
+            Redo::Undelete(key) => self.undelete(&key)?,
+            Redo::Clear => self.clear(),
+        }
+        Ok(())
+    }
+
+    fn record_redo(&mut self, redo: impl FnOnce() -> Redo) {
+        if let Some(optimistic) = &mut self.optimistic {
+            optimistic.redo.push(redo());
+        }
+    }
+
+    /// A snapshot of the work done so far, for deciding whether to split a
+    /// large transaction before committing it.
+    pub fn stats(&self) -> TxnStats {
+        TxnStats {
+            keys_written: self.keys_written,
+            pages_copied: self.pages_copied,
+            pages_allocated: self.alloc.get_next_pgno() - self.base.get_next_pgno(),
+            bytes_pending: (self.dirty.len() * self.page_size()) as u64,
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    pub fn tree(&self) -> BTree<'_> {
+        BTree::new(self, self.root, self.order)
+    }
+
+    /// Number of live entries, counting this transaction's own writes.
+    pub const fn len(&self) -> u64 {
+        self.entries
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads see this transaction's own writes. The value borrows the
+    /// transaction, so it has to be dropped (or copied, see `get_owned`)
+    /// before the next write.
+    pub fn get<'txn>(&'txn self, key: &[u8]) -> Result<&'txn [u8], DBError> {
+        self.tree().get(key)
+    }
+
+    pub fn get_owned(&self, key: &[u8]) -> Result<Vec<u8>, DBError> {
+        self.get(key).map(<[u8]>::to_vec)
+    }
+
+    /// See `BTree::contains_key`; sees this transaction's own writes.
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool, DBError> {
+        self.tree().contains_key(key)
+    }
+
+    /// See `BTree::get_many`; sees this transaction's own writes.
+    pub fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Option<&[u8]>>, DBError> {
+        self.tree().get_many(keys)
+    }
+
+    /// See `BTree::last_key_value`; sees this transaction's own writes.
+    pub fn last_key_value(&self) -> Result<Option<cursor::Entry<'_>>, DBError> {
+        self.tree().last_key_value()
+    }
+
+    /// See `BTree::scan_prefix`; sees this transaction's own writes.
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+    ) -> Result<impl Iterator<Item = Result<cursor::Entry<'_>, DBError>> + '_, DBError> {
+        self.tree().scan_prefix(prefix)
+    }
+
+    /// Empty values are stored like any other, for keys that only mark
+    /// membership. Empty keys are rejected with `EmptyKey`: the first node of
+    /// every branch page already uses the empty key to stand for "everything
+    /// before the next separator". Keys longer than `MAX_KEY_SIZE` fail with
+    /// `KeyTooLarge`, and values longer than `data_page::max_value_size` with
+    /// `ValueTooLarge`. Tells whether `key` was new or its value replaced.
+    pub fn put(&mut self, key: &[u8], data: &[u8]) -> Result<PutOutcome, DBError> {
+        self.put_with_flags(key, data, PutFlag::OVERWRITE)
+    }
+
+    /// Puts `key` only if it isn't there yet, failing with `KeyExists`
+    /// otherwise; see `put_with_flags`.
+    pub fn put_no_overwrite(&mut self, key: &[u8], data: &[u8]) -> Result<(), DBError> {
+        self.put_with_flags(key, data, PutFlag::NO_OVERWRITE)?;
+        Ok(())
+    }
+
+    /// Like `put`, but with `PutFlag::NO_OVERWRITE` fails with `KeyExists`
+    /// if `key` is already there, and with `PutFlag::APPEND` is `put_append`.
+    /// A put that fails leaves the transaction as it was.
+    pub fn put_with_flags(
+        &mut self,
+        key: &[u8],
+        data: &[u8],
+        flags: PutFlag,
+    ) -> Result<PutOutcome, DBError> {
+        if flags.contains(PutFlag::APPEND) {
+            self.put_append(key, data)?;
+            return Ok(PutOutcome::Inserted);
+        }
+        let (data, node_flags) = self.checked_entry(key, data)?;
+        // checked first so a rejected put doesn't copy its path
+        if flags.contains(PutFlag::NO_OVERWRITE) {
+            match self.get(key) {
+                Ok(_) => return Err(DBError::KeyExists),
+                Err(DBError::KeyNotFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let entries = self.entries;
+        self.put_checked(key, &data, node_flags, None, false)?;
+        match self.entries > entries {
+            true => Ok(PutOutcome::Inserted),
+            false => Ok(PutOutcome::Replaced),
+        }
+    }
+
+    /// Puts an entry whose key sorts after every key in the tree, soft-deleted
+    /// ones included, failing with `KeyExists` otherwise. Rather than search
+    /// for the key's place, this follows the last child of every page down to
+    /// the last leaf, and a full page is split with `SplitBias::Right`, so
+    /// keys appended in increasing order leave every page full.
+    pub fn put_append(&mut self, key: &[u8], data: &[u8]) -> Result<(), DBError> {
+        let (data, node_flags) = self.checked_entry(key, data)?;
+        let (last, found) = match self.tree().descend_last()? {
+            None => (None, None),
+            Some(descent) => {
+                let leaf = descent.leaf.as_data_page();
+                match leaf.num_nodes().checked_sub(1) {
+                    Some(idx) => {
+                        let last = leaf.read_node(idx)?.get_key().into_owned();
+                        (Some(last), Some((descent.path, leaf.get_pgno())))
+                    }
+                    // emptied by deletes, so the last key is in a leaf to the
+                    // left, which a key after it may not be routed past
+                    None => {
+                        let last = self.tree().last_key_value()?;
+                        (last.map(|(key, _)| key.into_owned()), None)
+                    }
+                }
+            }
+        };
+        if last.is_some_and(|last| self.order.compare(key, &last).is_le()) {
+            return Err(DBError::KeyExists);
+        }
+        self.put_checked(key, &data, node_flags, found.as_ref(), found.is_some())?;
+        Ok(())
+    }
+
+    /// Removes `key`, failing with `KeyNotFound` if it isn't there. A leaf
+    /// left under `EnvOptions::min_fill` is rebalanced with a neighbour.
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), DBError> {
+        // checked first so a missing key doesn't copy its path
+        self.check_writable()?;
+        self.get(key)?;
+        self.delete_at(key, None)
+    }
+
+    /// Hides `key` from reads, scans and the entry count, failing with
+    /// `KeyNotFound` if it isn't there, but leaves its entry in its leaf for
+    /// `undelete` to bring back; cursors only yield it once asked to with
+    /// `Cursor::include_deleted`. Putting the key again replaces it.
+    pub fn soft_delete(&mut self, key: &[u8]) -> Result<(), DBError> {
+        // checked first so a missing key doesn't copy its path
+        self.check_writable()?;
+        self.get(key)?;
+        let (_, leaf) = self.touch_leaf(key)?;
+        self.dirty.get_mut(leaf).expect("the path is touched first").soft_delete(key)?;
+        self.keys_written += 1;
+        self.entries -= 1;
+        self.record_redo(|| Redo::SoftDelete(key.to_vec()));
+        Ok(())
+    }
+
+    /// Brings back a key `soft_delete` hid, with the value it had, failing
+    /// with `KeyNotFound` unless `key` is soft-deleted.
+    pub fn undelete(&mut self, key: &[u8]) -> Result<(), DBError> {
+        self.check_writable()?;
+        let node = self.tree().descend(key)?.leaf.as_data_page().get_node(key)?;
+        if node.is_alive() {
+            return Err(DBError::KeyNotFound);
+        }
+        let (_, leaf) = self.touch_leaf(key)?;
+        self.dirty.get_mut(leaf).expect("the path is touched first").undelete(key)?;
+        self.keys_written += 1;
+        self.entries += 1;
+        self.record_redo(|| Redo::Undelete(key.to_vec()));
+        Ok(())
+    }
+
+    /// The entry for `key`, to read and then insert, update or remove it
+    /// without descending the tree again, as a `put` after a `get` does.
+    pub fn entry(&mut self, key: &[u8]) -> Result<Entry<'_, 'env>, DBError> {
+        let found = match self.tree().descend(key) {
+            Ok(descent) => {
+                let live = match descent.leaf.get(key) {
+                    Ok(_) => true,
+                    Err(DBError::KeyNotFound) => false,
+                    Err(err) => return Err(err),
+                };
+                Some(((descent.path, descent.leaf.as_data_page().get_pgno()), live))
+            }
+            // the tree is empty
+            Err(DBError::KeyNotFound) => None,
+            Err(err) => return Err(err),
+        };
+        let key = key.to_vec();
+        Ok(match found {
+            Some((found, true)) => Entry::Occupied(OccupiedEntry { txn: self, key, found }),
+            found => Entry::Vacant(VacantEntry {
+                txn: self,
+                key,
+                found: found.map(|(found, _)| found),
+            }),
+        })
+    }
+
+    /// The value of `key`, first putting the one `default` makes if the key
+    /// isn't there; see `Entry::or_insert_with`.
+    pub fn get_or_insert_with<V: AsRef<[u8]>>(
+        &mut self,
+        key: &[u8],
+        default: impl FnOnce() -> V,
+    ) -> Result<&[u8], DBError> {
+        self.entry(key)?.or_insert_with(default)
+    }
+
+    /// Sets `key` to `new` (or removes it, for `None`) only if its current
+    /// value is `expected`, with `None` meaning the key must be absent.
+    /// Otherwise leaves it alone and fails with `CompareFailed`, carrying the
+    /// value it actually holds.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<(), DBError> {
+        let actual = match self.get(key) {
+            Ok(actual) => Some(actual),
+            Err(DBError::KeyNotFound) => None,
+            Err(err) => return Err(err),
+        };
+        if actual != expected {
+            return Err(DBError::CompareFailed {
+                actual: actual.map(<[u8]>::to_vec),
+            });
+        }
+        match (actual, new) {
+            (_, Some(new)) => self.put(key, new).map(drop),
+            (Some(_), None) => self.delete(key),
+            (None, None) => Ok(()),
+        }
+    }
+
+    /// Combines `operand` with the value stored under `key` using the
+    /// environment's merge operator, so a counter or list can be updated
+    /// without the caller reading it first. The leaf is copied for the write
+    /// anyway, so the operand is folded in right away rather than kept pending
+    /// for later reads to apply. Fails with `NoMergeOperator` if none was
+    /// registered.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<(), DBError> {
+        let merge = self.env.get_merge_operator().ok_or(DBError::NoMergeOperator)?;
+        let merged = match self.get(key) {
+            Ok(existing) => merge(key, Some(existing), operand),
+            Err(DBError::KeyNotFound) => merge(key, None, operand),
+            Err(err) => return Err(err),
+        };
+        self.put(key, &merged)?;
+        Ok(())
+    }
+
+    /// Puts `entries`, which must be in strictly increasing key order. An
+    /// empty tree is built bottom-up, filling each page in turn; otherwise the
+    /// tree is descended once per leaf touched rather than once per entry.
+    /// Fails with `BatchNotSorted` at the first entry out of order, or with
+    /// the error `put` would give at an entry it can't store (`TxnFull`
+    /// included), leaving the entries before it in place.
+    pub fn write_batch<K, V>(
+        &mut self,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<(), DBError>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let mut prev = None;
+        if self.root.is_none() {
+            let mut builder = TreeBuilder::default();
+            for (key, data) in entries {
+                self.check_writable()?;
+                let (data, node_flags) = self.stored_value(data.as_ref());
+                self.check_entry(key.as_ref(), &data)?;
+                self.check_sorted(&mut prev, key.as_ref())?;
+                builder.push(self, 0, key.as_ref(), &data, node_flags)?;
+                self.keys_written += 1;
+                self.entries += 1;
+                self.record_redo(|| Redo::Put(key.as_ref().to_vec(), data.to_vec(), node_flags));
+            }
+            self.root = builder.finish(self)?;
+            return Ok(());
+        }
+
+        // the leaf the previous entry went into, and the first key past it
+        let mut current: Option<(LeafPath, Option<Vec<u8>>)> = None;
+        for (key, data) in entries {
+            self.check_writable()?;
+            let key = key.as_ref();
+            let (data, node_flags) = self.stored_value(data.as_ref());
+            self.check_entry(key, &data)?;
+            self.check_sorted(&mut prev, key)?;
+            let in_leaf = match &current {
+                Some((_, Some(bound))) => self.order.compare(key, bound).is_lt(),
+                Some((_, None)) => true,
+                None => false,
+            };
+            if !in_leaf {
+                let leaf_path = self.touch_leaf(key)?;
+                let bound = self.upper_bound(&leaf_path.0)?;
+                current = Some((leaf_path, bound));
+            }
+            let ((path, leaf), _) = current.as_ref().unwrap();
+            let is_new = !self.in_leaf(*leaf, key)?;
+            // a split moves entries around, so the next entry descends again
+            self.keys_written += 1;
+            let split = self.insert(path, *leaf, key, &data, node_flags, false)?;
+            self.entries += u64::from(is_new);
+            self.record_redo(|| Redo::Put(key.to_vec(), data.to_vec(), node_flags));
+            if split {
+                current = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds every entry of the database file at `path`, whose keys must all lie
+    /// in `key_range` and which must use this file's page size and key order;
+    /// no key in `key_range` may already be in the tree. The file's pages are
+    /// copied in and linked as a subtree when the trees are the same height
+    /// and one sorts entirely before the other; otherwise its entries are
+    /// written one by one. Nothing is linked if any check fails.
+    pub fn ingest_file<'k>(
+        &mut self,
+        path: impl AsRef<Path>,
+        key_range: impl RangeBounds<&'k [u8]>,
+    ) -> Result<(), DBError> {
+        self.check_writable()?;
+        if self.optimistic.is_some() {
+            return Err(DBError::Unsupported { reason: "ingest_file in an optimistic transaction" });
+        }
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let meta = Meta::read(&mmap)?;
+        if meta.get_page_size() != self.page_size() {
+            return Err(DBError::IncompatibleFile { reason: "page sizes differ" });
+        }
+        if !meta.has_key_order(self.order) {
+            return Err(DBError::IncompatibleFile { reason: "keys are in another order" });
+        }
+        let source = FilePages { mmap, page_size: meta.get_page_size() };
+        let ingested = BTree::new(&source, meta.get_root(), self.order);
+        let Some((first, last)) = ingested.key_bounds()? else {
+            return Ok(());
+        };
+        let in_range = |key: &[u8]| self.order.compare_to_range(key, &key_range).is_eq();
+        if !in_range(&first) || !in_range(&last) {
+            return Err(DBError::IncompatibleFile { reason: "keys fall outside the range" });
+        }
+        let mut cursor = self.tree().cursor()?;
+        if let Some(start) = match key_range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => Some(start),
+            Bound::Unbounded => None,
+        } {
+            cursor.seek(start)?;
+        }
+        for entry in cursor {
+            let (key, _) = entry?;
+            if in_range(&key) {
+                return Err(DBError::RangeOverlap);
+            }
+            // an excluded start is the only key past the seek that can still
+            // come before the range
+            if key_range.start_bound() != Bound::Excluded(&&key[..]) {
+                break;
+            }
+        }
+
+        let ingested_root = meta.get_root().unwrap();
+        let copy_ingested = |txn: &mut Self| {
+            txn.dirty.copy_subtree(&mut txn.alloc, &ingested, ingested_root, 0, &mut || Ok(()))
+        };
+        let Some(bounds) = self.tree().key_bounds()? else {
+            self.root = Some(copy_ingested(self)?);
+            self.entries += meta.get_entries();
+            return Ok(());
+        };
+        let (height, ingested_height) = (self.tree().height()?, ingested.height()?);
+        let before = self.order.compare(&last, &bounds.0).is_lt();
+        let after = self.order.compare(&bounds.1, &first).is_lt();
+        if height != ingested_height || !(before || after) {
+            // linking would unbalance the tree, so only a copy of the entries will do
+            let mut error = None;
+            let entries = ingested
+                .cursor()?
+                .map_while(|entry| entry.map_err(|err| error = Some(err)).ok());
+            self.write_batch(entries)?;
+            return error.map_or(Ok(()), Err);
+        }
+
+        let root = self.root.unwrap();
+        let subtree = copy_ingested(self)?;
+        // the old root may be committed, so its count has to be right already
+        let (root, subtree) = ((root, self.entries), (subtree, meta.get_entries()));
+        self.entries += meta.get_entries();
+        let (left, right, separator) = if before {
+            (subtree, root, bounds.0)
+        } else {
+            (root, subtree, first)
+        };
+        self.new_root(left, &separator, right)
+    }
+
+    /// Replaces the application's own metadata kept in the meta pages, which
+    /// commits carry over until one sets it again, with `user_meta`, as for a
+    /// schema version or the name of the application that owns the file.
+    /// Fails with `ValueTooLarge` if it is over `USER_META_SIZE` bytes.
+    pub fn set_user_meta(&mut self, user_meta: &[u8]) -> Result<(), DBError> {
+        self.check_writable()?;
+        self.base.with_user_meta(user_meta)?;
+        self.user_meta = Some(user_meta.to_vec());
+        Ok(())
+    }
+
+    /// The next id of the sequence the meta pages keep, for auto-increment
+    /// keys: 1 for the first one, then one more than the last each time, in
+    /// this transaction or any committed before it. Ids are taken with the
+    /// transaction's other writes, so an aborted transaction hands its ids
+    /// out again. Fails with `Unsupported` once every `u64` has been used.
+    pub fn next_id(&mut self) -> Result<u64, DBError> {
+        self.check_writable()?;
+        let reason = "sequence exhausted";
+        self.sequence = self.sequence.checked_add(1).ok_or(DBError::Unsupported { reason })?;
+        if let Some(optimistic) = &self.optimistic {
+            optimistic.reads.borrow_mut().insert(0);
+        }
+        Ok(self.sequence)
+    }
+
+    /// Removes every entry. The pages of the old tree are left alone, for
+    /// readers of earlier commits.
+    pub fn clear(&mut self) {
+        self.root = None;
+        self.entries = 0;
+        self.replaced = None;
+        self.record_redo(|| Redo::Clear);
+    }
+
+    /// Writes every dirty page and then the meta page that makes them the
+    /// current commit. With `EnvOptions::wal`, appends them to the log
+    /// instead, and lets the next write transaction begin while it waits for
+    /// a sync of the log to make the commit durable; if that sync fails, the
+    /// commit is current but may not survive a crash.
+    pub fn commit(mut self) -> Result<(), DBError> {
+        let meta_changed = self.user_meta.is_some() || self.sequence != self.base.get_sequence();
+        if self.dirty.is_empty() && self.root == self.base.get_root() && !meta_changed {
+            return Ok(());
+        }
+        let started = Instant::now();
+        if self.optimistic.is_some() {
+            return self.commit_optimistic(started);
+        }
+        let took_ids = self.sequence != self.base.get_sequence();
+        // the number of a meta page stands for the sequence, as in `Optimistic`
+        if let Some(replaced) = self.replaced.as_mut().filter(|_| took_ids) {
+            replaced.insert(0);
+        }
+        let meta = self.commit_meta()?;
+        let rebuilt_filter = self.fill_key_filter(&meta)?;
+        let env = self.env;
+        let wal_end = env.write_commit(self.dirty.into_runs(), meta, rebuilt_filter)?;
+        // while no other commit can come between
+        env.get_conflicts().record(meta.get_txnid(), self.replaced);
+        drop(self._writer);
+        if let Some((end, checkpointed)) = wal_end {
+            env.wait_for_wal(meta.get_txnid(), end, checkpointed)?;
+        }
+        env.get_metrics().commit(meta.get_txnid(), started.elapsed());
+        Ok(())
+    }
+
+    /// Writes every dirty page and durably records the commit they make up,
+    /// without making it current: readers don't see it until
+    /// `Env::commit_prepared` is called with the returned commit id, and
+    /// `Env::rollback_prepared` discards it. Either can come after a restart,
+    /// as the record survives it. Until then, further commits (and a second
+    /// prepare) fail with `PreparedTxnPending`.
+    pub fn prepare(mut self) -> Result<TxnId, DBError> {
+        let meta = self.commit_meta()?;
+        // a full filter is left for a later commit to rebuild
+        if let Some(filter) = self.env.get_key_filter() {
+            self.insert_dirty_keys(&filter)?;
+        }
+        self.env.write_prepared(self.dirty.into_runs(), meta)?;
+        self.env.get_conflicts().record(meta.get_txnid(), self.replaced);
+        Ok(meta.get_txnid())
+    }
+
+    // Takes the writer lock and commits as any transaction would if no
+    // commit came since the base, or else makes every write again in a
+    // transaction on the current commit, unless a commit since has replaced
+    // something read.
+    fn commit_optimistic(mut self, started: Instant) -> Result<(), DBError> {
+        let env = self.env;
+        let writer = env.lock_writer_txn();
+        let (current, file) = env.current();
+        let mut optimistic = self.optimistic.take().expect("checked by commit");
+        if current.get_txnid() == self.base.get_txnid() {
+            self._writer = Some(writer);
+            self.started = started;
+            return self.commit();
+        }
+        let reads = optimistic.reads.take();
+        env.get_conflicts().check(self.base.get_txnid(), current.get_txnid(), &reads)?;
+        let mut txn = WriteTxn::new(env, writer, current, file.view());
+        txn.started = started;
+        for redo in std::mem::take(&mut optimistic.redo) {
+            txn.redo(redo)?;
+        }
+        if let Some(user_meta) = &self.user_meta {
+            txn.set_user_meta(user_meta)?;
+        }
+        // a commit since that took ids too would have conflicted
+        txn.sequence = txn.sequence.max(self.sequence);
+        txn.commit()
+    }
+
+    /// Discards every change; the same as dropping the transaction.
+    pub fn abort(self) {}
+
+    /// A transaction nested in this one, which sees its writes and whose own
+    /// writes are kept by `NestedTxn::commit`, to be committed with this one,
+    /// or undone by `NestedTxn::abort` without touching the rest. Beginning
+    /// one copies the dirty pages held so far.
+    pub fn begin_nested(&mut self) -> NestedTxn<'_, 'env> {
+        let saved = self.save();
+        NestedTxn { txn: self, saved: Some(saved) }
+    }
+
+    /// Marks the transaction as it is now, for `rollback_to` to undo every
+    /// change made after it while keeping those made before. Unlike a nested
+    /// transaction, it doesn't borrow the transaction, so it can be taken
+    /// and then decided on later, once the writes after it are validated.
+    /// Taking one copies the dirty pages held so far.
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint { txn: (self.base.get_txnid(), self.started), saved: self.save() }
+    }
+
+    /// Puts the transaction back as it was when `savepoint` was taken. Fails
+    /// with `Unsupported` for a savepoint of another transaction.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) -> Result<(), DBError> {
+        if savepoint.txn != (self.base.get_txnid(), self.started) {
+            return Err(DBError::Unsupported { reason: "savepoint of another transaction" });
+        }
+        self.restore(savepoint.saved);
+        Ok(())
+    }
+
+    fn save(&self) -> Saved {
+        Saved {
+            root: self.root,
+            next_pgno: self.alloc.get_next_pgno(),
+            dirty: self.dirty.clone(),
+            keys_written: self.keys_written,
+            pages_copied: self.pages_copied,
+            entries: self.entries,
+            user_meta: self.user_meta.clone(),
+            sequence: self.sequence,
+            redo_len: self.optimistic.as_ref().map_or(0, |optimistic| optimistic.redo.len()),
+        }
+    }
+
+    fn restore(&mut self, saved: Saved) {
+        self.root = saved.root;
+        self.alloc = PageAllocator::new(saved.next_pgno);
+        self.dirty = saved.dirty;
+        self.keys_written = saved.keys_written;
+        self.pages_copied = saved.pages_copied;
+        self.entries = saved.entries;
+        self.user_meta = saved.user_meta;
+        self.sequence = saved.sequence;
+        // the pages replaced and read since stay recorded, which can only
+        // cause a conflict that wasn't needed
+        if let Some(optimistic) = &mut self.optimistic {
+            optimistic.redo.truncate(saved.redo_len);
+        }
+    }
+
+    // the flags of a new leaf, laid out for fixed-size keys if the
+    // environment has them
+    fn leaf_flags(&self) -> PageFlag {
+        match self.env.get_fixed_keys() {
+            true => PageFlag::ALIVE | PageFlag::FIXED_KEY,
+            false => PageFlag::ALIVE,
+        }
+    }
+
+    // the stored form of an entry about to be put, once it has passed every
+    // check that doesn't read the tree
+    fn checked_entry<'d>(
+        &self,
+        key: &[u8],
+        data: &'d [u8],
+    ) -> Result<(Cow<'d, [u8]>, NodeFlag), DBError> {
+        self.check_writable()?;
+        let (data, node_flags) = self.stored_value(data);
+        self.check_entry(key, &data)?;
+        Ok((data, node_flags))
+    }
+
+    // Puts an entry from `checked_entry` into its leaf, which `found` leads to
+    // if the caller has descended to it already; see `insert` for `append`.
+    // Returns the dirty leaf that holds the entry, unless a split may have
+    // moved it.
+    fn put_checked(
+        &mut self,
+        key: &[u8],
+        data: &[u8],
+        node_flags: NodeFlag,
+        found: Option<&LeafPath>,
+        append: bool,
+    ) -> Result<Option<Pgno>, DBError> {
+        if self.root.is_none() {
+            let pgno = self.alloc.alloc();
+            let leaf = DirtyPage::new(pgno, self.page_size(), self.leaf_flags(), self.order);
+            self.dirty.insert(leaf);
+            self.root = Some(pgno);
+        }
+        let (path, leaf) = match found {
+            Some((path, leaf)) => self.touch_path(path, *leaf)?,
+            None => self.touch_leaf(key)?,
+        };
+        let is_new = append || !self.in_leaf(leaf, key)?;
+        self.keys_written += 1;
+        let split = self.insert(&path, leaf, key, data, node_flags, append)?;
+        self.entries += u64::from(is_new);
+        self.record_redo(|| Redo::Put(key.to_vec(), data.to_vec(), node_flags));
+        Ok((!split).then_some(leaf))
+    }
+
+    // removes `key`, known to be live, from its leaf, like `put_checked`
+    fn delete_at(&mut self, key: &[u8], found: Option<&LeafPath>) -> Result<(), DBError> {
+        let (path, leaf) = match found {
+            Some((path, leaf)) => self.touch_path(path, *leaf)?,
+            None => self.touch_leaf(key)?,
+        };
+        self.dirty.get_mut(leaf).expect("the path is touched first").remove(key)?;
+        self.keys_written += 1;
+        self.entries -= 1;
+        self.record_redo(|| Redo::Delete(key.to_vec()));
+        self.rebalance(&path, leaf)
+    }
+
+    // the value of `key` in `leaf`, where it is known to be
+    fn leaf_value(&self, leaf: Pgno, key: &[u8]) -> Result<&[u8], DBError> {
+        let tree = self.tree();
+        let node = tree.get_page(leaf)?.get_node(key)?;
+        tree.get_value(&node)
+    }
+
+    // fails in a read-only environment, and once the transaction holds
+    // `EnvOptions::max_dirty_pages`
+    fn check_writable(&self) -> Result<(), DBError> {
+        if self.env.is_read_only() {
+            return Err(DBError::TxnReadOnly);
+        }
+        match self.env.get_max_dirty_pages() {
+            max if max > 0 && self.dirty.len() >= max => Err(DBError::TxnFull { max }),
+            _ => Ok(()),
+        }
+    }
+
+    fn page_size(&self) -> usize {
+        self.base.get_page_size()
+    }
+
+    // the bytes a put stores for `data`, and the flags of their node:
+    // compressed if the environment asks for it and that makes them smaller
+    #[cfg(feature = "compression")]
+    fn stored_value<'d>(&self, data: &'d [u8]) -> (Cow<'d, [u8]>, NodeFlag) {
+        let min_size = self.env.get_compress_values();
+        if min_size > 0 && data.len() >= min_size {
+            let compressed = compress::compress_value(data);
+            if compressed.len() < data.len() {
+                return (Cow::Owned(compressed), NodeFlag::ALIVE | NodeFlag::COMPRESSED);
+            }
+        }
+        (Cow::Borrowed(data), NodeFlag::ALIVE)
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn stored_value<'d>(&self, data: &'d [u8]) -> (Cow<'d, [u8]>, NodeFlag) {
+        (Cow::Borrowed(data), NodeFlag::ALIVE)
+    }
+
+    // rejected up front, since an entry that doesn't fit would otherwise only
+    // fail once its page is split, with the pages above half updated
+    fn check_entry(&self, key: &[u8], data: &[u8]) -> Result<(), DBError> {
+        if key.is_empty() {
+            return Err(DBError::EmptyKey);
+        }
+        if key.len() > MAX_KEY_SIZE {
+            return Err(DBError::KeyTooLarge { size: key.len(), max: MAX_KEY_SIZE });
+        }
+        if self.env.get_fixed_keys() && key.len() != FIXED_KEY_SIZE {
+            return Err(DBError::KeySizeMismatch { size: key.len(), expected: FIXED_KEY_SIZE });
+        }
+        let max = max_value_size(self.page_size(), key.len());
+        if data.len() > max {
+            return Err(DBError::ValueTooLarge { size: data.len(), max });
+        }
+        Ok(())
+    }
+
+    fn check_sorted(&self, prev: &mut Option<Vec<u8>>, key: &[u8]) -> Result<(), DBError> {
+        if let Some(prev) = prev {
+            if !self.order.compare(prev, key).is_lt() {
+                return Err(DBError::BatchNotSorted);
+            }
+            prev.clear();
+            prev.extend_from_slice(key);
+        } else {
+            *prev = Some(key.to_vec());
+        }
+        Ok(())
+    }
+
+    // returns the page's number in this transaction, copying it first if it
+    // still belongs to the previous commit
+    fn touch(&mut self, pgno: Pgno) -> Result<Pgno, DBError> {
+        if self.dirty.contains(pgno) {
+            return Ok(pgno);
+        }
+        let checks = self.env.get_page_checks();
+        let page = committed_page(&self.file, self.page_size(), pgno, self.order, checks)?;
+        self.pages_copied += 1;
+        if let Some(replaced) = &mut self.replaced {
+            replaced.insert(pgno);
+        }
+        Ok(self.dirty.clone_page(&mut self.alloc, &page))
+    }
+
+    // makes every page from the root down to `key`'s leaf dirty, pointing each
+    // parent at its child's new page number
+    fn touch_leaf(&mut self, key: &[u8]) -> Result<LeafPath, DBError> {
+        let descent = self.tree().descend(key)?;
+        let leaf = descent.leaf.as_data_page().get_pgno();
+        self.touch_path(&descent.path, leaf)
+    }
+
+    // like `touch_leaf`, along a path a descent found since the last write
+    fn touch_path(&mut self, path: &[(Pgno, usize)], leaf: Pgno) -> Result<LeafPath, DBError> {
+        let mut dirty_path = Vec::with_capacity(path.len());
+        let mut parent: Option<(Pgno, usize)> = None;
+        for pgno in path.iter().map(|&(pgno, _)| pgno).chain([leaf]) {
+            let new_pgno = self.touch(pgno)?;
+            if new_pgno != pgno {
+                match parent {
+                    Some((parent, idx)) => self.set_child(parent, idx, new_pgno)?,
+                    None => self.root = Some(new_pgno),
+                }
+            }
+            if let Some(&(_, idx)) = path.get(dirty_path.len()) {
+                dirty_path.push((new_pgno, idx));
+                parent = Some((new_pgno, idx));
+            } else {
+                return Ok((dirty_path, new_pgno));
+            }
+        }
+        unreachable!("the leaf ends the path")
+    }
+
+    fn set_child(&mut self, parent: Pgno, idx: usize, child: Pgno) -> Result<(), DBError> {
+        let page = self.dirty.get_mut(parent).expect("parents are touched first");
+        let entries = BranchPage::from(page.as_data_page()?)?.entries_at(idx)?;
+        // same size as the old pointer, so this overwrites it in place
+        page.replace_data(idx, &child_data(child, entries))
+    }
+
+    // whether `key` is live in `leaf`, the leaf it belongs in
+    fn in_leaf(&self, leaf: Pgno, key: &[u8]) -> Result<bool, DBError> {
+        match self.get_page(leaf)?.get(key) {
+            Ok(_) => Ok(true),
+            Err(DBError::KeyNotFound) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    // the meta of this transaction's commit, once the counts in its branch
+    // pages are brought up to date
+    fn commit_meta(&mut self) -> Result<Meta, DBError> {
+        let entries = match self.root {
+            Some(root) => self.count_entries(root)?,
+            None => 0,
+        };
+        debug_assert_eq!(entries, self.entries, "the tree and the counter disagree");
+        let meta = self.base.next_commit(self.root, self.alloc.get_next_pgno());
+        let meta = meta.with_sequence(self.sequence);
+        match &self.user_meta {
+            Some(user_meta) => meta.with_entries(entries).with_user_meta(user_meta),
+            None => Ok(meta.with_entries(entries)),
+        }
+    }
+
+    // Puts the keys of every dirty leaf into the environment's key filter, so
+    // that it holds every key of `meta`, or, once the filter is full, returns
+    // one twice the size of the tree holding every key of this transaction's
+    // tree, to replace it.
+    fn fill_key_filter(&self, meta: &Meta) -> Result<Option<KeyFilter>, DBError> {
+        let Some(filter) = self.env.get_key_filter() else {
+            return Ok(None);
+        };
+        self.insert_dirty_keys(&filter)?;
+        if !filter.is_full() {
+            return Ok(None);
+        }
+        let rebuilt = filter.resized(meta.get_entries().saturating_mul(2));
+        for entry in self.tree().cursor()? {
+            rebuilt.insert(&entry?.0);
+        }
+        Ok(Some(rebuilt))
+    }
+
+    // every key written ends up in a dirty leaf, along with the keys that
+    // happen to share it
+    fn insert_dirty_keys(&self, filter: &KeyFilter) -> Result<(), DBError> {
+        for page in self.dirty.pages() {
+            let page = page.as_data_page()?;
+            if page.get_flags().contains(PageFlag::BRANCH) {
+                continue;
+            }
+            for node in page.nodes() {
+                filter.insert(&node?.get_key());
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the number of entries under `pgno`, first writing the count of
+    // every dirty child of a dirty branch page below it; the counts kept for
+    // committed children are already right.
+    fn count_entries(&mut self, pgno: Pgno) -> Result<u64, DBError> {
+        let page = self.get_page(pgno)?;
+        if !page.get_flags().contains(PageFlag::BRANCH) {
+            return page.nodes().try_fold(0, |count, node| node.map(|_| count + 1));
+        }
+        let branch = BranchPage::from(page)?;
+        let children = (0..branch.num_children())
+            .map(|idx| Ok((branch.child_at(idx)?, branch.entries_at(idx)?)))
+            .collect::<Result<Vec<_>, DBError>>()?;
+        let mut total = 0;
+        for (idx, (child, mut entries)) in children.into_iter().enumerate() {
+            if self.dirty.contains(child) {
+                entries = self.count_entries(child)?;
+                let page = self.dirty.get_mut(pgno).expect("parents of dirty pages are dirty");
+                page.replace_data(idx, &child_data(child, entries))?;
+            }
+            total += entries;
+        }
+        Ok(total)
+    }
+
+    // the smallest key that belongs in a leaf to the right of `path`'s leaf
+    fn upper_bound(&self, path: &[(Pgno, usize)]) -> Result<Option<Vec<u8>>, DBError> {
+        let tree = self.tree();
+        for &(pgno, idx) in path.iter().rev() {
+            let branch = BranchPage::from(tree.get_page(pgno)?)?;
+            if idx + 1 < branch.num_children() {
+                let node = branch.as_data_page().read_node(idx + 1)?;
+                return Ok(Some(node.get_key().into_owned()));
+            }
+        }
+        Ok(None)
+    }
+
+    // a root over two children, each with its entry count, split at `separator`
+    fn new_root(
+        &mut self,
+        (left, left_entries): (Pgno, u64),
+        separator: &[u8],
+        (right, right_entries): (Pgno, u64),
+    ) -> Result<(), DBError> {
+        let pgno = self.alloc.alloc();
+        let flags = PageFlag::ALIVE | PageFlag::BRANCH;
+        let mut root = DirtyPage::new(pgno, self.page_size(), flags, self.order);
+        root.put(&[], &child_data(left, left_entries))?;
+        root.put(separator, &child_data(right, right_entries))?;
+        self.dirty.insert(root);
+        self.root = Some(pgno);
+        Ok(())
+    }
+
+    // Puts the entry into the dirty leaf at the end of `path`, splitting pages
+    // upward as far as needed. Returns whether any page was split. With
+    // `append`, the path runs down the right edge of the tree and the key
+    // sorts after every other, so every page it goes into takes it last,
+    // without a search, and splits to the right.
+    fn insert(
+        &mut self,
+        path: &[(Pgno, usize)],
+        leaf: Pgno,
+        key: &[u8],
+        data: &[u8],
+        mut node_flags: NodeFlag,
+        append: bool,
+    ) -> Result<bool, DBError> {
+        let bias = if append { SplitBias::Right } else { self.env.get_split_bias() };
+        let (mut pgno, mut level) = (leaf, path.len());
+        let (mut key, mut data) = (Cow::Borrowed(key), Cow::Borrowed(data));
+        loop {
+            let page = self.dirty.get_mut(pgno).expect("the path is touched first");
+            let node = || DataNode::from(&key, &data).with_flags(node_flags);
+            let put = match append {
+                true => page.append_node(node()),
+                false => page.put_node_with_flags(node(), PutFlag::OVERWRITE),
+            };
+            match put {
+                Ok(()) => return Ok(level < path.len()),
+                Err(DBError::PageFull) => {}
+                Err(err) => return Err(err),
+            }
+
+            // only taken once the split has succeeded, so a failed one leaves
+            // no gap in the file
+            let right_pgno = self.alloc.get_next_pgno();
+            let (left, right, separator) = page.split_insert_with_bias(right_pgno, node(), bias)?;
+            self.alloc.alloc();
+            self.dirty.insert(left);
+            self.dirty.insert(right);
+            self.env.get_metrics().page_split();
+
+            if level == 0 {
+                // both halves are dirty, and counted at commit
+                self.new_root((pgno, 0), &separator, (right_pgno, 0))?;
+                return Ok(true);
+            }
+            level -= 1;
+            pgno = path[level].0;
+            key = Cow::Owned(separator);
+            data = Cow::Owned(child_data(right_pgno, 0).to_vec());
+            node_flags = NodeFlag::ALIVE;
+        }
+    }
+
+    // Merges the dirty page at the end of `path` with a sibling, or evens the
+    // two out, if it has fallen under the environment's `min_fill`. A merge
+    // takes an entry out of the parent, so the parent is checked next, and so
+    // on up; a root left with a single child is then replaced by that child.
+    fn rebalance(&mut self, path: &[(Pgno, usize)], mut pgno: Pgno) -> Result<(), DBError> {
+        let min_fill = self.env.get_min_fill();
+        for level in (1..=path.len()).rev() {
+            if self.get_page(pgno)?.fill()? >= min_fill {
+                break;
+            }
+            let (parent, idx) = path[level - 1];
+            let branch = BranchPage::from(self.get_page(parent)?)?;
+            let sibling_idx = match idx + 1 < branch.num_children() {
+                true => idx + 1,
+                false if idx > 0 => idx - 1,
+                false => break,
+            };
+            let sibling = branch.child_at(sibling_idx)?;
+            let new_sibling = self.touch(sibling)?;
+            if new_sibling != sibling {
+                self.set_child(parent, sibling_idx, new_sibling)?;
+            }
+            let (right_idx, left, right) = match sibling_idx > idx {
+                true => (sibling_idx, pgno, new_sibling),
+                false => (idx, new_sibling, pgno),
+            };
+            let parent_page = self.get_page(parent)?;
+            let separator = parent_page.read_node(right_idx)?.get_key().into_owned();
+            let rebalanced =
+                btree_page::rebalance(&self.get_page(left)?, &self.get_page(right)?, &separator);
+
+            // the merged-away right page stays in the dirty set: its number
+            // is taken, and nothing is freed yet
+            let parent_page = self.dirty.get_mut(parent).expect("parents are touched first");
+            match rebalanced {
+                Ok(Rebalanced::Merged(merged)) => {
+                    parent_page.remove_at(right_idx);
+                    self.dirty.insert(merged);
+                    pgno = parent;
+                }
+                Ok(Rebalanced::Borrowed(left_page, right_page, separator)) => {
+                    // the new separator may not fit where the old one was,
+                    // so it is put like any other key and can split the parent
+                    parent_page.remove_at(right_idx);
+                    self.dirty.insert(left_page);
+                    self.dirty.insert(right_page);
+                    let child = child_data(right, 0);
+                    let path = &path[..level - 1];
+                    self.insert(path, parent, &separator, &child, NodeFlag::ALIVE, false)?;
+                    break;
+                }
+                Err(DBError::PageFull) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        while let Some(root) = self.root {
+            let page = self.get_page(root)?;
+            if !page.get_flags().contains(PageFlag::BRANCH) || page.num_nodes() > 1 {
+                break;
+            }
+            self.root = Some(BranchPage::from(page)?.child_at(0)?);
+        }
+        Ok(())
+    }
+}
+
+/// A write transaction inside another, from `WriteTxn::begin_nested`; it
+/// reads and writes like one until it is committed into its parent, and is
+/// aborted if dropped. Nested transactions can be nested in turn.
+pub struct NestedTxn<'parent, 'env> {
+    txn: &'parent mut WriteTxn<'env>,
+    // the parent as it was, put back unless the nested transaction commits
+    saved: Option<Saved>,
+}
+
+/// A write transaction as it was at some point, from `WriteTxn::savepoint`.
+pub struct Savepoint {
+    // the transaction it was taken in, by its base commit and start
+    txn: (TxnId, Instant),
+    saved: Saved,
+}
+
+struct Saved {
+    root: Option<Pgno>,
+    next_pgno: Pgno,
+    dirty: DirtySet,
+    keys_written: u64,
+    pages_copied: u64,
+    entries: u64,
+    user_meta: Option<Vec<u8>>,
+    sequence: u64,
+    // of an optimistic transaction's writes
+    redo_len: usize,
+}
+
+impl NestedTxn<'_, '_> {
+    /// Keeps every change in the parent, which commits or discards them with
+    /// its own.
+    pub fn commit(mut self) {
+        self.saved = None;
+    }
+
+    /// Undoes every change since `begin_nested`; the same as dropping the
+    /// transaction.
+    pub fn abort(self) {}
+}
+
+impl<'env> Deref for NestedTxn<'_, 'env> {
+    type Target = WriteTxn<'env>;
+
+    fn deref(&self) -> &WriteTxn<'env> {
+        self.txn
+    }
+}
+
+impl DerefMut for NestedTxn<'_, '_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.txn
+    }
+}
+
+impl Drop for NestedTxn<'_, '_> {
+    fn drop(&mut self) {
+        if let Some(saved) = self.saved.take() {
+            self.txn.restore(saved);
+        }
+    }
+}
+
+/// A key of a write transaction, from `WriteTxn::entry`, that is either in
+/// the tree or not, like the entries of a `BTreeMap`. It holds the path the
+/// lookup took, which stays valid because the entry borrows the transaction.
+pub enum Entry<'txn, 'env> {
+    Occupied(OccupiedEntry<'txn, 'env>),
+    Vacant(VacantEntry<'txn, 'env>),
+}
+
+/// An entry whose key is in the tree.
+pub struct OccupiedEntry<'txn, 'env> {
+    txn: &'txn mut WriteTxn<'env>,
+    key: Vec<u8>,
+    found: LeafPath,
+}
+
+/// An entry whose key isn't in the tree; `found` is `None` while the tree
+/// is empty.
+pub struct VacantEntry<'txn, 'env> {
+    txn: &'txn mut WriteTxn<'env>,
+    key: Vec<u8>,
+    found: Option<LeafPath>,
+}
+
+impl<'txn> Entry<'txn, '_> {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// The value, first putting `default` if the key isn't there.
+    pub fn or_insert(self, default: &[u8]) -> Result<&'txn [u8], DBError> {
+        self.or_insert_with(|| default)
+    }
+
+    /// The value, first putting the one `default` makes if the key isn't
+    /// there; `default` isn't called otherwise.
+    pub fn or_insert_with<V: AsRef<[u8]>>(
+        self,
+        default: impl FnOnce() -> V,
+    ) -> Result<&'txn [u8], DBError> {
+        match self {
+            Entry::Occupied(entry) => entry.into_value(),
+            Entry::Vacant(entry) => entry.insert(default().as_ref()),
+        }
+    }
+}
+
+impl<'txn> OccupiedEntry<'txn, '_> {
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn get(&self) -> Result<&[u8], DBError> {
+        self.txn.leaf_value(self.found.1, &self.key)
+    }
+
+    /// The value, borrowing the transaction rather than the entry.
+    pub fn into_value(self) -> Result<&'txn [u8], DBError> {
+        let txn: &'txn WriteTxn = self.txn;
+        txn.leaf_value(self.found.1, &self.key)
+    }
+
+    /// Replaces the value, failing like `put` would.
+    pub fn insert(self, value: &[u8]) -> Result<(), DBError> {
+        let (data, node_flags) = self.txn.checked_entry(&self.key, value)?;
+        self.txn.put_checked(&self.key, &data, node_flags, Some(&self.found), false)?;
+        Ok(())
+    }
+
+    /// Removes the key, like `delete`.
+    pub fn remove(self) -> Result<(), DBError> {
+        self.txn.check_writable()?;
+        self.txn.delete_at(&self.key, Some(&self.found))
+    }
+}
+
+impl<'txn> VacantEntry<'txn, '_> {
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Puts `value` under the key, failing like `put` would, and returns it
+    /// as `get` would.
+    pub fn insert(self, value: &[u8]) -> Result<&'txn [u8], DBError> {
+        let (data, node_flags) = self.txn.checked_entry(&self.key, value)?;
+        let found = self.found.as_ref();
+        let leaf = self.txn.put_checked(&self.key, &data, node_flags, found, false)?;
+        let txn: &'txn WriteTxn = self.txn;
+        match leaf {
+            Some(leaf) => txn.leaf_value(leaf, &self.key),
+            None => txn.get(&self.key),
+        }
+    }
+}
+
+// the pages of a file that isn't open as an environment, verified on every
+// read since nothing else has checked them
+struct FilePages {
+    mmap: Mmap,
+    page_size: usize,
+}
+
+impl PageSource for FilePages {
+    fn get_page(&self, pgno: Pgno) -> Result<DataPage<'_>, DBError> {
+        DataPage::from(PageRef::from_mmap_verified(&self.mmap, self.page_size, pgno as usize)?)
+    }
+}
+
+impl PageSource for WriteTxn<'_> {
+    fn get_page(&self, pgno: Pgno) -> Result<DataPage<'_>, DBError> {
+        match self.dirty.get(pgno) {
+            Some(page) => page.as_data_page(),
+            None => {
+                self.env.get_metrics().page_read();
+                let checks = self.env.get_page_checks();
+                let page = committed_page(&self.file, self.page_size(), pgno, self.order, checks)?;
+                // branch pages are copied by every commit, so reads of them
+                // would always conflict
+                if let Some(optimistic) = &self.optimistic {
+                    if !page.get_flags().contains(PageFlag::BRANCH) {
+                        optimistic.reads.borrow_mut().insert(pgno);
+                    }
+                }
+                Ok(page)
+            }
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    fn decompress(&self, data: &[u8]) -> Result<&[u8], DBError> {
+        Ok(self.values.hold(compress::decompress_value(data)?))
+    }
+
+    // dirty pages are in memory already
+    fn prefetch(&self, pgnos: &[Pgno]) {
+        let committed: Vec<Pgno> =
+            pgnos.iter().copied().filter(|&pgno| !self.dirty.contains(pgno)).collect();
+        self.file.prefetch(self.page_size(), &committed);
+    }
+
+    fn is_dirty(&self, pgno: Pgno) -> bool {
+        self.dirty.contains(pgno)
+    }
+}
+
+// Builds a tree bottom-up from entries in increasing key order: each level
+// fills one page at a time, and a full page is handed to the level above as a
+// child before the next one is started. `levels[0]` is the leaf being filled.
+#[derive(Default)]
+struct TreeBuilder {
+    levels: Vec<Option<(DirtyPage, Vec<u8>)>>,
+}
+
+impl TreeBuilder {
+    fn push(
+        &mut self,
+        txn: &mut WriteTxn,
+        level: usize,
+        key: &[u8],
+        data: &[u8],
+        node_flags: NodeFlag,
+    ) -> Result<(), DBError> {
+        if level == self.levels.len() {
+            self.levels.push(None);
+        }
+        let (page, _) = self.levels[level].get_or_insert_with(|| {
+            let flags = match level {
+                0 => txn.leaf_flags(),
+                _ => PageFlag::ALIVE | PageFlag::BRANCH,
+            };
+            let page = DirtyPage::new(txn.alloc.alloc(), txn.page_size(), flags, txn.order);
+            (page, key.to_vec())
+        });
+        // a branch page's first key is implied by its parent
+        let is_first = level > 0 && page.as_data_page()?.num_nodes() == 0;
+        let stored_key = if is_first { &[][..] } else { key };
+        let node = DataNode::from(stored_key, data).with_flags(node_flags);
+        match page.put_node_with_flags(node, PutFlag::OVERWRITE) {
+            Err(DBError::PageFull) if page.as_data_page()?.num_nodes() > 0 => {
+                self.finish_page(txn, level)?;
+                self.push(txn, level, key, data, node_flags)
+            }
+            result => result,
+        }
+    }
+
+    fn finish_page(&mut self, txn: &mut WriteTxn, level: usize) -> Result<(), DBError> {
+        let (page, first_key) = self.levels[level].take().expect("level has a page");
+        let pgno = page.get_pgno();
+        txn.dirty.insert(page);
+        self.push(txn, level + 1, &first_key, &child_data(pgno, 0), NodeFlag::ALIVE)
+    }
+
+    // Hands every partly filled page up to its parent; the page left alone at
+    // the top is the root.
+    fn finish(mut self, txn: &mut WriteTxn) -> Result<Option<Pgno>, DBError> {
+        let mut level = 0;
+        while level + 1 < self.levels.len() {
+            self.finish_page(txn, level)?;
+            level += 1;
+        }
+        Ok(self.levels.pop().flatten().map(|(page, _)| {
+            let pgno = page.get_pgno();
+            txn.dirty.insert(page);
+            pgno
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::check_file;
+    use crate::env::EnvOptions;
+    use crate::merge;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+    use std::fs::{self, File};
+    use tempfile::tempdir;
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("key-{i:08}").into_bytes()
+    }
+
+    fn value(i: u32) -> Vec<u8> {
+        format!("value-{}", i * 7).into_bytes()
+    }
+
+    fn check(path: &std::path::Path, page_size: usize) {
+        let mmap = unsafe { Mmap::map(&File::open(path).unwrap()).unwrap() };
+        let report = check_file(&mmap, page_size);
+        assert!(report.is_ok(), "{:?}", report.problems);
+    }
+
+    #[test]
+    fn test_puts_split_and_survive_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        // insert in a scattered order so splits happen all over the tree
+        let order: Vec<u32> = (0..5000).map(|i| (i * 7919) % 5000).collect();
+
+        let mut txn = env.begin_write();
+        for &i in &order[..2500] {
+            txn.put(&key(i), &value(i)).unwrap();
+        }
+        assert_eq!(txn.get(&key(order[0])).unwrap(), value(order[0]));
+        txn.commit().unwrap();
+
+        let before = env.begin_read().unwrap();
+        let mut txn = env.begin_write();
+        for &i in &order[2500..] {
+            txn.put(&key(i), &value(i)).unwrap();
+        }
+        txn.put(&key(order[0]), b"updated").unwrap();
+        txn.commit().unwrap();
+
+        // a reader of the previous commit isn't affected by the later one
+        assert_eq!(before.get(&key(order[0])).unwrap(), value(order[0]));
+        assert!(matches!(before.get(&key(order[2500])), Err(DBError::KeyNotFound)));
+        drop((before, env));
+
+        let env = Env::open(&path).unwrap();
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get_meta().get_txnid(), 2);
+        assert_eq!(txn.get(&key(order[0])).unwrap(), b"updated");
+        for &i in &order[1..] {
+            assert_eq!(txn.get(&key(i)).unwrap(), value(i));
+        }
+        check(&path, DEFAULT_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_get_owned_outlives_the_txn() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"first").unwrap();
+        let pending = txn.get_owned(b"key").unwrap();
+        txn.put(b"key", b"second").unwrap();
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        let committed = txn.get_owned(b"key").unwrap();
+        assert_eq!(txn.get(b"key").unwrap(), committed);
+        drop(txn);
+        assert_eq!((pending, committed), (b"first".to_vec(), b"second".to_vec()));
+        assert!(matches!(
+            env.begin_read().unwrap().get_owned(b"missing"),
+            Err(DBError::KeyNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_cursor_sees_uncommitted_changes() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        let mut expected = BTreeMap::new();
+        for i in (0..3000).step_by(2) {
+            txn.put(&key(i), &value(i)).unwrap();
+            expected.insert(key(i), value(i));
+        }
+        txn.commit().unwrap();
+
+        // touches some leaves and leaves others as they were committed
+        let mut txn = env.begin_write();
+        for i in (1..1000).step_by(2) {
+            txn.put(&key(i), b"new").unwrap();
+            expected.insert(key(i), b"new".to_vec());
+        }
+        for i in (2000..2500).step_by(2) {
+            txn.delete(&key(i)).unwrap();
+            expected.remove(&key(i));
+        }
+        assert_eq!(txn.get(&key(1)).unwrap(), b"new");
+        assert!(matches!(txn.get(&key(2000)), Err(DBError::KeyNotFound)));
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = txn
+            .tree()
+            .cursor()
+            .unwrap()
+            .map(|entry| entry.map(|(key, data)| (key.into_owned(), data.to_vec())))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(entries, expected.into_iter().collect::<Vec<_>>());
+        assert_eq!(txn.scan_prefix(b"key-0000000").unwrap().count(), 10);
+
+        // while readers still see the last commit
+        let read = env.begin_read().unwrap();
+        assert!(matches!(read.get(&key(1)), Err(DBError::KeyNotFound)));
+        assert_eq!(read.get(&key(2000)).unwrap(), value(2000));
+    }
+
+    #[test]
+    fn test_abort_discards_changes() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        txn.put(b"key", b"value").unwrap();
+        txn.abort();
+
+        assert!(matches!(env.begin_read().unwrap().get(b"key"), Err(DBError::KeyNotFound)));
+        assert_eq!(env.get_meta().get_txnid(), 0);
+    }
+
+    #[test]
+    fn test_entry_api() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        let Entry::Vacant(entry) = txn.entry(b"first").unwrap() else {
+            panic!("nothing is in an empty tree");
+        };
+        assert_eq!(entry.insert(b"1").unwrap(), b"1");
+
+        // enough inserts to split leaves, each read back from where it went
+        for i in 0..2000 {
+            assert_eq!(txn.get_or_insert_with(&key(i), || value(i)).unwrap(), value(i));
+        }
+        let kept = txn.get_or_insert_with(&key(7), || -> Vec<u8> { panic!("key 7 is there") });
+        assert_eq!(kept.unwrap(), value(7));
+        assert_eq!(txn.entry(&key(8)).unwrap().or_insert(b"ignored").unwrap(), value(8));
+        assert_eq!(txn.len(), 2001);
+
+        let Entry::Occupied(entry) = txn.entry(&key(9)).unwrap() else {
+            panic!("key 9 is there");
+        };
+        assert_eq!((entry.key(), entry.get().unwrap()), (&key(9)[..], &value(9)[..]));
+        entry.insert(b"updated").unwrap();
+        let Entry::Occupied(entry) = txn.entry(&key(10)).unwrap() else {
+            panic!("key 10 is there");
+        };
+        entry.remove().unwrap();
+        assert!(matches!(txn.entry(&key(10)).unwrap(), Entry::Vacant(_)));
+        assert!(matches!(txn.entry(b"").unwrap().or_insert(b"x"), Err(DBError::EmptyKey)));
+        assert_eq!(txn.len(), 2000);
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get(&key(9)).unwrap(), b"updated");
+        assert!(matches!(txn.get(&key(10)), Err(DBError::KeyNotFound)));
+        assert_eq!(txn.tree().cursor().unwrap().count(), 2000);
+        check(env.get_path(), DEFAULT_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_entry_count() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::bulk_load(&path, (0..3000).map(|i| (key(i), value(i)))).unwrap();
+        assert_eq!((env.len(), env.is_empty()), (3000, false));
+
+        let mut txn = env.begin_write();
+        txn.put(&key(10), b"overwritten").unwrap();
+        txn.put(&key(3000), b"new").unwrap();
+        txn.delete(&key(20)).unwrap();
+        txn.put(&key(20), b"back").unwrap();
+        txn.delete(&key(30)).unwrap();
+        txn.write_batch((2990..3010).map(|i| (key(i), value(i)))).unwrap();
+        let mut nested = txn.begin_nested();
+        nested.delete(&key(40)).unwrap();
+        assert_eq!(nested.len(), 3008);
+        nested.abort();
+        assert_eq!(txn.len(), 3009);
+        // readers see the count of their own commit until this one is made
+        assert_eq!(env.begin_read().unwrap().len(), 3000);
+        txn.commit().unwrap();
+        assert_eq!(env.begin_read().unwrap().len(), 3009);
+
+        env.compact().unwrap();
+        drop(env);
+        let env = Env::open(&path).unwrap();
+        assert_eq!(env.len(), 3009);
+        let mut txn = env.begin_write();
+        txn.clear();
+        assert!(txn.is_empty());
+        txn.commit().unwrap();
+        assert!(env.begin_read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let dir = tempdir().unwrap();
+        let env = Env::bulk_load(dir.path().join("db"), (0..2000).map(|i| (key(i), value(i))))
+            .unwrap();
+        let height = env.begin_read().unwrap().tree().height().unwrap() as u64;
+        let mut txn = env.begin_write();
+        let stats = txn.stats();
+        assert_eq!((stats.keys_written, stats.pages_allocated, stats.bytes_pending), (0, 0, 0));
+
+        txn.put(&key(3), b"new").unwrap();
+        txn.put(&key(4), b"new").unwrap();
+        txn.delete(&key(5)).unwrap();
+        let stats = txn.stats();
+        assert_eq!(stats.keys_written, 3);
+        // the path down to one leaf, copied on the first put
+        assert_eq!(stats.pages_copied, height);
+        assert_eq!(stats.pages_allocated, height);
+        assert_eq!(stats.bytes_pending, height * DEFAULT_PAGE_SIZE as u64);
+
+        txn.put(&key(1999), b"new").unwrap();
+        assert_eq!(txn.stats().pages_copied, height + 1);
+        assert!(txn.stats().elapsed >= stats.elapsed);
+    }
+
+    #[test]
+    fn test_empty_values_and_keys() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        let mut txn = env.begin_write();
+        // enough members to split, so empty values also go through branches
+        for i in 0..3000 {
+            txn.put(&key(i), b"").unwrap();
+        }
+        assert!(matches!(txn.put(b"", b"value"), Err(DBError::EmptyKey)));
+        assert!(matches!(txn.write_batch([(b"", b"")]), Err(DBError::EmptyKey)));
+        assert!(matches!(txn.compare_and_swap(b"", None, Some(b"")), Err(DBError::EmptyKey)));
+        txn.delete(&key(7)).unwrap();
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get(&key(0)).unwrap(), b"");
+        assert!(matches!(txn.get(&key(7)), Err(DBError::KeyNotFound)));
+        assert!(matches!(txn.get(b""), Err(DBError::KeyNotFound)));
+        let mut cursor = txn.tree().cursor().unwrap();
+        cursor.seek(b"").unwrap();
+        let entries: Vec<_> = cursor.map(Result::unwrap).collect();
+        assert_eq!(entries.len(), 2999);
+        assert!(entries.iter().all(|(_, value)| value.is_empty()));
+        assert_eq!(entries[0].0, key(0));
+        drop(txn);
+
+        let mut txn = env.begin_write();
+        txn.write_batch((3000..3100).map(|i| (key(i), b""))).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(env.begin_read().unwrap().get(&key(3099)).unwrap(), b"");
+        check(&path, DEFAULT_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let mut rng = StdRng::seed_from_u64(1040);
+        for page_size in [MIN_PAGE_SIZE, MAX_PAGE_SIZE] {
+            let dir = tempdir().unwrap();
+            let path = dir.path().join("db");
+            let env = EnvOptions::new().page_size(page_size).open(&path).unwrap();
+            let mut model = BTreeMap::new();
+            let mut txn = env.begin_write();
+            // sizes at and around each limit, mixed with small ones, so that
+            // full pages have to split around the largest nodes
+            for i in 0..400u32 {
+                let key_len = match rng.random_range(0..4) {
+                    0 => MAX_KEY_SIZE - rng.random_range(0..2),
+                    1 => MAX_KEY_SIZE + 1,
+                    _ => rng.random_range(4..32),
+                };
+                let max = max_value_size(page_size, key_len);
+                let value_len = match rng.random_range(0..4) {
+                    0 => max - rng.random_range(0..2),
+                    1 => max + 1,
+                    _ => rng.random_range(0..64),
+                };
+                let mut key = i.to_be_bytes().to_vec();
+                key.resize(key_len, rng.random());
+                let value = vec![rng.random(); value_len];
+                match txn.put(&key, &value) {
+                    Ok(outcome) => {
+                        let inserted = model.insert(key, value).is_none();
+                        assert_eq!(outcome == PutOutcome::Inserted, inserted);
+                    }
+                    Err(DBError::KeyTooLarge { size, max }) => {
+                        assert_eq!((size, max), (key_len, MAX_KEY_SIZE));
+                        assert!(key_len > MAX_KEY_SIZE);
+                    }
+                    Err(DBError::ValueTooLarge { size, max: limit }) => {
+                        assert_eq!((size, limit), (value_len, max));
+                        assert!(key_len <= MAX_KEY_SIZE && value_len > max);
+                    }
+                    Err(err) => panic!("{key_len}-byte key, {value_len}-byte value: {err:?}"),
+                }
+            }
+            let too_long = vec![0; MAX_KEY_SIZE + 1];
+            assert!(matches!(
+                txn.write_batch([(too_long, b"")]),
+                Err(DBError::KeyTooLarge { .. })
+            ));
+            txn.commit().unwrap();
+
+            let txn = env.begin_read().unwrap();
+            for (key, value) in &model {
+                assert_eq!(txn.get(key).unwrap(), value);
+            }
+            assert_eq!(txn.tree().cursor().unwrap().count(), model.len());
+            check(&path, page_size);
+        }
+    }
+
+    #[test]
+    fn test_delete_and_compare_and_swap() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        for i in 0..1000 {
+            txn.put(&key(i), &value(i)).unwrap();
+        }
+        txn.delete(&key(10)).unwrap();
+        assert!(matches!(txn.delete(&key(10)), Err(DBError::KeyNotFound)));
+        assert!(matches!(txn.get(&key(10)), Err(DBError::KeyNotFound)));
+
+        let version = |v: u8| [v; 4];
+        txn.compare_and_swap(b"version", None, Some(&version(1))).unwrap();
+        txn.compare_and_swap(b"version", Some(&version(1)), Some(&version(2))).unwrap();
+        match txn.compare_and_swap(b"version", Some(&version(1)), Some(&version(3))) {
+            Err(DBError::CompareFailed { actual }) => assert_eq!(actual, Some(version(2).to_vec())),
+            other => panic!("{other:?}"),
+        }
+        assert!(matches!(
+            txn.compare_and_swap(&key(10), Some(b"x"), None),
+            Err(DBError::CompareFailed { actual: None })
+        ));
+        txn.compare_and_swap(&key(11), Some(&value(11)), None).unwrap();
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get(b"version").unwrap(), version(2));
+        assert!(matches!(txn.get(&key(11)), Err(DBError::KeyNotFound)));
+        assert_eq!(txn.tree().cursor().unwrap().count(), 999);
+    }
+
+    #[test]
+    fn test_soft_delete() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        let mut txn = env.begin_write();
+        for i in 0..1000 {
+            txn.put(&key(i), &value(i)).unwrap();
+        }
+        txn.soft_delete(&key(10)).unwrap();
+        assert!(matches!(txn.soft_delete(&key(10)), Err(DBError::KeyNotFound)));
+        assert!(matches!(txn.undelete(&key(11)), Err(DBError::KeyNotFound)));
+        assert!(matches!(txn.undelete(b"missing"), Err(DBError::KeyNotFound)));
+        assert!(matches!(txn.get(&key(10)), Err(DBError::KeyNotFound)));
+        assert_eq!(txn.len(), 999);
+        txn.commit().unwrap();
+        drop(env);
+
+        let env = Env::open(&path).unwrap();
+        let txn = env.begin_read().unwrap();
+        assert!(matches!(txn.get(&key(10)), Err(DBError::KeyNotFound)));
+        assert_eq!((txn.len(), txn.tree().cursor().unwrap().count()), (999, 999));
+        let mut cursor = txn.tree().cursor().unwrap().include_deleted();
+        cursor.seek(&key(9)).unwrap();
+        let keys: Vec<_> = cursor.keys().take(3).map(|key| key.unwrap().into_owned()).collect();
+        assert_eq!(keys, [key(9), key(10), key(11)]);
+        drop(txn);
+
+        let mut txn = env.begin_write();
+        txn.undelete(&key(10)).unwrap();
+        assert_eq!(txn.get(&key(10)).unwrap(), value(10));
+        txn.commit().unwrap();
+        let txn = env.begin_read().unwrap();
+        assert_eq!((txn.len(), txn.tree().cursor().unwrap().count()), (1000, 1000));
+        assert!(env.check().unwrap().is_ok());
+    }
+
+    // every leaf at the same depth, and every page but the root at least
+    // `min_fill` full
+    fn assert_balanced(txn: &ReadTxn, min_fill: f64) {
+        let tree = txn.tree();
+        let mut pending: Vec<(Pgno, usize)> =
+            tree.get_root().map(|root| (root, 0)).into_iter().collect();
+        let mut leaf_depths = Vec::new();
+        while let Some((pgno, depth)) = pending.pop() {
+            let page = tree.get_page(pgno).unwrap();
+            if depth > 0 {
+                assert!(page.fill().unwrap() >= min_fill, "page {pgno} is underfull");
+            }
+            if !page.get_flags().contains(PageFlag::BRANCH) {
+                leaf_depths.push(depth);
+                continue;
+            }
+            let branch = BranchPage::from(page).unwrap();
+            assert!(depth > 0 || branch.num_children() > 1, "root has a single child");
+            for idx in 0..branch.num_children() {
+                pending.push((branch.child_at(idx).unwrap(), depth + 1));
+            }
+        }
+        leaf_depths.dedup();
+        assert!(leaf_depths.len() <= 1, "leaves at depths {leaf_depths:?}");
+    }
+
+    #[test]
+    fn test_put_flags() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        for i in (0..1000).step_by(2) {
+            txn.put_with_flags(&key(i), &value(i), PutFlag::APPEND).unwrap();
+        }
+        txn.commit().unwrap();
+
+        // rejected before anything is copied, wherever the key would go
+        let mut txn = env.begin_write();
+        for i in [0, 1, 500, 998] {
+            assert!(matches!(
+                txn.put_with_flags(&key(i), b"x", PutFlag::APPEND),
+                Err(DBError::KeyExists)
+            ));
+        }
+        assert!(matches!(txn.put_no_overwrite(&key(500), b"x"), Err(DBError::KeyExists)));
+        assert_eq!((txn.stats().keys_written, txn.stats().pages_copied), (0, 0));
+
+        txn.put_no_overwrite(&key(501), b"new").unwrap();
+        assert_eq!(txn.put(&key(500), b"changed").unwrap(), PutOutcome::Replaced);
+        assert_eq!(txn.put(&key(503), b"new").unwrap(), PutOutcome::Inserted);
+        assert_eq!(
+            txn.put_with_flags(&key(1000), b"last", PutFlag::APPEND).unwrap(),
+            PutOutcome::Inserted
+        );
+        // a key deleted in this transaction is inserted again
+        txn.delete(&key(503)).unwrap();
+        assert_eq!(txn.put(&key(503), b"again").unwrap(), PutOutcome::Inserted);
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get(&key(0)).unwrap(), value(0));
+        assert_eq!(txn.get(&key(500)).unwrap(), b"changed");
+        assert_eq!(txn.get(&key(501)).unwrap(), b"new");
+        assert_eq!(txn.get(&key(1000)).unwrap(), b"last");
+    }
+
+    #[test]
+    fn test_put_append() {
+        let dir = tempdir().unwrap();
+        let fill = |bias, append| {
+            let path = dir.path().join(format!("{bias:?}-{append}"));
+            let env = EnvOptions::new().split_bias(bias).open(&path).unwrap();
+            let mut txn = env.begin_write();
+            for i in 0..5000 {
+                match append {
+                    true => txn.put_append(&key(i), &value(i)).unwrap(),
+                    false => {
+                        txn.put(&key(i), &value(i)).unwrap();
+                    }
+                }
+            }
+            txn.commit().unwrap();
+            check(&path, DEFAULT_PAGE_SIZE);
+            let txn = env.begin_read().unwrap();
+            assert_eq!(txn.len(), 5000);
+            assert_eq!(txn.get(&key(4321)).unwrap(), value(4321));
+            let stat = txn.tree().stat().unwrap();
+            stat.fill_factor()
+        };
+        let middle = fill(SplitBias::Middle, false);
+        assert!(middle < 0.7, "{middle}");
+        for fill in [fill(SplitBias::Middle, true), fill(SplitBias::Right, false)] {
+            assert!(fill > 0.9, "{fill}");
+        }
+
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        for i in 0..1000 {
+            txn.put_append(&key(i), &value(i)).unwrap();
+        }
+        assert!(matches!(txn.put_append(&key(999), b"x"), Err(DBError::KeyExists)));
+        assert!(matches!(txn.put_append(&key(5), b"x"), Err(DBError::KeyExists)));
+        // the last leaf emptied, the last key is in the one before it
+        for i in (900..1000).rev() {
+            txn.delete(&key(i)).unwrap();
+        }
+        assert!(matches!(txn.put_append(&key(800), b"x"), Err(DBError::KeyExists)));
+        txn.put_append(&key(950), b"after").unwrap();
+        txn.commit().unwrap();
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.len(), 901);
+        assert_eq!(txn.get(&key(950)).unwrap(), b"after");
+    }
+
+    #[test]
+    fn test_deletes_rebalance() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let min_fill = 0.4;
+        let env = EnvOptions::new().min_fill(min_fill).open(&path).unwrap();
+        let mut rng = StdRng::seed_from_u64(1039);
+        let mut model = BTreeMap::new();
+        // grows the tree first, then mostly shrinks it
+        for round in 0..20 {
+            let put_chance = if round < 8 { 0.8 } else { 0.25 };
+            let mut txn = env.begin_write();
+            for _ in 0..1000 {
+                let i = rng.random_range(0..5000);
+                if rng.random_bool(put_chance) {
+                    txn.put(&key(i), &value(i)).unwrap();
+                    model.insert(key(i), value(i));
+                } else if model.remove(&key(i)).is_some() {
+                    txn.delete(&key(i)).unwrap();
+                }
+            }
+            txn.commit().unwrap();
+            let txn = env.begin_read().unwrap();
+            assert_balanced(&txn, min_fill);
+            let entries: Vec<_> = txn
+                .tree()
+                .cursor()
+                .unwrap()
+                .map(|entry| entry.map(|(key, value)| (key.into_owned(), value.to_vec())))
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert!(entries.iter().cloned().eq(model.clone()), "round {round}");
+        }
+        check(&path, DEFAULT_PAGE_SIZE);
+
+        let mut txn = env.begin_write();
+        for key in model.keys() {
+            txn.delete(key).unwrap();
+        }
+        txn.commit().unwrap();
+        let txn = env.begin_read().unwrap();
+        let stat = txn.tree().stat().unwrap();
+        assert_eq!((stat.depth, stat.entries), (1, 0));
+    }
+
+    #[test]
+    fn test_merge() {
+        let dir = tempdir().unwrap();
+        let options = EnvOptions::new().merge_operator(merge::add_u64_le);
+        let env = options.open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        for i in 0..10u64 {
+            txn.merge(b"counter", &i.to_le_bytes()).unwrap();
+        }
+        txn.commit().unwrap();
+        let mut txn = env.begin_write();
+        txn.merge(b"counter", &5u64.to_le_bytes()).unwrap();
+        assert_eq!(txn.get(b"counter").unwrap(), 50u64.to_le_bytes());
+        txn.commit().unwrap();
+
+        let options = EnvOptions::new().merge_operator(merge::append);
+        let env = options.open(dir.path().join("list")).unwrap();
+        let mut txn = env.begin_write();
+        for item in [&b"a"[..], b"bc", b"d"] {
+            txn.merge(b"list", item).unwrap();
+        }
+        assert_eq!(txn.get(b"list").unwrap(), b"abcd");
+
+        let env = Env::open(dir.path().join("plain")).unwrap();
+        assert!(matches!(env.begin_write().merge(b"list", b"a"), Err(DBError::NoMergeOperator)));
+    }
+
+    #[test]
+    fn test_bulk_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let entries = (0..20_000).map(|i| (key(i), value(i)));
+        let env = EnvOptions::new().page_size(8192).bulk_load(&path, entries).unwrap();
+
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get_meta().get_txnid(), 1);
+        for i in (0..20_000).step_by(37) {
+            assert_eq!(txn.get(&key(i)).unwrap(), value(i));
+        }
+        assert!(matches!(txn.get(b"missing"), Err(DBError::KeyNotFound)));
+        // built pages are filled rather than split, so they stay dense
+        let filled_pages = txn.get_meta().get_next_pgno() - 2;
+        assert!(filled_pages < 20_000 * 28 / 8192 + 10, "{filled_pages} pages");
+        check(&path, 8192);
+
+        assert!(Env::bulk_load(&path, [(b"a", b"b")]).is_err());
+        assert!(matches!(
+            Env::bulk_load(dir.path().join("unsorted"), [(b"b", b"1"), (b"a", b"2")]),
+            Err(DBError::BatchNotSorted)
+        ));
+    }
+
+    #[test]
+    fn test_ingest_file() {
+        let dir = tempdir().unwrap();
+        let env = Env::bulk_load(dir.path().join("db"), (0..3000).map(|i| (key(i), value(i))))
+            .unwrap();
+        let built = |name: &str, keys: std::ops::Range<u32>| {
+            let path = dir.path().join(name);
+            Env::bulk_load(&path, keys.map(|i| (key(i), value(i)))).unwrap();
+            path
+        };
+        // same height and entirely after: linked in as a subtree
+        let after = built("after", 3000..6000);
+        // a single leaf, so not the same height: copied entry by entry
+        let sparse = built("sparse", 1_000_000..1_000_010);
+
+        let mut txn = env.begin_write();
+        assert!(matches!(
+            txn.ingest_file(&after, &key(2000)[..]..&key(7000)[..]),
+            Err(DBError::RangeOverlap)
+        ));
+        assert!(matches!(
+            txn.ingest_file(&after, &key(3000)[..]..&key(4000)[..]),
+            Err(DBError::IncompatibleFile { .. })
+        ));
+        let next_pgno = txn.alloc.get_next_pgno();
+        txn.ingest_file(&after, &key(3000)[..]..).unwrap();
+        // only the pages of the file and one new root were added
+        let file_pages = fs::metadata(&after).unwrap().len() / DEFAULT_PAGE_SIZE as u64 - 2;
+        assert_eq!(txn.alloc.get_next_pgno() - next_pgno, file_pages + 1);
+        txn.ingest_file(&sparse, &key(1_000_000)[..]..=&key(1_000_009)[..]).unwrap();
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        let keys = (0..6000).chain(1_000_000..1_000_010);
+        assert_eq!(txn.tree().cursor().unwrap().count(), 6010);
+        for i in keys {
+            assert_eq!(txn.get(&key(i)).unwrap(), value(i), "key {i}");
+        }
+        check(env.get_path(), DEFAULT_PAGE_SIZE);
+
+        let odd_size = dir.path().join("odd-size");
+        EnvOptions::new().page_size(8192).bulk_load(&odd_size, [(b"z", b"1")]).unwrap();
+        assert!(matches!(
+            env.begin_write().ingest_file(&odd_size, ..),
+            Err(DBError::IncompatibleFile { .. })
+        ));
+    }
+
+    #[test]
+    fn test_write_batch_into_existing_tree() {
+        let dir = tempdir().unwrap();
+        let env = Env::bulk_load(dir.path().join("db"), (0..6000).map(|i| (key(2 * i), value(i))))
+            .unwrap();
+
+        // interleave the odd keys with what's there, overwriting a few even ones
+        let mut txn = env.begin_write();
+        let batch = (0..12000).filter(|i| i % 2 == 1 || i % 1000 == 0).map(|i| (key(i), value(i)));
+        txn.write_batch(batch).unwrap();
+        assert!(matches!(
+            txn.write_batch([(key(5), value(5)), (key(5), value(5))]),
+            Err(DBError::BatchNotSorted)
+        ));
+        txn.commit().unwrap();
+
+        let txn = env.begin_read().unwrap();
+        for i in 0..12000 {
+            let expected = if i % 2 == 1 || i % 1000 == 0 { value(i) } else { value(i / 2) };
+            assert_eq!(txn.get(&key(i)).unwrap(), expected, "key {i}");
+        }
+    }
+
+    #[test]
+    fn test_nested_txn() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        txn.put(&key(0), &value(0)).unwrap();
+
+        let mut nested = txn.begin_nested();
+        nested.put(&key(1), &value(1)).unwrap();
+        let mut inner = nested.begin_nested();
+        for i in 2..2000 {
+            inner.put(&key(i), &value(i)).unwrap();
+        }
+        inner.delete(&key(0)).unwrap();
+        inner.abort();
+        assert_eq!(nested.get(&key(0)).unwrap(), value(0));
+        assert!(matches!(nested.get(&key(2)), Err(DBError::KeyNotFound)));
+        nested.commit();
+        assert_eq!(txn.stats().keys_written, 2);
+
+        drop(txn.begin_nested());
+        txn.commit().unwrap();
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.tree().cursor().unwrap().count(), 2);
+        assert_eq!(txn.get(&key(1)).unwrap(), value(1));
+        drop(txn);
+        check(&dir.path().join("db"), DEFAULT_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_next_id() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = Env::open(&path).unwrap();
+        assert_eq!(env.next_id().unwrap(), 1);
+        let mut txn = env.begin_write();
+        assert_eq!((txn.next_id().unwrap(), txn.next_id().unwrap()), (2, 3));
+        txn.abort();
+        // an aborted transaction's ids are handed out again
+        let mut txn = env.begin_write();
+        assert_eq!(txn.next_id().unwrap(), 2);
+        let mut nested = txn.begin_nested();
+        assert_eq!(nested.next_id().unwrap(), 3);
+        nested.abort();
+        let id = txn.next_id().unwrap();
+        txn.put(&id.to_be_bytes(), b"row").unwrap();
+        txn.commit().unwrap();
+        drop(env);
+
+        let env = Env::open(&path).unwrap();
+        assert_eq!(env.info().unwrap().sequence, 3);
+        assert_eq!(env.begin_read().unwrap().get(&3u64.to_be_bytes()).unwrap(), b"row");
+        assert_eq!(env.next_id().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_optimistic_txns() {
+        let dir = tempdir().unwrap();
+        let env = EnvOptions::new().page_size(MIN_PAGE_SIZE).open(dir.path().join("db")).unwrap();
+        let key = |i: u32| format!("key{i:05}").into_bytes();
+        let mut txn = env.begin_write();
+        txn.write_batch((0..2_000).map(|i| (key(i), [7; 40]))).unwrap();
+        txn.commit().unwrap();
+        let conflict = |result| matches!(result, Err(DBError::Conflict { .. }));
+
+        // writes to leaves far apart both commit, the second over the first
+        let mut first = env.begin_optimistic().unwrap();
+        let mut second = env.begin_optimistic().unwrap();
+        first.put(&key(0), b"first").unwrap();
+        assert_eq!(second.get(&key(1_000)).unwrap(), [7; 40]);
+        second.put(&key(1_999), b"second").unwrap();
+        second.delete(&key(1_998)).unwrap();
+        first.commit().unwrap();
+        second.commit().unwrap();
+        let txn = env.begin_read().unwrap();
+        assert_eq!(txn.get(&key(0)).unwrap(), b"first");
+        assert_eq!(txn.get(&key(1_999)).unwrap(), b"second");
+        assert_eq!(txn.len(), 1_999);
+        drop(txn);
+
+        // the second read a leaf the first replaced
+        let mut first = env.begin_optimistic().unwrap();
+        let mut second = env.begin_optimistic().unwrap();
+        first.put(&key(1), b"first").unwrap();
+        assert_eq!(second.get(&key(1)).unwrap(), [7; 40]);
+        first.commit().unwrap();
+        second.put(&key(1_500), b"second").unwrap();
+        assert!(conflict(second.commit()));
+        assert_eq!(env.begin_read().unwrap().get(&key(1_500)).unwrap(), [7; 40]);
+
+        // a commit of the single writer counts as much as an optimistic one,
+        // and so does taking ids
+        let mut optimistic = env.begin_optimistic().unwrap();
+        optimistic.put(&key(2), b"optimistic").unwrap();
+        optimistic.next_id().unwrap();
+        let mut txn = env.begin_write();
+        txn.next_id().unwrap();
+        txn.commit().unwrap();
+        assert!(conflict(optimistic.commit()));
+
+        // with no commit in between, an optimistic transaction commits as is
+        let mut optimistic = env.begin_optimistic().unwrap();
+        assert_eq!(optimistic.next_id().unwrap(), 2);
+        optimistic.commit().unwrap();
+        assert_eq!(env.info().unwrap().sequence, 2);
+        assert!(env.check().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let mut txn = env.begin_write();
+        for i in 0..100 {
+            txn.put(&key(i), &value(i)).unwrap();
+        }
+        let before = txn.savepoint();
+        for i in 100..2000 {
+            txn.put(&key(i), &value(i)).unwrap();
+        }
+        txn.delete(&key(0)).unwrap();
+        txn.set_user_meta(b"late").unwrap();
+        let after = txn.savepoint();
+        txn.put(&key(5000), b"dropped").unwrap();
+        txn.rollback_to(after).unwrap();
+        assert_eq!(txn.len(), 1999);
+        assert!(matches!(txn.get(&key(5000)), Err(DBError::KeyNotFound)));
+
+        // the writes since validate too late, and go
+        txn.rollback_to(before).unwrap();
+        assert_eq!(txn.len(), 100);
+        assert_eq!(txn.get(&key(0)).unwrap(), value(0));
+        assert!(matches!(txn.get(&key(100)), Err(DBError::KeyNotFound)));
+        txn.put(&key(100), b"kept").unwrap();
+        let stale = txn.savepoint();
+        txn.commit().unwrap();
+
+        let mut txn = env.begin_write();
+        assert!(matches!(txn.rollback_to(stale), Err(DBError::Unsupported { .. })));
+        drop(txn);
+        assert_eq!(env.len(), 101);
+        assert!(env.info().unwrap().user_meta.is_empty());
+        check(&dir.path().join("db"), DEFAULT_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_txn_full() {
+        let dir = tempdir().unwrap();
+        let env = EnvOptions::new().max_dirty_pages(8).open(dir.path().join("db")).unwrap();
+        let mut next = 0;
+        let mut commits = 0;
+        while next < 5000 {
+            let mut txn = env.begin_write();
+            loop {
+                match txn.put(&key(next), &value(next)) {
+                    Ok(_) => next += 1,
+                    Err(DBError::TxnFull { max: 8 }) => break,
+                    Err(err) => panic!("{err}"),
+                }
+                if next == 5000 {
+                    break;
+                }
+            }
+            assert!(txn.stats().bytes_pending <= 10 * DEFAULT_PAGE_SIZE as u64);
+            txn.commit().unwrap();
+            commits += 1;
+        }
+        assert!(commits > 1);
+        assert_eq!(env.begin_read().unwrap().tree().cursor().unwrap().count(), 5000);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_values() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let env = EnvOptions::new().compress_values(64).open(&path).unwrap();
+        let large = |i: u32| format!("{:0>200}", i).into_bytes();
+        let mut rng = StdRng::seed_from_u64(3);
+        let random: Vec<u8> = (0..200).map(|_| rng.random()).collect();
+        let mut txn = env.begin_write();
+        for i in 0..500 {
+            txn.put(&key(i), &large(i)).unwrap();
+        }
+        txn.put(b"random", &random).unwrap();
+        txn.put(b"small", b"small").unwrap();
+        assert_eq!(txn.get(&key(7)).unwrap(), large(7));
+        txn.commit().unwrap();
+
+        let stat = env.begin_read().unwrap().tree().stat().unwrap();
+        // split across leaves, but neither the small value nor the one that
+        // wouldn't get smaller
+        assert_eq!(stat.compressed_values, 500);
+        assert_eq!(stat.uncompressed_bytes, 500 * 200);
+        assert!(stat.compression_ratio() > 5.0 && stat.leaf_pages > 1);
+        drop(env);
+
+        // reads don't need the option
+        let env = Env::open(&path).unwrap();
+        let txn = env.begin_read().unwrap();
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = txn
+            .tree()
+            .cursor()
+            .unwrap()
+            .map(|entry| entry.map(|(key, data)| (key.into_owned(), data.to_vec())))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 502);
+        assert_eq!(entries[499], (key(499), large(499)));
+        assert_eq!(txn.get(b"random").unwrap(), random);
+        check(&path, DEFAULT_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_fixed_keys() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = EnvOptions::new().fixed_keys(true);
+        let entries = (0..3000u64).map(|i| (i.to_be_bytes(), value(1)));
+        let env = options.bulk_load(&path, entries).unwrap();
+        let mut expected: BTreeMap<u64, Vec<u8>> = (0..3000).map(|i| (i, value(1))).collect();
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut txn = env.begin_write();
+        for _ in 0..4000 {
+            let key = rng.random_range(0..6000u64);
+            if rng.random_bool(0.3) && expected.remove(&key).is_some() {
+                txn.delete(&key.to_be_bytes()).unwrap();
+            } else {
+                let value = value(rng.random_range(0..100));
+                txn.put(&key.to_be_bytes(), &value).unwrap();
+                expected.insert(key, value);
+            }
+        }
+        assert!(matches!(
+            txn.put(b"key", b"value"),
+            Err(DBError::KeySizeMismatch { size: 3, expected: FIXED_KEY_SIZE })
+        ));
+        txn.commit().unwrap();
+        drop(env);
+        check(&path, DEFAULT_PAGE_SIZE);
+
+        // the pages say how they're laid out, whatever the options
+        let env = Env::open(&path).unwrap();
+        let txn = env.begin_read().unwrap();
+        let leaf = txn.tree().descend(&0u64.to_be_bytes()).unwrap().leaf;
+        assert!(leaf.as_data_page().has_fixed_keys());
+        let entries: Vec<_> = txn.tree().cursor().unwrap().map(|entry| entry.unwrap()).collect();
+        assert_eq!(entries.len(), expected.len());
+        for ((key, data), (expected_key, expected_data)) in entries.iter().zip(&expected) {
+            assert_eq!((&key[..], *data), (&expected_key.to_be_bytes()[..], &expected_data[..]));
+        }
+    }
+}
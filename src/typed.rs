@@ -0,0 +1,821 @@
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+use crate::constants::*;
+use crate::env::Env;
+
+// Key encoding, chosen so that byte order matches the order of the values:
+//   bool, integers: fixed width, big-endian, sign bit flipped for signed ones
+//   floats: big-endian bits, all flipped for negatives, sign flipped otherwise
+//   char: as a u32
+//   strings and bytes: 0x00 escaped as 0x00 0xff, ended by 0x00 0x00 (a
+//     length prefix would sort "b" after "aa")
+//   Option: 0 for None, or 1 then the value
+//   sequences and maps: 1 before each element (or key and value), 0 after
+//     the last, so a prefix sorts first
+//   enums: the variant index as a u32, then its fields
+//   tuples and structs: their fields in order, nothing else
+// Only the variant index and field order are recorded, so reordering either
+// changes what existing keys decode to.
+const SIGN_64: u64 = 1 << 63;
+const SIGN_32: u32 = 1 << 31;
+
+const MORE: u8 = 1;
+const END: u8 = 0;
+
+/// Encodes `key` so that the byte order of encoded keys matches the order of
+/// the values (as derived `Ord` sees them), for use with `KeyOrder::Bytes`.
+pub fn encode_key<K: Serialize + ?Sized>(key: &K) -> Result<Vec<u8>, DBError> {
+    let mut encoder = KeyEncoder { out: Vec::new() };
+    key.serialize(&mut encoder)?;
+    Ok(encoder.out)
+}
+
+pub fn decode_key<K: DeserializeOwned>(bytes: &[u8]) -> Result<K, DBError> {
+    let mut decoder = KeyDecoder { input: bytes };
+    let key = K::deserialize(&mut decoder)?;
+    if !decoder.input.is_empty() {
+        return Err(CodecError("trailing bytes after key".into()).into());
+    }
+    Ok(key)
+}
+
+/// Encodes `value` compactly with postcard; unlike keys, the bytes carry no
+/// order.
+pub fn encode_value<V: Serialize + ?Sized>(value: &V) -> Result<Vec<u8>, DBError> {
+    postcard::to_allocvec(value).map_err(|err| DBError::Encoding {
+        message: err.to_string(),
+    })
+}
+
+pub fn decode_value<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, DBError> {
+    postcard::from_bytes(bytes).map_err(|err| DBError::Encoding {
+        message: err.to_string(),
+    })
+}
+
+/// Typed access to an environment's tree: keys and values are encoded with
+/// `encode_key` and `encode_value`. Each call runs in its own transaction;
+/// use the encoding functions with a `WriteTxn` to group writes.
+pub struct TypedDb<'env, K, V> {
+    env: &'env Env,
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<'env, K, V> TypedDb<'env, K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn new(env: &'env Env) -> Self {
+        TypedDb {
+            env,
+            types: PhantomData,
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Result<V, DBError> {
+        decode_value(self.env.begin_read()?.get(&encode_key(key)?)?)
+    }
+
+    pub fn put(&self, key: &K, value: &V) -> Result<(), DBError> {
+        let mut txn = self.env.begin_write();
+        txn.put(&encode_key(key)?, &encode_value(value)?)?;
+        txn.commit()
+    }
+
+    /// Removes `key`, failing with `KeyNotFound` if it isn't there.
+    pub fn delete(&self, key: &K) -> Result<(), DBError> {
+        let mut txn = self.env.begin_write();
+        txn.delete(&encode_key(key)?)?;
+        txn.commit()
+    }
+
+    /// Every entry with a key in `range`, in key order.
+    pub fn range(&self, range: impl RangeBounds<K>) -> Result<Vec<(K, V)>, DBError> {
+        let encode = |bound: Bound<&K>| -> Result<Bound<Vec<u8>>, DBError> {
+            Ok(match bound {
+                Bound::Included(key) => Bound::Included(encode_key(key)?),
+                Bound::Excluded(key) => Bound::Excluded(encode_key(key)?),
+                Bound::Unbounded => Bound::Unbounded,
+            })
+        };
+        let (start, end) = (encode(range.start_bound())?, encode(range.end_bound())?);
+        let bytes_range = (start.as_ref().map(Vec::as_slice), end.as_ref().map(Vec::as_slice));
+        let txn = self.env.begin_read()?;
+        let entries = txn.tree().page(bytes_range, 0, usize::MAX)?;
+        entries
+            .into_iter()
+            .map(|(key, data)| Ok((decode_key(&key)?, decode_value(data)?)))
+            .collect()
+    }
+}
+
+// only lives inside the codec; surfaced as a `DBError`
+#[derive(Debug)]
+struct CodecError(String);
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl ser::Error for CodecError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        CodecError(msg.to_string())
+    }
+}
+
+impl de::Error for CodecError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        CodecError(msg.to_string())
+    }
+}
+
+impl From<CodecError> for DBError {
+    fn from(err: CodecError) -> Self {
+        DBError::Encoding { message: err.0 }
+    }
+}
+
+struct KeyEncoder {
+    out: Vec<u8>,
+}
+
+impl KeyEncoder {
+    fn escaped(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            match byte {
+                0 => self.out.extend_from_slice(&[0, 0xff]),
+                byte => self.out.push(byte),
+            }
+        }
+        self.out.extend_from_slice(&[0, 0]);
+    }
+}
+
+impl ser::Serializer for &mut KeyEncoder {
+    type Ok = ();
+    type Error = CodecError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<(), CodecError> {
+        self.out.push(v as u8);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), CodecError> {
+        self.serialize_u8(v as u8 ^ 0x80)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), CodecError> {
+        self.serialize_u16(v as u16 ^ 0x8000)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), CodecError> {
+        self.serialize_u32(v as u32 ^ SIGN_32)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), CodecError> {
+        self.serialize_u64(v as u64 ^ SIGN_64)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), CodecError> {
+        self.serialize_u128(v as u128 ^ (1 << 127))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), CodecError> {
+        self.out.push(v);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), CodecError> {
+        self.out.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), CodecError> {
+        self.out.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), CodecError> {
+        self.out.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), CodecError> {
+        self.out.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), CodecError> {
+        let bits = v.to_bits();
+        self.serialize_u32(if bits & SIGN_32 != 0 { !bits } else { bits ^ SIGN_32 })
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), CodecError> {
+        let bits = v.to_bits();
+        self.serialize_u64(if bits & SIGN_64 != 0 { !bits } else { bits ^ SIGN_64 })
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), CodecError> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), CodecError> {
+        self.escaped(v.as_bytes());
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), CodecError> {
+        self.escaped(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), CodecError> {
+        self.serialize_u8(0)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), CodecError> {
+        self.out.push(1);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), CodecError> {
+        self.serialize_u32(variant_index)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), CodecError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), CodecError> {
+        self.out.extend_from_slice(&variant_index.to_be_bytes());
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self, CodecError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, CodecError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self, CodecError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, CodecError> {
+        self.out.extend_from_slice(&variant_index.to_be_bytes());
+        Ok(self)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self, CodecError> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, CodecError> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, CodecError> {
+        self.out.extend_from_slice(&variant_index.to_be_bytes());
+        Ok(self)
+    }
+}
+
+impl ser::SerializeSeq for &mut KeyEncoder {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CodecError> {
+        self.out.push(MORE);
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), CodecError> {
+        self.out.push(END);
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut KeyEncoder {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), CodecError> {
+        self.out.push(MORE);
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CodecError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), CodecError> {
+        self.out.push(END);
+        Ok(())
+    }
+}
+
+// fixed-length compounds are their fields back to back
+macro_rules! fields {
+    ($($trait:ident :: $method:ident),*) => {$(
+        impl ser::$trait for &mut KeyEncoder {
+            type Ok = ();
+            type Error = CodecError;
+
+            fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CodecError> {
+                value.serialize(&mut **self)
+            }
+
+            fn end(self) -> Result<(), CodecError> {
+                Ok(())
+            }
+        }
+    )*};
+}
+
+fields!(
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+macro_rules! named_fields {
+    ($($trait:ident),*) => {$(
+        impl ser::$trait for &mut KeyEncoder {
+            type Ok = ();
+            type Error = CodecError;
+
+            fn serialize_field<T: Serialize + ?Sized>(
+                &mut self,
+                _key: &'static str,
+                value: &T,
+            ) -> Result<(), CodecError> {
+                value.serialize(&mut **self)
+            }
+
+            fn end(self) -> Result<(), CodecError> {
+                Ok(())
+            }
+        }
+    )*};
+}
+
+named_fields!(SerializeStruct, SerializeStructVariant);
+
+struct KeyDecoder<'de> {
+    input: &'de [u8],
+}
+
+impl<'de> KeyDecoder<'de> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+        if self.input.len() < N {
+            return Err(CodecError("key ends early".into()));
+        }
+        let (bytes, rest) = self.input.split_at(N);
+        self.input = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn escaped(&mut self) -> Result<Vec<u8>, CodecError> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.take::<1>()? {
+                [0] => match self.take::<1>()? {
+                    [0] => return Ok(bytes),
+                    [0xff] => bytes.push(0),
+                    _ => return Err(CodecError("malformed escape in key".into())),
+                },
+                [byte] => bytes.push(byte),
+            }
+        }
+    }
+
+    fn marker(&mut self) -> Result<bool, CodecError> {
+        match self.take::<1>()? {
+            [MORE] => Ok(true),
+            [END] => Ok(false),
+            _ => Err(CodecError("malformed sequence in key".into())),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut KeyDecoder<'de> {
+    type Error = CodecError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, CodecError> {
+        Err(CodecError("keys are not self-describing".into()))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        match self.take::<1>()? {
+            [0] => visitor.visit_bool(false),
+            [1] => visitor.visit_bool(true),
+            _ => Err(CodecError("malformed bool in key".into())),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_i8((self.take::<1>()?[0] ^ 0x80) as i8)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_i16((u16::from_be_bytes(self.take()?) ^ 0x8000) as i16)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_i32((u32::from_be_bytes(self.take()?) ^ SIGN_32) as i32)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_i64((u64::from_be_bytes(self.take()?) ^ SIGN_64) as i64)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_i128((u128::from_be_bytes(self.take()?) ^ (1 << 127)) as i128)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_u8(self.take::<1>()?[0])
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_u16(u16::from_be_bytes(self.take()?))
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_u32(u32::from_be_bytes(self.take()?))
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_u64(u64::from_be_bytes(self.take()?))
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_u128(u128::from_be_bytes(self.take()?))
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        let bits = u32::from_be_bytes(self.take()?);
+        let bits = if bits & SIGN_32 != 0 { bits ^ SIGN_32 } else { !bits };
+        visitor.visit_f32(f32::from_bits(bits))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        let bits = u64::from_be_bytes(self.take()?);
+        let bits = if bits & SIGN_64 != 0 { bits ^ SIGN_64 } else { !bits };
+        visitor.visit_f64(f64::from_bits(bits))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        let c = char::from_u32(u32::from_be_bytes(self.take()?));
+        visitor.visit_char(c.ok_or_else(|| CodecError("malformed char in key".into()))?)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        let string = String::from_utf8(self.escaped()?)
+            .map_err(|_| CodecError("malformed string in key".into()))?;
+        visitor.visit_string(string)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_byte_buf(self.escaped()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        match self.take::<1>()? {
+            [0] => visitor.visit_none(),
+            [1] => visitor.visit_some(self),
+            _ => Err(CodecError("malformed option in key".into())),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_seq(Elements { decoder: self, remaining: None })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        visitor.visit_seq(Elements { decoder: self, remaining: Some(len) })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        visitor.visit_map(Elements { decoder: self, remaining: None })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, CodecError> {
+        self.deserialize_any(visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+// the elements of a sequence or map, either marked one by one or a known
+// number of fields
+struct Elements<'a, 'de> {
+    decoder: &'a mut KeyDecoder<'de>,
+    remaining: Option<usize>,
+}
+
+impl Elements<'_, '_> {
+    fn has_next(&mut self) -> Result<bool, CodecError> {
+        match &mut self.remaining {
+            Some(0) => Ok(false),
+            Some(remaining) => {
+                *remaining -= 1;
+                Ok(true)
+            }
+            None => self.decoder.marker(),
+        }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_, 'de> {
+    type Error = CodecError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, CodecError> {
+        if !self.has_next()? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.decoder).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.remaining
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Elements<'_, 'de> {
+    type Error = CodecError;
+
+    fn next_key_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, CodecError> {
+        if !self.has_next()? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.decoder).map(Some)
+    }
+
+    fn next_value_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<T::Value, CodecError> {
+        seed.deserialize(&mut *self.decoder)
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut KeyDecoder<'de> {
+    type Error = CodecError;
+    type Variant = Self;
+
+    fn variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<(T::Value, Self), CodecError> {
+        let index = u32::from_be_bytes(self.take()?);
+        let variant = seed.deserialize(index.into_deserializer())?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut KeyDecoder<'de> {
+    type Error = CodecError;
+
+    fn unit_variant(self) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, CodecError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, CodecError> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::fmt::Debug;
+    use tempfile::tempdir;
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
+    enum Kind {
+        Plain,
+        Tagged(String),
+        Pair { a: i32, b: Option<u8> },
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
+    struct Id {
+        tenant: u16,
+        name: String,
+        kind: Kind,
+        path: Vec<i64>,
+    }
+
+    // encoded keys sort the way the values do, and decode back to them
+    fn assert_ordered<K: Serialize + DeserializeOwned + PartialOrd + Debug>(keys: &[K]) {
+        let encoded: Vec<Vec<u8>> = keys.iter().map(|key| encode_key(key).unwrap()).collect();
+        for (key, bytes) in keys.iter().zip(&encoded) {
+            assert_eq!(&decode_key::<K>(bytes).unwrap(), key);
+        }
+        for (i, pair) in encoded.windows(2).enumerate() {
+            assert!(pair[0] < pair[1], "{:?} >= {:?}", keys[i], keys[i + 1]);
+        }
+    }
+
+    #[test]
+    fn test_keys_keep_their_order() {
+        assert_ordered(&[i64::MIN, -300, -1, 0, 1, 255, 256, i64::MAX]);
+        assert_ordered(&[i8::MIN, -1, 0, i8::MAX]);
+        assert_ordered(&[f64::NEG_INFINITY, -2.5, -0.0, 0.0, 1e-9, 3.0, f64::INFINITY]);
+        assert_ordered(&["", "\0", "\0\0", "a", "a\0b", "aa", "b"].map(String::from));
+        assert_ordered(&[None, Some(0u32), Some(7)]);
+        assert_ordered(&[vec![], vec![1u8], vec![1, 0], vec![1, 2], vec![2]]);
+        assert_ordered(&[(1u8, "z".to_string()), (2, "a".to_string())]);
+        let id = |tenant, name: &str, kind, path: &[i64]| Id {
+            tenant,
+            name: name.into(),
+            kind,
+            path: path.to_vec(),
+        };
+        assert_ordered(&[
+            id(1, "a", Kind::Plain, &[]),
+            id(1, "a", Kind::Tagged("x".into()), &[]),
+            id(1, "a", Kind::Pair { a: -5, b: None }, &[]),
+            id(1, "a", Kind::Pair { a: -5, b: Some(1) }, &[-1]),
+            id(1, "b", Kind::Plain, &[]),
+            id(2, "", Kind::Plain, &[]),
+        ]);
+
+        assert!(matches!(decode_key::<u32>(&[0, 0, 1]), Err(DBError::Encoding { .. })));
+        assert!(matches!(decode_key::<u16>(&[0, 0, 1]), Err(DBError::Encoding { .. })));
+    }
+
+    #[test]
+    fn test_typed_db() {
+        let dir = tempdir().unwrap();
+        let env = Env::open(dir.path().join("db")).unwrap();
+        let db: TypedDb<(u32, String), Vec<f32>> = TypedDb::new(&env);
+        for (user, name) in [(2, "b"), (1, "z"), (10, "a"), (2, "a")] {
+            db.put(&(user, name.to_string()), &vec![user as f32; 3]).unwrap();
+        }
+        assert_eq!(db.get(&(10, "a".into())).unwrap(), [10.0; 3]);
+        assert!(matches!(db.get(&(3, "a".into())), Err(DBError::KeyNotFound)));
+
+        // numbers sort numerically, not by their decimal digits
+        let keys = |range| -> Vec<(u32, String)> {
+            db.range(range).unwrap().into_iter().map(|(key, _)| key).collect()
+        };
+        let all = keys((Bound::Unbounded, Bound::Unbounded));
+        let all: Vec<(u32, &str)> = all.iter().map(|(user, name)| (*user, &name[..])).collect();
+        assert_eq!(all, [(1, "z"), (2, "a"), (2, "b"), (10, "a")]);
+        let twos = keys((Bound::Included((2, String::new())), Bound::Excluded((3, String::new()))));
+        assert_eq!(twos.len(), 2);
+
+        db.delete(&(2, "a".into())).unwrap();
+        assert_eq!(keys((Bound::Excluded((1, "z".into())), Bound::Unbounded)).len(), 2);
+    }
+}
@@ -0,0 +1,308 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::buf::ByteBuf;
+use crate::constants::*;
+use crate::meta::Meta;
+use crate::metrics::Metrics;
+use crate::page::Page;
+
+// record header: body length (u32) + crc32 of the body (u32); the body is the
+// commit's meta page, then each run of its pages as pgno (u64), length (u32)
+// and the pages themselves
+const RECORD_HEADER_SIZE: usize = 4 + 4;
+const RUN_HEADER_SIZE: usize = 8 + 4;
+
+/// How `EnvOptions::wal` groups commits and when it checkpoints.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WalOptions {
+    /// How long the first commit of a group waits for others to join it
+    /// before syncing them all; 0, the default, syncs right away, so only
+    /// commits made while a sync is under way share the next one.
+    pub group_interval: Duration,
+    /// Bytes of records that sync a group without waiting out the interval.
+    pub group_bytes: u64,
+    /// Size the log may grow to before the commit that passes it
+    /// checkpoints; 0 leaves checkpoints to `Env::checkpoint`.
+    pub checkpoint_bytes: u64,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        WalOptions {
+            group_interval: Duration::ZERO,
+            group_bytes: 1 << 20,
+            checkpoint_bytes: 64 << 20,
+        }
+    }
+}
+
+/// What the log has done since the environment was opened, from
+/// `EnvStat::wal`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct WalStat {
+    /// Size of the log, which holds the commits since the last checkpoint.
+    pub bytes: u64,
+    pub commits: u64,
+    /// Syncs of the log, each making every commit appended before it
+    /// durable; fewer than `commits` when commits share them.
+    pub syncs: u64,
+    pub checkpoints: u64,
+}
+
+/// A commit as the log holds it: its meta page and the runs of pages it
+/// wrote, to be written again if the file lost them.
+pub struct WalRecord {
+    pub meta: Meta,
+    pub runs: Vec<(Pgno, Vec<u8>)>,
+}
+
+struct WalState {
+    // positions count every byte appended since the log was opened, so they
+    // go on growing across the truncations checkpoints make; `start` is the
+    // position of the file's first byte
+    start: u64,
+    end: u64,
+    synced: u64,
+    // when the first record not yet being synced was appended
+    group_start: Option<Instant>,
+    syncing: bool,
+    // the commit the file's meta page last durably recorded
+    checkpoint: Meta,
+    stat: WalStat,
+}
+
+/// The write-ahead log of an environment opened with `EnvOptions::wal`. A
+/// commit appends a record of its pages and meta page here instead of
+/// syncing them into the file, then waits for a sync of the log to cover
+/// it. The first commit to wait leads its group: it syncs every record
+/// appended so far, while commits made meanwhile wait for the next one. A
+/// checkpoint writes the meta page of the latest commit to the file, once
+/// the pages before it are synced, and empties the log.
+pub(crate) struct Wal {
+    file: File,
+    options: WalOptions,
+    state: Mutex<WalState>,
+    changed: Condvar,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl Wal {
+    /// Opens the log at `path`, creating it if need be, for a file whose
+    /// meta page records `checkpoint`.
+    pub fn open(path: &Path, options: WalOptions, checkpoint: Meta) -> Result<Self, DBError> {
+        let file =
+            OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let end = file.metadata()?.len();
+        Ok(Wal {
+            file,
+            options,
+            state: Mutex::new(WalState {
+                start: 0,
+                end,
+                synced: end,
+                group_start: None,
+                syncing: false,
+                checkpoint,
+                stat: WalStat::default(),
+            }),
+            changed: Condvar::new(),
+            metrics: None,
+        })
+    }
+
+    /// Also counts the log's syncs in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn sync(&self, sync: impl FnOnce(&File) -> io::Result<()>) -> io::Result<()> {
+        let started = Instant::now();
+        sync(&self.file)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.fsync(started.elapsed());
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, WalState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Every commit the log holds, in order, up to the first record that
+    /// wasn't fully written.
+    pub fn read_records(&self, page_size: usize) -> Result<Vec<WalRecord>, DBError> {
+        let mut log = vec![0; self.file.metadata()?.len() as usize];
+        self.file.read_exact_at(&mut log, 0)?;
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while let (Some(len), Some(crc)) = (log.read_u32_le(pos), log.read_u32_le(pos + 4)) {
+            let Some(body) = log.read_n_bytes(pos + RECORD_HEADER_SIZE, len as usize) else {
+                break;
+            };
+            if crc32fast::hash(body) != crc {
+                break;
+            }
+            let Some(record) = Self::parse_record(body, page_size) else {
+                break;
+            };
+            records.push(record);
+            pos += RECORD_HEADER_SIZE + body.len();
+        }
+        Ok(records)
+    }
+
+    fn parse_record(body: &[u8], page_size: usize) -> Option<WalRecord> {
+        let meta = Page::from_bytes(body.get(..page_size)?).and_then(|page| {
+            page.as_page_ref().verify_checksum()?;
+            Meta::from(page.as_page_ref())
+        });
+        let mut runs = Vec::new();
+        let mut pos = page_size;
+        while pos < body.len() {
+            let pgno = body.read_u64_le(pos)?;
+            let len = body.read_u32_le(pos + 8)? as usize;
+            runs.push((pgno, body.read_n_bytes(pos + RUN_HEADER_SIZE, len)?.to_vec()));
+            pos += RUN_HEADER_SIZE + len;
+        }
+        Some(WalRecord { meta: meta.ok()?, runs })
+    }
+
+    /// Appends the record of a commit, unsynced, and returns the position
+    /// `wait_synced` has to see synced for the commit to be durable.
+    pub fn append(&self, runs: &[(Pgno, Vec<u8>)], meta: &Meta) -> Result<u64, DBError> {
+        let mut body = meta.write_page().as_bytes().to_vec();
+        for (pgno, bytes) in runs {
+            body.extend_from_slice(&pgno.to_le_bytes());
+            body.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            body.extend_from_slice(bytes);
+        }
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + body.len());
+        record.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+        record.extend_from_slice(&body);
+
+        let mut state = self.lock();
+        self.file.write_all_at(&record, state.end - state.start)?;
+        state.end += record.len() as u64;
+        state.stat.commits += 1;
+        state.group_start.get_or_insert_with(Instant::now);
+        if state.end - state.synced >= self.options.group_bytes {
+            self.changed.notify_all();
+        }
+        Ok(state.end)
+    }
+
+    /// Waits until the log is synced up to `end`, syncing it, with every
+    /// record appended by then, if no other commit is about to.
+    pub fn wait_synced(&self, end: u64) -> Result<(), DBError> {
+        let mut state = self.lock();
+        loop {
+            if state.synced >= end {
+                return Ok(());
+            }
+            if state.syncing {
+                state = self.changed.wait(state).unwrap_or_else(PoisonError::into_inner);
+                continue;
+            }
+            let now = Instant::now();
+            let deadline = state.group_start.unwrap_or(now) + self.options.group_interval;
+            if now < deadline && state.end - state.synced < self.options.group_bytes {
+                let (waited, _) = self
+                    .changed
+                    .wait_timeout(state, deadline - now)
+                    .unwrap_or_else(PoisonError::into_inner);
+                state = waited;
+                continue;
+            }
+            let target = state.end;
+            state.syncing = true;
+            // records appended from here on make up the next group
+            state.group_start = None;
+            drop(state);
+            let synced = self.sync(File::sync_data);
+            state = self.lock();
+            state.syncing = false;
+            if synced.is_ok() {
+                state.synced = state.synced.max(target);
+                state.stat.syncs += 1;
+            }
+            self.changed.notify_all();
+            synced?;
+        }
+    }
+
+    /// Bytes of records appended since the last checkpoint.
+    pub fn len(&self) -> u64 {
+        let state = self.lock();
+        state.end - state.start
+    }
+
+    pub const fn get_options(&self) -> WalOptions {
+        self.options
+    }
+
+    /// See `reset`.
+    pub fn get_checkpoint(&self) -> Meta {
+        self.lock().checkpoint
+    }
+
+    /// Empties the log once the file durably records `checkpoint`, which
+    /// leaves every commit appended so far durable.
+    pub fn reset(&self, checkpoint: Meta) -> Result<(), DBError> {
+        let mut state = self.lock();
+        self.file.set_len(0)?;
+        // a log that came back after a crash would write its pages again
+        // over whatever later commits had put there
+        self.sync(File::sync_all)?;
+        state.start = state.end;
+        state.synced = state.end;
+        state.group_start = None;
+        state.checkpoint = checkpoint;
+        state.stat.checkpoints += 1;
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    pub fn stat(&self) -> WalStat {
+        let state = self.lock();
+        WalStat { bytes: state.end - state.start, ..state.stat }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_torn_record_ends_the_log() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db-wal");
+        let first = Meta::new(DEFAULT_PAGE_SIZE).unwrap();
+        let wal = Wal::open(&path, WalOptions::default(), first).unwrap();
+        let second = first.next_commit(Some(2), 3);
+        let pages = vec![(2, vec![7; DEFAULT_PAGE_SIZE])];
+        wal.append(&pages, &second).unwrap();
+        let end = wal.append(&[], &second.next_commit(Some(2), 3)).unwrap();
+        wal.wait_synced(end).unwrap();
+        assert_eq!(wal.stat().syncs, 1);
+
+        let records = wal.read_records(DEFAULT_PAGE_SIZE).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].meta, &records[0].runs), (second, &pages));
+        // the second record lost its last byte
+        wal.file.set_len(end - 1).unwrap();
+        assert_eq!(wal.read_records(DEFAULT_PAGE_SIZE).unwrap().len(), 1);
+
+        wal.reset(second).unwrap();
+        assert!(wal.read_records(DEFAULT_PAGE_SIZE).unwrap().is_empty());
+        // positions go on from where they were
+        assert!(wal.append(&[], &second).unwrap() > end);
+    }
+}
@@ -0,0 +1,42 @@
+use std::process::{Command, Output};
+
+use tempfile::tempdir;
+
+fn mmdb(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mmdb")).args(args).output().unwrap()
+}
+
+fn stdout(args: &[&str]) -> String {
+    let output = mmdb(args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_shell_round_trip() {
+    let dir = tempdir().unwrap();
+    let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+    let (db, dump, copy) = (path("db"), path("db.dump"), path("copy"));
+
+    for (key, value) in [("user/1", "ann"), ("user/2", "bob\n"), ("group/1", "admins")] {
+        stdout(&["put", &db, key, value]);
+    }
+    assert_eq!(stdout(&["get", &db, "user/2"]), "bob\\n\n");
+    assert_eq!(stdout(&["scan", &db, "user/"]), "user/1\tann\nuser/2\tbob\\n\n");
+    stdout(&["del", &db, "user/1"]);
+    let missing = mmdb(&["get", &db, "user/1"]);
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("key not found"));
+
+    assert!(stdout(&["stat", &db]).contains("entries: 2\n"));
+    let info = stdout(&["info", &db]);
+    assert!(info.contains("last commit: 4\nentries: 2\n"), "{info}");
+    assert!(info.contains("byte order: little-endian (0x01020304)\n"));
+    assert!(stdout(&["check", &db]).starts_with("ok"));
+
+    stdout(&["dump", &db, &dump]);
+    stdout(&["load", &copy, &dump]);
+    assert_eq!(stdout(&["scan", &copy]), stdout(&["scan", &db]));
+
+    assert!(!mmdb(&["put", &db]).status.success());
+}
@@ -0,0 +1,230 @@
+//! Random sequences of operations run against an environment and against a
+//! `BTreeMap` standing in for it, comparing every result. A failure names the
+//! seed and the operation it went wrong at; `MMDB_MODEL_SEED=<seed>` runs that
+//! sequence alone, and `MMDB_MODEL_RUNS=<n>` runs more sequences than usual.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+
+use mmdb::constants::*;
+use mmdb::env::{Env, EnvOptions};
+use mmdb::txn::ReadTxn;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tempfile::tempdir;
+
+const RUNS: u64 = 24;
+const OPS_PER_RUN: usize = 400;
+// few enough keys that puts overwrite and deletes find them, with values
+// large enough to split the smallest pages into a tree a few levels deep
+const KEYS: u32 = 400;
+const MAX_VALUE_SIZE: usize = 600;
+
+type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+#[derive(Debug)]
+enum Op {
+    Put(Vec<u8>, Vec<u8>),
+    // sorted, as `write_batch` takes them
+    Batch(Vec<(Vec<u8>, Vec<u8>)>),
+    Delete(Vec<u8>),
+    Get(Vec<u8>),
+    ScanPrefix(Vec<u8>),
+    // the entries from the first key at or after this one
+    Seek(Vec<u8>, usize),
+    Len,
+    Last,
+    Commit,
+    Abort,
+    // holds a read transaction on the last commit through later ones
+    Snapshot,
+    // drops the write transaction, uncommitted, and opens the file again
+    Reopen,
+}
+
+fn random_key(rng: &mut StdRng) -> Vec<u8> {
+    let i = rng.random_range(0..KEYS);
+    // keys of several lengths sharing prefixes, so pages store some once
+    match i % 3 {
+        0 => format!("user/{i:04}").into_bytes(),
+        1 => format!("user/{i:04}/profile/{}", "x".repeat(i as usize % 40)).into_bytes(),
+        _ => i.to_be_bytes().to_vec(),
+    }
+}
+
+fn random_value(rng: &mut StdRng) -> Vec<u8> {
+    let len = match rng.random_range(0..8) {
+        0 => 0,
+        1 => MAX_VALUE_SIZE,
+        _ => rng.random_range(1..MAX_VALUE_SIZE),
+    };
+    vec![rng.random(); len]
+}
+
+fn random_op(rng: &mut StdRng) -> Op {
+    match rng.random_range(0..100) {
+        0..35 => Op::Put(random_key(rng), random_value(rng)),
+        35..40 => {
+            let len = rng.random_range(1..30);
+            let batch: Model = (0..len).map(|_| (random_key(rng), random_value(rng))).collect();
+            Op::Batch(batch.into_iter().collect())
+        }
+        40..55 => Op::Delete(random_key(rng)),
+        55..75 => Op::Get(random_key(rng)),
+        75..80 => {
+            let mut prefix = random_key(rng);
+            prefix.truncate(rng.random_range(0..6));
+            Op::ScanPrefix(prefix)
+        }
+        80..84 => Op::Seek(random_key(rng), rng.random_range(0..20)),
+        84..85 => Op::Len,
+        85..86 => Op::Last,
+        86..93 => Op::Commit,
+        93..95 => Op::Abort,
+        95..97 => Op::Snapshot,
+        _ => Op::Reopen,
+    }
+}
+
+fn missing_as_none(found: Result<&[u8], DBError>) -> Option<&[u8]> {
+    match found {
+        Ok(value) => Some(value),
+        Err(DBError::KeyNotFound) => None,
+        Err(err) => panic!("lookup failed: {err:?}"),
+    }
+}
+
+fn prefixed<'m>(model: &'m Model, prefix: &[u8]) -> Vec<(&'m [u8], &'m [u8])> {
+    model
+        .range(prefix.to_vec()..)
+        .take_while(|(key, _)| key.starts_with(prefix))
+        .map(|(key, value)| (&key[..], &value[..]))
+        .collect()
+}
+
+fn assert_matches(txn: &ReadTxn, model: &Model, context: &str) {
+    let entries: Vec<_> = txn.tree().cursor().unwrap().map(Result::unwrap).collect();
+    assert_eq!(entries.len(), model.len(), "{context}");
+    for ((key, value), (model_key, model_value)) in entries.iter().zip(model) {
+        assert_eq!((&key[..], *value), (&model_key[..], &model_value[..]), "{context}");
+    }
+    assert_eq!(txn.len(), model.len() as u64, "{context}");
+}
+
+fn open(path: &Path) -> Env {
+    EnvOptions::new().page_size(MIN_PAGE_SIZE).open(path).unwrap()
+}
+
+fn run(seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let ops: Vec<Op> = (0..OPS_PER_RUN).map(|_| random_op(&mut rng)).collect();
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("db");
+    let mut ops = ops.iter().enumerate();
+    let mut committed = Model::new();
+    // every session ends with a reopen, or when the operations run out
+    loop {
+        let env = open(&path);
+        let context = format!("seed {seed}, on open");
+        assert_matches(&env.begin_read().unwrap(), &committed, &context);
+        assert!(env.check().unwrap().is_ok(), "{context}");
+        let mut snapshot: Option<(ReadTxn, Model)> = None;
+        // every transaction ends with a commit or an abort
+        let reopen = 'txn: loop {
+            let mut txn = env.begin_write();
+            let mut pending = committed.clone();
+            let reader = env.begin_read().unwrap();
+            loop {
+                let Some((i, op)) = ops.next() else {
+                    break 'txn false;
+                };
+                let context = format!("seed {seed}, op {i}: {op:?}");
+                match op {
+                    Op::Put(key, value) => {
+                        txn.put(key, value).unwrap();
+                        pending.insert(key.clone(), value.clone());
+                    }
+                    Op::Batch(entries) => {
+                        txn.write_batch(entries.iter().cloned()).unwrap();
+                        pending.extend(entries.iter().cloned());
+                    }
+                    Op::Delete(key) => match (txn.delete(key), pending.remove(key)) {
+                        (Ok(()), Some(_)) | (Err(DBError::KeyNotFound), None) => {}
+                        (result, expected) => panic!("{context}: {result:?}, {expected:?}"),
+                    },
+                    Op::Get(key) => {
+                        let found = missing_as_none(txn.get(key));
+                        assert_eq!(found, pending.get(key).map(Vec::as_slice), "{context}");
+                        // readers see only what was committed when they began
+                        let found = missing_as_none(reader.get(key));
+                        assert_eq!(found, committed.get(key).map(Vec::as_slice), "{context}");
+                        if let Some((snapshot, model)) = &snapshot {
+                            let found = missing_as_none(snapshot.get(key));
+                            assert_eq!(found, model.get(key).map(Vec::as_slice), "{context}");
+                        }
+                    }
+                    Op::ScanPrefix(prefix) => {
+                        let scanned: Vec<_> =
+                            txn.scan_prefix(prefix).unwrap().map(Result::unwrap).collect();
+                        let scanned: Vec<_> =
+                            scanned.iter().map(|(key, value)| (&key[..], *value)).collect();
+                        assert_eq!(scanned, prefixed(&pending, prefix), "{context}");
+                    }
+                    Op::Seek(key, count) => {
+                        let tree = txn.tree();
+                        let mut cursor = tree.cursor().unwrap();
+                        cursor.seek(key).unwrap();
+                        let seen: Vec<_> = cursor.take(*count).map(Result::unwrap).collect();
+                        let seen: Vec<_> =
+                            seen.iter().map(|(key, value)| (&key[..], *value)).collect();
+                        let expected: Vec<_> = pending
+                            .range(key.clone()..)
+                            .take(*count)
+                            .map(|(key, value)| (&key[..], &value[..]))
+                            .collect();
+                        assert_eq!(seen, expected, "{context}");
+                    }
+                    Op::Len => assert_eq!(txn.len(), pending.len() as u64, "{context}"),
+                    Op::Last => {
+                        let last = txn.last_key_value().unwrap();
+                        let last = last.as_ref().map(|(key, value)| (&key[..], *value));
+                        let expected = pending.last_key_value();
+                        let expected = expected.map(|(key, value)| (&key[..], &value[..]));
+                        assert_eq!(last, expected, "{context}");
+                    }
+                    Op::Commit => {
+                        txn.commit().unwrap();
+                        committed = pending;
+                        assert_matches(&env.begin_read().unwrap(), &committed, &context);
+                        continue 'txn;
+                    }
+                    Op::Abort => {
+                        txn.abort();
+                        assert_matches(&env.begin_read().unwrap(), &committed, &context);
+                        continue 'txn;
+                    }
+                    Op::Snapshot => snapshot = Some((env.begin_read().unwrap(), committed.clone())),
+                    Op::Reopen => break 'txn true,
+                }
+            }
+        };
+        if let Some((snapshot, model)) = &snapshot {
+            assert_matches(snapshot, model, &format!("seed {seed}, snapshot at the end"));
+        }
+        if !reopen {
+            return;
+        }
+    }
+}
+
+#[test]
+fn test_against_model() {
+    if let Ok(seed) = env::var("MMDB_MODEL_SEED") {
+        return run(seed.parse().expect("MMDB_MODEL_SEED is a number"));
+    }
+    let runs = env::var("MMDB_MODEL_RUNS").map_or(RUNS, |runs| runs.parse().unwrap());
+    for seed in 0..runs {
+        run(seed);
+    }
+}